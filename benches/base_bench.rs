@@ -1,28 +1,82 @@
+mod synthetic;
+
+use ahash::AHashSet;
 use criterion::{criterion_group, criterion_main, Criterion};
 use rewatch::build;
+use rewatch::build::build_types::BuildState;
 use rewatch::build::packages;
 use rewatch::helpers;
 
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::Path;
+
+fn make_build_state(project_root: &str) -> BuildState {
+    let packages = packages::make(
+        &None,
+        project_root,
+        None,
+        true,
+        &[],
+        &packages::PackageOptions::default(),
+    );
+    let root_config_name = packages::get_package_name(project_root);
+    let mut build_state = BuildState::new(project_root.to_string(), root_config_name, packages);
+    packages::parse_packages(&mut build_state, true);
+    build_state
+}
 
 fn criterion_benchmark(c: &mut Criterion) {
+    let project = synthetic::scaffold("base");
+    let project_root = project.root.to_string_lossy().to_string();
+
     c.bench_function("build-package-tree", |b| {
-        // Folder for the testrepo
-        let folder = "walnut_monorepo";
-        let project_root = helpers::get_abs_path(folder);
+        b.iter(|| {
+            packages::make(
+                &None,
+                &project_root,
+                None,
+                true,
+                &[],
+                &packages::PackageOptions::default(),
+            );
+        })
+    });
+
+    c.bench_function("find-source-files", |b| {
+        b.iter(|| make_build_state(&project_root));
+    });
 
+    c.bench_function("get-dep-modules", |b| {
         b.iter(|| {
-            packages::make(&project_root);
+            for ast_file in project.ast_files.iter() {
+                build::ast::get_dep_modules(ast_file).unwrap();
+            }
         })
     });
 
+    c.bench_function("collect-deps", |b| {
+        let mut build_state = make_build_state(&project_root);
+        b.iter(|| build::deps::get_deps(&mut build_state, &AHashSet::new()));
+    });
+
+    // Folder for the testrepo, this needs the compiler in node_modules
+    let folder = "testrepo";
+    if !Path::new(&helpers::get_abs_path(folder))
+        .join("node_modules/rescript")
+        .exists()
+    {
+        return;
+    }
     c.bench_function("clean-build-change-build", |b| {
-        // Folder for the testrepo
-        let folder = "testrepo";
         let filename = "testrepo/packages/dep02/src/Dep02.res";
+        let build_options = build::BuildOptions {
+            respect_ignore: true,
+            ..build::BuildOptions::default()
+        };
+        let build = || build::build(folder, &build_options);
         // Clean the build
-        build::clean(folder);
+        build::clean::clean(folder, &build_options.package_options);
         // Read the file we'll be mutating
         let mut file = File::options()
             .read(true)
@@ -35,16 +89,16 @@ fn criterion_benchmark(c: &mut Criterion) {
 
         b.iter(|| {
             // Create initial build
-            let _ = build::build(folder);
+            let _ = build();
             // Update the file
             let _ = writeln!(file, r#"let log2 = () => ["a", "b"]->forEach(Js.log);log2()"#);
             // Create another build
-            let _ = build::build(folder);
+            let _ = build();
 
             // Reset state
             File::create(filename).unwrap();
             file.write_all(contents.as_bytes()).unwrap();
-            let _ = build::build(folder);
+            let _ = build();
         })
     });
 }
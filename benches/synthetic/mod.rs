@@ -0,0 +1,118 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A synthetic project for the benchmarks, which doesn't need the compiler: the ASTs are written
+// directly, with the same header (the dependencies of the module) as the ASTs of bsc. The size is
+// configurable with `REWATCH_BENCH_PACKAGES` and `REWATCH_BENCH_MODULES` (modules per package).
+
+pub struct SyntheticProject {
+    pub root: PathBuf,
+    pub ast_files: Vec<String>,
+}
+
+impl Drop for SyntheticProject {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn get_size(env_var: &str, default: usize) -> usize {
+    std::env::var(env_var)
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(default)
+}
+
+fn module_name(package: usize, module: usize) -> String {
+    format!("P{}M{}", package, module)
+}
+
+fn write_ast(build_path: &Path, source_file: &Path, deps: &[String]) -> String {
+    let ast_file = build_path.join(format!(
+        "{}.ast",
+        source_file.file_stem().unwrap().to_string_lossy()
+    ));
+    let mut contents = b"\x84\x95\xa6\xbe\0\0\0\x0c\n".to_vec();
+    for dep in deps {
+        contents.extend(dep.as_bytes());
+        contents.push(b'\n');
+    }
+    contents.extend(source_file.to_string_lossy().as_bytes());
+    contents.push(b'\n');
+    // the binary AST, which the dependency parser should never read
+    contents.extend(vec![0u8; 4096]);
+    fs::write(&ast_file, contents).unwrap();
+    ast_file.to_string_lossy().to_string()
+}
+
+/// Scaffolds a project with `REWATCH_BENCH_PACKAGES` packages (default 10) in node_modules, with
+/// `REWATCH_BENCH_MODULES` modules each (default 100), into a temporary directory. Every module
+/// depends on the previous module in its package, and on a module of the previous package. The
+/// directory is removed when the project is dropped.
+pub fn scaffold(name: &str) -> SyntheticProject {
+    let num_packages = get_size("REWATCH_BENCH_PACKAGES", 10);
+    let num_modules = get_size("REWATCH_BENCH_MODULES", 100);
+    let root = std::env::temp_dir().join(format!("rewatch-bench-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let package_names = (0..num_packages)
+        .map(|package| format!("package-{}", package))
+        .collect::<Vec<String>>();
+
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("package.json"), r#"{ "name": "synthetic" }"#).unwrap();
+    fs::write(
+        root.join("bsconfig.json"),
+        format!(
+            r#"{{ "name": "synthetic", "sources": "src", "bs-dependencies": {:?} }}"#,
+            package_names
+        ),
+    )
+    .unwrap();
+    fs::write(root.join("src/Main.res"), "").unwrap();
+    fs::create_dir_all(root.join("lib/ocaml")).unwrap();
+    let root = root.canonicalize().unwrap();
+
+    let mut ast_files = vec![write_ast(
+        &root.join("lib/ocaml"),
+        &root.join("src/Main.res"),
+        &(0..num_packages)
+            .map(|package| module_name(package, 0))
+            .collect::<Vec<String>>(),
+    )];
+    for (package, package_name) in package_names.iter().enumerate() {
+        let package_path = root.join("node_modules").join(package_name);
+        let build_path = package_path.join("lib/ocaml");
+        fs::create_dir_all(package_path.join("src")).unwrap();
+        fs::create_dir_all(&build_path).unwrap();
+        let bs_dependencies = if package > 0 {
+            vec![package_names[package - 1].to_owned()]
+        } else {
+            vec![]
+        };
+        fs::write(
+            package_path.join("bsconfig.json"),
+            format!(
+                r#"{{ "name": "{}", "sources": "src", "bs-dependencies": {:?} }}"#,
+                package_name, bs_dependencies
+            ),
+        )
+        .unwrap();
+
+        for module in 0..num_modules {
+            let source_file = package_path
+                .join("src")
+                .join(module_name(package, module) + ".res");
+            fs::write(&source_file, "let value = 1\n").unwrap();
+            let mut deps = vec!["Belt".to_string()];
+            if module > 0 {
+                deps.push(module_name(package, module - 1));
+            }
+            if package > 0 {
+                deps.push(module_name(package - 1, module));
+            }
+            ast_files.push(write_ast(&build_path, &source_file, &deps));
+        }
+    }
+
+    SyntheticProject { root, ast_files }
+}
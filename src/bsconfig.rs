@@ -1,8 +1,10 @@
 use serde::Deserialize;
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
 
 pub static DEFAULT_SUFFIX: &str = ".mjs";
+pub static DEFAULT_BUILD_DIR: &str = "lib/ocaml";
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(untagged)]
@@ -81,6 +83,45 @@ pub struct PackageSpec {
     pub module: String,
     #[serde(rename = "in-source")]
     pub in_source: bool,
+    pub suffix: Option<String>,
+}
+
+impl PackageSpec {
+    /// The suffix of the spec itself, falling back to the top level suffix of the config.
+    pub fn get_suffix(&self, config: &T) -> String {
+        self.suffix
+            .to_owned()
+            .or(config.suffix.to_owned())
+            .unwrap_or(String::from(DEFAULT_SUFFIX))
+    }
+
+    /// The directory (relative to the package) of the output of the source files in `source_dir`.
+    /// In-source output goes next to the source files, otherwise it goes to lib/js, lib/es6 or
+    /// lib/es6_global (like bsb does), mirroring the directory structure of the sources.
+    pub fn get_output_dir(&self, source_dir: &Path) -> PathBuf {
+        if self.in_source {
+            return source_dir.to_path_buf();
+        }
+        let module_dir = match self.module.as_str() {
+            "commonjs" => "js",
+            "es6-global" => "es6_global",
+            module => module,
+        };
+        Path::new("lib").join(module_dir).join(source_dir)
+    }
+
+    /// The value of the `-bs-package-output` argument for the source files in `source_dir`, ie.
+    /// `es6-global:lib/es6_global/src:.mjs`. With `es6-global`, bsc emits relative imports to the
+    /// dependencies (`../../node_modules/dep/lib/es6_global/src/Dep.mjs`) instead of package
+    /// imports (`dep/lib/es6/src/Dep.mjs`), so the output runs without a bundler.
+    pub fn get_package_output(&self, config: &T, source_dir: &Path) -> String {
+        format!(
+            "{}:{}:{}",
+            self.module,
+            self.get_output_dir(source_dir).to_string_lossy(),
+            self.get_suffix(config)
+        )
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -132,6 +173,31 @@ pub struct JsxSpecs {
     pub v3_dependencies: Option<Vec<String>>,
 }
 
+/// Configuration in the root bsconfig that is shared with all the packages.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SharedConfig {
+    #[serde(rename = "ppx-flags")]
+    pub ppx_flags: Option<Vec<OneOrMore<String>>>,
+    #[serde(rename = "bsc-flags")]
+    pub bsc_flags: Option<Vec<OneOrMore<String>>>,
+    pub warnings: Option<Warnings>,
+    #[serde(rename = "ppx-working-directory")]
+    pub ppx_working_directory: Option<PpxWorkingDirectory>,
+}
+
+/// The working directory of bsc (and so of the PPXes) when generating the ASTs of a package. Some
+/// PPXes read config files relative to their working directory.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PpxWorkingDirectory {
+    /// The `lib/ocaml` folder of the package (the default)
+    Build,
+    /// The root folder of the package
+    Package,
+    /// The root folder of the project
+    Project,
+}
+
 /// # bsconfig.json representation
 /// This is tricky, there is a lot of ambiguity. This is probably incomplete.
 #[derive(Deserialize, Debug, Clone)]
@@ -148,6 +214,8 @@ pub struct T {
     pub bs_dependencies: Option<Vec<String>>,
     #[serde(rename = "bs-dev-dependencies")]
     pub bs_dev_dependencies: Option<Vec<String>>,
+    #[serde(rename = "bs-external-includes")]
+    pub bs_external_includes: Option<Vec<String>>,
     #[serde(rename = "ppx-flags")]
     pub ppx_flags: Option<Vec<OneOrMore<String>>>,
     #[serde(rename = "bsc-flags")]
@@ -162,28 +230,327 @@ pub struct T {
     // this is a new feature of rewatch, and it's not part of the bsconfig.json spec
     #[serde(rename = "allowed-dependents")]
     pub allowed_dependents: Option<Vec<String>>,
+    // this is a new feature of rewatch, and it's not part of the bsconfig.json spec
+    #[serde(rename = "shared-config")]
+    pub shared_config: Option<SharedConfig>,
+    // this is a new feature of rewatch, and it's not part of the bsconfig.json spec
+    #[serde(rename = "ppx-working-directory")]
+    pub ppx_working_directory: Option<PpxWorkingDirectory>,
+    // this is a new feature of rewatch, and it's not part of the bsconfig.json spec
+    pub bsc: Option<String>,
+    // this is a new feature of rewatch, and it's not part of the bsconfig.json spec
+    #[serde(rename = "external-js")]
+    pub external_js: Option<Vec<String>>,
+    // this is a new feature of rewatch, and it's not part of the bsconfig.json spec
+    #[serde(rename = "build-dir")]
+    pub build_dir: Option<String>,
+}
+
+fn get_ppx_name(flag: &OneOrMore<String>) -> Option<&String> {
+    match flag {
+        OneOrMore::Single(ppx) => Some(ppx),
+        OneOrMore::Multiple(ppx) => ppx.first(),
+    }
 }
 
-/// This flattens string flags
+/// Merges the shared config (from the root bsconfig) into the config of a package. The settings of
+/// the package take precedence: its bsc-flags come after the shared ones (so they win when bsc
+/// reads conflicting flags), and the warning settings and ppx-working-directory of the package
+/// override the shared ones.
+///
+/// The PPXes form a pipeline, in the order they are declared. The shared PPXes run first, followed
+/// by the PPXes of the package. A ppx that's configured in both replaces the shared one at its
+/// position (ie. to give it other arguments), so it still runs in the order of the shared config.
+pub fn merge_shared_config(config: &T, shared_config: &SharedConfig) -> T {
+    let bsc_flags = match (&shared_config.bsc_flags, &config.bsc_flags) {
+        (None, flags) | (flags, None) => flags.to_owned(),
+        (Some(shared_flags), Some(flags)) => Some([shared_flags.to_owned(), flags.to_owned()].concat()),
+    };
+
+    let ppx_flags = match (&shared_config.ppx_flags, &config.ppx_flags) {
+        (None, flags) | (flags, None) => flags.to_owned(),
+        (Some(shared_flags), Some(flags)) => {
+            let package_flag = |ppx: &String| flags.iter().find(|flag| get_ppx_name(flag) == Some(ppx));
+            let shared_ppxs = shared_flags
+                .iter()
+                .filter_map(get_ppx_name)
+                .collect::<Vec<&String>>();
+            Some(
+                shared_flags
+                    .iter()
+                    .filter_map(|flag| get_ppx_name(flag).map(|ppx| package_flag(ppx).unwrap_or(flag)))
+                    .chain(flags.iter().filter(|flag| {
+                        get_ppx_name(flag)
+                            .map(|ppx| !shared_ppxs.contains(&ppx))
+                            .unwrap_or(false)
+                    }))
+                    .cloned()
+                    .collect(),
+            )
+        }
+    };
+
+    let warnings = match (&shared_config.warnings, &config.warnings) {
+        (None, warnings) | (warnings, None) => warnings.to_owned(),
+        (Some(shared_warnings), Some(warnings)) => Some(Warnings {
+            number: warnings.number.to_owned().or(shared_warnings.number.to_owned()),
+            error: warnings.error.to_owned().or(shared_warnings.error.to_owned()),
+        }),
+    };
+
+    T {
+        bsc_flags,
+        ppx_flags,
+        warnings,
+        ppx_working_directory: config
+            .ppx_working_directory
+            .to_owned()
+            .or(shared_config.ppx_working_directory.to_owned()),
+        ..config.to_owned()
+    }
+}
+
+/// Returns the module formats a package is compiled to. When the config doesn't have package-specs,
+/// we output es6 next to the source files.
+pub fn get_package_specs(config: &T) -> Vec<PackageSpec> {
+    match config.package_specs.to_owned() {
+        None => vec![PackageSpec {
+            module: String::from("es6"),
+            in_source: true,
+            suffix: None,
+        }],
+        Some(OneOrMore::Single(spec)) => vec![spec],
+        Some(OneOrMore::Multiple(specs)) => specs,
+    }
+}
+
+/// The paths of the JS files that are output for a source file, one for every package spec. The
+/// source file is either relative to the package path, or an absolute path within the package.
+pub fn get_output_paths(config: &T, package_path: &str, source_file: &str) -> Vec<String> {
+    let source_file = Path::new(source_file);
+    let relative_source_file = source_file.strip_prefix(package_path).unwrap_or(source_file);
+    let source_dir = relative_source_file.parent().unwrap_or(Path::new(""));
+    let file_stem = relative_source_file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    get_package_specs(config)
+        .iter()
+        .map(|spec| {
+            Path::new(package_path)
+                .join(spec.get_output_dir(source_dir))
+                .join(file_stem.to_owned() + &spec.get_suffix(config))
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect()
+}
+
+/// This flattens string flags. The flags can be a flat array (`["-w", "-30"]`), an array of arrays
+/// (`[["-w", "-30"], ["-bs-g"]]`) or a mix of both, and a flag can contain its argument
+/// (`"-open Belt"`), so the flags are split on whitespace as well.
 pub fn flatten_flags(flags: &Option<Vec<OneOrMore<String>>>) -> Vec<String> {
     match flags {
         None => vec![],
         Some(xs) => xs
             .iter()
-            .map(|x| match x {
+            .flat_map(|x| match x {
                 OneOrMore::Single(y) => vec![y.to_owned()],
                 OneOrMore::Multiple(ys) => ys.to_owned(),
             })
-            .flatten()
-            .collect::<Vec<String>>()
-            .iter()
-            .map(|str| str.split(" "))
-            .flatten()
-            .map(|str| str.to_string())
+            .flat_map(|str| {
+                str.split_whitespace()
+                    .map(|str| str.to_string())
+                    .collect::<Vec<String>>()
+            })
             .collect::<Vec<String>>(),
     }
 }
 
+/// Splits the extra flags for bsc from the command line (`--bsc-flag`), for quick experiments
+/// without editing the bsconfig. They apply to all packages, after the `bsc-flags` of the package
+/// (see `CompileOptions`).
+pub fn split_extra_bsc_flags(flags: &[String]) -> Vec<String> {
+    flags
+        .iter()
+        .flat_map(|flag| flag.split(' '))
+        .filter(|flag| !flag.is_empty())
+        .map(|flag| flag.to_string())
+        .collect()
+}
+
+/// The `bsc-flags` of the package, followed by the extra flags from the command line.
+pub fn get_bsc_flags(config: &T, extra_flags: &[String]) -> Vec<String> {
+    [flatten_flags(&config.bsc_flags), extra_flags.to_vec()].concat()
+}
+
+/// Where the flags of bsc come from (see `explain::explain_flags`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlagSource {
+    /// The bsconfig.json of the package
+    Package,
+    /// The `shared-config` of the root bsconfig.json, merged into the package (see
+    /// `merge_shared_config`)
+    SharedConfig,
+    /// The root bsconfig.json, whose settings apply to all packages (ie. `jsx` and `package-specs`)
+    Root,
+    /// A flag of the command line
+    CommandLine(&'static str),
+    /// Rewatch itself (ie. the include paths and the files)
+    Rewatch,
+}
+
+impl fmt::Display for FlagSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FlagSource::Package => write!(f, "bsconfig.json of the package"),
+            FlagSource::SharedConfig => write!(f, "shared-config of the root bsconfig.json"),
+            FlagSource::Root => write!(f, "root bsconfig.json"),
+            FlagSource::CommandLine(flag) => write!(f, "command line ({})", flag),
+            FlagSource::Rewatch => write!(f, "rewatch"),
+        }
+    }
+}
+
+/// The flags of `get_bsc_flags` with where they come from. `config` is merged with `shared_config`,
+/// which puts the shared flags first (see `merge_shared_config`).
+pub fn get_bsc_flag_sources(
+    config: &T,
+    shared_config: Option<&SharedConfig>,
+    extra_flags: &[String],
+) -> Vec<(FlagSource, Vec<String>)> {
+    let flags = flatten_flags(&config.bsc_flags);
+    let shared_count = shared_config
+        .map_or(0, |shared_config| flatten_flags(&shared_config.bsc_flags).len())
+        .min(flags.len());
+    let (shared_flags, package_flags) = flags.split_at(shared_count);
+    vec![
+        (FlagSource::SharedConfig, shared_flags.to_vec()),
+        (FlagSource::Package, package_flags.to_vec()),
+        (FlagSource::CommandLine("--bsc-flag"), extra_flags.to_vec()),
+    ]
+}
+
+/// Translates the jsx config into the `-bs-jsx` argument. The `jsx` object takes precedence over the
+/// deprecated `reason.react-jsx`, which is ignored when both are set (see `get_jsx_warning`).
+pub fn get_jsx_args(config: &T) -> Vec<String> {
+    match (config.reason.to_owned(), config.jsx.to_owned()) {
+        (_, Some(jsx)) => match jsx.version {
+            Some(version) if version == 3 || version == 4 => {
+                vec!["-bs-jsx".to_string(), version.to_string()]
+            }
+            Some(_version) => panic!("Unsupported JSX version"),
+            None => vec![],
+        },
+        (Some(reason), None) => {
+            vec!["-bs-jsx".to_string(), format!("{}", reason.react_jsx)]
+        }
+        _ => vec![],
+    }
+}
+
+/// Returns a warning when the config sets both the `jsx` object and the deprecated
+/// `reason.react-jsx`, because the latter is ignored then.
+pub fn get_jsx_warning(config: &T) -> Option<String> {
+    match (&config.reason, &config.jsx) {
+        (Some(reason), Some(_)) => Some(format!(
+            "\"reason\": {{ \"react-jsx\": {} }} in the config of {} is deprecated and ignored, because \"jsx\" is set. Please remove it.",
+            reason.react_jsx, config.name
+        )),
+        _ => None,
+    }
+}
+
+/// Translates the `warnings` object into bsc arguments: `number` becomes `-w <spec>` and `error`
+/// becomes `-warn-error <spec>` (or `-warn-error A` when it's `true`). The warning specs from the
+/// command line (`--warn-spec` and `--warn-error-spec`, ie. `-27+20`) follow them: bsc applies the
+/// specs of repeated `-w` and `-warn-error` arguments in order, so the spec from the command line
+/// composes with the one of the config: `-w +a -w -27` enables all warnings but 27.
+pub fn get_warning_args(
+    config: &T,
+    warn_spec: Option<String>,
+    warn_error_spec: Option<String>,
+) -> Vec<String> {
+    let config_args = get_config_warning_args(config);
+    let extra_args = [("-w", warn_spec), ("-warn-error", warn_error_spec)]
+        .into_iter()
+        .filter_map(|(arg, spec)| spec.map(|spec| vec![arg.to_string(), spec]))
+        .flatten();
+    config_args.into_iter().chain(extra_args).collect()
+}
+
+/// The arguments of `get_warning_args` with where they come from. `config` is merged with
+/// `shared_config`, so a setting that's the same as the shared one comes from the shared config.
+pub fn get_warning_arg_sources(
+    config: &T,
+    shared_config: Option<&SharedConfig>,
+    warn_spec: Option<String>,
+    warn_error_spec: Option<String>,
+) -> Vec<(FlagSource, Vec<String>)> {
+    let shared_args = shared_config
+        .map(|shared_config| get_warnings_args(shared_config.warnings.to_owned()))
+        .unwrap_or_default();
+    let shared_args = shared_args.chunks(2).collect::<Vec<&[String]>>();
+    let config_args = get_config_warning_args(config)
+        .chunks(2)
+        .map(|arg| match shared_args.contains(&arg) {
+            true => (FlagSource::SharedConfig, arg.to_vec()),
+            false => (FlagSource::Package, arg.to_vec()),
+        })
+        .collect::<Vec<(FlagSource, Vec<String>)>>();
+    let extra_args = [
+        ("-w", "--warn-spec", warn_spec),
+        ("-warn-error", "--warn-error-spec", warn_error_spec),
+    ]
+    .into_iter()
+    .filter_map(|(arg, flag, spec)| {
+        spec.map(|spec| (FlagSource::CommandLine(flag), vec![arg.to_string(), spec]))
+    });
+    config_args.into_iter().chain(extra_args).collect()
+}
+
+fn get_config_warning_args(config: &T) -> Vec<String> {
+    get_warnings_args(config.warnings.to_owned())
+}
+
+fn get_warnings_args(warnings: Option<Warnings>) -> Vec<String> {
+    match warnings {
+        None => vec![],
+        Some(warnings) => {
+            let warn_number = match warnings.number {
+                None => vec![],
+                Some(warnings) => {
+                    vec!["-w".to_string(), warnings.to_string()]
+                }
+            };
+
+            let warn_error = match warnings.error {
+                Some(Error::Catchall(true)) => {
+                    vec!["-warn-error".to_string(), "A".to_string()]
+                }
+                Some(Error::Qualified(errors)) => {
+                    vec!["-warn-error".to_string(), errors.to_string()]
+                }
+                _ => vec![],
+            };
+
+            vec![warn_number, warn_error].concat()
+        }
+    }
+}
+
+/// Resolves the path of a ppx binary. Paths starting with a `.` are relative to the package,
+/// absolute paths (ie. a ppx that isn't installed in node_modules) are used as is, and everything
+/// else is resolved from node_modules.
+fn resolve_ppx_path(node_modules_dir: &String, ppx: &String, package_name: &String) -> String {
+    match ppx.chars().next() {
+        Some('.') => node_modules_dir.to_owned() + "/" + package_name + "/" + ppx,
+        _ if Path::new(ppx).is_absolute() => ppx.to_owned(),
+        _ => node_modules_dir.to_owned() + "/" + ppx,
+    }
+}
+
 /// Since ppx-flags could be one or more, and could be nested potentiall, this function takes the
 /// flags and flattens them outright.
 pub fn flatten_ppx_flags(
@@ -197,24 +564,14 @@ pub fn flatten_ppx_flags(
             .iter()
             .map(|x| match x {
                 OneOrMore::Single(y) => {
-                    let first_character = y.chars().nth(0);
-                    match first_character {
-                        Some('.') => {
-                            vec![
-                                "-ppx".to_string(),
-                                node_modules_dir.to_owned() + "/" + package_name + "/" + y,
-                            ]
-                        }
-                        _ => vec!["-ppx".to_string(), node_modules_dir.to_owned() + "/" + y],
-                    }
+                    vec![
+                        "-ppx".to_string(),
+                        resolve_ppx_path(node_modules_dir, y, package_name),
+                    ]
                 }
                 OneOrMore::Multiple(ys) if ys.len() == 0 => vec![],
                 OneOrMore::Multiple(ys) => {
-                    let first_character = ys[0].chars().nth(0);
-                    let ppx = match first_character {
-                        Some('.') => node_modules_dir.to_owned() + "/" + package_name + "/" + &ys[0],
-                        _ => node_modules_dir.to_owned() + "/" + &ys[0],
-                    };
+                    let ppx = resolve_ppx_path(node_modules_dir, &ys[0], package_name);
                     vec![
                         "-ppx".to_string(),
                         vec![ppx]
@@ -230,12 +587,534 @@ pub fn flatten_ppx_flags(
     }
 }
 
+/// Makes a hand-edited config valid JSON, like the official tooling accepts it: removes `//` and
+/// `/* */` comments, and the commas before a closing `}` or `]`. They are replaced with spaces
+/// (the newlines are kept), so the lines and columns in the errors of serde still match the file.
+pub fn strip_comments_and_trailing_commas(json: &str) -> String {
+    let mut chars = json.chars().collect::<Vec<char>>();
+    let mut in_string = false;
+    let mut i = 0;
+    while i < chars.len() {
+        match (in_string, chars[i], chars.get(i + 1)) {
+            (true, '\\', _) => i += 1,
+            (_, '"', _) => in_string = !in_string,
+            (false, '/', Some('/')) => {
+                while i < chars.len() && chars[i] != '\n' {
+                    chars[i] = ' ';
+                    i += 1;
+                }
+            }
+            (false, '/', Some('*')) => {
+                let end = (i + 2..chars.len())
+                    .find(|j| chars[*j - 1] == '*' && chars[*j] == '/' && *j > i + 2)
+                    .map_or(chars.len(), |j| j + 1);
+                for c in chars[i..end].iter_mut().filter(|c| **c != '\n') {
+                    *c = ' ';
+                }
+                i = end;
+                continue;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+
+    // the comments are gone, so a trailing comma is only followed by whitespace
+    let mut in_string = false;
+    let mut i = 0;
+    while i < chars.len() {
+        match (in_string, chars[i]) {
+            (true, '\\') => i += 1,
+            (_, '"') => in_string = !in_string,
+            (false, ',') => {
+                let next = chars[i + 1..].iter().find(|c| !c.is_whitespace());
+                if matches!(next, Some('}') | Some(']')) {
+                    chars[i] = ' ';
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    chars.into_iter().collect()
+}
+
 /// Try to convert a bsconfig from a certain path to a bsconfig struct
-pub fn read(path: String) -> T {
+pub fn try_read(path: String) -> Result<T, String> {
     fs::read_to_string(path.clone())
         .map_err(|e| format!("Could not read bsconfig. {path} - {e}"))
         .and_then(|x| {
-            serde_json::from_str::<T>(&x).map_err(|e| format!("Could not parse bsconfig. {path} - {e}"))
+            serde_json::from_str::<T>(&strip_comments_and_trailing_commas(&x))
+                .map_err(|e| format!("Could not parse bsconfig. {path} - {e}"))
         })
-        .expect("Errors reading bsconfig")
+}
+
+pub fn read(path: String) -> T {
+    try_read(path).expect("Errors reading bsconfig")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_fixture::TestProject;
+
+    fn flags(config: &T) -> Vec<String> {
+        flatten_flags(&config.bsc_flags)
+    }
+
+    #[test]
+    fn should_flatten_flat_nested_and_mixed_bsc_flags() {
+        let config = |bsc_flags: &str| {
+            serde_json::from_str::<T>(&format!(
+                r#"{{ "name": "package", "sources": "src", "bsc-flags": {} }}"#,
+                bsc_flags
+            ))
+            .unwrap()
+        };
+        let expected = vec!["-w", "-30", "-bs-g"];
+
+        assert_eq!(flags(&config(r#"["-w", "-30", "-bs-g"]"#)), expected);
+        assert_eq!(flags(&config(r#"[["-w", "-30"], ["-bs-g"]]"#)), expected);
+        assert_eq!(flags(&config(r#"[["-w", "-30"], "-bs-g"]"#)), expected);
+        assert_eq!(flags(&config(r#"["-w -30", ["-bs-g"]]"#)), expected);
+    }
+
+    #[test]
+    fn should_split_a_bsc_flag_with_its_argument() {
+        let config = serde_json::from_str::<T>(
+            r#"{ "name": "package", "sources": "src", "bsc-flags": ["-ppx foo", " -open  Belt ", ""] }"#,
+        )
+        .unwrap();
+
+        assert_eq!(flags(&config), vec!["-ppx", "foo", "-open", "Belt"]);
+    }
+
+    #[test]
+    fn should_give_package_config_precedence_over_shared_config() {
+        let root = serde_json::from_str::<T>(
+            r#"{
+                "name": "root",
+                "sources": "src",
+                "shared-config": {
+                    "bsc-flags": ["-open Belt"],
+                    "ppx-flags": ["shared-ppx", ["other-ppx", "-shared"]],
+                    "warnings": { "number": "+a", "error": "+5" }
+                }
+            }"#,
+        )
+        .unwrap();
+        let package = serde_json::from_str::<T>(
+            r#"{
+                "name": "package",
+                "sources": "src",
+                "bsc-flags": ["-bs-no-version-header"],
+                "ppx-flags": [["other-ppx", "-package"]],
+                "warnings": { "number": "-44" }
+            }"#,
+        )
+        .unwrap();
+
+        let merged = merge_shared_config(&package, root.shared_config.as_ref().unwrap());
+
+        assert_eq!(flags(&merged), vec!["-open", "Belt", "-bs-no-version-header"]);
+        assert_eq!(
+            flatten_ppx_flags(&"nm".to_string(), &merged.ppx_flags, &"package".to_string()),
+            vec!["-ppx", "nm/shared-ppx", "-ppx", "nm/other-ppx -package"]
+        );
+        let warnings = merged.warnings.unwrap();
+        assert_eq!(warnings.number, Some("-44".to_string()));
+        assert!(matches!(warnings.error, Some(Error::Qualified(error)) if error == "+5"));
+    }
+
+    #[test]
+    fn should_run_the_ppxes_in_the_declared_order() {
+        let shared_config =
+            serde_json::from_str::<SharedConfig>(r#"{ "ppx-flags": ["ppx-a", "ppx-b"] }"#).unwrap();
+        let ppxs = |package_ppx_flags: &str| {
+            let package = serde_json::from_str::<T>(&format!(
+                r#"{{ "name": "package", "sources": "src", "ppx-flags": {} }}"#,
+                package_ppx_flags
+            ))
+            .unwrap();
+            let merged = merge_shared_config(&package, &shared_config);
+            flatten_ppx_flags(&"nm".to_string(), &merged.ppx_flags, &"package".to_string())
+        };
+
+        // ppx-b transforms what ppx-a generates, the package ppxes run after the shared ones
+        assert_eq!(
+            ppxs(r#"["ppx-d", "ppx-c"]"#),
+            vec!["-ppx", "nm/ppx-a", "-ppx", "nm/ppx-b", "-ppx", "nm/ppx-d", "-ppx", "nm/ppx-c"]
+        );
+        // giving ppx-a other arguments doesn't move it after ppx-b
+        assert_eq!(
+            ppxs(r#"["ppx-c", ["ppx-a", "-debug"]]"#),
+            vec!["-ppx", "nm/ppx-a -debug", "-ppx", "nm/ppx-b", "-ppx", "nm/ppx-c"]
+        );
+    }
+
+    #[test]
+    fn should_use_shared_config_when_package_has_none() {
+        let shared_config =
+            serde_json::from_str::<SharedConfig>(r#"{ "bsc-flags": ["-open Belt"] }"#).unwrap();
+        let package = serde_json::from_str::<T>(r#"{ "name": "package", "sources": "src" }"#).unwrap();
+
+        let merged = merge_shared_config(&package, &shared_config);
+
+        assert_eq!(flags(&merged), vec!["-open", "Belt"]);
+        assert!(merged.ppx_flags.is_none());
+        assert!(merged.warnings.is_none());
+    }
+
+    fn config_with_warnings(warnings: &str) -> T {
+        serde_json::from_str::<T>(&format!(
+            r#"{{ "name": "package", "sources": "src", "warnings": {} }}"#,
+            warnings
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn should_translate_permissive_warnings() {
+        let config = config_with_warnings(r#"{ "number": "-a", "error": false }"#);
+        assert_eq!(get_warning_args(&config, None, None), vec!["-w", "-a"]);
+    }
+
+    #[test]
+    fn should_translate_strict_warnings() {
+        let config = config_with_warnings(r#"{ "number": "+a-4-9", "error": "+5" }"#);
+        assert_eq!(
+            get_warning_args(&config, None, None),
+            vec!["-w", "+a-4-9", "-warn-error", "+5"]
+        );
+
+        let config = config_with_warnings(r#"{ "error": true }"#);
+        assert_eq!(get_warning_args(&config, None, None), vec!["-warn-error", "A"]);
+    }
+
+    #[test]
+    fn should_pass_the_warning_specs_from_the_command_line_after_the_config() {
+        let config = config_with_warnings(r#"{ "number": "+a", "error": true }"#);
+        assert_eq!(
+            get_warning_args(&config, Some("-27".to_string()), Some("-27+20".to_string())),
+            vec![
+                "-w",
+                "+a",
+                "-warn-error",
+                "A",
+                "-w",
+                "-27",
+                "-warn-error",
+                "-27+20"
+            ]
+        );
+
+        let config = config_with_warnings(r#"{ "number": "-a" }"#);
+        assert_eq!(
+            get_warning_args(&config, None, Some("+20".to_string())),
+            vec!["-w", "-a", "-warn-error", "+20"]
+        );
+
+        let config = serde_json::from_str::<T>(r#"{ "name": "package", "sources": "src" }"#).unwrap();
+        assert_eq!(
+            get_warning_args(&config, Some("+a".to_string()), None),
+            vec!["-w", "+a"]
+        );
+    }
+
+    #[test]
+    fn should_not_pass_warning_args_without_warnings() {
+        let config = serde_json::from_str::<T>(r#"{ "name": "package", "sources": "src" }"#).unwrap();
+        assert_eq!(get_warning_args(&config, None, None), Vec::<String>::new());
+    }
+
+    #[test]
+    fn should_default_to_in_source_es6() {
+        let config = serde_json::from_str::<T>(r#"{ "name": "package", "sources": "src" }"#).unwrap();
+
+        let specs = get_package_specs(&config);
+
+        assert_eq!(specs.len(), 1);
+        assert_eq!(specs[0].module, "es6");
+        assert!(specs[0].in_source);
+        assert_eq!(specs[0].get_suffix(&config), ".mjs");
+    }
+
+    #[test]
+    fn should_read_suffix_per_package_spec() {
+        let config = serde_json::from_str::<T>(
+            r#"{
+                "name": "package",
+                "sources": "src",
+                "suffix": ".bs.js",
+                "package-specs": [
+                    { "module": "es6", "in-source": true, "suffix": ".mjs" },
+                    { "module": "commonjs", "in-source": true }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let specs = get_package_specs(&config);
+
+        assert_eq!(
+            specs
+                .iter()
+                .map(|spec| spec.module.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["es6", "commonjs"]
+        );
+        assert_eq!(
+            specs
+                .iter()
+                .map(|spec| spec.get_suffix(&config))
+                .collect::<Vec<String>>(),
+            vec![".mjs", ".bs.js"]
+        );
+    }
+
+    #[test]
+    fn should_use_the_suffix_independent_of_the_module_format() {
+        for (suffix, expected_suffix) in [(Some(".bs.js"), ".bs.js"), (Some(".js"), ".js"), (None, ".mjs")] {
+            let suffix = suffix
+                .map(|suffix| format!(r#""suffix": "{}","#, suffix))
+                .unwrap_or_default();
+            let config = serde_json::from_str::<T>(&format!(
+                r#"{{
+                    "name": "package",
+                    "sources": "src",
+                    {}
+                    "package-specs": {{ "module": "es6", "in-source": true }}
+                }}"#,
+                suffix
+            ))
+            .unwrap();
+            let spec = &get_package_specs(&config)[0];
+
+            assert_eq!(
+                spec.get_package_output(&config, Path::new("src")),
+                format!("es6:src:{}", expected_suffix)
+            );
+            // the output paths are also the files that are removed when cleaning
+            assert_eq!(
+                get_output_paths(&config, "/package", "src/A.res"),
+                vec![format!("/package/src/A{}", expected_suffix)]
+            );
+        }
+    }
+
+    #[test]
+    fn should_resolve_relative_and_absolute_ppx_paths() {
+        let config = serde_json::from_str::<T>(
+            r#"{
+                "name": "package",
+                "sources": "src",
+                "ppx-flags": ["some-ppx/ppx", "./ppx.exe", "/usr/local/bin/ppx", ["/opt/ppx", "-flag"]]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            flatten_ppx_flags(&"nm".to_string(), &config.ppx_flags, &"package".to_string()),
+            vec![
+                "-ppx",
+                "nm/some-ppx/ppx",
+                "-ppx",
+                "nm/package/./ppx.exe",
+                "-ppx",
+                "/usr/local/bin/ppx",
+                "-ppx",
+                "/opt/ppx -flag"
+            ]
+        );
+    }
+
+    #[test]
+    fn should_place_output_in_source_or_in_lib() {
+        let config = serde_json::from_str::<T>(
+            r#"{
+                "name": "package",
+                "sources": "src",
+                "package-specs": [
+                    { "module": "es6", "in-source": true },
+                    { "module": "commonjs", "in-source": false, "suffix": ".js" },
+                    { "module": "es6-global", "in-source": false }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            get_output_paths(&config, "/pkg", "src/sub/Module.res"),
+            vec![
+                "/pkg/src/sub/Module.mjs",
+                "/pkg/lib/js/src/sub/Module.js",
+                "/pkg/lib/es6_global/src/sub/Module.mjs"
+            ]
+        );
+        assert_eq!(
+            get_output_paths(&config, "/pkg", "/pkg/src/Module.res"),
+            vec![
+                "/pkg/src/Module.mjs",
+                "/pkg/lib/js/src/Module.js",
+                "/pkg/lib/es6_global/src/Module.mjs"
+            ]
+        );
+    }
+
+    #[test]
+    fn should_prefer_jsx_over_the_deprecated_reason_config() {
+        let config = serde_json::from_str::<T>(
+            r#"{
+                "name": "package",
+                "sources": "src",
+                "reason": { "react-jsx": 3 },
+                "jsx": { "version": 4 }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(get_jsx_args(&config), vec!["-bs-jsx", "4"]);
+        assert_eq!(
+            get_jsx_warning(&config),
+            Some(
+                "\"reason\": { \"react-jsx\": 3 } in the config of package is deprecated and ignored, because \"jsx\" is set. Please remove it."
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn should_emit_es6_global_to_its_own_output() {
+        let config = serde_json::from_str::<T>(
+            r#"{
+                "name": "package",
+                "sources": "src",
+                "suffix": ".mjs",
+                "package-specs": [
+                    { "module": "es6", "in-source": false },
+                    { "module": "es6-global", "in-source": false }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let outputs = get_package_specs(&config)
+            .iter()
+            .map(|spec| spec.get_package_output(&config, Path::new("src")))
+            .collect::<Vec<String>>();
+
+        assert_eq!(
+            outputs,
+            vec!["es6:lib/es6/src:.mjs", "es6-global:lib/es6_global/src:.mjs"]
+        );
+    }
+
+    #[test]
+    fn should_take_the_ppx_working_directory_from_the_shared_config() {
+        let config = serde_json::from_str::<T>(r#"{ "name": "package", "sources": "src" }"#).unwrap();
+        let shared_config =
+            serde_json::from_str::<SharedConfig>(r#"{ "ppx-working-directory": "project" }"#).unwrap();
+
+        let merged = merge_shared_config(&config, &shared_config);
+        assert_eq!(merged.ppx_working_directory, Some(PpxWorkingDirectory::Project));
+
+        let config = serde_json::from_str::<T>(
+            r#"{ "name": "package", "sources": "src", "ppx-working-directory": "package" }"#,
+        )
+        .unwrap();
+        let merged = merge_shared_config(&config, &shared_config);
+        assert_eq!(merged.ppx_working_directory, Some(PpxWorkingDirectory::Package));
+    }
+
+    #[test]
+    fn should_read_a_config_with_comments_and_trailing_commas() {
+        let project = TestProject::new("lenient-config");
+        let path = project.write(
+            "bsconfig.json",
+            r#"{
+  // the name of the package
+  "name": "package", /* a comment, with a "string" */
+  "sources": "src",
+  /*
+   * a comment over multiple lines,
+   */
+  "bs-dependencies": ["@rescript/core", "https://example.com/a//b/*c*/",],
+  "bsc-flags": ["-open RescriptCore", /* trailing */],
+}
+"#,
+        );
+        let invalid_path = project.write(
+            "invalid.json",
+            "{\n  // the name\n  \"name\": \"package\" \"sources\": \"src\"\n}",
+        );
+
+        let config = try_read(path.to_string_lossy().to_string());
+        let invalid = try_read(invalid_path.to_string_lossy().to_string());
+
+        let config = config.unwrap();
+        assert_eq!(config.name, "package");
+        assert_eq!(
+            config.bs_dependencies,
+            Some(vec![
+                "@rescript/core".to_string(),
+                "https://example.com/a//b/*c*/".to_string()
+            ])
+        );
+        assert_eq!(flags(&config), vec!["-open", "RescriptCore"]);
+        // the comments are replaced with spaces, so the errors point at the right line
+        assert!(invalid.unwrap_err().contains("line 3"));
+    }
+
+    #[test]
+    fn should_tell_where_the_flags_of_a_package_come_from() {
+        let shared_config = serde_json::from_str::<SharedConfig>(
+            r#"{ "bsc-flags": ["-open Belt"], "warnings": { "number": "-27", "error": "+8" } }"#,
+        )
+        .unwrap();
+        let config = serde_json::from_str::<T>(
+            r#"{
+                "name": "package",
+                "sources": "src",
+                "bsc-flags": ["-bs-no-version-header"],
+                "warnings": { "number": "+a" }
+            }"#,
+        )
+        .unwrap();
+        let merged = merge_shared_config(&config, &shared_config);
+
+        assert_eq!(
+            get_bsc_flag_sources(&merged, Some(&shared_config), &["-bs-g".to_string()]),
+            vec![
+                (
+                    FlagSource::SharedConfig,
+                    vec!["-open".to_string(), "Belt".to_string()]
+                ),
+                (FlagSource::Package, vec!["-bs-no-version-header".to_string()]),
+                (FlagSource::CommandLine("--bsc-flag"), vec!["-bs-g".to_string()]),
+            ]
+        );
+        assert_eq!(
+            get_warning_arg_sources(&merged, Some(&shared_config), None, Some("-3".to_string())),
+            vec![
+                (FlagSource::Package, vec!["-w".to_string(), "+a".to_string()]),
+                (
+                    FlagSource::SharedConfig,
+                    vec!["-warn-error".to_string(), "+8".to_string()]
+                ),
+                (
+                    FlagSource::CommandLine("--warn-error-spec"),
+                    vec!["-warn-error".to_string(), "-3".to_string()]
+                ),
+            ]
+        );
+        // without a shared config, all flags are the package's
+        assert_eq!(
+            get_bsc_flag_sources(&config, None, &[]),
+            vec![
+                (FlagSource::SharedConfig, vec![]),
+                (FlagSource::Package, vec!["-bs-no-version-header".to_string()]),
+                (FlagSource::CommandLine("--bsc-flag"), vec![]),
+            ]
+        );
+    }
 }
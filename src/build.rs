@@ -1,32 +1,74 @@
+pub mod artifact_cache;
+pub mod ast;
 pub mod build_types;
+pub mod changed_files;
 pub mod clean;
 pub mod compile;
 pub mod deps;
+pub mod deps_tree;
+pub mod diagnostics;
+pub mod doctor;
+pub mod events;
+pub mod explain;
+pub mod flags_cache;
+pub mod format;
+pub mod interrupt;
+pub mod junit;
 pub mod logs;
+pub mod metrics;
 pub mod namespaces;
 pub mod packages;
 pub mod parse;
 pub mod read_compile_state;
+pub mod single_file;
+pub mod trace;
+pub mod verify;
 
+use crate::bsconfig;
 use crate::helpers;
 use crate::helpers::emojis::*;
+use crate::{report, reportln, status, statusln};
+use ahash::AHashSet;
 use build_types::*;
 use console::style;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{error, info};
 use std::io::{stdout, Write};
 use std::process::Command;
-use std::time::Instant;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-pub fn get_version(bsc_path: &str) -> String {
+/// Runs `bsc -v` and returns the version, ie. `11.0.0`.
+pub fn try_get_bsc_version(bsc_path: &str) -> Result<String, String> {
     let version_cmd = Command::new(bsc_path)
         .args(["-v"])
         .output()
-        .expect("failed to find version");
+        .map_err(|e| format!("Could not run {}: {}", bsc_path, e))?;
 
-    std::str::from_utf8(&version_cmd.stdout)
-        .expect("Could not read version from rescript")
+    // not `helpers::decode_output`, its note about invalid UTF-8 would end up in the version
+    Ok(String::from_utf8_lossy(&version_cmd.stdout)
         .replace("\n", "")
-        .replace("ReScript ", "")
+        .replace("ReScript ", ""))
+}
+
+/// Returns the version of the compiler, ie. `11.0.0`. This is read from the package.json of the
+/// rescript package, and only when that fails we run `bsc -v`, unless it's given with
+/// `--compiler-version`. The version is determined once per build, and passed down to where it's
+/// needed.
+pub fn try_get_version(bsc_path: &str, compile_options: &CompileOptions) -> Result<String, String> {
+    if let Some(version) = &compile_options.compiler_version {
+        return Ok(version.to_owned());
+    }
+    match helpers::get_rescript_version_from_package_json(bsc_path) {
+        Some(version) => Ok(version),
+        None => try_get_bsc_version(bsc_path),
+    }
+}
+
+pub fn get_version(bsc_path: &str, compile_options: &CompileOptions) -> String {
+    try_get_version(bsc_path, compile_options).expect("failed to find version")
 }
 
 fn is_dirty(module: &Module) -> bool {
@@ -44,30 +86,330 @@ fn is_dirty(module: &Module) -> bool {
     }
 }
 
-pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Result<BuildState, ()> {
-    let timing_total = Instant::now();
-    let project_root = helpers::get_abs_path(path);
-    let workspace_root = helpers::get_workspace_root(&project_root);
-    let bsc_path = helpers::get_bsc(&project_root, workspace_root.to_owned());
-    let root_config_name = packages::get_package_name(&project_root);
-    let rescript_version = get_version(&bsc_path);
-    let default_timing: Option<std::time::Duration> = if no_timing {
-        Some(std::time::Duration::new(0.0 as u64, 0.0 as u32))
+/// Exits after the build was interrupted (see `interrupt`), the outputs of the unfinished modules are
+/// removed by then. The caches of the build (deps, flags) aren't written, so the next build detects
+/// the dirty modules again.
+fn exit_interrupted() -> ! {
+    reportln!(
+        "{}\r{}Interrupted, the outputs of the unfinished modules were removed",
+        LINE_CLEAR,
+        CROSS
+    );
+    std::process::exit(interrupt::EXIT_CODE)
+}
+
+/// Prints the errors and warnings of bsc grouped by file, and any other output as is.
+fn print_compile_output(output: &str) {
+    let (diagnostics, other_output) = diagnostics::parse_diagnostics(output);
+    if !diagnostics.is_empty() {
+        report!(
+            "{}",
+            diagnostics::render_diagnostics(&diagnostics, console::colors_enabled())
+        );
+    }
+    if helpers::contains_ascii_characters(&other_output) {
+        report!("{}", other_output);
+    }
+}
+
+/// Prints the dependencies of modules that are not a module of the project, its dependencies or the
+/// standard library (`--check-deps`).
+fn print_unresolved_deps(
+    build_state: &BuildState,
+    raw_deps: &ahash::AHashMap<String, deps::RawDeps>,
+    bsc_path: &str,
+) {
+    let external_modules = match deps::get_external_modules(build_state, bsc_path) {
+        Some(external_modules) => external_modules,
+        None => {
+            info!("Could not find the standard library, not checking the dependencies");
+            return;
+        }
+    };
+    for (module_name, dep) in deps::get_unresolved_deps(build_state, raw_deps, &external_modules) {
+        reportln!(
+            "{}: {} depends on {}, which is not a module of the project or of its dependencies",
+            style("Warning").yellow(),
+            style(module_name).bold(),
+            style(dep).bold()
+        );
+    }
+}
+
+/// Reports the modules of the isolated packages (`--isolated`) that use modules of packages that
+/// aren't in their bs-dependencies.
+fn validate_declared_deps(build_state: &BuildState, package_names: &AHashSet<String>) -> bool {
+    let undeclared = deps::get_undeclared_deps(build_state, package_names);
+    if !undeclared.is_empty() {
+        reportln!(
+            "{}\r{} {}Found undeclared dependencies",
+            LINE_CLEAR,
+            style("[5/7]").bold().dim(),
+            CROSS
+        );
+    }
+    for (module_name, dep, dep_package) in &undeclared {
+        let package_name = &build_state.get_module(module_name).unwrap().package_name;
+        reportln!(
+            "{}: {} depends on {} of {}, which is not in the bs-dependencies of {}",
+            style("Error").red(),
+            style(module_name).bold(),
+            style(dep).bold(),
+            style(dep_package).bold(),
+            style(package_name).bold()
+        );
+    }
+    undeclared.is_empty()
+}
+
+/// Prints the modules that took longer to compile than `--time-budget`.
+fn print_time_budget_report(build_state: &BuildState, options: &BuildOptions) {
+    if let Some(report) = compile::get_time_budget_report(build_state, options.time_budget) {
+        report!("{}", report);
+    }
+}
+
+fn print_memory_report() {
+    if let Some(report) = helpers::get_memory_report(&helpers::get_peak_memory()) {
+        report!("{}", report);
+    }
+}
+
+/// Reports the outputs that bsc didn't write for the modules that compiled (`--verify-artifacts`),
+/// returns whether all of them are there.
+fn verify_artifacts(build_state: &BuildState) -> bool {
+    let missing = verify::get_missing_artifacts(build_state, build_state.compile_options.check_only);
+    for (module_name, path) in &missing {
+        reportln!(
+            "{}: {} compiled without writing {}",
+            style("Error").red(),
+            style(module_name).bold(),
+            path
+        );
+    }
+    missing.is_empty()
+}
+
+fn print_package_reports(build_state: &BuildState, options: &BuildOptions) {
+    if options.report_packages {
+        reportln!(
+            "{}",
+            compile::get_package_reports_table(&compile::get_package_reports(build_state))
+        );
+    }
+}
+
+/// Why a build didn't finish.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildError {
+    /// The build failed, and the errors are printed
+    Failed,
+    /// The build was cancelled with its token (see `build`)
+    Cancelled,
+}
+
+/// Stops the build after it was interrupted or cancelled (see `interrupt::is_cancelled`), the
+/// outputs of the unfinished modules are removed by then. On Ctrl-C we exit, a build that was
+/// cancelled by an embedder returns `BuildError::Cancelled` instead.
+fn stop_cancelled<T>() -> Result<T, BuildError> {
+    if interrupt::is_interrupted() {
+        exit_interrupted();
+    }
+    reportln!("{}\r{}The build was cancelled", LINE_CLEAR, CROSS);
+    Err(BuildError::Cancelled)
+}
+
+/// Prints the last line of a failed build, in red, with the number of errors of bsc in `output`.
+fn print_failure(output: &str) {
+    let (diagnostics, _) = diagnostics::parse_diagnostics(output);
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == diagnostics::Severity::Error)
+        .count();
+    let message = match errors {
+        // ie. a circular dependency
+        0 => "Failed to compile".to_string(),
+        1 => "Failed to compile: 1 error".to_string(),
+        errors => format!("Failed to compile: {} errors", errors),
+    };
+    reportln!("{}{}", CROSS, style(message).red().bold());
+}
+
+/// The last line of a build with `--fail-on-warning`, when bsc reported warnings in `output`.
+fn get_warnings_failure(output: &str) -> Option<String> {
+    let (diagnostics, _) = diagnostics::parse_diagnostics(output);
+    let warnings = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == diagnostics::Severity::Warning)
+        .count();
+    match warnings {
+        0 => None,
+        1 => Some("Failed on 1 warning (--fail-on-warning)".to_string()),
+        warnings => Some(format!("Failed on {} warnings (--fail-on-warning)", warnings)),
+    }
+}
+
+/// The durations that are printed instead of the measured ones, with `--no-timing`.
+fn get_default_timing(no_timing: bool) -> Option<Duration> {
+    if no_timing {
+        Some(Duration::new(0.0 as u64, 0.0 as u32))
     } else {
         None
-    };
+    }
+}
 
-    print!(
+/// The options of a build, ie. from the command line. The defaults build every package and source
+/// file, and don't skip the ignored files.
+#[derive(Clone, Default)]
+pub struct BuildOptions {
+    /// Only builds the source files that match (`--filter`)
+    pub filter: Option<regex::Regex>,
+    /// Prints all durations as 0s (`--no-timing`)
+    pub no_timing: bool,
+    /// Only builds the packages that match (`--package-filter`)
+    pub package_filter: Option<regex::Regex>,
+    /// Also builds the dependencies of the packages of `package_filter` (`--with-deps`)
+    pub with_deps: bool,
+    /// Builds the packages of `package_filter` with only their dependencies, and fails when they
+    /// use modules of packages that are not in their bs-dependencies (`--isolated`)
+    pub isolated: bool,
+    /// The packages to build, as listed in the file of `--packages-from` (ie. the packages that
+    /// are affected by a change, computed by the CI). Their dependencies are built as well.
+    pub packages_from: Option<Vec<String>>,
+    /// Skips the source files that are excluded by `.gitignore` / `.ignore` files
+    pub respect_ignore: bool,
+    /// Fails the build when a package has source files that aren't in its `sources`
+    /// (`--strict-sources`, see `packages::validate_sources`)
+    pub strict_sources: bool,
+    /// Parses and compiles all modules (`--force`)
+    pub force: bool,
+    /// Only the modules that changed since this git ref are dirty (`--changed-from`)
+    pub changed_from: Option<String>,
+    /// The options that change the arguments of bsc (`--check-only`, `--bsc-flag`)
+    pub compile_options: CompileOptions,
+    /// The options that change how the packages are read (ie. `--namespace`, `--no-dev`)
+    pub package_options: packages::PackageOptions,
+    /// Changes the dependencies of the modules (see `deps::compile_dependency_rules`)
+    pub dependency_rules: Vec<deps::DependencyRule>,
+    /// Sources that are generated in memory, ie. by codegen tooling (see `packages::VirtualSource`)
+    pub virtual_sources: Vec<packages::VirtualSource>,
+    /// Receives the events of the build (see `events`)
+    pub events: Option<Sender<events::BuildEvent>>,
+    /// Cancels the build when it's set (see `build`)
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Stops the compilation after this many modules failed to compile (`--max-errors`)
+    pub max_errors: Option<usize>,
+    /// Prints a table with how the modules of every package compiled after the build
+    /// (`--report-packages`)
+    pub report_packages: bool,
+    /// Reports the dependencies of modules that don't resolve to a module (`--check-deps`)
+    pub check_deps: bool,
+    /// Writes a Makefile `.d` file next to the `.cmj` of every module (`--emit-depfiles`, see
+    /// `deps::write_depfiles`)
+    pub emit_depfiles: bool,
+    /// Restores the outputs of modules from (and stores them in) this folder (`--cache-dir`, see
+    /// `artifact_cache`)
+    pub cache_dir: Option<String>,
+    /// Records the invocations of bsc (`--trace`, see `trace`)
+    pub trace: Option<Arc<trace::Trace>>,
+    /// Checks that the modules that compiled wrote all their outputs (`--verify-artifacts`), to
+    /// catch bsc exiting without errors but without writing an output (see `verify`)
+    pub verify_artifacts: bool,
+    /// Warns about the modules that take longer than this to compile (`--time-budget`)
+    pub time_budget: Option<Duration>,
+}
+
+fn get_compile_context<'a>(
+    bsc_path: &'a str,
+    rescript_version: &'a str,
+    options: &'a BuildOptions,
+) -> CompileContext<'a> {
+    CompileContext {
+        max_errors: options.max_errors,
+        events: options.events.as_ref(),
+        cancel: options.cancel.as_deref(),
+        cache_dir: options.cache_dir.as_deref(),
+        trace: options.trace.as_deref(),
+        ..CompileContext::new(bsc_path, rescript_version, &options.compile_options)
+    }
+}
+
+/// The state after the parse phase of the build (see `parse_build`), which the compile phase
+/// continues with.
+pub struct ParsedBuild {
+    pub build_state: BuildState,
+    pub bsc_path: String,
+    pub rescript_version: String,
+    deleted_module_names: AHashSet<String>,
+    packages_with_changed_flags: AHashSet<String>,
+    /// For the metrics of the build (see `metrics`)
+    phase_durations: Vec<(&'static str, Duration)>,
+}
+
+/// The first half of the build: reads the packages and source files, cleans up the previous build
+/// and generates the ASTs of the dirty modules. Doesn't compile anything. With `check_only`, it stops
+/// after marking the dirty modules, and doesn't write anything (see `check`).
+pub fn parse_build(
+    project_root: &str,
+    options: &BuildOptions,
+    check_only: bool,
+) -> Result<ParsedBuild, BuildError> {
+    let BuildOptions {
+        filter,
+        package_filter,
+        with_deps,
+        respect_ignore,
+        force,
+        changed_from,
+        ..
+    } = options;
+    let default_timing = get_default_timing(options.no_timing);
+    let workspace_root = helpers::get_workspace_root(project_root);
+    let bsc_path = helpers::get_bsc(
+        project_root,
+        workspace_root.to_owned(),
+        options.package_options.node_modules.as_deref(),
+    );
+    let root_config_name = packages::get_package_name(project_root);
+    let rescript_version = get_version(&bsc_path, &options.compile_options);
+    info!(
+        "Building {} with {} (version {})",
+        project_root, bsc_path, rescript_version
+    );
+    if let Some(warning) = helpers::get_unsupported_version_warning(&rescript_version) {
+        statusln!("{} {}", style("Warning:").yellow().bold(), warning);
+    }
+
+    info!("Building package tree");
+    status!(
         "{} {} Building package tree...",
         style("[1/7]").bold().dim(),
         TREE
     );
     let _ = stdout().flush();
     let timing_package_tree = Instant::now();
-    let packages = packages::make(&filter, &project_root, workspace_root.to_owned());
+    let packages = if check_only {
+        packages::read_package_tree(
+            filter,
+            project_root,
+            workspace_root.to_owned(),
+            *respect_ignore,
+            &options.virtual_sources,
+            &options.package_options,
+        )
+    } else {
+        packages::make(
+            filter,
+            project_root,
+            workspace_root.to_owned(),
+            *respect_ignore,
+            &options.virtual_sources,
+            &options.package_options,
+        )
+    };
     let timing_package_tree_elapsed = timing_package_tree.elapsed();
 
-    println!(
+    statusln!(
         "{}\r{} {}Built package tree in {:.2}s",
         LINE_CLEAR,
         style("[1/7]").bold().dim(),
@@ -78,22 +420,86 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
     );
 
     if !packages::validate_packages_dependencies(&packages) {
-        return Err(());
+        return Err(BuildError::Failed);
+    }
+
+    if options.strict_sources && !packages::validate_sources(&packages, *respect_ignore) {
+        return Err(BuildError::Failed);
+    }
+
+    if !packages::validate_module_names(&packages) {
+        return Err(BuildError::Failed);
+    }
+
+    // only the jsx config of the root package is used
+    if let Some(warning) = packages
+        .get(&root_config_name)
+        .and_then(|root_package| bsconfig::get_jsx_warning(&root_package.bsconfig))
+    {
+        statusln!("{} {}", style("Warning:").yellow().bold(), warning);
+    }
+
+    let filtered_package_names = match &options.packages_from {
+        Some(listed_package_names) => {
+            match packages::get_listed_package_names(&packages, listed_package_names) {
+                Ok(package_names) => Some(package_names),
+                Err(missing) => {
+                    reportln!(
+                        "{}{}{}",
+                        LINE_CLEAR,
+                        CROSS,
+                        style(format!(
+                            "These packages of --packages-from are not in the project: {}",
+                            missing.join(", ")
+                        ))
+                        .red()
+                    );
+                    return Err(BuildError::Failed);
+                }
+            }
+        }
+        None => package_filter
+            .as_ref()
+            .map(|package_filter| packages::filter_package_names(&packages, package_filter, *with_deps)),
+    };
+    if let Some(package_names) = &filtered_package_names {
+        reportln!(
+            "{}{} of {} packages match the {}",
+            LINE_CLEAR,
+            package_names.len(),
+            packages.len(),
+            if options.packages_from.is_some() {
+                "packages from the file (with their dependencies)"
+            } else {
+                "package filter"
+            }
+        );
+        if package_names.is_empty() {
+            return Err(BuildError::Failed);
+        }
     }
 
     let timing_source_files = Instant::now();
+    info!("Finding source files");
 
-    print!(
+    status!(
         "{} {} Finding source files...",
         style("[2/7]").bold().dim(),
         LOOKING_GLASS
     );
     let _ = stdout().flush();
-    let mut build_state = BuildState::new(project_root, root_config_name, packages);
-    packages::parse_packages(&mut build_state);
-    logs::initialize(&build_state.packages);
+    let mut build_state = BuildState::new(project_root.to_owned(), root_config_name, packages);
+    build_state.compile_options = options.compile_options.to_owned();
+    build_state.dependency_rules = options.dependency_rules.to_owned();
+    packages::parse_packages(&mut build_state, !check_only);
+    if let Some(package_names) = &filtered_package_names {
+        build_state.retain_package_modules(package_names);
+    }
+    if !check_only {
+        logs::initialize(&build_state.packages);
+    }
     let timing_source_files_elapsed = timing_source_files.elapsed();
-    println!(
+    statusln!(
         "{}\r{} {}Found source files in {:.2}s",
         LINE_CLEAR,
         style("[2/7]").bold().dim(),
@@ -103,17 +509,82 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
             .as_secs_f64()
     );
 
-    print!(
+    info!("Cleaning up previous build");
+    status!(
         "{} {} Cleaning up previous build...",
         style("[3/7]").bold().dim(),
         SWEEP
     );
+    let changed_compiler_version = flags_cache::get_changed_compiler_version(&build_state, &rescript_version);
+    if let Some(previous_version) = &changed_compiler_version {
+        statusln!(
+            "{}\r{} ReScript was changed from {} to {}, so all modules are rebuilt.",
+            LINE_CLEAR,
+            style("[3/7]").bold().dim(),
+            previous_version,
+            rescript_version
+        );
+    } else if *force {
+        statusln!(
+            "{}\r{} Forced to rebuild all modules.",
+            LINE_CLEAR,
+            style("[3/7]").bold().dim(),
+        );
+    }
     let timing_cleanup = Instant::now();
-    let compile_assets_state = read_compile_state::read(&mut build_state);
+    let mut compile_assets_state = read_compile_state::read(&mut build_state);
+    if let Some(package_names) = &filtered_package_names {
+        compile_assets_state.retain_packages(package_names);
+    }
     let (diff_cleanup, total_cleanup, deleted_module_names) =
-        clean::cleanup_previous_build(&mut build_state, compile_assets_state);
+        clean::cleanup_previous_build(&mut build_state, compile_assets_state, !check_only);
+    for (old_module_name, new_module_name) in clean::get_renamed_modules(&build_state) {
+        statusln!(
+            "{}\r{} Renamed {} to {}",
+            LINE_CLEAR,
+            style("[3/7]").bold().dim(),
+            old_module_name,
+            new_module_name
+        );
+    }
+    let packages_with_changed_flags = flags_cache::mark_packages_with_changed_flags_dirty(
+        &mut build_state,
+        &rescript_version,
+        match (&changed_compiler_version, *force) {
+            (Some(_), _) => Some(DirtyReason::CompilerChanged),
+            (None, true) => Some(DirtyReason::Forced),
+            (None, false) => None,
+        },
+    );
+    if let Some(git_ref) = changed_from {
+        match changed_files::get_changed_files(&build_state.project_root, git_ref) {
+            Ok(changed_files) => {
+                let changed_modules =
+                    changed_files::mark_changed_modules_dirty(&mut build_state, &changed_files);
+                statusln!(
+                    "{}\r{} {} modules changed since {}",
+                    LINE_CLEAR,
+                    style("[3/7]").bold().dim(),
+                    changed_modules,
+                    git_ref
+                );
+            }
+            Err(e) => {
+                error!("Could not get the changed files since {}: {}", git_ref, e);
+                reportln!(
+                    "{}\r{} {}Could not get the changed files since {}: {}",
+                    LINE_CLEAR,
+                    style("[3/7]").bold().dim(),
+                    CROSS,
+                    git_ref,
+                    e
+                );
+                return Err(BuildError::Failed);
+            }
+        }
+    }
     let timing_cleanup_elapsed = timing_cleanup.elapsed();
-    println!(
+    statusln!(
         "{}\r{} {}Cleaned {}/{} {:.2}s",
         LINE_CLEAR,
         style("[3/7]").bold().dim(),
@@ -123,9 +594,36 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
         default_timing.unwrap_or(timing_cleanup_elapsed).as_secs_f64()
     );
 
+    if explain::is_explain_dirty() {
+        reportln!("{}", explain::get_dirty_report(&build_state.modules));
+    }
+
+    if check_only {
+        if total_cleanup == 0 {
+            reportln!(
+                "{}{}{}",
+                LINE_CLEAR,
+                CROSS,
+                style("Nothing was built before, run a build first to check against").red()
+            );
+            return Err(BuildError::Failed);
+        }
+        return Ok(ParsedBuild {
+            build_state,
+            bsc_path,
+            rescript_version,
+            deleted_module_names,
+            packages_with_changed_flags,
+            phase_durations: vec![],
+        });
+    }
+
     let num_dirty_modules = build_state.modules.values().filter(|m| is_dirty(m)).count() as u64;
 
     let pb = ProgressBar::new(num_dirty_modules);
+    if helpers::is_summary_only() {
+        pb.set_draw_target(ProgressDrawTarget::hidden());
+    }
     pb.set_style(
         ProgressStyle::with_template(&format!(
             "{} {} Parsing... {{spinner}} {{pos}}/{{len}} {{msg}}",
@@ -136,18 +634,29 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
     );
 
     let timing_ast = Instant::now();
+    info!("Parsing {} source files", num_dirty_modules);
     let result_asts = parse::generate_asts(
-        &rescript_version,
         &mut build_state,
         || pb.inc(1),
-        &bsc_path,
         workspace_root.to_owned(),
+        &get_compile_context(&bsc_path, &rescript_version, options),
     );
     let timing_ast_elapsed = timing_ast.elapsed();
+    if interrupt::is_cancelled(options.cancel.as_deref()) {
+        pb.finish_and_clear();
+        let parsed_modules = build_state
+            .modules
+            .iter()
+            .filter(|(_, module)| is_dirty(module))
+            .map(|(module_name, _)| module_name.to_owned())
+            .collect::<AHashSet<String>>();
+        clean::remove_interrupted_outputs(&build_state, &parsed_modules);
+        return stop_cancelled();
+    }
 
     match result_asts {
         Ok(err) => {
-            println!(
+            statusln!(
                 "{}\r{} {}Parsed {} source files in {:.2}s",
                 LINE_CLEAR,
                 style("[4/7]").bold().dim(),
@@ -155,28 +664,98 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
                 num_dirty_modules,
                 default_timing.unwrap_or(timing_ast_elapsed).as_secs_f64()
             );
-            print!("{}", &err);
+            status!("{}", &err);
         }
         Err(err) => {
+            error!("Could not parse all source files");
             logs::finalize(&build_state.packages);
-            println!(
+            reportln!(
                 "{}\r{} {}Error parsing source files in {:.2}s",
                 LINE_CLEAR,
                 style("[4/7]").bold().dim(),
                 CROSS,
                 default_timing.unwrap_or(timing_ast_elapsed).as_secs_f64()
             );
-            print!("{}", &err);
+            report!("{}", &err);
+            print_failure(&err);
             clean::cleanup_after_build(&build_state);
-            return Err(());
+            return Err(BuildError::Failed);
         }
     }
 
+    Ok(ParsedBuild {
+        build_state,
+        bsc_path,
+        rescript_version,
+        deleted_module_names,
+        packages_with_changed_flags,
+        phase_durations: vec![
+            ("package_tree", timing_package_tree_elapsed),
+            ("source_files", timing_source_files_elapsed),
+            ("cleanup", timing_cleanup_elapsed),
+            ("parse", timing_ast_elapsed),
+        ],
+    })
+}
+
+/// Builds the project. Embedders (ie. a language server) can cancel a running build by setting
+/// `cancel`: the build then stops starting bsc processes, waits for the running ones, removes the
+/// outputs of the modules it was working on, and returns `BuildError::Cancelled` without writing the
+/// caches of the build, so the next build picks up where it stopped.
+pub fn build(path: &str, options: &BuildOptions) -> Result<BuildState, BuildError> {
+    let _building = interrupt::start_build();
+    let timing_total = Instant::now();
+    let default_timing = get_default_timing(options.no_timing);
+
+    let ParsedBuild {
+        mut build_state,
+        bsc_path,
+        rescript_version,
+        deleted_module_names,
+        packages_with_changed_flags,
+        mut phase_durations,
+    } = parse_build(path, options, false)?;
+
+    info!("Collecting deps");
     let timing_deps = Instant::now();
-    deps::get_deps(&mut build_state, &deleted_module_names);
+    let raw_deps = deps::get_deps(&mut build_state, &deleted_module_names);
+    deps::write_deps_files(&build_state, &raw_deps, &rescript_version);
+    if options.emit_depfiles {
+        deps::write_depfiles(&build_state);
+    }
+    if options.check_deps {
+        print_unresolved_deps(&build_state, &raw_deps, &bsc_path);
+    }
+    if let (true, Some(package_filter)) = (options.isolated, &options.package_filter) {
+        let package_names = packages::filter_package_names(&build_state.packages, package_filter, false);
+        if !validate_declared_deps(&build_state, &package_names) {
+            logs::finalize(&build_state.packages);
+            return Err(BuildError::Failed);
+        }
+    }
     let timing_deps_elapsed = timing_deps.elapsed();
 
-    println!(
+    let dev_deps = deps::get_dev_deps_of_non_dev_modules(&build_state);
+    if !dev_deps.is_empty() {
+        logs::finalize(&build_state.packages);
+        reportln!(
+            "{}\r{} {}Found dependencies on dev sources",
+            LINE_CLEAR,
+            style("[5/7]").bold().dim(),
+            CROSS
+        );
+        for (module_name, dep) in dev_deps {
+            reportln!(
+                "{}: {} depends on {}, which is in a dev source folder (\"type\": \"dev\")",
+                style("Error").red(),
+                style(module_name).bold(),
+                style(dep).bold()
+            );
+        }
+        return Err(BuildError::Failed);
+    }
+
+    statusln!(
         "{}\r{} {}Collected deps in {:.2}s",
         LINE_CLEAR,
         style("[5/7]").bold().dim(),
@@ -184,8 +763,12 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
         default_timing.unwrap_or(timing_deps_elapsed).as_secs_f64()
     );
 
+    info!("Compiling");
     let start_compiling = Instant::now();
     let pb = ProgressBar::new(build_state.modules.len().try_into().unwrap());
+    if helpers::is_summary_only() {
+        pb.set_draw_target(ProgressDrawTarget::hidden());
+    }
     pb.set_style(
         ProgressStyle::with_template(&format!(
             "{} {} Compiling... {{spinner}} {{pos}}/{{len}} {{msg}}",
@@ -197,21 +780,36 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
     let (compile_errors, compile_warnings, num_compiled_modules) = compile::compile(
         &mut build_state,
         &deleted_module_names,
-        &rescript_version,
         || pb.inc(1),
         |size| pb.set_length(size),
-        &bsc_path,
+        &get_compile_context(&bsc_path, &rescript_version, options),
     );
     let compile_duration = start_compiling.elapsed();
 
     logs::finalize(&build_state.packages);
+    if interrupt::is_cancelled(options.cancel.as_deref()) {
+        pb.finish_and_clear();
+        return stop_cancelled();
+    }
     pb.finish();
     clean::cleanup_after_build(&build_state);
+    flags_cache::write_flags(&build_state, &packages_with_changed_flags, &rescript_version);
+    flags_cache::write_compiler_version(&build_state, &rescript_version);
+    phase_durations.extend([("deps", timing_deps_elapsed), ("compile", compile_duration)]);
+    if let Err(e) = metrics::write_metrics(
+        &build_state,
+        &phase_durations,
+        timing_total.elapsed(),
+        compile_errors.is_empty(),
+    ) {
+        reportln!("{}", e);
+    }
+    if let Err(e) = junit::write_junit_report(&build_state) {
+        reportln!("{}", e);
+    }
     if compile_errors.len() > 0 {
-        if helpers::contains_ascii_characters(&compile_warnings) {
-            println!("{}", &compile_warnings);
-        }
-        println!(
+        error!("Could not compile all modules");
+        reportln!(
             "{}\r{} {}Compiled {} modules in {:.2}s",
             LINE_CLEAR,
             style("[6/7]").bold().dim(),
@@ -219,10 +817,18 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
             num_compiled_modules,
             default_timing.unwrap_or(compile_duration).as_secs_f64()
         );
-        print!("{}", &compile_errors);
-        return Err(());
+        print_compile_output(&(compile_warnings + &compile_errors));
+        statusln!(
+            "{}",
+            compile::get_compile_outcomes_report(&build_state, helpers::is_verbose())
+        );
+        print_time_budget_report(&build_state, options);
+        print_memory_report();
+        print_package_reports(&build_state, options);
+        print_failure(&compile_errors);
+        return Err(BuildError::Failed);
     } else {
-        println!(
+        statusln!(
             "{}\r{} {}Compiled {} modules in {:.2}s",
             LINE_CLEAR,
             style("[6/7]").bold().dim(),
@@ -230,19 +836,253 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
             num_compiled_modules,
             default_timing.unwrap_or(compile_duration).as_secs_f64()
         );
-        if helpers::contains_ascii_characters(&compile_warnings) {
-            print!("{}", &compile_warnings);
+        if !helpers::is_summary_only() {
+            print_compile_output(&compile_warnings);
+        }
+        statusln!(
+            "{}",
+            compile::get_compile_outcomes_report(&build_state, helpers::is_verbose())
+        );
+        print_time_budget_report(&build_state, options);
+        print_memory_report();
+        print_package_reports(&build_state, options);
+        if options.verify_artifacts && !verify_artifacts(&build_state) {
+            return Err(BuildError::Failed);
+        }
+        if let (true, Some(message)) = (
+            options.compile_options.fail_on_warning,
+            get_warnings_failure(&compile_warnings),
+        ) {
+            reportln!("{}{}", CROSS, style(message).red().bold());
+            return Err(BuildError::Failed);
         }
     }
 
     let timing_total_elapsed = timing_total.elapsed();
-    println!(
+    info!("Finished compilation of {} modules", num_compiled_modules);
+    statusln!(
         "{}\r{} {}Finished Compilation in {:.2}s",
         LINE_CLEAR,
         style("[7/7]").bold().dim(),
         CHECKMARK,
         default_timing.unwrap_or(timing_total_elapsed).as_secs_f64()
     );
+    // also the summary of `--summary-only`
+    reportln!(
+        "{}{}",
+        CHECKMARK,
+        style(format!(
+            "Compiled {} modules across {} packages in {:.2}s",
+            num_compiled_modules,
+            build_state.packages.len(),
+            default_timing.unwrap_or(timing_total_elapsed).as_secs_f64()
+        ))
+        .green()
+    );
 
     Ok(build_state)
 }
+
+/// Returns the absolute paths of the JavaScript files of the modules that were compiled (without
+/// errors) in this build, for all the package-specs of the root package.
+pub fn get_compiled_output_paths(build_state: &BuildState) -> Vec<String> {
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Could not find root package");
+    let mut output_paths = build_state
+        .modules
+        .values()
+        .filter_map(|module| match &module.source_type {
+            SourceType::SourceFile(source_file)
+                if !source_file.interface_only
+                    && matches!(
+                        source_file.implementation.compile_state,
+                        CompileState::Success | CompileState::Warning
+                    ) =>
+            {
+                let package = build_state.get_package(&module.package_name)?;
+                Some(bsconfig::get_output_paths(
+                    &root_package.bsconfig,
+                    &package.path,
+                    &source_file.implementation.path,
+                ))
+            }
+            _ => None,
+        })
+        .flatten()
+        .collect::<Vec<String>>();
+    output_paths.sort();
+    output_paths
+}
+
+/// Only generates the ASTs (the parse phase of the build), for tools that work with the ASTs, and
+/// prints where the ASTs of every module are.
+pub fn generate_asts_only(path: &str, options: &BuildOptions) -> Result<(), String> {
+    let _building = interrupt::start_build();
+    // the errors of the build are printed already
+    let ParsedBuild { build_state, .. } =
+        parse_build(path, options, false).map_err(|_| String::from("Could not generate the ASTs"))?;
+    logs::finalize(&build_state.packages);
+
+    let mut module_names = build_state.modules.keys().collect::<Vec<&String>>();
+    module_names.sort();
+    for module_name in module_names {
+        let module = build_state.get_module(module_name).unwrap();
+        let package = build_state
+            .get_package(&module.package_name)
+            .expect("Package not found");
+        if let SourceType::SourceFile(source_file) = &module.source_type {
+            if !source_file.interface_only {
+                println!(
+                    "{} {}",
+                    module_name,
+                    package.get_ast_path(&source_file.implementation.path)
+                );
+            }
+            if let Some(interface) = &source_file.interface {
+                println!("{} {}", module_name, package.get_iast_path(&interface.path));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the build is up-to-date (`rewatch check`), ie. for CI, that the generated output is
+/// committed. It detects the dirty modules the same way as the build does (modification times,
+/// changed flags and compiler, removed files), and lists the modules that a build would parse or
+/// compile again. It doesn't parse or compile anything, and doesn't write any files.
+///
+/// It compares against the outputs of the previous build in `lib/bs` (the ASTs, compiled
+/// interfaces and the flags of every package), so it requires a successful build before.
+///
+/// With `json`, it prints the stale modules to stdout as a JSON array of `{module, file_path,
+/// reason}` sorted by module (ie. for an external build orchestrator), and only fails when the
+/// check itself fails.
+pub fn check(path: &str, options: &BuildOptions, json: bool) -> Result<(), String> {
+    // the errors of the build are printed already
+    let ParsedBuild {
+        build_state,
+        deleted_module_names,
+        ..
+    } = parse_build(path, options, true).map_err(|_| String::from("Could not check the build"))?;
+
+    if json {
+        let stale_modules = explain::get_stale_module_list(&build_state, &deleted_module_names);
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&stale_modules).expect("Could not serialize the modules")
+        );
+        return Ok(());
+    }
+    let stale_modules = explain::get_stale_modules(&build_state.modules, &deleted_module_names);
+    if stale_modules.is_empty() {
+        statusln!(
+            "{}{}The build is up-to-date ({} modules)",
+            LINE_CLEAR,
+            CHECKMARK,
+            build_state.modules.len()
+        );
+        return Ok(());
+    }
+    Err(format!(
+        "{}{}{}\n{}",
+        LINE_CLEAR,
+        CROSS,
+        style(format!("{} modules are not up-to-date:", stale_modules.len())).red(),
+        stale_modules
+            .iter()
+            .map(|(module_name, reason)| format!("  {}: {}", module_name, reason))
+            .collect::<Vec<String>>()
+            .join("\n")
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, get_warnings_failure, packages, BuildOptions};
+    use crate::helpers;
+    use crate::test_fixture::TestProject;
+    use std::path::Path;
+
+    #[test]
+    fn should_fail_on_the_warnings_of_bsc() {
+        let warning = |file: &str| {
+            format!(
+                "\n  Warning number 26\n  /project/src/{}:1:24-29\n\n  1 │ let f = () => {{ let unused = 1; () }}\n\n  unused variable unused.\n",
+                file
+            )
+        };
+
+        assert_eq!(get_warnings_failure(""), None);
+        assert_eq!(
+            get_warnings_failure(&warning("A.res")),
+            Some("Failed on 1 warning (--fail-on-warning)".to_string())
+        );
+        assert_eq!(
+            get_warnings_failure(&(warning("A.res") + &warning("B.res"))),
+            Some("Failed on 2 warnings (--fail-on-warning)".to_string())
+        );
+    }
+
+    #[test]
+    fn should_not_write_anything_when_checking() {
+        fn read_files(dir: &Path, files: &mut Vec<(String, Vec<u8>)>) {
+            for entry in std::fs::read_dir(dir).unwrap().flatten() {
+                let path = entry.path();
+                match path.is_dir() {
+                    true => read_files(&path, files),
+                    false => files.push((path.to_string_lossy().to_string(), std::fs::read(&path).unwrap())),
+                }
+            }
+        }
+
+        let project = TestProject::new("check");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "namespace": true }"#,
+        );
+        // the version is read from the package.json, so bsc is never called
+        let rescript = project.join("node_modules/rescript");
+        for platform in ["darwin", "darwinarm64", "linux", "win32"] {
+            std::fs::create_dir_all(rescript.join(platform)).unwrap();
+            std::fs::write(rescript.join(platform).join("bsc.exe"), "").unwrap();
+        }
+        std::fs::write(rescript.join("package.json"), r#"{ "version": "11.0.0" }"#).unwrap();
+        project.write("src/A.res", "let x = 1");
+        project.write("src/B.res", "let y = A.x");
+        let project_root = project.path();
+
+        // a previous build with A and the removed module C, before B was added
+        let package = project.make().remove("root").unwrap();
+        for source_file in ["src/A.res", "src/C.res"] {
+            let ast = format!("\n{}\n", project.join(source_file).to_string_lossy());
+            std::fs::write(package.get_ast_path(source_file), ast).unwrap();
+            for extension in ["cmi", "cmj", "cmt"] {
+                let asset = helpers::get_compiler_asset(&package, &package.namespace, source_file, extension);
+                std::fs::write(asset, "").unwrap();
+            }
+        }
+        std::fs::write(package.get_mlmap_path(), "randjbuildsystem\nA\nC\n").unwrap();
+        let mut before = vec![];
+        read_files(&project.root, &mut before);
+        before.sort();
+
+        let options = BuildOptions {
+            no_timing: true,
+            virtual_sources: vec![packages::VirtualSource {
+                package_name: "root".to_string(),
+                module_name: "Generated".to_string(),
+                contents: "let z = 1".to_string(),
+            }],
+            ..BuildOptions::default()
+        };
+        // B and Generated are not built, so the build is not up-to-date
+        let result = check(&project_root, &options, false);
+        let mut after = vec![];
+        read_files(&project.root, &mut after);
+        after.sort();
+
+        assert!(result.is_err());
+        assert_eq!(after, before);
+    }
+}
@@ -0,0 +1,300 @@
+use super::build_types::*;
+use super::compile::{self, CompileResult};
+use super::flags_cache;
+use super::packages;
+use crate::bsconfig;
+use crate::helpers;
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// With `--cache-dir`, the outputs of a module (its compiler assets and JavaScript) are stored in a
+// folder per content key, so they can be shared between builds on different machines (ie. in CI).
+// Before compiling a module, we look up its key, and copy the outputs from the cache instead of
+// running bsc. The key is a hash of everything the outputs depend on:
+//
+// - the flags of the package and the version of the compiler (see `flags_cache`)
+// - the name and the path (within the package) of the module, and its source files
+// - the cmi files of its dependencies, so a module is compiled again when an interface it uses
+//   changed
+//
+// A folder of the cache is written to a temporary folder first, and renamed into place when it's
+// complete, so another build never restores half of the outputs.
+
+/// Increment this when the layout of the cache changes, so the old entries aren't used anymore.
+const CACHE_VERSION: u32 = 1;
+
+/// The outputs of a module, as the name of the file in the cache and the path of the output.
+fn get_outputs(
+    package: &packages::Package,
+    root_package: &packages::Package,
+    source_file: &SourceFile,
+) -> Vec<(String, PathBuf)> {
+    let path = &source_file.implementation.path;
+    let asset = |extension: &str| {
+        (
+            extension.to_string(),
+            PathBuf::from(helpers::get_compiler_asset(
+                package,
+                &package.namespace,
+                path,
+                extension,
+            )),
+        )
+    };
+    let mut outputs = vec![asset("cmi")];
+    if source_file.interface.is_some() {
+        outputs.push(asset("cmti"));
+    }
+    if !source_file.interface_only {
+        outputs.push(asset("cmj"));
+        outputs.push(asset("cmt"));
+        outputs.extend(
+            bsconfig::get_output_paths(&root_package.bsconfig, &package.path, path)
+                .into_iter()
+                .enumerate()
+                .map(|(index, output_path)| (format!("js.{}", index), PathBuf::from(output_path))),
+        );
+    }
+    outputs
+}
+
+fn hash_file(hasher: &mut blake3::Hasher, path: &str) -> Option<()> {
+    hasher.update(helpers::compute_file_hash(path)?.as_bytes());
+    Some(())
+}
+
+/// Returns the key of the outputs of a module in the cache, or `None` when the outputs can't be
+/// cached (ie. the cmi of a dependency is missing).
+pub fn get_key(build_state: &BuildState, module_name: &str, version: &str) -> Option<String> {
+    let module = build_state.get_module(module_name)?;
+    let source_file = match &module.source_type {
+        SourceType::SourceFile(source_file) => source_file,
+        SourceType::MlMap(_) => return None,
+    };
+    let package = build_state.get_package(&module.package_name)?;
+    let root_package = build_state.get_package(&build_state.root_config_name)?;
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(format!("{}\n", CACHE_VERSION).as_bytes());
+    hasher.update(
+        flags_cache::get_flags_hash(package, root_package, version, &build_state.compile_options).as_bytes(),
+    );
+    hasher.update(format!("\n{}\n{}\n", package.name, module_name).as_bytes());
+    for path in [
+        Some(&source_file.implementation.path),
+        source_file.interface.as_ref().map(|i| &i.path),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        hasher.update(format!("{}\n", path).as_bytes());
+        hash_file(
+            &mut hasher,
+            &Path::new(&package.path).join(path).to_string_lossy(),
+        )?;
+    }
+
+    let mut deps = module.deps.iter().collect::<Vec<&String>>();
+    deps.sort();
+    for dep in deps {
+        let dep_module = build_state.get_module(dep)?;
+        let dep_package = build_state.get_package(&dep_module.package_name)?;
+        let cmi_path = match &dep_module.source_type {
+            SourceType::SourceFile(dep_source_file) => helpers::get_compiler_asset(
+                dep_package,
+                &dep_package.namespace,
+                &dep_source_file.implementation.path,
+                "cmi",
+            ),
+            SourceType::MlMap(_) => dep_package.get_mlmap_compile_path(),
+        };
+        hasher.update(format!("{}\n", dep).as_bytes());
+        hash_file(&mut hasher, &cmi_path)?;
+    }
+
+    Some(hasher.finalize().to_hex().to_string())
+}
+
+fn get_warnings(cache_path: &Path, name: &str) -> CompileResult {
+    Ok(fs::read_to_string(cache_path.join(name)).ok())
+}
+
+/// Copies the outputs of a module from the cache in `cache_dir`, instead of compiling it. Returns the
+/// results of the compilation (with the warnings) when the outputs were in the cache.
+pub fn restore(
+    cache_dir: &str,
+    build_state: &BuildState,
+    module_name: &str,
+    key: &str,
+) -> Option<(Option<CompileResult>, CompileResult)> {
+    let cache_path = Path::new(cache_dir).join(key);
+    if !cache_path.is_dir() {
+        return None;
+    }
+    let module = build_state.get_module(module_name)?;
+    let source_file = match &module.source_type {
+        SourceType::SourceFile(source_file) => source_file,
+        SourceType::MlMap(_) => return None,
+    };
+    let package = build_state.get_package(&module.package_name)?;
+    let root_package = build_state.get_package(&build_state.root_config_name)?;
+
+    for (name, output_path) in get_outputs(package, root_package, source_file) {
+        let cached_path = cache_path.join(&name);
+        if !cached_path.exists() {
+            continue;
+        }
+        if let Some(parent) = output_path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if fs::copy(&cached_path, &output_path).is_err() {
+            return None;
+        }
+    }
+    if source_file.interface.is_some() {
+        compile::copy_compiler_assets(package, module, true);
+    }
+    compile::copy_compiler_assets(package, module, false);
+    debug!("Restored {} from the cache ({})", module_name, key);
+
+    Some((
+        source_file
+            .interface
+            .as_ref()
+            .map(|_| get_warnings(&cache_path, "interface-warnings")),
+        get_warnings(&cache_path, "warnings"),
+    ))
+}
+
+/// Stores the outputs of a module that compiled in the cache in `cache_dir`.
+pub fn store(
+    cache_dir: &str,
+    build_state: &BuildState,
+    module_name: &str,
+    key: &str,
+    interface_result: &Option<CompileResult>,
+    result: &CompileResult,
+) {
+    let module = match build_state.get_module(module_name) {
+        Some(module) => module,
+        None => return,
+    };
+    let (interface_warnings, warnings) = match (interface_result, result) {
+        (None, Ok(warnings)) => (None, warnings),
+        (Some(Ok(interface_warnings)), Ok(warnings)) => (interface_warnings.as_ref(), warnings),
+        _ => return,
+    };
+    let source_file = match &module.source_type {
+        SourceType::SourceFile(source_file) => source_file,
+        SourceType::MlMap(_) => return,
+    };
+    let cache_path = Path::new(cache_dir).join(key);
+    if cache_path.exists() {
+        return;
+    }
+    let package = build_state
+        .get_package(&module.package_name)
+        .expect("Package not found");
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Could not find root package");
+
+    let temp_path = Path::new(cache_dir).join(format!("{}.{}.tmp", key, std::process::id()));
+    let result = fs::create_dir_all(&temp_path).and_then(|_| {
+        for (name, output_path) in get_outputs(package, root_package, source_file) {
+            if output_path.exists() {
+                fs::copy(&output_path, temp_path.join(name))?;
+            }
+        }
+        for (name, warnings) in [
+            ("interface-warnings", interface_warnings),
+            ("warnings", warnings.as_ref()),
+        ] {
+            if let Some(warnings) = warnings {
+                fs::write(temp_path.join(name), warnings)?;
+            }
+        }
+        fs::rename(&temp_path, &cache_path)
+    });
+    if let Err(e) = result {
+        // ie. another build stored the module at the same time
+        debug!("Could not store {} in the cache: {}", module_name, e);
+        let _ = fs::remove_dir_all(&temp_path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_key, restore, store};
+    use crate::build::build_types::*;
+    use crate::build::packages;
+    use crate::test_fixture::TestProject;
+
+    #[test]
+    fn should_restore_the_outputs_of_a_module_by_its_key() {
+        let project = TestProject::new("artifact-cache");
+        project.write("project/bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("project/src/A.res", "let a = B.b");
+        project.write("project/src/B.res", "let b = 1");
+        let project_root = project.join("project").to_string_lossy().to_string();
+        let packages = packages::make(
+            &None,
+            &project_root,
+            None,
+            true,
+            &[],
+            &packages::PackageOptions::default(),
+        );
+        let mut build_state = BuildState::new(project_root.to_owned(), "root".to_string(), packages);
+        packages::parse_packages(&mut build_state, true);
+        build_state
+            .modules
+            .get_mut("A")
+            .unwrap()
+            .deps
+            .insert("B".to_string());
+        // the outputs of compiling A, and the interface of its dependency B
+        let build_path = project.join("project/lib/ocaml");
+        for (file, contents) in [
+            ("A.cmi", "cmi"),
+            ("A.cmj", "cmj"),
+            ("A.cmt", "cmt"),
+            ("B.cmi", "B"),
+        ] {
+            std::fs::write(build_path.join(file), contents).unwrap();
+        }
+        project.write("project/src/A.mjs", "js");
+
+        let cache_dir = project.join("cache").to_string_lossy().to_string();
+        let key = get_key(&build_state, "A", "11.0.0").unwrap();
+        let missed = restore(&cache_dir, &build_state, "A", &key);
+        store(
+            &cache_dir,
+            &build_state,
+            "A",
+            &key,
+            &None,
+            &Ok(Some("warning".to_string())),
+        );
+        std::fs::remove_file(build_path.join("A.cmi")).unwrap();
+        std::fs::remove_file(project.join("project/src/A.mjs")).unwrap();
+        let restored = restore(&cache_dir, &build_state, "A", &key);
+        let restored_cmi = std::fs::read_to_string(build_path.join("A.cmi")).unwrap();
+        let restored_js = std::fs::read_to_string(project.join("project/src/A.mjs")).unwrap();
+        // the interface of B changed, so A needs to be compiled again
+        std::fs::write(build_path.join("B.cmi"), "B changed").unwrap();
+        let key_after_dep_changed = get_key(&build_state, "A", "11.0.0").unwrap();
+        project.write("project/src/A.res", "let a = B.b + 1");
+        let key_after_source_changed = get_key(&build_state, "A", "11.0.0").unwrap();
+        let key_with_other_compiler = get_key(&build_state, "A", "11.1.0").unwrap();
+
+        assert!(missed.is_none());
+        assert_eq!(restored, Some((None, Ok(Some("warning".to_string())))));
+        assert_eq!(restored_cmi, "cmi");
+        assert_eq!(restored_js, "js");
+        assert_ne!(key_after_dep_changed, key);
+        assert_ne!(key_after_source_changed, key_after_dep_changed);
+        assert_ne!(key_with_other_compiler, key_after_source_changed);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::helpers;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+// ie. `Belt` or `MyNamespace.MyModule`
+fn is_module_path(line: &str) -> bool {
+    line.split('.')
+        .all(|name| !name.is_empty() && helpers::is_non_exotic_module_name(name))
+}
+
+/// Returns none when the lines end before the path of the source file, ie. for an empty file.
+fn parse_dep_modules(lines: impl Iterator<Item = String>) -> Option<Vec<String>> {
+    let mut modules = vec![];
+    for line in lines
+        .map(|line| line.trim().to_string())
+        .skip_while(|line| !helpers::is_source_path(line) && !is_module_path(line))
+    {
+        if helpers::is_source_path(&line) {
+            return Some(modules);
+        }
+        if !line.is_empty() {
+            modules.push(line);
+        }
+    }
+    None
+}
+
+/// Reads the modules that a `.ast` or `.iast` file (as generated by `bsc -bs-ast`) depends on, in
+/// the order they appear in the file. These are the modules as referenced in the source, so
+/// namespaced modules are `MyNamespace.MyModule`, and they are not resolved to packages.
+///
+/// The file has the following format:
+///
+/// ```text
+/// <binary header: the size of the dependency list>
+/// Belt
+/// MyNamespace.MyModule
+/// /absolute/path/to/src/File.res
+/// <binary AST>
+/// ```
+///
+/// The header isn't valid UTF-8, and can contain newline bytes, so we don't assume it's exactly
+/// one line, but skip everything until the first module name. The dependencies end at the path of
+/// the source file (absolute, or relative with `--relative-paths`). A module without dependencies
+/// has an empty line instead. A file without the path of the source file (ie. an empty file, when bsc
+/// failed to write it) is an error.
+///
+/// Reading the dependencies from the output of bsc instead (without the file) wouldn't save any
+/// IO: the `.ast` is the input of the compile step, so bsc has to write it to disk anyway, and we
+/// only read its header, which is still in the page cache right after parsing. Unchanged modules
+/// don't read the file at all, their dependencies come from the deps file (see `deps::get_raw_deps`).
+pub fn get_dep_modules(ast_file: &str) -> io::Result<Vec<String>> {
+    let file = File::open(ast_file)?;
+    // we stop reading at the path of the source file, so we don't read the (large) AST itself
+    let mut read_error = None;
+    let modules = parse_dep_modules(
+        // the binary header isn't necessarily valid UTF-8, so we read the lines as bytes
+        BufReader::new(file).split(b'\n').map_while(|line| match line {
+            Ok(line) => Some(String::from_utf8_lossy(&line).to_string()),
+            Err(e) => {
+                read_error = Some(e);
+                None
+            }
+        }),
+    );
+    match (read_error, modules) {
+        (Some(e), _) => Err(e),
+        (None, Some(modules)) => Ok(modules),
+        (None, None) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the AST ends before the path of the source file",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_dep_modules, parse_dep_modules};
+    use crate::test_fixture::TestProject;
+
+    fn parse(lines: &[&str]) -> Vec<String> {
+        parse_dep_modules(lines.iter().map(|line| line.to_string())).unwrap()
+    }
+
+    #[test]
+    fn should_parse_deps_after_a_one_line_header() {
+        assert_eq!(
+            parse(&[
+                "\0\0\0\u{c}",
+                "Belt",
+                "MyNamespace.MyModule",
+                "/src/File.res",
+                "Binary"
+            ]),
+            vec!["Belt", "MyNamespace.MyModule"]
+        );
+    }
+
+    #[test]
+    fn should_parse_deps_without_a_header() {
+        assert_eq!(
+            parse(&["Belt", "MyModule", "/src/File.res", "Binary"]),
+            vec!["Belt", "MyModule"]
+        );
+    }
+
+    #[test]
+    fn should_parse_deps_after_a_two_line_header() {
+        assert_eq!(
+            parse(&["\0\0", "\u{b}", "Belt", "/src/File.res", "Binary"]),
+            vec!["Belt"]
+        );
+    }
+
+    #[test]
+    fn should_parse_deps_before_a_relative_source_path() {
+        assert_eq!(
+            parse(&["\0\0\0\u{c}", "Belt.Array", "../../src/File.res", "Binary"]),
+            vec!["Belt.Array"]
+        );
+        assert_eq!(
+            parse(&["\0\0\0\u{1}", "", "File.resi", "Binary"]),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn should_not_read_the_ast_when_there_are_no_deps() {
+        assert_eq!(
+            parse(&["\0\0\0\u{1}", "", "/src/File.res", "Binary"]),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn should_return_an_error_for_a_missing_ast() {
+        assert!(get_dep_modules("/this/ast/does/not/exist.ast").is_err());
+    }
+
+    #[test]
+    fn should_return_an_error_for_an_empty_or_truncated_ast() {
+        let project = TestProject::new("empty-ast");
+        let path = project.write("A.ast", "");
+        let empty = get_dep_modules(&path.to_string_lossy());
+        project.write("A.ast", "\0\0\0\u{c}\nBelt\n");
+        let truncated = get_dep_modules(&path.to_string_lossy());
+
+        assert_eq!(empty.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(truncated.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(parse_dep_modules(["Belt".to_string()].into_iter()), None);
+    }
+}
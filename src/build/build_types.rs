@@ -1,6 +1,12 @@
+use crate::build::deps::DependencyRule;
+use crate::build::events::BuildEvent;
 use crate::build::packages::{Namespace, Package};
+use crate::build::trace::Trace;
 use ahash::{AHashMap, AHashSet};
-use std::time::SystemTime;
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, SystemTime};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ParseState {
@@ -17,6 +23,31 @@ pub enum CompileState {
     Warning,
     Success,
 }
+/// Why the AST of a source file is generated again (printed with `--verbose`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum DirtyReason {
+    AstMissing,
+    SourceModified,
+    InterfaceRemoved,
+    FlagsChanged,
+    CompilerChanged,
+    Forced,
+}
+
+impl fmt::Display for DirtyReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            DirtyReason::AstMissing => "ast missing",
+            DirtyReason::SourceModified => "source modified",
+            DirtyReason::InterfaceRemoved => "interface removed",
+            DirtyReason::FlagsChanged => "flags changed",
+            DirtyReason::CompilerChanged => "compiler changed",
+            DirtyReason::Forced => "forced",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Interface {
     pub path: String,
@@ -24,6 +55,7 @@ pub struct Interface {
     pub compile_state: CompileState,
     pub last_modified: SystemTime,
     pub dirty: bool,
+    pub dirty_reason: Option<DirtyReason>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -33,12 +65,22 @@ pub struct Implementation {
     pub compile_state: CompileState,
     pub last_modified: SystemTime,
     pub dirty: bool,
+    pub dirty_reason: Option<DirtyReason>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct SourceFile {
     pub implementation: Implementation,
     pub interface: Option<Interface>,
+    /// A module with only an interface (ie. a `.resi` without a `.res`) only gets a `.cmi`. The
+    /// implementation then points to where the `.res` would be, but it isn't parsed or compiled.
+    pub interface_only: bool,
+    /// The module is in a source folder with `"type": "dev"` (ie. tests), the other modules can't
+    /// depend on it.
+    pub is_dev: bool,
+    /// The contents of a source that was generated in memory (see `packages::VirtualSource`),
+    /// bsc reads it from a copy in the build folder of the package.
+    pub virtual_contents: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -52,6 +94,33 @@ pub enum SourceType {
     MlMap(MlMap),
 }
 
+/// Whether a source file is an implementation or an interface, by its extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceFileKind {
+    Implementation,
+    Interface,
+}
+
+impl SourceFileKind {
+    /// The kind of a source file with this extension (without the dot), `None` when the file isn't a
+    /// source file, so it can be skipped.
+    pub fn from_extension(extension: &str) -> Option<SourceFileKind> {
+        match extension {
+            "res" | "ml" | "re" => Some(SourceFileKind::Implementation),
+            "resi" | "mli" | "rei" => Some(SourceFileKind::Interface),
+            _ => None,
+        }
+    }
+
+    /// The extension of the AST that bsc writes for a source file of this kind.
+    pub fn get_ast_extension(&self) -> &'static str {
+        match self {
+            SourceFileKind::Implementation => "ast",
+            SourceFileKind::Interface => "iast",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Module {
     pub source_type: SourceType,
@@ -61,6 +130,31 @@ pub struct Module {
     pub compile_dirty: bool,
     pub last_compiled_cmi: Option<SystemTime>,
     pub last_compiled_cmt: Option<SystemTime>,
+    /// How long compiling the module took in this build (see `--time-budget`)
+    pub compile_duration: Option<Duration>,
+    /// How the module was compiled in this build, `None` when it was up to date
+    pub compile_outcome: Option<CompileOutcome>,
+    /// The errors and warnings of bsc for the module in this build (see `--junit`)
+    pub diagnostics: Vec<String>,
+}
+
+/// How the modules of a package compiled in this build (see `compile::get_package_reports`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackageReport {
+    /// The modules that were compiled (or restored from the cache) in this build
+    pub compiled: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// The time it took to compile the modules of the package, added up
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompileOutcome {
+    /// Compiled by bsc
+    Compiled,
+    /// The outputs were restored from the cache of `--cache-dir`
+    Restored,
 }
 
 impl Module {
@@ -78,6 +172,91 @@ impl Module {
     }
 }
 
+/// The options from the command line that change the arguments of bsc, and with that the compiler
+/// assets (see `flags_cache`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompileOptions {
+    /// Only type checks the modules (`--check-only`): they are compiled to `.cmi`, `.cmj` and
+    /// `.cmt`, but without the outputs of the package specs, so no JavaScript is written
+    pub check_only: bool,
+    /// Extra flags for bsc (`--bsc-flag`), after the `bsc-flags` of every package (see
+    /// `bsconfig::split_extra_bsc_flags`)
+    pub bsc_flags: Vec<String>,
+    /// Compiles with the release profile (`--release`), instead of the debug profile (`--debug`,
+    /// the default, see `compile::get_profile_args`)
+    pub release: bool,
+    /// Compiles against the standard library in this folder (`--stdlib-path` or
+    /// `RESCRIPT_STDLIB_PATH`), instead of the one of the installed compiler
+    pub stdlib_path: Option<String>,
+    /// The warning spec from the command line (`--warn-spec`, ie. `-27+20`), for all packages after
+    /// the `warnings` of the package (see `bsconfig::get_warning_args`)
+    pub warn_spec: Option<String>,
+    /// The spec of the warnings that are errors from the command line (`--warn-error-spec`)
+    pub warn_error_spec: Option<String>,
+    /// Fails the build when there are warnings (`--fail-on-warning`), after compiling all modules.
+    /// bsc then doesn't turn any warnings into errors (the `-warn-error` arguments are left out), so
+    /// it still writes the JavaScript of the modules with warnings.
+    pub fail_on_warning: bool,
+    /// Uses this version of the compiler (`--compiler-version`) instead of the version of the
+    /// installed compiler (see `build::try_get_version`)
+    pub compiler_version: Option<String>,
+    /// Parses the source files without `-absname`, and passes them to bsc relative to the folder it
+    /// runs in (`--relative-paths`). The include paths of the compile step are relative to the build
+    /// folder as well. The ASTs, the compiled output and the commands then don't contain the path of
+    /// the checkout, so they can be cached across machines. The downside is that the locations in
+    /// the errors and in the `.cmt` files are relative to that folder (see `ppx-working-directory`),
+    /// which editor integrations can't always resolve.
+    pub relative_paths: bool,
+}
+
+/// What the steps of the build that run bsc need besides the module itself: the compiler, the
+/// options of the build, and where the build reports to.
+#[derive(Debug, Clone, Copy)]
+pub struct CompileContext<'a> {
+    /// The path of bsc, or the bsc of the package (see `Package::get_bsc_path`)
+    pub bsc_path: &'a str,
+    /// The version of bsc, or the version of the package (see `Package::get_version`)
+    pub version: &'a str,
+    pub compile_options: &'a CompileOptions,
+    /// Stops the compilation after this many modules failed to compile (`--max-errors`)
+    pub max_errors: Option<usize>,
+    /// Receives the events of the build (see `events`)
+    pub events: Option<&'a Sender<BuildEvent>>,
+    /// Stops the build when it's set (see `interrupt::is_cancelled`)
+    pub cancel: Option<&'a AtomicBool>,
+    /// Restores the outputs of modules from (and stores them in) this folder (`--cache-dir`, see
+    /// `artifact_cache`)
+    pub cache_dir: Option<&'a str>,
+    /// Records the invocations of bsc (`--trace`, see `trace`)
+    pub trace: Option<&'a Trace>,
+}
+
+impl<'a> CompileContext<'a> {
+    /// A context without a limit of errors, events, cache or trace, that can't be cancelled
+    pub fn new(bsc_path: &'a str, version: &'a str, compile_options: &'a CompileOptions) -> Self {
+        Self {
+            bsc_path,
+            version,
+            compile_options,
+            max_errors: None,
+            events: None,
+            cancel: None,
+            cache_dir: None,
+            trace: None,
+        }
+    }
+
+    /// The context with the compiler of the package, which can be a different one than the
+    /// compiler of the build
+    pub fn for_package(&self, bsc_path: &'a str, version: &'a str) -> Self {
+        Self {
+            bsc_path,
+            version,
+            ..*self
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BuildState {
     pub modules: AHashMap<String, Module>,
@@ -85,6 +264,9 @@ pub struct BuildState {
     pub module_names: AHashSet<String>,
     pub project_root: String,
     pub root_config_name: String,
+    pub compile_options: CompileOptions,
+    /// The `dependency-rules` of rewatch.json (see `deps::compile_dependency_rules`)
+    pub dependency_rules: Vec<DependencyRule>,
 }
 
 impl BuildState {
@@ -102,12 +284,24 @@ impl BuildState {
             packages: packages,
             project_root: project_root,
             root_config_name: root_config_name,
+            compile_options: CompileOptions::default(),
+            dependency_rules: vec![],
         }
     }
     pub fn insert_module(&mut self, module_name: &str, module: Module) {
         self.modules.insert(module_name.to_owned(), module);
         self.module_names.insert(module_name.to_owned());
     }
+
+    /// Only keeps the modules of the given packages. The other packages remain known (we still need
+    /// their build paths), but their modules won't be parsed or compiled.
+    pub fn retain_package_modules(&mut self, package_names: &AHashSet<String>) {
+        self.modules
+            .retain(|_, module| package_names.contains(&module.package_name));
+        let modules = &self.modules;
+        self.module_names
+            .retain(|module_name| modules.contains_key(module_name));
+    }
 }
 
 pub struct AstModule {
@@ -117,7 +311,6 @@ pub struct AstModule {
     pub last_modified: SystemTime,
     pub ast_file_path: String,
     pub is_root: bool,
-    pub suffix: Option<String>,
 }
 
 pub struct CompileAssetsState {
@@ -127,3 +320,45 @@ pub struct CompileAssetsState {
     pub ast_rescript_file_locations: AHashSet<String>,
     pub rescript_file_locations: AHashSet<String>,
 }
+
+impl CompileAssetsState {
+    /// Forget about the compile assets of packages that are not part of this build, so we don't clean
+    /// them up as if their source files were removed.
+    pub fn retain_packages(&mut self, package_names: &AHashSet<String>) {
+        self.ast_modules
+            .retain(|_, ast_module| package_names.contains(&ast_module.package_name));
+        let ast_modules = &self.ast_modules;
+        self.ast_rescript_file_locations
+            .retain(|location| ast_modules.contains_key(location));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SourceFileKind;
+
+    #[test]
+    fn should_get_the_kind_of_a_source_file_from_its_extension() {
+        for extension in ["res", "ml", "re"] {
+            assert_eq!(
+                SourceFileKind::from_extension(extension),
+                Some(SourceFileKind::Implementation)
+            );
+        }
+        for extension in ["resi", "mli", "rei"] {
+            assert_eq!(
+                SourceFileKind::from_extension(extension),
+                Some(SourceFileKind::Interface)
+            );
+        }
+        assert_eq!(SourceFileKind::Implementation.get_ast_extension(), "ast");
+        assert_eq!(SourceFileKind::Interface.get_ast_extension(), "iast");
+    }
+
+    #[test]
+    fn should_not_get_a_kind_for_an_unknown_extension() {
+        for extension in ["js", "mjs", "resx", "RES", ""] {
+            assert_eq!(SourceFileKind::from_extension(extension), None);
+        }
+    }
+}
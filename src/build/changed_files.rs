@@ -0,0 +1,132 @@
+use super::build_types::*;
+use super::packages;
+use ahash::{AHashMap, AHashSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// With `--changed-from <git-ref>` we trust git instead of the modification times to find the
+// changed source files. This is meant for CI: after a checkout all source files are newer than the
+// (cached) build artifacts, so without it everything would be parsed and compiled again.
+
+fn run_git(project_root: &str, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .current_dir(project_root)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Could not run git: {}", e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// Returns the absolute paths of the files that changed since `git_ref` (including the
+/// uncommitted changes).
+pub fn get_changed_files(project_root: &str, git_ref: &str) -> Result<AHashSet<PathBuf>, String> {
+    let toplevel = run_git(project_root, &["rev-parse", "--show-toplevel"])?;
+    let toplevel = Path::new(toplevel.trim());
+    let changed_files = run_git(project_root, &["diff", "--name-only", git_ref, "--"])?;
+    Ok(changed_files
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|file| toplevel.join(file))
+        .collect())
+}
+
+fn is_config_changed(package: &packages::Package, changed_files: &AHashSet<PathBuf>) -> bool {
+    ["bsconfig.json", "rescript.json"]
+        .iter()
+        .any(|config| changed_files.contains(&Path::new(&package.path).join(config)))
+}
+
+/// Marks the modules with changed source files as dirty, and all modules of packages with a changed
+/// config. The other modules that only look modified because of their modification time are marked
+/// clean. Modules without an AST or with changed flags stay dirty. The dependents of the dirty
+/// modules are compiled as usual. Returns the number of changed modules.
+pub fn mark_changed_modules_dirty(build_state: &mut BuildState, changed_files: &AHashSet<PathBuf>) -> usize {
+    let package_paths = build_state
+        .packages
+        .iter()
+        .map(|(package_name, package)| {
+            (
+                package_name.to_owned(),
+                (
+                    PathBuf::from(&package.path),
+                    is_config_changed(package, changed_files),
+                ),
+            )
+        })
+        .collect::<AHashMap<String, (PathBuf, bool)>>();
+    let is_changed = |package_name: &str, file: &str| {
+        let (package_path, config_changed) = &package_paths[package_name];
+        *config_changed || changed_files.contains(&package_path.join(file))
+    };
+
+    let mut changed_modules = 0;
+    for module in build_state.modules.values_mut() {
+        let package_name = module.package_name.to_owned();
+        if let SourceType::SourceFile(source_file) = &mut module.source_type {
+            let implementation = &mut source_file.implementation;
+            let implementation_changed = is_changed(&package_name, &implementation.path);
+            mark_changed(
+                &mut implementation.dirty,
+                &mut implementation.dirty_reason,
+                implementation_changed,
+            );
+
+            let interface_changed = match source_file.interface.as_mut() {
+                Some(interface) => {
+                    let changed = is_changed(&package_name, &interface.path);
+                    mark_changed(&mut interface.dirty, &mut interface.dirty_reason, changed);
+                    changed
+                }
+                None => false,
+            };
+
+            if implementation_changed || interface_changed {
+                module.compile_dirty = true;
+                changed_modules += 1;
+            }
+        }
+    }
+    changed_modules
+}
+
+fn mark_changed(dirty: &mut bool, dirty_reason: &mut Option<DirtyReason>, changed: bool) {
+    if changed {
+        *dirty = true;
+        *dirty_reason = Some(DirtyReason::SourceModified);
+    } else if *dirty_reason == Some(DirtyReason::SourceModified) {
+        *dirty = false;
+        *dirty_reason = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::mark_changed;
+    use crate::build::build_types::DirtyReason;
+
+    #[test]
+    fn should_only_trust_git_for_modified_sources() {
+        let mut dirty = true;
+        let mut dirty_reason = Some(DirtyReason::SourceModified);
+        mark_changed(&mut dirty, &mut dirty_reason, false);
+        assert_eq!((dirty, dirty_reason), (false, None));
+
+        let mut dirty = true;
+        let mut dirty_reason = Some(DirtyReason::AstMissing);
+        mark_changed(&mut dirty, &mut dirty_reason, false);
+        assert_eq!((dirty, dirty_reason), (true, Some(DirtyReason::AstMissing)));
+
+        let mut dirty = false;
+        let mut dirty_reason = None;
+        mark_changed(&mut dirty, &mut dirty_reason, true);
+        assert_eq!((dirty, dirty_reason), (true, Some(DirtyReason::SourceModified)));
+    }
+}
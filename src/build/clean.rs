@@ -1,9 +1,12 @@
 use super::build_types::*;
+use super::deps;
+use super::deps_tree;
+use super::explain;
 use super::packages;
 use crate::bsconfig;
 use crate::helpers;
 use crate::helpers::emojis::*;
-use ahash::AHashSet;
+use ahash::{AHashMap, AHashSet};
 use console::style;
 use rayon::prelude::*;
 use std::io::Write;
@@ -27,12 +30,12 @@ fn remove_iast(package: &packages::Package, source_file: &str) {
     ));
 }
 
-fn remove_mjs_file(source_file: &str, suffix: &String) {
-    let _ = std::fs::remove_file(helpers::change_extension(
-        source_file,
-        // suffix.to_string includes the ., so we need to remove it
-        &suffix.to_string()[1..],
-    ));
+fn remove_mjs_files(root_package: &packages::Package, package: &packages::Package, source_file: &str) {
+    for output_path in bsconfig::get_output_paths(&root_package.bsconfig, &package.path, source_file) {
+        if !package.is_external_js(&output_path) {
+            let _ = std::fs::remove_file(output_path);
+        }
+    }
 }
 
 fn remove_compile_asset(package: &packages::Package, source_file: &str, extension: &str) {
@@ -53,12 +56,17 @@ fn remove_compile_asset(package: &packages::Package, source_file: &str, extensio
 pub fn remove_compile_assets(package: &packages::Package, source_file: &str) {
     // optimization
     // only issue cmti if htere is an interfacce file
-    for extension in &["cmj", "cmi", "cmt", "cmti"] {
+    for extension in &["cmj", "cmi", "cmt", "cmti", "d"] {
         remove_compile_asset(package, source_file, extension);
     }
 }
 
 pub fn clean_mjs_files(build_state: &BuildState) {
+    let root_package = build_state
+        .packages
+        .get(&build_state.root_config_name)
+        .expect("Could not find root package");
+
     // get all rescript file locations
     let rescript_file_locations = build_state
         .modules
@@ -66,37 +74,28 @@ pub fn clean_mjs_files(build_state: &BuildState) {
         .filter_map(|module| match &module.source_type {
             SourceType::SourceFile(source_file) => {
                 let package = build_state.packages.get(&module.package_name).unwrap();
-                let root_package = build_state
-                    .packages
-                    .get(&build_state.root_config_name)
-                    .expect("Could not find root package");
-                Some((
-                    std::path::PathBuf::from(package.path.to_string())
-                        .join(source_file.implementation.path.to_string())
-                        .to_string_lossy()
-                        .to_string(),
-                    root_package
-                        .bsconfig
-                        .suffix
-                        .to_owned()
-                        .unwrap_or(String::from(bsconfig::DEFAULT_SUFFIX)),
-                ))
+                Some((package, source_file.implementation.path.to_string()))
             }
             _ => None,
         })
-        .collect::<Vec<(String, String)>>();
+        .collect::<Vec<(&packages::Package, String)>>();
 
     rescript_file_locations
         .par_iter()
-        .for_each(|(rescript_file_location, suffix)| remove_mjs_file(&rescript_file_location, &suffix));
+        .for_each(|(package, rescript_file_location)| {
+            remove_mjs_files(root_package, package, rescript_file_location)
+        });
 }
 
 // TODO: change to scan_previous_build => CompileAssetsState
 // and then do cleanup on that state (for instance remove all .mjs files that are not in the state)
 
+/// With `remove_outputs` false (`rewatch check`), the outputs of the removed source files are left
+/// alone, and only the dirty modules are marked.
 pub fn cleanup_previous_build(
     build_state: &mut BuildState,
     compile_assets_state: CompileAssetsState,
+    remove_outputs: bool,
 ) -> (usize, usize, AHashSet<String>) {
     // delete the .mjs file which appear in our previous compile assets
     // but does not exists anymore
@@ -110,6 +109,10 @@ pub fn cleanup_previous_build(
         .collect::<Vec<&String>>();
 
     let diff_len = diff.len();
+    let root_package = build_state
+        .packages
+        .get(&build_state.root_config_name)
+        .expect("Could not find root package");
 
     let deleted_interfaces = diff
         .par_iter()
@@ -118,7 +121,6 @@ pub fn cleanup_previous_build(
                 module_name,
                 package_name,
                 ast_file_path,
-                suffix,
                 ..
             } = compile_assets_state
                 .ast_modules
@@ -129,15 +131,12 @@ pub fn cleanup_previous_build(
                 .packages
                 .get(package_name)
                 .expect("Could not find package");
-            remove_compile_assets(package, res_file_location);
-            remove_mjs_file(
-                &res_file_location,
-                &suffix
-                    .to_owned()
-                    .unwrap_or(String::from(bsconfig::DEFAULT_SUFFIX)),
-            );
-            remove_iast(package, res_file_location);
-            remove_ast(package, res_file_location);
+            if remove_outputs {
+                remove_compile_assets(package, res_file_location);
+                remove_mjs_files(root_package, package, res_file_location);
+                remove_iast(package, res_file_location);
+                remove_ast(package, res_file_location);
+            }
             match helpers::get_extension(ast_file_path).as_str() {
                 "iast" => Some(module_name.to_owned()),
                 "ast" => None,
@@ -191,14 +190,26 @@ pub fn cleanup_previous_build(
                         let source_last_modified = interface.last_modified;
                         if ast_last_modified > &source_last_modified {
                             interface.dirty = false;
+                            interface.dirty_reason = None;
+                        } else {
+                            interface.dirty_reason = Some(DirtyReason::SourceModified);
+                        }
+                        // a module without implementation has no AST for it, so it's only dirty
+                        // when the interface is
+                        if source_file.interface_only {
+                            source_file.implementation.dirty = interface.dirty;
+                            source_file.implementation.dirty_reason = None;
                         }
                     } else {
                         let implementation = &mut source_file.implementation;
                         let source_last_modified = implementation.last_modified;
-                        if ast_last_modified > &source_last_modified
-                            && !deleted_interfaces.contains(module_name)
-                        {
+                        if deleted_interfaces.contains(module_name) {
+                            implementation.dirty_reason = Some(DirtyReason::InterfaceRemoved);
+                        } else if ast_last_modified > &source_last_modified {
                             implementation.dirty = false;
+                            implementation.dirty_reason = None;
+                        } else {
+                            implementation.dirty_reason = Some(DirtyReason::SourceModified);
                         }
                     }
                 }
@@ -265,6 +276,45 @@ pub fn cleanup_previous_build(
     )
 }
 
+/// Finds the modules that were renamed since the previous build (after `cleanup_previous_build`): a
+/// module that was removed, and a new module (that has no AST yet) with the same contents, by the
+/// hashes of the sources in the deps files of the previous build. Returns the old and the new module
+/// names, sorted. The outputs of the old module are removed and the modules that import it are
+/// compiled again like for any removed module, this only lets the build report the rename.
+pub fn get_renamed_modules(build_state: &BuildState) -> Vec<(String, String)> {
+    let mut removed_modules: AHashMap<String, Vec<String>> = AHashMap::new();
+    for (module_name, source_hash) in deps::read_source_hashes(build_state) {
+        if !build_state.modules.contains_key(&module_name) {
+            removed_modules.entry(source_hash).or_default().push(module_name);
+        }
+    }
+    if removed_modules.is_empty() {
+        return vec![];
+    }
+
+    let mut renamed_modules = build_state
+        .modules
+        .par_iter()
+        .filter_map(|(module_name, module)| match &module.source_type {
+            SourceType::SourceFile(source_file)
+                if source_file.implementation.dirty_reason == Some(DirtyReason::AstMissing) =>
+            {
+                let package = build_state.get_package(&module.package_name)?;
+                let path = std::path::Path::new(&package.path).join(&source_file.implementation.path);
+                let source_hash = helpers::compute_file_hash(&path.to_string_lossy())?.to_string();
+                // when several removed modules had the same contents, it's unclear which one was renamed
+                match removed_modules.get(&source_hash).map(|names| names.as_slice()) {
+                    Some([old_module_name]) => Some((old_module_name.to_owned(), module_name.to_owned())),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect::<Vec<(String, String)>>();
+    renamed_modules.sort();
+    renamed_modules
+}
+
 fn failed_to_parse(module: &Module) -> bool {
     match &module.source_type {
         SourceType::SourceFile(SourceFile {
@@ -338,11 +388,32 @@ pub fn cleanup_after_build(build_state: &BuildState) {
     });
 }
 
-pub fn clean(path: &str) {
-    let project_root = helpers::get_abs_path(path);
-    let workspace_root = helpers::get_workspace_root(&project_root);
-    let packages = packages::make(&None, &project_root, workspace_root);
-    let root_config_name = packages::get_package_name(&project_root);
+/// Removes the ASTs, compiler assets and JavaScript files of the modules the build was working on
+/// when it was interrupted, because they can be half written. These modules are then built again
+/// by the next build.
+pub fn remove_interrupted_outputs(build_state: &BuildState, module_names: &AHashSet<String>) {
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Could not find root package");
+    module_names
+        .par_iter()
+        .filter_map(|module_name| build_state.get_module(module_name))
+        .for_each(|module| {
+            if let SourceType::SourceFile(source_file) = &module.source_type {
+                let package = build_state.get_package(&module.package_name).unwrap();
+                let path = &source_file.implementation.path;
+                remove_ast(package, path);
+                remove_iast(package, path);
+                remove_compile_assets(package, path);
+                remove_mjs_files(root_package, package, path);
+            }
+        });
+}
+
+pub fn clean(project_root: &str, package_options: &packages::PackageOptions) {
+    let workspace_root = helpers::get_workspace_root(project_root);
+    let packages = packages::make(&None, project_root, workspace_root, true, &[], package_options);
+    let root_config_name = packages::get_package_name(project_root);
 
     let timing_clean_compiler_assets = Instant::now();
     print!(
@@ -384,7 +455,7 @@ pub fn clean(path: &str) {
     print!("{} {} Cleaning mjs files...", style("[2/2]").bold().dim(), SWEEP);
     std::io::stdout().flush().unwrap();
     let mut build_state = BuildState::new(project_root.to_owned(), root_config_name, packages);
-    packages::parse_packages(&mut build_state);
+    packages::parse_packages(&mut build_state, true);
     clean_mjs_files(&build_state);
     let timing_clean_mjs_elapsed = timing_clean_mjs.elapsed();
     println!(
@@ -396,3 +467,304 @@ pub fn clean(path: &str) {
     );
     std::io::stdout().flush().unwrap();
 }
+
+/// Removes the outputs of a module (`clean --only`), and with `with_dependents` of all the modules
+/// that (transitively) depend on it, so only they are built again in the next build. Their ASTs are
+/// removed too, so they are also parsed again. The dependents are read from the deps files of the
+/// previous build.
+pub fn clean_modules(
+    project_root: &str,
+    module_name: &str,
+    with_dependents: bool,
+    package_options: &packages::PackageOptions,
+) -> Result<(), String> {
+    let workspace_root = helpers::get_workspace_root(project_root);
+    let build_state = explain::read_build_state(project_root, workspace_root, package_options);
+
+    let module_name = match explain::resolve_module(&build_state.modules, module_name) {
+        Some((module_name, _)) => module_name,
+        None => {
+            return Err(format!(
+                "{}: Could not find module {}",
+                style("Error").red(),
+                module_name
+            ))
+        }
+    };
+
+    let mut module_names = vec![module_name.to_owned()];
+    if with_dependents {
+        let graph = deps::read_deps_files(&build_state);
+        if !graph.contains_key(module_name) {
+            return Err(format!(
+                "{}: The dependents of {} are unknown, please build the project first",
+                style("Error").red(),
+                module_name
+            ));
+        }
+        module_names.extend(deps_tree::get_transitive_dependents(&graph, module_name));
+    }
+    module_names.sort();
+
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Could not find root package");
+    for module_name in &module_names {
+        let module = match build_state.get_module(module_name) {
+            Some(module) => module,
+            None => continue,
+        };
+        let package = build_state
+            .get_package(&module.package_name)
+            .expect("Could not find package");
+        if let SourceType::SourceFile(source_file) = &module.source_type {
+            let path = &source_file.implementation.path;
+            remove_compile_assets(package, path);
+            remove_mjs_files(root_package, package, path);
+            remove_ast(package, path);
+            remove_iast(package, path);
+        }
+    }
+
+    println!(
+        "{}Cleaned {} modules: {}",
+        CHECKMARK,
+        module_names.len(),
+        module_names.join(", ")
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clean, clean_modules, cleanup_previous_build, get_renamed_modules};
+    use crate::build::build_types::*;
+    use crate::build::{compile, deps, packages, read_compile_state};
+    use crate::test_fixture::TestProject;
+    use crate::{bsconfig, helpers};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    fn write_at(path: &Path, contents: &str, modified: SystemTime) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    #[test]
+    fn should_remove_the_outputs_of_a_deleted_module_and_compile_its_dependents_again() {
+        let project = TestProject::new("deleted-module");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        let source_time = SystemTime::now() - Duration::from_secs(60);
+        let build_time = source_time + Duration::from_secs(10);
+        write_at(&project.join("src/A.res"), "", source_time);
+        write_at(&project.join("src/B.res"), "A.log()", source_time);
+
+        // the outputs of a previous build, where B depends on A
+        let package = project.make().remove("root").unwrap();
+        let build_path = Path::new(&package.get_build_path()).to_path_buf();
+        for (module_name, deps) in [("A", ""), ("B", "A\n")] {
+            let source_file = project.join(format!("src/{}.res", module_name));
+            let ast = format!("\n{}{}\n", deps, source_file.to_string_lossy());
+            write_at(&build_path.join(format!("{}.ast", module_name)), &ast, build_time);
+            for extension in ["cmi", "cmj", "cmt"] {
+                let asset = build_path.join(format!("{}.{}", module_name, extension));
+                write_at(&asset, "", build_time + Duration::from_secs(1));
+            }
+        }
+        let a_outputs = bsconfig::get_output_paths(&package.bsconfig, &package.path, "src/A.res");
+        for output_path in &a_outputs {
+            write_at(Path::new(output_path), "", build_time);
+        }
+        std::fs::remove_file(project.join("src/A.res")).unwrap();
+
+        let mut build_state = project.build_state("root");
+        let compile_assets_state = read_compile_state::read(&mut build_state);
+        let (_, _, deleted_module_names) =
+            cleanup_previous_build(&mut build_state, compile_assets_state, true);
+        let b_was_compile_dirty = build_state.get_module("B").unwrap().compile_dirty;
+        build_state
+            .modules
+            .get_mut("B")
+            .unwrap()
+            .deps
+            .insert("A".to_string());
+        compile::mark_modules_with_deleted_deps_dirty(&mut build_state, &deleted_module_names);
+        let a_assets = ["ast", "cmi", "cmj", "cmt"]
+            .iter()
+            .map(|extension| {
+                helpers::get_compiler_asset(
+                    &package,
+                    &packages::Namespace::NoNamespace,
+                    "src/A.res",
+                    extension,
+                )
+            })
+            .chain(a_outputs)
+            .filter(|path| Path::new(path).exists())
+            .collect::<Vec<String>>();
+        let b_ast_exists = build_path.join("B.ast").exists();
+
+        assert_eq!(Vec::from_iter(deleted_module_names), vec!["A".to_string()]);
+        assert_eq!(a_assets, Vec::<String>::new());
+        assert!(b_ast_exists);
+        assert!(!b_was_compile_dirty);
+        assert!(build_state.get_module("B").unwrap().compile_dirty);
+    }
+
+    #[test]
+    fn should_detect_a_renamed_module_and_compile_the_modules_that_imported_it_again() {
+        let project = TestProject::new("renamed-module");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        let source_time = SystemTime::now() - Duration::from_secs(60);
+        let build_time = source_time + Duration::from_secs(10);
+        write_at(&project.join("src/A.res"), "let log = () => ()", source_time);
+        write_at(&project.join("src/B.res"), "A.log()", source_time);
+
+        // the outputs and deps files of a previous build, where B depends on A
+        let project_root = project.path();
+        let packages = project.make();
+        let package = packages.get("root").unwrap().to_owned();
+        let mut previous_build_state = BuildState::new(project_root.to_owned(), "root".to_string(), packages);
+        packages::parse_packages(&mut previous_build_state, true);
+        let build_path = Path::new(&package.get_build_path()).to_path_buf();
+        for (module_name, deps) in [("A", ""), ("B", "A\n")] {
+            let source_file = project.join(format!("src/{}.res", module_name));
+            let ast = format!("\n{}{}\n", deps, source_file.to_string_lossy());
+            write_at(&build_path.join(format!("{}.ast", module_name)), &ast, build_time);
+            for extension in ["cmi", "cmj", "cmt"] {
+                let asset = build_path.join(format!("{}.{}", module_name, extension));
+                write_at(&asset, "", build_time + Duration::from_secs(1));
+            }
+        }
+        deps::write_deps_files(&previous_build_state, &ahash::AHashMap::new(), "11.0.0");
+        std::fs::rename(project.join("src/A.res"), project.join("src/C.res")).unwrap();
+
+        let mut build_state = project.build_state("root");
+        let compile_assets_state = read_compile_state::read(&mut build_state);
+        let (_, _, deleted_module_names) =
+            cleanup_previous_build(&mut build_state, compile_assets_state, true);
+        let renamed_modules = get_renamed_modules(&build_state);
+        build_state
+            .modules
+            .get_mut("B")
+            .unwrap()
+            .deps
+            .insert("A".to_string());
+        compile::mark_modules_with_deleted_deps_dirty(&mut build_state, &deleted_module_names);
+        let a_cmj_exists = build_path.join("A.cmj").exists();
+
+        assert_eq!(renamed_modules, vec![("A".to_string(), "C".to_string())]);
+        assert!(!a_cmj_exists);
+        assert!(build_state.get_module("B").unwrap().compile_dirty);
+        match &build_state.get_module("C").unwrap().source_type {
+            SourceType::SourceFile(source_file) => assert!(source_file.implementation.dirty),
+            SourceType::MlMap(_) => panic!("C is not a source file"),
+        }
+    }
+
+    #[test]
+    fn should_only_clean_a_module_and_its_dependents() {
+        let project = TestProject::new("clean-only");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        // B depends on A, C depends on B, and D is on its own
+        for module_name in ["A", "B", "C", "D"] {
+            project.write(format!("src/{}.res", module_name), "");
+        }
+        let project_root = project.path();
+        let packages = project.make();
+        let package = packages.get("root").unwrap().to_owned();
+        let mut build_state = BuildState::new(project_root.to_owned(), "root".to_string(), packages);
+        packages::parse_packages(&mut build_state, true);
+        for (module_name, dep) in [("B", "A"), ("C", "B")] {
+            build_state
+                .modules
+                .get_mut(module_name)
+                .unwrap()
+                .deps
+                .insert(dep.to_string());
+        }
+        deps::write_deps_files(&build_state, &ahash::AHashMap::new(), "11.0.0");
+        let outputs = |module_name: &str| {
+            let path = format!("src/{}.res", module_name);
+            ["ast", "cmi", "cmj", "cmt"]
+                .iter()
+                .map(|extension| {
+                    helpers::get_compiler_asset(&package, &packages::Namespace::NoNamespace, &path, extension)
+                })
+                .chain(bsconfig::get_output_paths(
+                    &package.bsconfig,
+                    &package.path,
+                    &path,
+                ))
+                .collect::<Vec<String>>()
+        };
+        for module_name in ["A", "B", "C", "D"] {
+            for output_path in outputs(module_name) {
+                std::fs::create_dir_all(Path::new(&output_path).parent().unwrap()).unwrap();
+                std::fs::write(output_path, "").unwrap();
+            }
+        }
+
+        let only_b = clean_modules(&project_root, "B", false, &packages::PackageOptions::default());
+        let remaining_after_only_b = ["A", "B", "C", "D"]
+            .map(|module_name| outputs(module_name).iter().all(|path| Path::new(path).exists()));
+        let a_with_dependents = clean_modules(&project_root, "A", true, &packages::PackageOptions::default());
+        let remaining_after_a = ["A", "B", "C", "D"]
+            .map(|module_name| outputs(module_name).iter().any(|path| Path::new(path).exists()));
+
+        assert!(only_b.is_ok());
+        assert_eq!(remaining_after_only_b, [true, false, true, true]);
+        assert!(a_with_dependents.is_ok());
+        assert_eq!(remaining_after_a, [false, false, false, true]);
+    }
+
+    #[test]
+    fn should_keep_the_external_js_files_when_cleaning() {
+        let project = TestProject::new("external-js");
+        project.write(
+            "bsconfig.json",
+            r#"{
+                "name": "root",
+                "sources": "src",
+                "package-specs": { "module": "es6", "in-source": true },
+                "suffix": ".mjs",
+                "external-js": ["src/Helper.mjs"]
+            }"#,
+        );
+        project.write("src/A.res", "");
+        project.write("src/A.mjs", "");
+        project.write("src/Helper.mjs", "export let log = () => {}");
+
+        // a previous build had a Helper.res, which is replaced by the hand-written Helper.mjs
+        let project_root = project.path();
+        let packages = project.make();
+        let package = packages.get("root").unwrap().to_owned();
+        let build_path = Path::new(&package.get_build_path()).to_path_buf();
+        let helper_ast = format!("\n{}\n", project.join("src/Helper.res").to_string_lossy());
+        std::fs::create_dir_all(&build_path).unwrap();
+        std::fs::write(build_path.join("Helper.ast"), helper_ast).unwrap();
+
+        let mut build_state = BuildState::new(project_root.to_owned(), "root".to_string(), packages);
+        packages::parse_packages(&mut build_state, true);
+        let compile_assets_state = read_compile_state::read(&mut build_state);
+        let (_, _, deleted_module_names) =
+            cleanup_previous_build(&mut build_state, compile_assets_state, true);
+        let helper_exists_after_cleanup = project.join("src/Helper.mjs").exists();
+        let helper_ast_exists_after_cleanup = build_path.join("Helper.ast").exists();
+        clean(&project_root, &packages::PackageOptions::default());
+        let helper_exists_after_clean = project.join("src/Helper.mjs").exists();
+        let a_exists_after_clean = project.join("src/A.mjs").exists();
+
+        assert_eq!(Vec::from_iter(deleted_module_names), vec!["Helper".to_string()]);
+        assert!(helper_exists_after_cleanup);
+        assert!(!helper_ast_exists_after_cleanup);
+        assert!(helper_exists_after_clean);
+        assert!(!a_exists_after_clean);
+    }
+}
@@ -1,25 +1,229 @@
 mod dependency_cycle;
 
+use super::artifact_cache;
 use super::build_types::*;
+use super::clean;
+use super::diagnostics;
+use super::events::{self, BuildEvent};
+use super::interrupt;
 use super::logs;
 use super::packages;
-use crate::bsconfig;
+use super::trace;
+use crate::bsconfig::{self, FlagSource};
 use crate::helpers;
 use ahash::{AHashMap, AHashSet};
 use console::style;
 use log::debug;
 use log::{info, log_enabled, Level::Info};
 use rayon::prelude::*;
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// The flags of the build profile: the debug profile compiles with `-bs-g`, which keeps the names of
+/// variants and exceptions in the JavaScript output for debugging. bsc has no flags for sourcemaps or
+/// further optimizations, so the release profile just leaves it out.
+pub fn get_profile_args(release: bool) -> Vec<String> {
+    if release {
+        vec![]
+    } else {
+        vec!["-bs-g".to_string()]
+    }
+}
+
+/// One output per package spec, so we can emit multiple module formats in a single run. Like bsb,
+/// the specs of the root package apply to all packages, because the imports between the packages
+/// only resolve when the dependencies are compiled to the same module formats. Without them (with
+/// `check_only`), bsc doesn't write any JavaScript.
+fn get_package_output_args(
+    root_package: &packages::Package,
+    source_dir: &Path,
+    check_only: bool,
+) -> Vec<String> {
+    if check_only {
+        return vec![];
+    }
+    bsconfig::get_package_specs(&root_package.bsconfig)
+        .iter()
+        .flat_map(|spec| {
+            [
+                "-bs-package-output".to_string(),
+                spec.get_package_output(&root_package.bsconfig, source_dir),
+            ]
+        })
+        .collect()
+}
+
+/// The include of the standard library: the `stdlib_path` of the compile options, or the `lib/ocaml`
+/// of the rescript package of the project (see `helpers::get_node_modules_path`). The folders of `-I` are searched in order, so it comes
+/// after the includes of the package and its dependencies, like the standard library of bsc itself.
+/// Without a rescript package (ie. in a workspace), bsc finds its standard library by itself.
+fn get_stdlib_path(root_package: &packages::Package, compile_options: &CompileOptions) -> Option<String> {
+    compile_options.stdlib_path.to_owned().or_else(|| {
+        let path = Path::new(&helpers::get_node_modules_path(
+            &root_package.path,
+            root_package.node_modules.as_deref(),
+        )?)
+        .join("rescript/lib/ocaml");
+        path.is_dir().then(|| path.to_string_lossy().to_string())
+    })
+}
+
+fn is_compile_error(module: &Module) -> bool {
+    match &module.source_type {
+        SourceType::SourceFile(source_file) => {
+            source_file.implementation.compile_state == CompileState::Error
+                || source_file
+                    .interface
+                    .as_ref()
+                    .is_some_and(|interface| interface.compile_state == CompileState::Error)
+        }
+        SourceType::MlMap(_) => false,
+    }
+}
+
+/// Adds up the compiled modules of this build by package: how many compiled, succeeded and failed,
+/// and how long they took. Every package with modules in the build is included.
+pub fn get_package_reports(build_state: &BuildState) -> BTreeMap<String, PackageReport> {
+    let mut reports: BTreeMap<String, PackageReport> = BTreeMap::new();
+    for module in build_state.modules.values().filter(|module| !module.is_mlmap()) {
+        let report = reports.entry(module.package_name.to_owned()).or_default();
+        let failed = is_compile_error(module);
+        if module.compile_outcome.is_some() || failed {
+            report.compiled += 1;
+            match failed {
+                true => report.failed += 1,
+                false => report.succeeded += 1,
+            }
+        }
+        report.duration += module.compile_duration.unwrap_or_default();
+    }
+    reports
+}
+
+/// The reports of `get_package_reports` as a table, with a row per package.
+pub fn get_package_reports_table(reports: &BTreeMap<String, PackageReport>) -> String {
+    let width = reports
+        .keys()
+        .map(|package_name| package_name.len())
+        .chain(std::iter::once("Package".len()))
+        .max()
+        .unwrap_or_default();
+    let mut table = format!(
+        "{:<width$}  {:>8}  {:>9}  {:>6}  {:>8}",
+        "Package",
+        "Compiled",
+        "Succeeded",
+        "Failed",
+        "Time",
+        width = width
+    );
+    for (package_name, report) in reports {
+        let row = format!(
+            "{:<width$}  {:>8}  {:>9}  {:>6}  {:>7.2}s",
+            package_name,
+            report.compiled,
+            report.succeeded,
+            report.failed,
+            report.duration.as_secs_f64(),
+            width = width
+        );
+        table.push('\n');
+        table.push_str(&match report.failed {
+            0 => row,
+            _ => style(row).red().to_string(),
+        });
+    }
+    table
+}
+
+/// Counts the modules that were compiled, restored from the cache (`--cache-dir`) and up to date in
+/// this build, ie. `42 compiled, 938 cached`. With `verbose`, every module is listed first, with how
+/// it was compiled.
+pub fn get_compile_outcomes_report(build_state: &BuildState, verbose: bool) -> String {
+    let mut modules = build_state
+        .modules
+        .iter()
+        .filter(|(_, module)| !module.is_mlmap())
+        .map(|(module_name, module)| (module_name, module.compile_outcome))
+        .collect::<Vec<(&String, Option<CompileOutcome>)>>();
+    modules.sort_by_key(|(module_name, _)| *module_name);
+    let count = |outcome: Option<CompileOutcome>| {
+        modules
+            .iter()
+            .filter(|(_, module_outcome)| *module_outcome == outcome)
+            .count()
+    };
+
+    let mut report = String::new();
+    if verbose {
+        for (module_name, outcome) in &modules {
+            let marker = match outcome {
+                Some(CompileOutcome::Compiled) => style("compiled").green(),
+                Some(CompileOutcome::Restored) => style("restored").cyan(),
+                None => style("cached  ").dim(),
+            };
+            report.push_str(&format!("  {} {}\n", marker, module_name));
+        }
+    }
+    let restored = count(Some(CompileOutcome::Restored));
+    report.push_str(&format!(
+        "{}{}, {}",
+        style(format!("{} compiled", count(Some(CompileOutcome::Compiled)))).green(),
+        if restored > 0 {
+            format!(", {}", style(format!("{} restored", restored)).cyan())
+        } else {
+            String::new()
+        },
+        style(format!("{} cached", count(None))).dim()
+    ));
+    report
+}
+
+/// With a time budget, warns about the modules of the build that took longer to compile, and lists
+/// the 5 slowest modules.
+pub fn get_time_budget_report(build_state: &BuildState, time_budget: Option<Duration>) -> Option<String> {
+    let time_budget = time_budget?;
+    let mut durations = build_state
+        .modules
+        .iter()
+        .filter_map(|(module_name, module)| module.compile_duration.map(|duration| (module_name, duration)))
+        .collect::<Vec<(&String, Duration)>>();
+    if durations.is_empty() {
+        return None;
+    }
+    durations.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then(a_name.cmp(b_name)));
+
+    let mut report = String::new();
+    for (module_name, duration) in durations.iter().filter(|(_, duration)| *duration > time_budget) {
+        report.push_str(&format!(
+            "{} {} took {:.2}s to compile, more than the time budget of {:.2}s\n",
+            style("Warning:").yellow().bold(),
+            module_name,
+            duration.as_secs_f64(),
+            time_budget.as_secs_f64()
+        ));
+    }
+    report.push_str("Slowest modules:\n");
+    for (module_name, duration) in durations.iter().take(5) {
+        report.push_str(&format!("  {:.2}s {}\n", duration.as_secs_f64(), module_name));
+    }
+    Some(report)
+}
+
+// 0 means no limit
+fn is_max_errors_reached(max_errors: Option<usize>, errors: usize) -> bool {
+    max_errors.is_some_and(|max_errors| max_errors > 0 && errors >= max_errors)
+}
 
 pub fn compile(
     mut build_state: &mut BuildState,
     deleted_module_names: &AHashSet<String>,
-    rescript_version: &str,
     inc: impl Fn() -> () + std::marker::Sync,
     set_length: impl Fn(u64) -> (),
-    bsc_path: &str,
+    context: &CompileContext,
 ) -> (String, String, usize) {
     let mut compiled_modules = AHashSet::<String>::new();
 
@@ -60,6 +264,13 @@ pub fn compile(
     let mut compile_errors = "".to_string();
     let mut compile_warnings = "".to_string();
     let mut num_compiled_modules = 0;
+    // the modules are compiled in parallel, so we count the failed modules while compiling, to not
+    // start any new compilations once the maximum number of errors is reached
+    let failed_modules_count = AtomicUsize::new(0);
+    let skipped_modules_count = AtomicUsize::new(0);
+    let mut reported_errors_count = 0;
+    // the modules that were compiled while the build was interrupted
+    let mut interrupted_modules = AHashSet::<String>::new();
     let mut sorted_modules = build_state.module_names.iter().collect::<Vec<&String>>();
     sorted_modules.sort();
 
@@ -87,6 +298,10 @@ pub fn compile(
     let compile_universe_count = compile_universe.len();
     set_length(compile_universe_count as u64);
 
+    // There is no precomputed (topological) compile order: a module is compiled as soon as its
+    // deps in the compile universe are compiled, and only the dirty part of the graph is visited.
+    // Persisting an order between builds wouldn't save anything, because the compile universe (and
+    // which modules turn out to be clean) differs on every build.
     // start off with all modules that have no deps in this compile universe
     let mut in_progress_modules = compile_universe
         .iter()
@@ -97,6 +312,27 @@ pub fn compile(
         .map(|module_name| module_name.to_string())
         .collect::<AHashSet<String>>();
 
+    // packages are compiled in the order of their dependencies: a module can only be compiled when
+    // all modules (in this compile universe) of the packages its package depends on are compiled.
+    // Within a package everything runs in parallel.
+    let package_dependencies = build_state
+        .packages
+        .keys()
+        .map(|package_name| {
+            (
+                package_name.to_owned(),
+                packages::get_dependencies(&build_state.packages, package_name),
+            )
+        })
+        .collect::<AHashMap<String, AHashSet<String>>>();
+    let mut pending_modules_per_package: AHashMap<String, usize> = AHashMap::new();
+    for module_name in compile_universe.iter() {
+        let module = build_state.get_module(module_name).unwrap();
+        *pending_modules_per_package
+            .entry(module.package_name.to_owned())
+            .or_insert(0) += 1;
+    }
+
     loop {
         files_current_loop_count = 0;
         loop_count += 1;
@@ -109,23 +345,54 @@ pub fn compile(
         );
 
         let current_in_progres_modules = in_progress_modules.clone();
+        // the in progress modules are a hash set, so they are compiled in the order of their names,
+        // to apply the results (and log and report the errors) in the same order in every build
+        let mut sorted_in_progress_modules = current_in_progres_modules
+            .iter()
+            .cloned()
+            .collect::<Vec<String>>();
+        sorted_in_progress_modules.sort();
 
-        current_in_progres_modules
+        sorted_in_progress_modules
             .par_iter()
             .map(|module_name| {
                 let module = build_state.get_module(module_name).unwrap();
                 let package = build_state
                     .get_package(&module.package_name)
                     .expect("Package not found");
+                let dependency_packages_compiled = package_dependencies
+                    .get(&module.package_name)
+                    .map(|dependencies| {
+                        dependencies
+                            .iter()
+                            .all(|dependency| pending_modules_per_package.get(dependency).unwrap_or(&0) == &0)
+                    })
+                    .unwrap_or(true);
                 // all dependencies that we care about are compiled
-                if module
-                    .deps
-                    .intersection(&compile_universe)
-                    .all(|dep| compiled_modules.contains(dep))
+                if dependency_packages_compiled
+                    && module
+                        .deps
+                        .intersection(&compile_universe)
+                        .all(|dep| compiled_modules.contains(dep))
                 {
                     if !module.compile_dirty {
                         // we are sure we don't have to compile this, so we can mark it as compiled and clean
-                        return Some((module_name.to_string(), Ok(None), Some(Ok(None)), true, false));
+                        return Some((
+                            module_name.to_string(),
+                            Ok(None),
+                            Some(Ok(None)),
+                            true,
+                            None,
+                            None,
+                        ));
+                    }
+                    if interrupt::is_cancelled(context.cancel) {
+                        return None;
+                    }
+                    if is_max_errors_reached(context.max_errors, failed_modules_count.load(Ordering::Relaxed))
+                    {
+                        skipped_modules_count.fetch_add(1, Ordering::Relaxed);
+                        return None;
                     }
                     match module.source_type.to_owned() {
                         SourceType::MlMap(_) => {
@@ -138,7 +405,8 @@ pub fn compile(
                                 Ok(None),
                                 Some(Ok(None)),
                                 false,
-                                false,
+                                None,
+                                None,
                             ))
                         }
                         SourceType::SourceFile(source_file) => {
@@ -150,6 +418,9 @@ pub fn compile(
                             );
 
                             let cmi_digest = helpers::compute_file_hash(&cmi_path);
+                            let cmi_modified = std::fs::metadata(&cmi_path)
+                                .and_then(|metadata| metadata.modified())
+                                .ok();
 
                             let package = build_state
                                 .get_package(&module.package_name)
@@ -158,37 +429,83 @@ pub fn compile(
                             let root_package =
                                 build_state.get_package(&build_state.root_config_name).unwrap();
 
-                            let interface_result = match source_file.interface.to_owned() {
-                                Some(Interface { path, .. }) => {
-                                    let result = compile_file(
-                                        &package,
-                                        &root_package,
-                                        &package.get_iast_path(&path),
-                                        module,
-                                        &rescript_version,
-                                        true,
-                                        bsc_path,
-                                        &build_state.packages,
-                                    );
-                                    Some(result)
+                            events::emit(
+                                context.events,
+                                BuildEvent::CompileStarted {
+                                    module_name: module_name.to_string(),
+                                },
+                            );
+
+                            let start_compiling = Instant::now();
+                            // with --cache-dir, the outputs can be in the cache already
+                            let cache_key = context.cache_dir.and_then(|cache_dir| {
+                                artifact_cache::get_key(build_state, module_name, context.version)
+                                    .map(|key| (cache_dir, key))
+                            });
+                            let restored = cache_key.as_ref().and_then(|(cache_dir, key)| {
+                                artifact_cache::restore(cache_dir, build_state, module_name, key)
+                            });
+                            let outcome = match restored {
+                                Some(_) => CompileOutcome::Restored,
+                                None => CompileOutcome::Compiled,
+                            };
+                            let (interface_result, result) = match restored {
+                                Some(results) => results,
+                                None => {
+                                    let (interface_result, result) =
+                                        compile_source_file(&source_file, |is_interface| {
+                                            let ast_path = match (is_interface, &source_file.interface) {
+                                                (true, Some(interface)) => {
+                                                    package.get_iast_path(&interface.path)
+                                                }
+                                                _ => package.get_ast_path(&source_file.implementation.path),
+                                            };
+                                            let bsc_path = package.get_bsc_path(context.bsc_path);
+                                            let version = package.get_version(
+                                                context.bsc_path,
+                                                context.version,
+                                                context.compile_options,
+                                            );
+                                            compile_file(
+                                                &package,
+                                                &root_package,
+                                                &ast_path,
+                                                module,
+                                                is_interface,
+                                                &build_state.packages,
+                                                &context.for_package(&bsc_path, &version),
+                                            )
+                                        });
+                                    if let Some((cache_dir, key)) = &cache_key {
+                                        artifact_cache::store(
+                                            cache_dir,
+                                            build_state,
+                                            module_name,
+                                            key,
+                                            &interface_result,
+                                            &result,
+                                        );
+                                    }
+                                    (interface_result, result)
                                 }
-                                _ => None,
                             };
-                            let result = compile_file(
-                                &package,
-                                &root_package,
-                                &package.get_ast_path(&source_file.implementation.path),
-                                module,
-                                &rescript_version,
-                                false,
-                                bsc_path,
-                                &build_state.packages,
-                            );
                             // if let Err(error) = result.to_owned() {
                             //     println!("{}", error);
                             //     panic!("Implementation compilation error!");
                             // }
+                            let compile_duration = start_compiling.elapsed();
                             let cmi_digest_after = helpers::compute_file_hash(&cmi_path);
+                            if result.is_err() || matches!(interface_result, Some(Err(_))) {
+                                failed_modules_count.fetch_add(1, Ordering::Relaxed);
+                            }
+                            events::emit(
+                                context.events,
+                                BuildEvent::CompileFinished {
+                                    module_name: module_name.to_string(),
+                                    success: result.is_ok()
+                                        && interface_result.as_ref().map(|r| r.is_ok()).unwrap_or(true),
+                                },
+                            );
 
                             // println!(
                             //     "cmi path {}, digest: {:?} / {:?}",
@@ -206,13 +523,20 @@ pub fn compile(
 
                                 _ => false,
                             };
+                            // bsc writes the .cmi again, also when the interface didn't change. Keeping
+                            // its modification time keeps the dependents from being compiled again in
+                            // the next build as well (see mark_modules_with_expired_deps_dirty)
+                            if let (true, Some(modified)) = (is_clean_cmi, cmi_modified) {
+                                let _ = helpers::set_modified(&cmi_path, modified);
+                            }
 
                             Some((
                                 module_name.to_string(),
                                 result,
                                 interface_result,
                                 is_clean_cmi,
-                                true,
+                                Some(outcome),
+                                Some(compile_duration),
                             ))
                         }
                     }
@@ -232,20 +556,29 @@ pub fn compile(
                     Result<Option<String>, String>,
                     Option<Result<Option<String>, String>>,
                     bool,
-                    bool,
+                    Option<CompileOutcome>,
+                    Option<Duration>,
                 )>,
             >>()
             .iter()
             .for_each(|result| match result {
-                Some((module_name, result, interface_result, is_clean, is_compiled)) => {
+                Some((module_name, result, interface_result, is_clean, outcome, compile_duration)) => {
                     in_progress_modules.remove(module_name);
+                    let is_compiled = outcome.is_some();
 
-                    if *is_compiled {
+                    if is_compiled {
                         num_compiled_modules += 1;
+                        if interrupt::is_cancelled(context.cancel) {
+                            interrupted_modules.insert(module_name.to_string());
+                        }
                     }
 
                     files_current_loop_count += 1;
                     compiled_modules.insert(module_name.to_string());
+                    let package_name = &build_state.get_module(module_name).unwrap().package_name;
+                    if let Some(pending) = pending_modules_per_package.get_mut(package_name) {
+                        *pending -= 1;
+                    }
 
                     if *is_clean {
                         // actually add it to a list of clean modules
@@ -268,6 +601,15 @@ pub fn compile(
                     }
 
                     let module = build_state.modules.get_mut(module_name).unwrap();
+                    module.compile_duration = *compile_duration;
+                    module.compile_outcome = *outcome;
+                    module.diagnostics = [result, interface_result.as_ref().unwrap_or(&Ok(None))]
+                        .into_iter()
+                        .filter_map(|result| match result {
+                            Ok(Some(err)) | Err(err) => Some(err.to_owned()),
+                            Ok(None) => None,
+                        })
+                        .collect();
                     let package = build_state
                         .packages
                         .get(&module.package_name)
@@ -280,12 +622,20 @@ pub fn compile(
                                     source_file.implementation.compile_state = CompileState::Warning;
                                     logs::append(package, &err);
                                     compile_warnings.push_str(&err);
+                                    events::emit_diagnostic(context.events, module_name, err);
+                                }
+                                Ok(None) if is_compiled => {
+                                    source_file.implementation.compile_state = CompileState::Success;
                                 }
                                 Ok(None) => (),
                                 Err(err) => {
                                     source_file.implementation.compile_state = CompileState::Error;
                                     logs::append(package, &err);
-                                    compile_errors.push_str(&err);
+                                    if !is_max_errors_reached(context.max_errors, reported_errors_count) {
+                                        compile_errors.push_str(&err);
+                                    }
+                                    reported_errors_count += 1;
+                                    events::emit_diagnostic(context.events, module_name, err);
                                 }
                             };
                             match interface_result {
@@ -294,13 +644,18 @@ pub fn compile(
                                         CompileState::Warning;
                                     logs::append(package, &err);
                                     compile_warnings.push_str(&err);
+                                    events::emit_diagnostic(context.events, module_name, err);
                                 }
                                 Some(Ok(None)) => (),
                                 Some(Err(err)) => {
                                     source_file.interface.as_mut().unwrap().compile_state =
                                         CompileState::Error;
                                     logs::append(package, &err);
-                                    compile_errors.push_str(&err);
+                                    if !is_max_errors_reached(context.max_errors, reported_errors_count) {
+                                        compile_errors.push_str(&err);
+                                    }
+                                    reported_errors_count += 1;
+                                    events::emit_diagnostic(context.events, module_name, err);
                                 }
                                 _ => (),
                             };
@@ -312,6 +667,10 @@ pub fn compile(
 
         files_total_count += files_current_loop_count;
 
+        if interrupt::is_cancelled(context.cancel) {
+            clean::remove_interrupted_outputs(build_state, &interrupted_modules);
+            break;
+        }
         if files_total_count == compile_universe_count {
             break;
         }
@@ -334,21 +693,126 @@ pub fn compile(
         };
     }
 
+    let max_errors = context.max_errors.unwrap_or(0);
+    let skipped_modules_count = skipped_modules_count.load(Ordering::Relaxed);
+    if max_errors > 0 && (reported_errors_count > max_errors || skipped_modules_count > 0) {
+        compile_errors.push_str(&format!(
+            "\n{}\n",
+            style(format!(
+                "Stopped after {} errors (--max-errors): {} more errors were not shown, and {} modules were not compiled.",
+                max_errors,
+                reported_errors_count - max_errors.min(reported_errors_count),
+                skipped_modules_count
+            ))
+            .red()
+        ));
+    }
+
     (compile_errors, compile_warnings, num_compiled_modules)
 }
 
-fn compile_file(
+/// The warnings (when there are any) or the errors of compiling a file.
+pub type CompileResult = Result<Option<String>, String>;
+
+/// Compiles the interface of a module (when it has one) and then its implementation. The
+/// implementation is compiled with `-bs-read-cmi`, so it's checked against the cmi of the interface,
+/// which is why it always waits for the interface (the modules compile in parallel, but the files of
+/// a module don't). When the interface doesn't compile, the implementation isn't compiled either,
+/// because it would be checked against a stale (or missing) cmi. A module without implementation
+/// only gets the cmi of its interface.
+pub fn compile_source_file(
+    source_file: &SourceFile,
+    compile: impl Fn(bool) -> CompileResult,
+) -> (Option<CompileResult>, CompileResult) {
+    let interface_result = source_file.interface.as_ref().map(|_| compile(true));
+    let result = match interface_result {
+        _ if source_file.interface_only => Ok(None),
+        Some(Err(_)) => Err(format!(
+            "Did not compile {}, because its interface has errors\n",
+            source_file.implementation.path
+        )),
+        _ => compile(false),
+    };
+    (interface_result, result)
+}
+
+fn get_read_cmi_args(has_interface: bool, is_interface: bool) -> Vec<String> {
+    if has_interface && !is_interface {
+        vec!["-bs-read-cmi".to_string()]
+    } else {
+        vec![]
+    }
+}
+
+/// Turns the include paths into `-I` arguments. The same dependency can end up in the list more than
+/// once, so we deduplicate the paths (keeping the first occurrence) to not bloat the command line.
+fn include_args(include_paths: Vec<String>) -> Vec<String> {
+    let mut seen = AHashSet::new();
+    include_paths
+        .into_iter()
+        .filter(|path| seen.insert(path.to_owned()))
+        .flat_map(|path| vec!["-I".to_string(), path])
+        .collect()
+}
+
+/// With `--relative-paths`, the include paths are relative to the build folder of the package, which
+/// bsc compiles in, so the command is the same on every machine. The paths that don't exist stay
+/// absolute, as we can't resolve them.
+fn get_relative_include_paths(include_paths: Vec<String>, build_path: &str) -> Vec<String> {
+    let build_path = match helpers::canonicalize_string_path(build_path) {
+        Some(build_path) => build_path,
+        None => return include_paths,
+    };
+    include_paths
+        .into_iter()
+        .map(|path| match helpers::canonicalize_string_path(&path) {
+            Some(canonical_path) => {
+                helpers::get_relative_path(Path::new(&build_path), Path::new(&canonical_path))
+            }
+            None => path,
+        })
+        .collect()
+}
+
+pub fn compiler_args(
     package: &packages::Package,
     root_package: &packages::Package,
     ast_path: &str,
     module: &Module,
-    version: &str,
     is_interface: bool,
-    bsc_path: &str,
     packages: &AHashMap<String, packages::Package>,
-) -> Result<Option<String>, String> {
-    let build_path_abs = package.get_build_path();
-    let bsc_flags = bsconfig::flatten_flags(&package.bsconfig.bsc_flags);
+    context: &CompileContext,
+) -> Vec<String> {
+    compiler_arg_sources(
+        package,
+        root_package,
+        ast_path,
+        module,
+        is_interface,
+        packages,
+        context,
+    )
+    .into_iter()
+    .flat_map(|(_, args)| args)
+    .collect()
+}
+
+/// The arguments of `compiler_args`, grouped by where they come from (see `explain-flags`).
+pub fn compiler_arg_sources(
+    package: &packages::Package,
+    root_package: &packages::Package,
+    ast_path: &str,
+    module: &Module,
+    is_interface: bool,
+    packages: &AHashMap<String, packages::Package>,
+    context: &CompileContext,
+) -> Vec<(FlagSource, Vec<String>)> {
+    let shared_config = root_package.bsconfig.shared_config.as_ref();
+    let bsc_flags = bsconfig::get_bsc_flag_sources(
+        &package.bsconfig,
+        shared_config,
+        &context.compile_options.bsc_flags,
+    );
 
     let normal_deps = package
         .bsconfig
@@ -366,17 +830,44 @@ fn compile_file(
     //     .unwrap_or(&vec![])
     //     .to_owned();
 
-    let deps = vec![normal_deps]
+    // extra directories with compiled assets (.cmi / .cmj) outside of the dependencies, relative to
+    // the package
+    let external_includes = package
+        .bsconfig
+        .bs_external_includes
+        .to_owned()
+        .unwrap_or_default()
+        .iter()
+        .map(|include| {
+            Path::new(&package.path)
+                .join(include)
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect::<Vec<String>>();
+
+    let include_paths = vec![normal_deps]
         .concat()
         .into_iter()
         .map(|x| {
             let package = &packages.get(&x).expect("expect package");
-            vec![
-                "-I".to_string(),
-                helpers::canonicalize_string_path(&package.get_build_path()).unwrap(),
-            ]
+            // a missing build folder is reported by `check_dependency_build_paths`
+            helpers::canonicalize_string_path(&package.get_build_path()).unwrap_or(package.get_build_path())
+        })
+        .chain(external_includes)
+        .collect::<Vec<String>>();
+    let include_args = |include_paths: Vec<String>| {
+        include_args(match context.compile_options.relative_paths {
+            true => get_relative_include_paths(include_paths, &package.get_build_path()),
+            false => include_paths,
         })
-        .collect::<Vec<Vec<String>>>();
+    };
+    let deps = include_args(include_paths);
+    let stdlib_args = include_args(
+        get_stdlib_path(root_package, context.compile_options)
+            .into_iter()
+            .collect(),
+    );
 
     let implementation_file_path = match module.source_type {
         SourceType::SourceFile(ref source_file) => &source_file.implementation.path,
@@ -409,42 +900,11 @@ fn compile_file(
     let jsx_args = root_package.get_jsx_args();
     let jsx_module_args = root_package.get_jsx_module_args();
     let jsx_mode_args = root_package.get_jsx_mode_args();
-    let uncurried_args = package.get_uncurried_args(version, &root_package);
-
-    let warning_args: Vec<String> = match package.bsconfig.warnings.to_owned() {
-        None => vec![],
-        Some(warnings) => {
-            let warn_number = match warnings.number {
-                None => vec![],
-                Some(warnings) => {
-                    vec!["-w".to_string(), warnings.to_string()]
-                }
-            };
+    let uncurried_args = package.get_uncurried_args(context.version, &root_package);
 
-            let warn_error = match warnings.error {
-                Some(bsconfig::Error::Catchall(true)) => {
-                    vec!["-warn-error".to_string(), "A".to_string()]
-                }
-                Some(bsconfig::Error::Qualified(errors)) => {
-                    vec!["-warn-error".to_string(), errors.to_string()]
-                }
-                _ => vec![],
-            };
-
-            vec![warn_number, warn_error].concat()
-        }
-    };
+    let warning_args = package.get_warning_arg_sources(shared_config, context.compile_options);
 
-    let read_cmi_args = match module.get_interface() {
-        Some(_) => {
-            if is_interface {
-                vec![]
-            } else {
-                vec!["-bs-read-cmi".to_string()]
-            }
-        }
-        _ => vec![],
-    };
+    let read_cmi_args = get_read_cmi_args(module.get_interface().is_some(), is_interface);
 
     let implementation_args = if is_interface {
         debug!("Compiling interface file: {}", &module_name);
@@ -452,133 +912,176 @@ fn compile_file(
     } else {
         debug!("Compiling file: {}", &module_name);
 
-        // TODO: Also read suffix from package-spec.
-        let suffix = match root_package.bsconfig.suffix.to_owned() {
-            Some(suffix) => suffix,
-            None => String::from(bsconfig::DEFAULT_SUFFIX),
-        };
+        let source_dir = Path::new(implementation_file_path).parent().unwrap();
+
+        let package_output_args =
+            get_package_output_args(root_package, source_dir, context.compile_options.check_only);
 
         vec![
-            "-bs-package-name".to_string(),
-            package.bsconfig.name.to_owned(),
-            "-bs-package-output".to_string(),
-            format!(
-                "es6:{}:{}",
-                Path::new(implementation_file_path)
-                    .parent()
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
-                suffix
+            (
+                FlagSource::Rewatch,
+                vec!["-bs-package-name".to_string(), package.bsconfig.name.to_owned()],
             ),
+            (FlagSource::Root, package_output_args),
         ]
     };
 
-    let to_mjs_args = vec![
-        namespace_args,
-        read_cmi_args,
-        vec!["-I".to_string(), ".".to_string()],
-        deps.concat(),
-        jsx_args,
-        jsx_module_args,
-        jsx_mode_args,
-        uncurried_args,
+    [
+        vec![
+            (FlagSource::Package, namespace_args),
+            (FlagSource::Rewatch, read_cmi_args),
+            (FlagSource::Rewatch, vec!["-I".to_string(), ".".to_string()]),
+            (FlagSource::Package, deps),
+            (FlagSource::Root, jsx_args),
+            (FlagSource::Root, jsx_module_args),
+            (FlagSource::Root, jsx_mode_args),
+            (FlagSource::Root, uncurried_args),
+        ],
         bsc_flags,
+        vec![(
+            FlagSource::Rewatch,
+            get_profile_args(context.compile_options.release),
+        )],
+        // the warnings of the package, and the specs from the command line (--warn-spec and
+        // --warn-error-spec). There is no hardcoded `-warn-error A`, it fails for bisect-ppx
         warning_args,
-        // vec!["-warn-error".to_string(), "A".to_string()],
-        // ^^ this one fails for bisect-ppx
-        // this is the default
-        // we should probably parse the right ones from the package config
-        // vec!["-w".to_string(), "a".to_string()],
         implementation_args,
-        // vec![
-        //     "-I".to_string(),
-        //     abs_node_modules_path.to_string() + "/rescript/ocaml",
-        // ],
-        vec![ast_path.to_string()],
+        vec![
+            (FlagSource::Rewatch, stdlib_args),
+            (FlagSource::Rewatch, vec![ast_path.to_string()]),
+        ],
     ]
-    .concat();
+    .concat()
+    .into_iter()
+    .filter(|(_, args)| !args.is_empty())
+    .collect()
+}
+
+/// Checks that the build folders of the dependencies of a package exist. The dependencies in this
+/// build are compiled before their dependents, so the folder is only missing when a precompiled
+/// dependency isn't installed properly. Without this check bsc fails with an unbound module error
+/// that doesn't mention the dependency.
+fn check_dependency_build_paths(
+    package: &packages::Package,
+    packages: &AHashMap<String, packages::Package>,
+    module_name: &str,
+) -> Result<(), String> {
+    let missing = package
+        .bsconfig
+        .bs_dependencies
+        .to_owned()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|dependency| {
+            let build_path = packages.get(&dependency)?.get_build_path();
+            if Path::new(&build_path).exists() {
+                None
+            } else {
+                Some(format!(
+                    "{} (expected the compiled assets in {})",
+                    dependency, build_path
+                ))
+            }
+        })
+        .collect::<Vec<String>>();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Could not compile {}: the build folder of these dependencies of {} is missing:\n  {}\nMake sure the dependencies are installed, or run a clean build.\n",
+            module_name,
+            package.name,
+            missing.join("\n  ")
+        ))
+    }
+}
+
+pub fn compile_file(
+    package: &packages::Package,
+    root_package: &packages::Package,
+    ast_path: &str,
+    module: &Module,
+    is_interface: bool,
+    packages: &AHashMap<String, packages::Package>,
+    context: &CompileContext,
+) -> Result<Option<String>, String> {
+    let build_path_abs = package.get_build_path();
+    let implementation_file_path = match module.source_type {
+        SourceType::SourceFile(ref source_file) => &source_file.implementation.path,
+        _ => panic!("Not a source file"),
+    };
+    let module_name = helpers::file_path_to_module_name(implementation_file_path, &package.namespace);
+    let to_mjs_args = compiler_args(
+        package,
+        root_package,
+        ast_path,
+        module,
+        is_interface,
+        packages,
+        context,
+    );
+    check_dependency_build_paths(package, packages, &module_name)?;
+    let response_file_path = Path::new(&build_path_abs)
+        .join(format!("{}.rsp", ast_path))
+        .to_string_lossy()
+        .to_string();
+    let to_mjs_args = helpers::get_command_line_args(
+        context.bsc_path,
+        to_mjs_args,
+        &response_file_path,
+        helpers::get_max_command_line_length(),
+    )
+    .map_err(|e| {
+        format!(
+            "Could not write the arguments of bsc to {}: {}\n",
+            response_file_path, e
+        )
+    })?;
 
-    let to_mjs = Command::new(bsc_path)
-        .current_dir(helpers::canonicalize_string_path(&build_path_abs.to_owned()).unwrap())
-        .args(to_mjs_args)
-        .output();
+    let trace_name = if is_interface {
+        format!("{} (interface)", module_name)
+    } else {
+        module_name.to_owned()
+    };
+    let to_mjs = trace::span(context.trace, "compile", &trace_name, || {
+        helpers::run_command(
+            Command::new(context.bsc_path)
+                .current_dir(helpers::canonicalize_string_path(&build_path_abs.to_owned()).unwrap())
+                .args(to_mjs_args),
+        )
+    });
+    let _ = std::fs::remove_file(&response_file_path);
 
     match to_mjs {
         Ok(x) if !x.status.success() => {
-            let stderr = String::from_utf8_lossy(&x.stderr);
-            let stdout = String::from_utf8_lossy(&x.stdout);
-            Err(stderr.to_string() + &stdout)
+            let stderr = helpers::decode_output(&x.stderr);
+            let stdout = helpers::decode_output(&x.stdout);
+            let output = stderr + &stdout;
+            let (diagnostics, _) = diagnostics::parse_diagnostics(&output);
+            // ie. a crash of bsc, which would otherwise look like a module without errors
+            if !diagnostics
+                .iter()
+                .any(|diagnostic| diagnostic.severity == diagnostics::Severity::Error)
+            {
+                let file = match (is_interface, &module.source_type) {
+                    (
+                        true,
+                        SourceType::SourceFile(SourceFile {
+                            interface: Some(interface),
+                            ..
+                        }),
+                    ) => &interface.path,
+                    _ => implementation_file_path,
+                };
+                return Err(output + &diagnostics::compiler_failed(file, x.status.code()));
+            }
+            Err(output)
         }
         Err(e) => Err(format!("ERROR, {}, {:?}", e, ast_path)),
         Ok(x) => {
-            let err = std::str::from_utf8(&x.stderr)
-                .expect("stdout should be non-null")
-                .to_string();
+            let err = helpers::decode_output(&x.stderr);
 
-            let dir = std::path::Path::new(implementation_file_path).parent().unwrap();
-
-            // perhaps we can do this copying somewhere else
-            if !is_interface {
-                let _ = std::fs::copy(
-                    build_path_abs.to_string() + "/" + &module_name + ".cmi",
-                    std::path::Path::new(&package.get_bs_build_path())
-                        .join(dir)
-                        // because editor tooling doesn't support namespace entries yet
-                        // we just remove the @ for now. This makes sure the editor support
-                        // doesn't break
-                        .join(module_name.to_owned().replace("@", "") + ".cmi"),
-                );
-                let _ = std::fs::copy(
-                    build_path_abs.to_string() + "/" + &module_name + ".cmj",
-                    std::path::Path::new(&package.get_bs_build_path())
-                        .join(dir)
-                        .join(module_name.to_owned() + ".cmj"),
-                );
-                let _ = std::fs::copy(
-                    build_path_abs.to_string() + "/" + &module_name + ".cmt",
-                    std::path::Path::new(&package.get_bs_build_path())
-                        .join(dir)
-                        // because editor tooling doesn't support namespace entries yet
-                        // we just remove the @ for now. This makes sure the editor support
-                        // doesn't break
-                        .join(module_name.to_owned().replace("@", "") + ".cmt"),
-                );
-            } else {
-                let _ = std::fs::copy(
-                    build_path_abs.to_string() + "/" + &module_name + ".cmti",
-                    std::path::Path::new(&package.get_bs_build_path())
-                        .join(dir)
-                        .join(module_name.to_owned() + ".cmti"),
-                );
-            }
-            match &module.source_type {
-                SourceType::SourceFile(SourceFile {
-                    interface: Some(Interface { path, .. }),
-                    ..
-                })
-                | SourceType::SourceFile(SourceFile {
-                    implementation: Implementation { path, .. },
-                    ..
-                }) => {
-                    // we need to copy the source file to the build directory.
-                    // editor tools expects the source file in lib/bs for finding the current package
-                    // and in lib/ocaml when referencing modules in other packages
-                    let _ = std::fs::copy(
-                        std::path::Path::new(&package.path).join(path),
-                        std::path::Path::new(&package.get_bs_build_path()).join(path),
-                    )
-                    .expect("copying source file failed");
-
-                    let _ = std::fs::copy(
-                        std::path::Path::new(&package.path).join(path),
-                        std::path::Path::new(&package.get_build_path())
-                            .join(std::path::Path::new(path).file_name().unwrap()),
-                    )
-                    .expect("copying source file failed");
-                }
-                _ => (),
-            }
+            copy_compiler_assets(package, module, is_interface);
 
             if helpers::contains_ascii_characters(&err) {
                 if package.is_pinned_dep {
@@ -594,6 +1097,100 @@ fn compile_file(
     }
 }
 
+/// Copies the compiler assets of a file (from the build folder, where bsc writes them) next to the
+/// sources in lib/bs, and copies the source file to the build folders, for the editor tooling.
+pub fn copy_compiler_assets(package: &packages::Package, module: &Module, is_interface: bool) {
+    let build_path_abs = package.get_build_path();
+    let implementation_file_path = match module.source_type {
+        SourceType::SourceFile(ref source_file) => &source_file.implementation.path,
+        _ => panic!("Not a source file"),
+    };
+    // the compiler assets are named after the file, so `foo.res` compiles to foo.cmi (of module Foo)
+    let asset_name =
+        helpers::file_path_to_compiler_asset_basename(implementation_file_path, &package.namespace);
+    let dir = std::path::Path::new(implementation_file_path).parent().unwrap();
+
+    let interface_only = match module.source_type {
+        SourceType::SourceFile(ref source_file) => source_file.interface_only,
+        _ => false,
+    };
+
+    // with a `build-dir` of lib/bs, the assets of the files at the root of the package are in place
+    // already (and copying a file onto itself empties it)
+    let in_place = Path::new(&package.get_bs_build_path()).join(dir) == Path::new(&build_path_abs);
+
+    // perhaps we can do this copying somewhere else
+    if !is_interface && !in_place {
+        let _ = std::fs::copy(
+            build_path_abs.to_string() + "/" + &asset_name + ".cmi",
+            std::path::Path::new(&package.get_bs_build_path())
+                .join(dir)
+                // because editor tooling doesn't support namespace entries yet
+                // we just remove the @ for now. This makes sure the editor support
+                // doesn't break
+                .join(asset_name.to_owned().replace("@", "") + ".cmi"),
+        );
+        let _ = std::fs::copy(
+            build_path_abs.to_string() + "/" + &asset_name + ".cmj",
+            std::path::Path::new(&package.get_bs_build_path())
+                .join(dir)
+                .join(asset_name.to_owned() + ".cmj"),
+        );
+        let _ = std::fs::copy(
+            build_path_abs.to_string() + "/" + &asset_name + ".cmt",
+            std::path::Path::new(&package.get_bs_build_path())
+                .join(dir)
+                // because editor tooling doesn't support namespace entries yet
+                // we just remove the @ for now. This makes sure the editor support
+                // doesn't break
+                .join(asset_name.to_owned().replace("@", "") + ".cmt"),
+        );
+    } else if !in_place {
+        if interface_only {
+            // there is no implementation that copies the cmi
+            let _ = std::fs::copy(
+                build_path_abs.to_string() + "/" + &asset_name + ".cmi",
+                std::path::Path::new(&package.get_bs_build_path())
+                    .join(dir)
+                    .join(asset_name.to_owned().replace("@", "") + ".cmi"),
+            );
+        }
+        let _ = std::fs::copy(
+            build_path_abs.to_string() + "/" + &asset_name + ".cmti",
+            std::path::Path::new(&package.get_bs_build_path())
+                .join(dir)
+                .join(asset_name.to_owned() + ".cmti"),
+        );
+    }
+    match &module.source_type {
+        SourceType::SourceFile(SourceFile {
+            interface: Some(Interface { path, .. }),
+            ..
+        })
+        | SourceType::SourceFile(SourceFile {
+            implementation: Implementation { path, .. },
+            ..
+        }) => {
+            // we need to copy the source file to the build directory.
+            // editor tools expects the source file in lib/bs for finding the current package
+            // and in lib/ocaml when referencing modules in other packages
+            let _ = std::fs::copy(
+                std::path::Path::new(&package.path).join(path),
+                std::path::Path::new(&package.get_bs_build_path()).join(path),
+            )
+            .expect("copying source file failed");
+
+            let _ = std::fs::copy(
+                std::path::Path::new(&package.path).join(path),
+                std::path::Path::new(&package.get_build_path())
+                    .join(std::path::Path::new(path).file_name().unwrap()),
+            )
+            .expect("copying source file failed");
+        }
+        _ => (),
+    }
+}
+
 pub fn mark_modules_with_deleted_deps_dirty(
     build_state: &mut BuildState,
     deleted_modules: &AHashSet<String>,
@@ -672,3 +1269,1383 @@ pub fn mark_modules_with_expired_deps_dirty(build_state: &mut BuildState) {
         }
     });
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        compile_file, compile_source_file, compiler_args, get_compile_outcomes_report, get_package_reports,
+        get_package_reports_table, get_profile_args, get_read_cmi_args, get_relative_include_paths,
+        get_stdlib_path, get_time_budget_report, include_args,
+    };
+    use crate::bsconfig;
+    use crate::build::build_types::*;
+    use crate::build::{deps, diagnostics, packages, parse};
+    use crate::helpers::{self, get_command_line_length, MAX_WINDOWS_COMMAND_LINE_LENGTH};
+    use crate::test_fixture::TestProject;
+    use ahash::{AHashMap, AHashSet};
+    use std::cell::RefCell;
+    use std::time::{Duration, SystemTime};
+
+    // ie. an interface `type hello; let hello: hello` that narrows the implementation `let hello = 1`
+    fn module_with_interface() -> SourceFile {
+        SourceFile {
+            implementation: Implementation {
+                path: "src/ModuleWithInterface.res".to_string(),
+                parse_state: ParseState::Pending,
+                compile_state: CompileState::Pending,
+                last_modified: SystemTime::UNIX_EPOCH,
+                dirty: true,
+                dirty_reason: None,
+            },
+            interface: Some(Interface {
+                path: "src/ModuleWithInterface.resi".to_string(),
+                parse_state: ParseState::Pending,
+                compile_state: CompileState::Pending,
+                last_modified: SystemTime::UNIX_EPOCH,
+                dirty: true,
+                dirty_reason: None,
+            }),
+            interface_only: false,
+            is_dev: false,
+            virtual_contents: None,
+        }
+    }
+
+    #[test]
+    fn should_count_the_compiled_and_cached_modules() {
+        let mut build_state = BuildState::new(String::new(), "root".to_string(), AHashMap::new());
+        for (module_name, compile_outcome) in [
+            ("A", Some(CompileOutcome::Compiled)),
+            ("B", None),
+            ("C", Some(CompileOutcome::Compiled)),
+            ("D", None),
+            ("E", None),
+        ] {
+            build_state.insert_module(
+                module_name,
+                Module {
+                    source_type: SourceType::SourceFile(module_with_interface()),
+                    deps: AHashSet::new(),
+                    dependents: AHashSet::new(),
+                    package_name: "root".to_string(),
+                    compile_dirty: false,
+                    last_compiled_cmi: None,
+                    last_compiled_cmt: None,
+                    compile_duration: None,
+                    compile_outcome,
+                    diagnostics: vec![],
+                },
+            );
+        }
+        let report = |build_state: &BuildState, verbose| {
+            console::strip_ansi_codes(&get_compile_outcomes_report(build_state, verbose)).to_string()
+        };
+
+        assert_eq!(report(&build_state, false), "2 compiled, 3 cached");
+        build_state.modules.get_mut("B").unwrap().compile_outcome = Some(CompileOutcome::Restored);
+        assert_eq!(report(&build_state, false), "2 compiled, 1 restored, 2 cached");
+        assert_eq!(
+            report(&build_state, true),
+            [
+                "  compiled A",
+                "  restored B",
+                "  compiled C",
+                "  cached   D",
+                "  cached   E",
+                "2 compiled, 1 restored, 2 cached"
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn should_add_up_the_compiled_modules_by_package() {
+        let mut build_state = BuildState::new(String::new(), "root".to_string(), AHashMap::new());
+        for (module_name, package_name, compile_outcome, compile_state, compile_duration) in [
+            (
+                "A",
+                "root",
+                Some(CompileOutcome::Compiled),
+                CompileState::Success,
+                Some(200),
+            ),
+            (
+                "B",
+                "root",
+                Some(CompileOutcome::Compiled),
+                CompileState::Error,
+                Some(100),
+            ),
+            ("C", "root", None, CompileState::Pending, None),
+            (
+                "D",
+                "dep",
+                Some(CompileOutcome::Restored),
+                CompileState::Success,
+                None,
+            ),
+        ] {
+            let mut source_file = module_with_interface();
+            source_file.implementation.compile_state = compile_state;
+            build_state.insert_module(
+                module_name,
+                Module {
+                    source_type: SourceType::SourceFile(source_file),
+                    deps: AHashSet::new(),
+                    dependents: AHashSet::new(),
+                    package_name: package_name.to_string(),
+                    compile_dirty: false,
+                    last_compiled_cmi: None,
+                    last_compiled_cmt: None,
+                    compile_duration: compile_duration.map(Duration::from_millis),
+                    compile_outcome,
+                    diagnostics: vec![],
+                },
+            );
+        }
+
+        let reports = get_package_reports(&build_state);
+        assert_eq!(
+            reports.get("root"),
+            Some(&PackageReport {
+                compiled: 2,
+                succeeded: 1,
+                failed: 1,
+                duration: Duration::from_millis(300),
+            })
+        );
+        assert_eq!(
+            console::strip_ansi_codes(&get_package_reports_table(&reports)),
+            [
+                "Package  Compiled  Succeeded  Failed      Time",
+                "dep             1          1       0     0.00s",
+                "root            2          1       1     0.30s",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn should_report_the_modules_over_the_time_budget() {
+        let mut build_state = BuildState::new(String::new(), "root".to_string(), AHashMap::new());
+        for (module_name, compile_duration) in [
+            ("A", Some(100)),
+            ("B", Some(2500)),
+            ("C", Some(3000)),
+            ("D", Some(50)),
+            ("E", Some(1000)),
+            ("F", Some(200)),
+            ("NotCompiled", None),
+        ] {
+            build_state.insert_module(
+                module_name,
+                Module {
+                    source_type: SourceType::SourceFile(module_with_interface()),
+                    deps: AHashSet::new(),
+                    dependents: AHashSet::new(),
+                    package_name: "root".to_string(),
+                    compile_dirty: false,
+                    last_compiled_cmi: None,
+                    last_compiled_cmt: None,
+                    compile_duration: compile_duration.map(Duration::from_millis),
+                    compile_outcome: None,
+                    diagnostics: vec![],
+                },
+            );
+        }
+
+        let without_budget = get_time_budget_report(&build_state, None);
+        let report = get_time_budget_report(&build_state, Some(Duration::from_secs(2)));
+
+        assert_eq!(without_budget, None);
+        assert_eq!(
+            console::strip_ansi_codes(&report.unwrap()),
+            "Warning: C took 3.00s to compile, more than the time budget of 2.00s
+Warning: B took 2.50s to compile, more than the time budget of 2.00s
+Slowest modules:
+  3.00s C
+  2.50s B
+  1.00s E
+  0.20s F
+  0.10s A
+"
+        );
+    }
+
+    #[test]
+    fn should_compile_the_interface_before_the_implementation() {
+        let compiled = RefCell::new(vec![]);
+
+        let (interface_result, result) = compile_source_file(&module_with_interface(), |is_interface| {
+            compiled.borrow_mut().push(is_interface);
+            Ok(None)
+        });
+
+        assert_eq!(compiled.into_inner(), vec![true, false]);
+        assert_eq!(interface_result, Some(Ok(None)));
+        assert_eq!(result, Ok(None));
+        // only the implementation reads the cmi of the interface
+        assert_eq!(get_read_cmi_args(true, true), Vec::<String>::new());
+        assert_eq!(get_read_cmi_args(true, false), vec!["-bs-read-cmi"]);
+        assert_eq!(get_read_cmi_args(false, false), Vec::<String>::new());
+    }
+
+    #[test]
+    fn should_not_compile_the_implementation_when_the_interface_fails() {
+        let compiled = RefCell::new(vec![]);
+
+        let (interface_result, result) = compile_source_file(&module_with_interface(), |is_interface| {
+            compiled.borrow_mut().push(is_interface);
+            Err("error".to_string())
+        });
+
+        assert_eq!(compiled.into_inner(), vec![true]);
+        assert_eq!(interface_result, Some(Err("error".to_string())));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_pass_the_bsc_flags_from_the_command_line_for_every_module() {
+        let project = TestProject::new("bsc-flag");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "bsc-flags": ["-open Belt"] }"#,
+        );
+        project.write("src/A.res", "");
+        project.write("src/B.res", "");
+        project.write("src/B.resi", "");
+
+        let project_root = project.path();
+        let build_state = project.build_state("root");
+        let compile_options = CompileOptions {
+            bsc_flags: bsconfig::split_extra_bsc_flags(&["-bs-g".to_string()]),
+            ..CompileOptions::default()
+        };
+
+        let root_package = build_state.get_package("root").unwrap();
+        let source_modules = build_state
+            .modules
+            .values()
+            .filter_map(|module| match module.source_type {
+                SourceType::SourceFile(ref source_file) => Some((module, source_file)),
+                SourceType::MlMap(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(source_modules.len(), 2);
+        for (module, source_file) in source_modules {
+            let package = build_state.get_package(&module.package_name).unwrap();
+            let (_, parser_args) = parse::parser_args(
+                package,
+                root_package,
+                &source_file.implementation.path,
+                &project_root,
+                "11.0.0",
+                None,
+                &compile_options,
+            );
+            let compiler_args = compiler_args(
+                package,
+                root_package,
+                "A.ast",
+                module,
+                false,
+                &build_state.packages,
+                &CompileContext::new("bsc", "11.0.0", &compile_options),
+            );
+            for args in [parser_args, compiler_args] {
+                // after the bsc-flags of the package
+                let open = args.iter().position(|arg| arg == "Belt").unwrap();
+                let bs_g = args.iter().position(|arg| arg == "-bs-g").unwrap();
+                assert!(open < bs_g);
+            }
+        }
+    }
+
+    #[test]
+    fn should_pass_the_compiler_version_from_the_command_line_to_bsc() {
+        let project = TestProject::new("compiler-version");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "");
+        let project_root = project.path();
+        let packages = project.make();
+        let package = packages.get("root").unwrap();
+
+        let compile_options = CompileOptions {
+            compiler_version: Some("11.1.0-custom".to_string()),
+            ..CompileOptions::default()
+        };
+        // there is no compiler in this project, so the version can only come from the override
+        let version =
+            crate::build::try_get_version(&project.join("bsc.exe").to_string_lossy(), &compile_options);
+        let (_, parser_args) = parse::parser_args(
+            package,
+            package,
+            "src/A.res",
+            &project_root,
+            version.as_ref().unwrap(),
+            None,
+            &compile_options,
+        );
+
+        assert_eq!(version, Ok("11.1.0-custom".to_string()));
+        let bs_v = parser_args.iter().position(|arg| arg == "-bs-v").unwrap();
+        assert_eq!(parser_args[bs_v + 1], "11.1.0-custom");
+    }
+
+    #[test]
+    fn should_only_pass_absolute_paths_to_bsc_without_relative_paths() {
+        let project = TestProject::new("relative-paths");
+        std::fs::create_dir_all(project.join("lib/ocaml")).unwrap();
+        project.write("src/A.res", "");
+        let file = project.join("src/A.res").to_string_lossy().to_string();
+        let build_path = project.join("lib/ocaml").to_string_lossy().to_string();
+
+        let absolute = parse::get_source_file_args(&file, &build_path, false);
+        let relative = parse::get_source_file_args(&file, &build_path, true);
+
+        assert_eq!(absolute, vec!["-absname".to_string(), file]);
+        assert_eq!(relative, vec!["../../src/A.res".to_string()]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_stop_starting_bsc_when_the_build_is_cancelled() {
+        use crate::build::events::BuildEvent;
+
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let project = TestProject::new("cancel");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        for module_name in ["A", "B", "C", "D"] {
+            project.write(format!("src/{}.res", module_name), "");
+        }
+        // a bsc that logs how it's called, writes an AST without dependencies, and takes a while to
+        // compile
+        let log_path = project.join("bsc.log");
+        let bsc_path = project.write_script(
+            "bsc.exe",
+            &format!(
+                "echo \"$@\" >> {}\nfor arg in \"$@\"; do\n  [ \"$prev\" = \"-o\" ] && out=\"$arg\"\n  prev=\"$arg\"\ndone\ncase \"$*\" in *-bs-ast*) printf '\\n%s\\n' \"$arg\" > \"$out\";; *) sleep 0.3;; esac\n",
+                log_path.to_string_lossy()
+            ),
+        );
+        let bsc_path = bsc_path.to_string_lossy().to_string();
+
+        let mut build_state = project.build_state("root");
+        let parsed = parse::generate_asts(
+            &mut build_state,
+            || (),
+            None,
+            &CompileContext::new(&bsc_path, "11.0.0", &CompileOptions::default()),
+        );
+        crate::build::deps::get_deps(&mut build_state, &AHashSet::new());
+        // cancel the build as soon as the first module starts compiling, like an embedder would
+        // when a newer edit comes in
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let listener = {
+            let cancel = cancel.clone();
+            std::thread::spawn(move || {
+                for event in receiver {
+                    if let BuildEvent::CompileStarted { .. } = event {
+                        cancel.store(true, Ordering::SeqCst);
+                    }
+                }
+            })
+        };
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        pool.install(|| {
+            super::compile(
+                &mut build_state,
+                &AHashSet::new(),
+                || (),
+                |_| (),
+                &CompileContext {
+                    events: Some(&sender),
+                    cancel: Some(&cancel),
+                    ..CompileContext::new(&bsc_path, "11.0.0", &CompileOptions::default())
+                },
+            )
+        });
+        drop(sender);
+        listener.join().unwrap();
+        let log = std::fs::read_to_string(&log_path).unwrap();
+
+        assert!(parsed.is_ok());
+        let compiles = log.lines().filter(|call| !call.contains("-bs-ast")).count();
+        assert_eq!(compiles, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_compile_the_mlmap_of_a_namespace_before_its_modules() {
+        let project = TestProject::new("mlmap-order");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "namespace": "MyNamespace" }"#,
+        );
+        project.write("src/A.res", "");
+        project.write("src/B.res", "");
+        // a bsc that logs how it's called, and writes an AST without dependencies
+        let log_path = project.join("bsc.log");
+        let bsc_path = project.write_script(
+            "bsc.exe",
+            &format!(
+                "echo \"$@\" >> {}\nfor arg in \"$@\"; do\n  [ \"$prev\" = \"-o\" ] && out=\"$arg\"\n  prev=\"$arg\"\ndone\ncase \"$*\" in *-bs-ast*) printf '\\n%s\\n' \"$arg\" > \"$out\";; esac\n",
+                log_path.to_string_lossy()
+            ),
+        );
+        let bsc_path = bsc_path.to_string_lossy().to_string();
+
+        let mut build_state = project.build_state("root");
+        let parsed = parse::generate_asts(
+            &mut build_state,
+            || (),
+            None,
+            &CompileContext::new(&bsc_path, "11.0.0", &CompileOptions::default()),
+        );
+        crate::build::deps::get_deps(&mut build_state, &AHashSet::new());
+        let (errors, _, _) = super::compile(
+            &mut build_state,
+            &AHashSet::new(),
+            || (),
+            |_| (),
+            &CompileContext::new(&bsc_path, "11.0.0", &CompileOptions::default()),
+        );
+        let log = std::fs::read_to_string(&log_path).unwrap();
+
+        assert!(parsed.is_ok());
+        assert_eq!(errors, "");
+        let calls = log.lines().collect::<Vec<&str>>();
+        let mlmap = calls
+            .iter()
+            .position(|call| call.ends_with("MyNamespace.mlmap"))
+            .unwrap();
+        let compiled_modules = calls
+            .iter()
+            .enumerate()
+            .filter(|(_, call)| call.contains("-bs-ns MyNamespace"))
+            .map(|(index, _)| index)
+            .collect::<Vec<usize>>();
+        assert_eq!(compiled_modules.len(), 2);
+        assert!(compiled_modules.iter().all(|index| *index > mlmap));
+    }
+
+    #[test]
+    fn should_build_a_package_without_its_namespace_when_asked() {
+        let project = TestProject::new("no-namespace");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "no-namespace-test", "sources": "src", "namespace": "MyNamespace" }"#,
+        );
+        project.write("src/A.res", "");
+        let project_root = project.path();
+
+        let packages = packages::make(
+            &None,
+            &project_root,
+            None,
+            true,
+            &[],
+            &packages::PackageOptions {
+                no_namespace: vec!["no-namespace-test".to_string()],
+                ..packages::PackageOptions::default()
+            },
+        );
+        let mut build_state =
+            BuildState::new(project_root.to_owned(), "no-namespace-test".to_string(), packages);
+        packages::parse_packages(&mut build_state, true);
+        let package = build_state.get_package("no-namespace-test").unwrap();
+        let args = compiler_args(
+            package,
+            package,
+            "A.ast",
+            build_state.get_module("A").unwrap(),
+            false,
+            &build_state.packages,
+            &CompileContext::new("bsc", "11.0.0", &CompileOptions::default()),
+        );
+        let mlmap_exists = project.join("lib/ocaml/MyNamespace.mlmap").exists();
+
+        assert_eq!(package.namespace.to_suffix(), None);
+        assert!(build_state.get_module("MyNamespace").is_none());
+        assert!(!mlmap_exists);
+        assert!(!args.contains(&"-bs-ns".to_string()));
+    }
+
+    #[test]
+    fn should_build_a_package_with_a_namespace_when_asked() {
+        let project = TestProject::new("force-namespace");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "force-namespace-test", "sources": "src", "namespace": false }"#,
+        );
+        project.write("src/A.res", "");
+        let project_root = project.path();
+
+        let packages = packages::make(
+            &None,
+            &project_root,
+            None,
+            true,
+            &[],
+            &packages::PackageOptions {
+                namespace: vec!["force-namespace-test".to_string()],
+                ..packages::PackageOptions::default()
+            },
+        );
+        let mut build_state = BuildState::new(
+            project_root.to_owned(),
+            "force-namespace-test".to_string(),
+            packages,
+        );
+        packages::parse_packages(&mut build_state, true);
+        let package = build_state.get_package("force-namespace-test").unwrap();
+        let mlmap_exists = project.join("lib/ocaml/ForceNamespaceTest.mlmap").exists();
+
+        assert_eq!(
+            package.namespace.to_suffix(),
+            Some("ForceNamespaceTest".to_string())
+        );
+        assert!(build_state.get_module("ForceNamespaceTest").is_some());
+        assert!(build_state.get_module("A-ForceNamespaceTest").is_some());
+        assert!(mlmap_exists);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_parse_the_modules_of_a_package_with_its_own_bsc() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let project = TestProject::new("package-bsc");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "bs-dependencies": ["dep"], "bsc": "bin/bsc.exe" }"#,
+        );
+        project.write(
+            "node_modules/dep/bsconfig.json",
+            r#"{ "name": "dep", "sources": "src", "bsc": "bin/bsc.exe" }"#,
+        );
+        project.write("src/A.res", "");
+        project.write("node_modules/dep/src/B.res", "");
+        // every bsc logs which files it got, and tells its own version
+        let log = project.join("bsc.log");
+        for (dir, version) in [
+            (project.root.to_owned(), "11.1.0"),
+            (project.join("node_modules/dep"), "10.1.4"),
+        ] {
+            let bsc_path = dir.join("bin/bsc.exe");
+            std::fs::create_dir_all(bsc_path.parent().unwrap()).unwrap();
+            std::fs::write(
+                &bsc_path,
+                format!(
+                    "#!/bin/sh\n[ \"$1\" = \"-v\" ] && echo \"ReScript {}\" && exit 0\nfor arg in \"$@\"; do\n  [ \"$prev\" = \"-o\" ] && out=\"$arg\"\n  prev=\"$arg\"\ndone\necho \"$0 $(basename \"$arg\")\" >> {}\ncase \"$*\" in *-bs-ast*) printf '\\n%s\\n' \"$arg\" > \"$out\";; esac\n",
+                    version,
+                    log.to_string_lossy()
+                ),
+            )
+            .unwrap();
+            std::fs::set_permissions(&bsc_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let project_root = project.path();
+        let mut build_state = project.build_state("root");
+        crate::build::logs::initialize(&build_state.packages);
+
+        let parsed = parse::generate_asts(
+            &mut build_state,
+            || (),
+            None,
+            &CompileContext::new("/missing/bsc.exe", "11.0.0", &CompileOptions::default()),
+        );
+        let versions = ["root", "dep"].map(|package_name| {
+            build_state.get_package(package_name).unwrap().get_version(
+                "/missing/bsc.exe",
+                "11.0.0",
+                &CompileOptions::default(),
+            )
+        });
+        let log = std::fs::read_to_string(&log).unwrap_or_default();
+
+        assert!(parsed.is_ok());
+        let root_bsc = format!("{}/bin/bsc.exe", project_root);
+        let dep_bsc = format!("{}/node_modules/dep/bin/bsc.exe", project_root);
+        let mut lines = log.lines().collect::<Vec<&str>>();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![format!("{} A.res", root_bsc), format!("{} B.res", dep_bsc)]
+        );
+        assert_eq!(versions, ["11.1.0".to_string(), "10.1.4".to_string()]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_build_a_package_without_source_files_without_its_namespace() {
+        let project = TestProject::new("empty-package");
+        std::fs::create_dir_all(project.join("node_modules/empty/src")).unwrap();
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "bs-dependencies": ["empty"] }"#,
+        );
+        project.write("src/A.res", "");
+        project.write(
+            "node_modules/empty/bsconfig.json",
+            r#"{ "name": "empty", "sources": "src", "namespace": "Empty" }"#,
+        );
+        // a bsc that writes an AST without dependencies
+        let bsc_path = project.write_script("bsc.exe", "for arg in \"$@\"; do\n  [ \"$prev\" = \"-o\" ] && out=\"$arg\"\n  prev=\"$arg\"\ndone\ncase \"$*\" in *-bs-ast*) printf '\\n%s\\n' \"$arg\" > \"$out\";; esac\n");
+        let bsc_path = bsc_path.to_string_lossy().to_string();
+
+        let mut build_state = project.build_state("root");
+        crate::build::logs::initialize(&build_state.packages);
+        let parsed = parse::generate_asts(
+            &mut build_state,
+            || (),
+            None,
+            &CompileContext::new(&bsc_path, "11.0.0", &CompileOptions::default()),
+        );
+        let (errors, _, _) = super::compile(
+            &mut build_state,
+            &AHashSet::new(),
+            || (),
+            |_| (),
+            &CompileContext::new(&bsc_path, "11.0.0", &CompileOptions::default()),
+        );
+        let mlmap_exists = project.join("node_modules/empty/lib/ocaml/Empty.mlmap").exists();
+
+        assert!(parsed.is_ok());
+        assert_eq!(errors, "");
+        assert!(build_state.get_module("Empty").is_none());
+        assert!(build_state.get_module("A").is_some());
+        assert!(!mlmap_exists);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_compile_a_virtual_source_that_depends_on_a_source_file() {
+        let project = TestProject::new("virtual-source");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "virtual-source-test", "sources": "src" }"#,
+        );
+        project.write("src/A.res", "let x = 1");
+        // a bsc that writes an AST with the modules that the source uses, and logs what it compiles
+        let log = project.join("bsc.log");
+        let bsc_path = project.write_script(
+            "bsc.exe",
+            &format!(
+                "for arg in \"$@\"; do\n  [ \"$prev\" = \"-o\" ] && out=\"$arg\"\n  prev=\"$arg\"\ndone\ncase \"$*\" in\n  *-bs-ast*) {{ echo; grep -o '[A-Z][A-Za-z]*[.]' \"$arg\" | tr -d .; echo \"$arg\"; }} > \"$out\";;\n  *) basename \"$arg\" >> {};;\nesac\n",
+                log.to_string_lossy()
+            ),
+        );
+        let bsc_path = bsc_path.to_string_lossy().to_string();
+
+        let project_root = project.path();
+        let virtual_sources = [packages::VirtualSource {
+            package_name: "virtual-source-test".to_string(),
+            module_name: "Generated".to_string(),
+            contents: "let y = A.x + 1".to_string(),
+        }];
+        let packages = packages::make(
+            &None,
+            &project_root,
+            None,
+            true,
+            &virtual_sources,
+            &packages::PackageOptions::default(),
+        );
+        let mut build_state = BuildState::new(
+            project_root.to_owned(),
+            "virtual-source-test".to_string(),
+            packages,
+        );
+        packages::parse_packages(&mut build_state, true);
+        crate::build::logs::initialize(&build_state.packages);
+        let parsed = parse::generate_asts(
+            &mut build_state,
+            || (),
+            None,
+            &CompileContext::new(&bsc_path, "11.0.0", &CompileOptions::default()),
+        );
+        crate::build::deps::get_deps(&mut build_state, &AHashSet::new());
+        let (errors, _, _) = super::compile(
+            &mut build_state,
+            &AHashSet::new(),
+            || (),
+            |_| (),
+            &CompileContext::new(&bsc_path, "11.0.0", &CompileOptions::default()),
+        );
+        let written =
+            std::fs::read_to_string(project.join("lib/bs/virtual/Generated.res")).unwrap_or_default();
+        let log = std::fs::read_to_string(&log).unwrap_or_default();
+
+        assert!(parsed.is_ok());
+        assert_eq!(errors, "");
+        assert_eq!(written, "let y = A.x + 1");
+        let generated = build_state.get_module("Generated").unwrap();
+        assert_eq!(generated.deps, AHashSet::from(["A".to_string()]));
+        match &generated.source_type {
+            SourceType::SourceFile(source_file) => {
+                assert_eq!(source_file.implementation.path, "lib/bs/virtual/Generated.res");
+                assert_eq!(source_file.virtual_contents, Some("let y = A.x + 1".to_string()));
+            }
+            SourceType::MlMap(_) => panic!("Generated is not a source file"),
+        }
+        assert_eq!(log.lines().collect::<Vec<&str>>(), vec!["A.ast", "Generated.ast"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_compile_in_the_order_of_the_dependency_rules() {
+        let project = TestProject::new("dependency-rules");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "dependency-rules-test", "sources": "src" }"#,
+        );
+        // in the source, Codegen depends on Glue
+        project.write("src/Codegen.res", "let x = Glue.y");
+        project.write("src/Glue.res", "let y = 1");
+        // a bsc that writes an AST with the modules that the source uses, and logs what it compiles
+        let log = project.join("bsc.log");
+        let bsc_path = project.write_script(
+            "bsc.exe",
+            &format!(
+                "for arg in \"$@\"; do\n  [ \"$prev\" = \"-o\" ] && out=\"$arg\"\n  prev=\"$arg\"\ndone\ncase \"$*\" in\n  *-bs-ast*) {{ echo; grep -o '[A-Z][A-Za-z]*[.]' \"$arg\" | tr -d .; echo \"$arg\"; }} > \"$out\";;\n  *) basename \"$arg\" >> {};;\nesac\n",
+                log.to_string_lossy()
+            ),
+        );
+        let bsc_path = bsc_path.to_string_lossy().to_string();
+        let rules = serde_json::from_str::<Vec<crate::rewatch_config::DependencyRule>>(
+            r#"[
+                { "module": "^Codegen$", "remove": "^Glue$" },
+                { "module": "^Glue$", "add": ["Codegen", "Missing"] }
+            ]"#,
+        )
+        .unwrap();
+
+        let mut build_state = project.build_state("dependency-rules-test");
+        crate::build::logs::initialize(&build_state.packages);
+        let parsed = parse::generate_asts(
+            &mut build_state,
+            || (),
+            None,
+            &CompileContext::new(&bsc_path, "11.0.0", &CompileOptions::default()),
+        );
+        build_state.dependency_rules = crate::build::deps::compile_dependency_rules(&rules).unwrap();
+        crate::build::deps::get_deps(&mut build_state, &AHashSet::new());
+        let (errors, _, _) = super::compile(
+            &mut build_state,
+            &AHashSet::new(),
+            || (),
+            |_| (),
+            &CompileContext::new(&bsc_path, "11.0.0", &CompileOptions::default()),
+        );
+        let log = std::fs::read_to_string(&log).unwrap_or_default();
+
+        assert!(parsed.is_ok());
+        assert_eq!(errors, "");
+        assert_eq!(build_state.get_module("Codegen").unwrap().deps, AHashSet::new());
+        assert_eq!(
+            build_state.get_module("Glue").unwrap().deps,
+            AHashSet::from(["Codegen".to_string()])
+        );
+        assert_eq!(
+            log.lines().collect::<Vec<&str>>(),
+            vec!["Codegen.ast", "Glue.ast"]
+        );
+    }
+
+    #[test]
+    fn should_include_the_standard_library_after_the_dependencies() {
+        let project = TestProject::new("stdlib");
+        std::fs::create_dir_all(project.join("node_modules/rescript/lib/ocaml")).unwrap();
+        std::fs::create_dir_all(project.join("stdlib")).unwrap();
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "");
+        let build_state = project.build_state("root");
+        let package = build_state.get_package("root").unwrap();
+        let args = |compile_options: &CompileOptions| {
+            compiler_args(
+                package,
+                package,
+                "A.ast",
+                build_state.get_module("A").unwrap(),
+                false,
+                &build_state.packages,
+                &CompileContext::new("bsc", "11.0.0", compile_options),
+            )
+        };
+        let get_stdlib_include = |args: &Vec<String>| {
+            let includes = args
+                .iter()
+                .enumerate()
+                .filter(|(_, arg)| *arg == "-I")
+                .map(|(index, _)| args[index + 1].to_owned())
+                .collect::<Vec<String>>();
+            // the last include, right before the AST
+            assert_eq!(args[args.len() - 3], "-I");
+            includes.last().unwrap().to_owned()
+        };
+
+        let default_include = get_stdlib_include(&args(&CompileOptions::default()));
+        let overridden_include = get_stdlib_include(&args(&CompileOptions {
+            stdlib_path: Some(project.join("stdlib").to_string_lossy().to_string()),
+            ..CompileOptions::default()
+        }));
+
+        assert!(std::path::Path::new(&default_include).is_dir());
+        assert!(std::path::Path::new(&overridden_include).is_dir());
+        assert!(default_include.ends_with("node_modules/rescript/lib/ocaml"));
+        assert!(overridden_include.ends_with("stdlib"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_not_compile_the_dependents_when_the_interface_did_not_change() {
+        use std::time::SystemTime;
+
+        let project = TestProject::new("early-cutoff");
+        std::fs::create_dir_all(project.join("lib/ocaml")).unwrap();
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "let a = 2");
+        project.write("src/B.res", "let b = A.a");
+        // a bsc that logs what it compiles, and always writes the same interface
+        let log_path = project.join("bsc.log");
+        let bsc_path = project.write_script(
+            "bsc.exe",
+            &format!(
+                "for arg in \"$@\"; do last=\"$arg\"; done\nname=$(basename \"$last\" .ast)\necho $name >> {}\nprintf 'val a : int' > {}/$name.cmi\ntouch {}/$name.cmt\n",
+                log_path.to_string_lossy(),
+                project.join("lib/ocaml").to_string_lossy(),
+                project.join("lib/ocaml").to_string_lossy()
+            ),
+        );
+        let bsc_path = bsc_path.to_string_lossy().to_string();
+        // the outputs of the previous build
+        let previous_build = SystemTime::now() - Duration::from_secs(60);
+        for (asset, modified) in [
+            ("A.cmi", previous_build),
+            ("A.cmt", previous_build),
+            ("B.cmi", previous_build),
+            ("B.cmt", previous_build + Duration::from_secs(1)),
+        ] {
+            let path = project
+                .join("lib/ocaml")
+                .join(asset)
+                .to_string_lossy()
+                .to_string();
+            std::fs::write(&path, "val a : int").unwrap();
+            crate::helpers::set_modified(&path, modified).unwrap();
+        }
+
+        let compile_build = |a_changed: bool| {
+            let mut build_state = project.build_state("root");
+            for (module_name, module) in build_state.modules.iter_mut() {
+                let modified = |extension: &str| {
+                    std::fs::metadata(project.join(format!("lib/ocaml/{}.{}", module_name, extension)))
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                };
+                module.last_compiled_cmi = modified("cmi");
+                module.last_compiled_cmt = modified("cmt");
+                module.compile_dirty = module_name == "A" && a_changed;
+            }
+            build_state
+                .modules
+                .get_mut("B")
+                .unwrap()
+                .deps
+                .insert("A".to_string());
+            build_state
+                .modules
+                .get_mut("A")
+                .unwrap()
+                .dependents
+                .insert("B".to_string());
+            let (errors, _, _) = super::compile(
+                &mut build_state,
+                &AHashSet::new(),
+                || (),
+                |_| (),
+                &CompileContext::new(&bsc_path, "11.0.0", &CompileOptions::default()),
+            );
+            errors
+        };
+        // only the body of A changed
+        let errors = compile_build(true);
+        let a_cmi_modified = std::fs::metadata(project.join("lib/ocaml/A.cmi"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        let next_errors = compile_build(false);
+        let log = std::fs::read_to_string(&log_path).unwrap();
+
+        assert_eq!(errors, "");
+        assert_eq!(next_errors, "");
+        assert_eq!(log, "A\n");
+        assert_eq!(a_cmi_modified, previous_build);
+    }
+
+    #[test]
+    fn should_resolve_the_dependencies_and_the_standard_library_from_a_hoisted_node_modules() {
+        let workspace = TestProject::new("hoisted");
+        std::fs::create_dir_all(workspace.join("node_modules/rescript/lib/ocaml")).unwrap();
+        workspace.write(
+            "packages/app/bsconfig.json",
+            r#"{ "name": "app", "sources": "src", "bs-dependencies": ["dep"] }"#,
+        );
+        workspace.write("packages/app/src/App.res", "");
+        workspace.write(
+            "node_modules/dep/bsconfig.json",
+            r#"{ "name": "dep", "sources": "src" }"#,
+        );
+        workspace.write("node_modules/dep/src/Dep.res", "");
+
+        let project_root = workspace.join("packages/app").to_string_lossy().to_string();
+        let packages = packages::make(
+            &None,
+            &project_root,
+            None,
+            true,
+            &[],
+            &packages::PackageOptions::default(),
+        );
+        let dep_path = packages.get("dep").map(|dep| dep.path.to_owned());
+        let stdlib_path = get_stdlib_path(packages.get("app").unwrap(), &CompileOptions::default());
+
+        assert_eq!(
+            dep_path,
+            Some(workspace.join("node_modules/dep").to_string_lossy().to_string())
+        );
+        assert!(stdlib_path.unwrap().ends_with("/node_modules/rescript/lib/ocaml"));
+    }
+
+    #[test]
+    fn should_only_compile_with_debug_info_in_the_debug_profile() {
+        let project = TestProject::new("profile");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "");
+        let build_state = project.build_state("root");
+        let package = build_state.get_package("root").unwrap();
+        let get_args = |compile_options: &CompileOptions| {
+            compiler_args(
+                package,
+                package,
+                "A.ast",
+                build_state.get_module("A").unwrap(),
+                false,
+                &build_state.packages,
+                &CompileContext::new("bsc", "11.0.0", compile_options),
+            )
+        };
+        let args = get_args(&CompileOptions::default());
+        let release_args = get_args(&CompileOptions {
+            release: true,
+            ..CompileOptions::default()
+        });
+
+        // the debug profile is the default
+        assert!(args.contains(&"-bs-g".to_string()));
+        assert!(!release_args.contains(&"-bs-g".to_string()));
+        assert_eq!(get_profile_args(false), vec!["-bs-g"]);
+        assert_eq!(get_profile_args(true), Vec::<String>::new());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_report_type_errors_without_writing_javascript_when_only_type_checking() {
+        let project = TestProject::new("check-only");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "let a: string = 1");
+        // a bsc that reports a type error, and only writes the JavaScript for a package output
+        let bsc_path = project.write_script(
+            "bsc.exe",
+            &format!(
+                "case \"$*\" in *-bs-package-output*) touch {};; esac\nprintf '\\n  We'\\''ve found a bug for you!\\n  {}:1:17\\n\\n  This has type: int\\n\\n' >&2\nexit 2\n",
+                project.join("src/A.mjs").to_string_lossy(),
+                project.join("src/A.res").to_string_lossy()
+            ),
+        );
+
+        let build_state = project.build_state("root");
+        let package = build_state.get_package("root").unwrap();
+        let compile = |check_only: bool| {
+            compile_file(
+                package,
+                package,
+                "src/A.ast",
+                build_state.get_module("A").unwrap(),
+                false,
+                &build_state.packages,
+                &CompileContext::new(
+                    &bsc_path.to_string_lossy(),
+                    "11.0.0",
+                    &CompileOptions {
+                        check_only,
+                        ..CompileOptions::default()
+                    },
+                ),
+            )
+        };
+        let result = compile(true);
+        let checked_mjs = project.join("src/A.mjs").exists();
+        let _ = compile(false);
+        let compiled_mjs = project.join("src/A.mjs").exists();
+
+        let (diagnostics, _) = diagnostics::parse_diagnostics(&result.unwrap_err());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, diagnostics::Severity::Error);
+        assert!(!checked_mjs);
+        assert!(compiled_mjs);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_fail_when_bsc_exits_with_an_error_code_without_output() {
+        let project = TestProject::new("bsc-exit");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "");
+        // a bsc that crashes without printing anything
+        let bsc_path = project.write_script("bsc.exe", "exit 139\n");
+
+        let build_state = project.build_state("root");
+        let package = build_state.get_package("root").unwrap();
+        let result = compile_file(
+            package,
+            package,
+            "src/A.ast",
+            build_state.get_module("A").unwrap(),
+            false,
+            &build_state.packages,
+            &CompileContext::new(&bsc_path.to_string_lossy(), "11.0.0", &CompileOptions::default()),
+        );
+
+        let error = result.unwrap_err();
+        let (diagnostics, _) = diagnostics::parse_diagnostics(&error);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, diagnostics::Severity::Error);
+        assert_eq!(
+            diagnostics[0].message,
+            "bsc exited with code 139 while compiling src/A.res"
+        );
+    }
+
+    #[test]
+    fn should_include_the_build_folder_of_the_dependencies() {
+        let project = TestProject::new("install");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "bs-dependencies": ["dep"] }"#,
+        );
+        project.write("src/A.res", "");
+        project.write(
+            "node_modules/dep/bsconfig.json",
+            r#"{ "name": "dep", "sources": "src" }"#,
+        );
+        project.write("node_modules/dep/src/Dep.res", "");
+
+        let build_state = project.build_state("root");
+        let root_package = build_state.get_package("root").unwrap();
+        let dep_package = build_state.get_package("dep").unwrap();
+        let args = compiler_args(
+            root_package,
+            root_package,
+            "src/A.ast",
+            build_state.get_module("A").unwrap(),
+            false,
+            &build_state.packages,
+            &CompileContext::new("bsc", "11.0.0", &CompileOptions::default()),
+        );
+        let dep_build_path = dep_package.get_build_path();
+        let is_dep_build_path_created = std::path::Path::new(&dep_build_path).is_dir();
+
+        assert!(args
+            .windows(2)
+            .any(|arg| arg[0] == "-I" && arg[1] == dep_build_path));
+        assert!(dep_build_path.ends_with("/node_modules/dep/lib/ocaml"));
+        assert!(is_dep_build_path_created);
+    }
+
+    #[test]
+    fn should_include_the_build_dir_of_a_dependency() {
+        let project = TestProject::new("build-dir");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "bs-dependencies": ["dep"] }"#,
+        );
+        project.write("src/A.res", "");
+        // the dependency was built into lib/bs
+        project.write(
+            "node_modules/dep/bsconfig.json",
+            r#"{ "name": "dep", "sources": "src", "build-dir": "lib/bs" }"#,
+        );
+        project.write("node_modules/dep/src/Dep.res", "");
+
+        let build_state = project.build_state("root");
+        let root_package = build_state.get_package("root").unwrap();
+        let dep_package = build_state.get_package("dep").unwrap();
+        let args = compiler_args(
+            root_package,
+            root_package,
+            "src/A.ast",
+            build_state.get_module("A").unwrap(),
+            false,
+            &build_state.packages,
+            &CompileContext::new("bsc", "11.0.0", &CompileOptions::default()),
+        );
+        let dep_build_path = dep_package.get_build_path();
+        let root_build_path = root_package.get_build_path();
+
+        assert!(dep_build_path.ends_with("/node_modules/dep/lib/bs"));
+        assert!(root_build_path.ends_with("/lib/ocaml"));
+        assert!(args
+            .windows(2)
+            .any(|arg| arg[0] == "-I" && arg[1] == dep_build_path));
+        assert!(!args
+            .iter()
+            .any(|arg| arg.ends_with("/node_modules/dep/lib/ocaml")));
+    }
+
+    #[test]
+    fn should_compile_the_modules_of_a_namespace_under_their_internal_name() {
+        let project = TestProject::new("bs-ns");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "namespace": "Dep" }"#,
+        );
+        project.write("src/Internal.res", "");
+
+        let build_state = project.build_state("root");
+        let package = build_state.get_package("root").unwrap();
+        let module = build_state.get_module("Internal-Dep");
+        let args = module.map(|module| {
+            compiler_args(
+                package,
+                package,
+                "src/Internal.ast",
+                module,
+                false,
+                &build_state.packages,
+                &CompileContext::new("bsc", "11.0.0", &CompileOptions::default()),
+            )
+        });
+
+        // bsc names the compiler assets Internal-Dep, a module that can't be referenced from source
+        // code, so the module is only reachable through the namespace
+        assert!(args.unwrap().windows(2).any(|arg| arg == ["-bs-ns", "Dep"]));
+        assert!(build_state.get_module("Internal").is_none());
+    }
+
+    #[test]
+    fn should_deduplicate_include_paths_preserving_order() {
+        let paths = vec![
+            "/a/lib/ocaml",
+            "/b/lib/ocaml",
+            "/a/lib/ocaml",
+            "/c/lib/ocaml",
+            "/b/lib/ocaml",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<String>>();
+
+        assert_eq!(
+            include_args(paths),
+            vec!["-I", "/a/lib/ocaml", "-I", "/b/lib/ocaml", "-I", "/c/lib/ocaml"]
+        )
+    }
+
+    #[test]
+    fn should_resolve_the_relative_include_paths_from_the_build_folder() {
+        let project = TestProject::new("relative-includes");
+        std::fs::create_dir_all(project.join("lib/ocaml")).unwrap();
+        std::fs::create_dir_all(project.join("node_modules/dep/lib/ocaml")).unwrap();
+        let build_path = project.join("lib/ocaml");
+        let dep_path = project
+            .join("node_modules/dep/lib/ocaml")
+            .to_string_lossy()
+            .to_string();
+        let missing_path = project
+            .join("node_modules/missing/lib/ocaml")
+            .to_string_lossy()
+            .to_string();
+
+        let paths = get_relative_include_paths(
+            vec![dep_path.to_owned(), missing_path.to_owned()],
+            &build_path.to_string_lossy(),
+        );
+        let resolved = build_path.join(&paths[0]).canonicalize().unwrap();
+        let dep_path = std::path::PathBuf::from(dep_path).canonicalize().unwrap();
+
+        assert_eq!(
+            paths,
+            vec!["../../node_modules/dep/lib/ocaml".to_string(), missing_path]
+        );
+        assert_eq!(resolved, dep_path);
+    }
+
+    #[test]
+    fn should_return_no_include_args_without_dependencies() {
+        assert_eq!(include_args(vec![]), Vec::<String>::new())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_build_the_same_artifacts_every_time() {
+        use std::path::Path;
+
+        fn list_files(dir: &Path, root: &Path, files: &mut Vec<String>) {
+            for entry in std::fs::read_dir(dir).unwrap().flatten() {
+                let path = entry.path();
+                match path.is_dir() {
+                    true => list_files(&path, root, files),
+                    false => files.push(path.strip_prefix(root).unwrap().to_string_lossy().to_string()),
+                }
+            }
+        }
+
+        let project = TestProject::new("deterministic");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": { "dir": "src", "subdirs": true } }"#,
+        );
+        // a graph of modules in a few folders, where M<i> uses M<i/2> and M<i-1>, every fifth module
+        // has an interface, and some modules fail to compile
+        let failing = [7, 20, 33, 46, 59];
+        for i in 0..60 {
+            let dir = project.join(["src/a", "src/b", "src/c"][i % 3]);
+            std::fs::create_dir_all(&dir).unwrap();
+            let source = match i {
+                0 => "let x = 1".to_string(),
+                i => format!("let x = M{}.x + M{}.x", i / 2, i - 1),
+            };
+            std::fs::write(dir.join(format!("M{}.res", i)), source).unwrap();
+            if i % 5 == 0 {
+                std::fs::write(dir.join(format!("M{}.resi", i)), "let x: int").unwrap();
+            }
+        }
+        // a bsc that writes an AST with the modules that the source uses, and the compiler assets
+        let bsc_path = project.write_script(
+            "bsc.exe",
+            &format!(
+                "for arg in \"$@\"; do\n  [ \"$prev\" = \"-o\" ] && out=\"$arg\"\n  prev=\"$arg\"\ndone\nname=$(basename \"$arg\")\nname=${{name%.*}}\ncase \"$*\" in\n  *-bs-ast*) {{ echo; grep -o 'M[0-9]*[.]' \"$arg\" | tr -d .; echo \"$arg\"; }} > \"$out\";;\n  *) case \"$name\" in {}) exit 139;; esac\n    case \"$arg\" in\n      *.iast) touch \"$name.cmi\" \"$name.cmti\";;\n      *) touch \"$name.cmi\" \"$name.cmj\" \"$name.cmt\";;\n    esac;;\nesac\n",
+                failing.map(|i| format!("M{}", i)).join("|")
+            ),
+        );
+        let bsc_path = bsc_path.to_string_lossy().to_string();
+
+        // every build state has its own (randomly seeded) hash maps, so the modules are parsed and
+        // compiled in another order in every build
+        let build = || {
+            let _ = std::fs::remove_dir_all(project.join("lib"));
+            let mut build_state = project.build_state("root");
+            crate::build::logs::initialize(&build_state.packages);
+            let parsed = parse::generate_asts(
+                &mut build_state,
+                || (),
+                None,
+                &CompileContext::new(&bsc_path, "11.0.0", &CompileOptions::default()),
+            );
+            let raw_deps = deps::get_deps(&mut build_state, &AHashSet::new());
+            deps::write_deps_files(&build_state, &raw_deps, "11.0.0");
+            let (errors, _, compiled) = super::compile(
+                &mut build_state,
+                &AHashSet::new(),
+                || (),
+                |_| (),
+                &CompileContext::new(&bsc_path, "11.0.0", &CompileOptions::default()),
+            );
+
+            let mut modules = build_state
+                .modules
+                .iter()
+                .map(|(module_name, module)| {
+                    let mut module_deps = module.deps.iter().cloned().collect::<Vec<String>>();
+                    module_deps.sort();
+                    let compile_state = match &module.source_type {
+                        SourceType::SourceFile(source_file) => {
+                            Some(source_file.implementation.compile_state.to_owned())
+                        }
+                        SourceType::MlMap(_) => None,
+                    };
+                    format!("{} {:?} {:?}", module_name, module_deps, compile_state)
+                })
+                .collect::<Vec<String>>();
+            modules.sort();
+            let mut artifacts = vec![];
+            list_files(&project.join("lib"), &project.root, &mut artifacts);
+            artifacts.sort();
+            let deps_file = std::fs::read(project.join("lib/bs").join(deps::DEPS_FILE)).unwrap();
+            // without the times at which the build started and finished
+            let log = std::fs::read_to_string(project.join("lib/bs/.compiler.log"))
+                .unwrap()
+                .lines()
+                .filter(|line| !line.starts_with('#'))
+                .collect::<Vec<&str>>()
+                .join("\n");
+            (parsed, errors, compiled, modules, artifacts, deps_file, log)
+        };
+
+        let builds = (0..10).map(|_| build()).collect::<Vec<_>>();
+
+        let (parsed, errors, _, _, artifacts, _, log) = &builds[0];
+        assert!(parsed.is_ok());
+        assert!(artifacts.contains(&"lib/ocaml/M1.cmj".to_string()));
+        assert!(!artifacts.contains(&"lib/ocaml/M7.cmj".to_string()));
+        // every later module depends on M7 through M<i-1>, so M7 is the only one that fails
+        assert!(errors.contains("src/b/M7.res"));
+        assert!(log.contains("src/b/M7.res"));
+        assert!(!errors.contains("M20.res"));
+        for build in &builds[1..] {
+            assert_eq!(build, &builds[0]);
+        }
+    }
+
+    #[test]
+    fn should_pass_the_arguments_in_a_response_file_with_hundreds_of_dependencies() {
+        use std::path::Path;
+
+        let dependencies = (0..400)
+            .map(|i| {
+                format!(
+                    "C:\\Users\\someone\\projects\\monorepo\\node_modules\\@scope\\package-{}\\lib\\ocaml",
+                    i
+                )
+            })
+            .collect::<Vec<String>>();
+        let project = TestProject::new("response-file");
+        let response_file_path = project.join("A.ast.rsp").to_string_lossy().to_string();
+
+        let few_args = include_args(dependencies[0..10].to_vec());
+        let many_args = [include_args(dependencies.to_owned()), vec!["A.ast".to_string()]].concat();
+        let few_to_mjs_args = helpers::get_command_line_args(
+            "bsc.exe",
+            few_args.to_owned(),
+            &response_file_path,
+            Some(MAX_WINDOWS_COMMAND_LINE_LENGTH),
+        )
+        .unwrap();
+        let few_response_file_exists = Path::new(&response_file_path).exists();
+        let many_to_mjs_args = helpers::get_command_line_args(
+            "bsc.exe",
+            many_args.to_owned(),
+            &response_file_path,
+            Some(MAX_WINDOWS_COMMAND_LINE_LENGTH),
+        )
+        .unwrap();
+        let response_file = std::fs::read_to_string(&response_file_path).unwrap();
+
+        assert!(get_command_line_length("bsc.exe", &few_args) < MAX_WINDOWS_COMMAND_LINE_LENGTH);
+        assert!(get_command_line_length("bsc.exe", &many_args) > MAX_WINDOWS_COMMAND_LINE_LENGTH);
+        assert_eq!(few_to_mjs_args, few_args);
+        assert!(!few_response_file_exists);
+        assert_eq!(many_to_mjs_args, vec![format!("@{}", response_file_path)]);
+        let lines = response_file.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 801);
+        for dependency in &dependencies {
+            assert!(lines.windows(2).any(|pair| pair == ["-I", dependency.as_str()]));
+        }
+        assert_eq!(lines.last(), Some(&"A.ast"));
+    }
+}
@@ -1,38 +1,83 @@
+use super::ast;
 use super::build_types::*;
 use super::packages;
 use crate::helpers;
-use ahash::AHashSet;
+use crate::rewatch_config;
+use ahash::{AHashMap, AHashSet};
+use log::error;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::SystemTime;
 
-fn get_dep_modules(
-    ast_file: &str,
+/// A rule of the `dependency-rules` of rewatch.json, with its patterns compiled.
+#[derive(Debug, Clone)]
+pub struct DependencyRule {
+    module: regex::Regex,
+    add: Vec<String>,
+    remove: Option<regex::Regex>,
+}
+
+/// Compiles the `dependency-rules` of rewatch.json, which change the dependencies of the modules,
+/// ie. to add the dependencies of generated code that don't show up in the AST (see
+/// `apply_dependency_rules`). Returns an error when a pattern isn't a valid regex.
+pub fn compile_dependency_rules(
+    rules: &[rewatch_config::DependencyRule],
+) -> Result<Vec<DependencyRule>, String> {
+    let compile = |pattern: &str| {
+        regex::Regex::new(pattern).map_err(|e| {
+            format!(
+                "Invalid pattern {} in the dependency-rules of rewatch.json: {}",
+                pattern, e
+            )
+        })
+    };
+    rules
+        .iter()
+        .map(|rule| {
+            Ok(DependencyRule {
+                module: compile(&rule.module)?,
+                add: rule.add.to_owned().unwrap_or_default(),
+                remove: rule.remove.as_deref().map(compile).transpose()?,
+            })
+        })
+        .collect()
+}
+
+/// Applies the rules that match the name of a module to its dependencies, in order: a rule first
+/// removes the deps that match `remove`, then adds the modules of `add` that are in the build. The
+/// rules run after the deps are resolved, so they use the names of the modules in the build (with
+/// the namespace, ie. `Module-Namespace`). As the deps decide the compile order, an added dep is
+/// compiled before the module (and the module again when the dep changes), and a removed dep isn't
+/// waited for anymore. An added dep that depends on the module is a cycle, which fails the build.
+fn apply_dependency_rules(
+    module_name: &str,
+    deps: &mut AHashSet<String>,
+    rules: &[DependencyRule],
+    is_module: impl Fn(&str) -> bool,
+) {
+    for rule in rules.iter().filter(|rule| rule.module.is_match(module_name)) {
+        if let Some(remove) = &rule.remove {
+            deps.retain(|dep| !remove.is_match(dep));
+        }
+        deps.extend(rule.add.iter().filter(|dep| is_module(dep)).cloned());
+    }
+}
+
+fn read_dep_modules(ast_file: &str) -> AHashSet<String> {
+    match ast::get_dep_modules(ast_file) {
+        Ok(modules) => modules.into_iter().collect(),
+        Err(e) => panic!("Could not read file {}. \n{:?}", ast_file, e),
+    }
+}
+
+fn resolve_dep_modules(
+    deps: &AHashSet<String>,
     namespace: Option<String>,
     package_modules: &AHashSet<String>,
     valid_modules: &AHashSet<String>,
 ) -> AHashSet<String> {
-    let mut deps = AHashSet::new();
-    if let Ok(lines) = helpers::read_lines(ast_file.to_string()) {
-        // we skip the first line with is some null characters
-        // the following lines in the AST are the dependency modules
-        // we stop when we hit a line that starts with a "/", this is the path of the file.
-        // this is the point where the dependencies end and the actual AST starts
-        for line in lines.skip(1) {
-            match line {
-                Ok(line) => {
-                    let line = line.trim().to_string();
-                    if line.starts_with('/') {
-                        break;
-                    } else if !line.is_empty() {
-                        deps.insert(line);
-                    }
-                }
-                Err(_) => (),
-            }
-        }
-    } else {
-        panic!("Could not read file {}", ast_file);
-    }
-
     return deps
         .iter()
         .map(|dep| {
@@ -67,38 +112,157 @@ fn get_dep_modules(
         .collect::<AHashSet<String>>();
 }
 
-pub fn get_deps(build_state: &mut BuildState, deleted_modules: &AHashSet<String>) {
-    let all_mod = &build_state.module_names.union(deleted_modules).cloned().collect();
+/// The dependency modules as they are listed in the ASTs of a module, before they are resolved to
+/// the modules of the build (so without namespaces applied).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RawDeps {
+    pub implementation: AHashSet<String>,
+    pub interface: Option<AHashSet<String>>,
+}
+
+/// Returns the raw deps of a module. The ASTs that were generated again in this build are read,
+/// for the other ASTs we take the deps from the deps file of the previous build, so an unchanged
+/// module doesn't touch the filesystem. The cached deps are only used when they belong to the same
+/// version of the source file (the ASTs can be newer than the deps file when the previous build
+/// stopped after parsing).
+///
+/// So the deps come from the deps file first, and from the AST otherwise. The `.cmt` of a compiled
+/// module isn't a source of deps: its imports are the cmi files that the type checker loaded, which
+/// include the modules that are only reached through types, the mlmaps of the namespaces and the
+/// stdlib, so they would add dependencies (and cycles) that aren't in the source. It's also the
+/// marshaled typed tree of the compiler, so its format changes with the version of the compiler.
+/// The AST of an unchanged module can't be stale either, as it's generated again when the source
+/// changes.
+fn get_raw_deps(
+    source_file: &SourceFile,
+    ast_path: &str,
+    iast_path: Option<String>,
+    cached: Option<&ModuleDeps>,
+    read_dep_modules: impl Fn(&str) -> AHashSet<String>,
+) -> RawDeps {
+    let cached = cached.filter(|cached| cached.file_path == source_file.implementation.path);
+    let implementation = match cached {
+        // there is no AST of the implementation
+        _ if source_file.interface_only => AHashSet::new(),
+        Some(cached)
+            if !source_file.implementation.dirty
+                && cached.last_modified == Some(source_file.implementation.last_modified) =>
+        {
+            cached.ast_raw_deps.iter().cloned().collect()
+        }
+        _ => read_dep_modules(ast_path),
+    };
+
+    let interface = source_file
+        .interface
+        .as_ref()
+        .zip(iast_path)
+        .map(
+            |(interface, iast_path)| match cached.and_then(|cached| cached.iast_raw_deps.as_ref()) {
+                Some(iast_raw_deps)
+                    if !interface.dirty
+                        && cached.and_then(|cached| cached.interface_path.as_ref())
+                            == Some(&interface.path)
+                        && cached.and_then(|cached| cached.interface_last_modified)
+                            == Some(interface.last_modified) =>
+                {
+                    iast_raw_deps.iter().cloned().collect()
+                }
+                _ => read_dep_modules(&iast_path),
+            },
+        );
+
+    RawDeps {
+        implementation,
+        interface,
+    }
+}
+
+/// Reads the deps file of the previous build of a package. Returns an empty map when there is no
+/// (valid) deps file, or when it was written in another format, so all ASTs are read.
+fn read_deps_file(package: &packages::Package) -> BTreeMap<String, ModuleDeps> {
+    std::fs::read_to_string(std::path::Path::new(&package.get_bs_build_path()).join(DEPS_FILE))
+        .ok()
+        .and_then(|json| serde_json::from_str::<DepsFile>(&json).ok())
+        .filter(|deps_file| deps_file.version == DEPS_FILE_VERSION)
+        .map(|deps_file| deps_file.modules)
+        .unwrap_or_default()
+}
+
+/// Reads the dependencies of the modules of all packages from the deps files of the previous
+/// build, without reading any ASTs.
+pub fn read_deps_files(build_state: &BuildState) -> AHashMap<String, AHashSet<String>> {
+    build_state
+        .packages
+        .values()
+        .flat_map(|package| read_deps_file(package).into_iter())
+        .map(|(module_name, module_deps)| (module_name, module_deps.ast_deps.into_iter().collect()))
+        .collect()
+}
+
+/// Reads the hashes of the source files of the modules of all packages from the deps files of the
+/// previous build.
+pub fn read_source_hashes(build_state: &BuildState) -> AHashMap<String, String> {
     build_state
+        .packages
+        .values()
+        .flat_map(|package| read_deps_file(package).into_iter())
+        .filter_map(|(module_name, module_deps)| Some((module_name, module_deps.source_hash?)))
+        .collect()
+}
+
+/// Collects the dependencies of all modules and sets the `deps` and `dependents`. Returns the raw
+/// deps of the modules, so they can be cached in the deps files (see `write_deps_files`).
+pub fn get_deps(
+    build_state: &mut BuildState,
+    deleted_modules: &AHashSet<String>,
+) -> AHashMap<String, RawDeps> {
+    let all_mod = &build_state.module_names.union(deleted_modules).cloned().collect();
+    let dependency_rules = &build_state.dependency_rules;
+    let deps_files = build_state
+        .packages
+        .par_iter()
+        .filter(|(_, package)| package.modules.is_some())
+        .map(|(package_name, package)| (package_name.to_owned(), read_deps_file(package)))
+        .collect::<Vec<(String, BTreeMap<String, ModuleDeps>)>>()
+        .into_iter()
+        .collect::<AHashMap<String, BTreeMap<String, ModuleDeps>>>();
+
+    let module_deps = build_state
         .modules
         .par_iter()
-        .map(|(module_name, module)| match &module.source_type {
-            SourceType::MlMap(_) => (module_name.to_string(), module.deps.to_owned()),
+        .filter_map(|(module_name, module)| match &module.source_type {
+            SourceType::MlMap(_) => None,
             SourceType::SourceFile(source_file) => {
                 let package = build_state
                     .get_package(&module.package_name)
                     .expect("Package not found");
-                let ast_path = package.get_ast_path(&source_file.implementation.path);
+                let raw_deps = get_raw_deps(
+                    source_file,
+                    &package.get_ast_path(&source_file.implementation.path),
+                    source_file
+                        .interface
+                        .as_ref()
+                        .map(|interface| package.get_iast_path(&interface.path)),
+                    deps_files
+                        .get(&module.package_name)
+                        .and_then(|deps_file| deps_file.get(module_name)),
+                    read_dep_modules,
+                );
 
-                let mut deps = get_dep_modules(
-                    &ast_path,
+                let mut deps = resolve_dep_modules(
+                    &raw_deps.implementation,
                     package.namespace.to_suffix(),
-                    &package.modules.as_ref().unwrap(),
+                    package.modules.as_ref().unwrap(),
                     all_mod,
                 );
-
-                match &source_file.interface {
-                    Some(interface) => {
-                        let iast_path = package.get_iast_path(&interface.path);
-
-                        deps.extend(get_dep_modules(
-                            &iast_path,
-                            package.namespace.to_suffix(),
-                            &package.modules.as_ref().unwrap(),
-                            all_mod,
-                        ))
-                    }
-                    None => (),
+                if let Some(interface_deps) = &raw_deps.interface {
+                    deps.extend(resolve_dep_modules(
+                        interface_deps,
+                        package.namespace.to_suffix(),
+                        package.modules.as_ref().unwrap(),
+                        all_mod,
+                    ))
                 }
                 match &package.namespace {
                     packages::Namespace::NamespaceWithEntry { namespace: _, entry }
@@ -108,13 +272,36 @@ pub fn get_deps(build_state: &mut BuildState, deleted_modules: &AHashSet<String>
                     }
                     _ => (),
                 }
+                apply_dependency_rules(module_name, &mut deps, dependency_rules, |dep| {
+                    build_state.modules.contains_key(dep)
+                });
                 deps.remove(module_name);
-                (module_name.to_string(), deps)
+                Some((module_name.to_string(), deps, raw_deps))
             }
         })
-        .collect::<Vec<(String, AHashSet<String>)>>()
+        .collect::<Vec<(String, AHashSet<String>, RawDeps)>>();
+
+    // The mlmap of a namespace depends on the modules of the namespace (its deps are set when the
+    // packages are parsed), not the other way around: the mlmap is compiled while parsing (see
+    // `parse::generate_asts`), before any module is compiled. So the namespace is a dependent of its
+    // modules, and it's only marked as compiled after them.
+    let mlmap_deps = build_state
+        .modules
+        .iter()
+        .filter(|(_, module)| matches!(module.source_type, SourceType::MlMap(_)))
+        .map(|(module_name, module)| (module_name.to_owned(), module.deps.to_owned()))
+        .collect::<Vec<(String, AHashSet<String>)>>();
+    for (module_name, deps) in mlmap_deps {
+        for dep_name in deps {
+            if let Some(module) = build_state.modules.get_mut(&dep_name) {
+                module.dependents.insert(module_name.to_owned());
+            }
+        }
+    }
+
+    module_deps
         .into_iter()
-        .for_each(|(module_name, deps)| {
+        .map(|(module_name, deps, raw_deps)| {
             if let Some(module) = build_state.modules.get_mut(&module_name) {
                 module.deps = deps.clone();
             }
@@ -123,5 +310,765 @@ pub fn get_deps(build_state: &mut BuildState, deleted_modules: &AHashSet<String>
                     module.dependents.insert(module_name.to_string());
                 }
             });
+            (module_name, raw_deps)
+        })
+        .collect()
+}
+
+/// The names of the modules of which there is an interface (a `.cmi` file) in `dir`, ie. the
+/// modules of the standard library in `node_modules/rescript/lib/ocaml`.
+fn read_cmi_modules(dir: &Path) -> AHashSet<String> {
+    std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|extension| extension == "cmi"))
+                .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+                .map(|stem| helpers::capitalize(&stem))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The modules outside of the packages of the build that modules can depend on: the standard library
+/// next to the compiler, and the modules in the `bs-external-includes` of the packages. Returns
+/// `None` when the standard library can't be found.
+pub fn get_external_modules(build_state: &BuildState, bsc_path: &str) -> Option<AHashSet<String>> {
+    let stdlib_path = Path::new(bsc_path).parent()?.parent()?.join("lib/ocaml");
+    if !stdlib_path.is_dir() {
+        return None;
+    }
+    let mut modules = read_cmi_modules(&stdlib_path);
+    for package in build_state.packages.values() {
+        for include in package.bsconfig.bs_external_includes.iter().flatten() {
+            modules.extend(read_cmi_modules(&Path::new(&package.path).join(include)));
+        }
+    }
+    Some(modules)
+}
+
+/// Returns the (raw) dependencies of the modules that are neither a module of the build nor one of
+/// the `external_modules`, as the module and its dependency, sorted. These are usually modules of a
+/// package that isn't installed or listed in the `bs-dependencies`. As the ASTs list the modules as
+/// they are written, a module of an opened module (ie. `Array` after `open Belt`) is reported too.
+pub fn get_unresolved_deps(
+    build_state: &BuildState,
+    raw_deps: &AHashMap<String, RawDeps>,
+    external_modules: &AHashSet<String>,
+) -> Vec<(String, String)> {
+    let mut unresolved = raw_deps
+        .iter()
+        .filter_map(|(module_name, raw_deps)| {
+            let module = build_state.get_module(module_name)?;
+            let package = build_state.get_package(&module.package_name)?;
+            Some((module_name, raw_deps, package))
+        })
+        .flat_map(|(module_name, raw_deps, package)| {
+            raw_deps
+                .implementation
+                .iter()
+                .chain(raw_deps.interface.iter().flatten())
+                .filter(|dep| {
+                    let dep_first = dep.split('.').next().unwrap();
+                    let resolved = resolve_dep_modules(
+                        &AHashSet::from_iter([dep.to_string()]),
+                        package.namespace.to_suffix(),
+                        package.modules.as_ref().unwrap(),
+                        &build_state.module_names,
+                    );
+                    resolved.is_empty()
+                        && package.namespace.to_suffix().as_deref() != Some(dep_first)
+                        && !external_modules.contains(dep_first)
+                })
+                .map(|dep| (module_name.to_owned(), dep.split('.').next().unwrap().to_string()))
+                .collect::<Vec<(String, String)>>()
+        })
+        .collect::<Vec<(String, String)>>();
+    unresolved.sort();
+    unresolved.dedup();
+    unresolved
+}
+
+/// Returns the dependencies of the modules of `package_names` on modules of other packages that are
+/// not in the bs-dependencies (or the pinned or dev dependencies) of their package, as the module,
+/// its dependency and the package of the dependency, sorted. The compiler only finds the modules of
+/// the declared dependencies, so these only compile when the package is built in a project that
+/// happens to include the other package.
+pub fn get_undeclared_deps(
+    build_state: &BuildState,
+    package_names: &AHashSet<String>,
+) -> Vec<(String, String, String)> {
+    let mut undeclared = build_state
+        .modules
+        .iter()
+        .filter(|(_, module)| package_names.contains(&module.package_name))
+        .flat_map(|(module_name, module)| {
+            let bsconfig = &build_state
+                .get_package(&module.package_name)
+                .expect("Package not found")
+                .bsconfig;
+            let declared = [
+                &bsconfig.bs_dependencies,
+                &bsconfig.pinned_dependencies,
+                &bsconfig.bs_dev_dependencies,
+            ]
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect::<AHashSet<&String>>();
+            module
+                .deps
+                .iter()
+                .filter_map(|dep| build_state.get_module(dep).map(|dep_module| (dep, dep_module)))
+                .filter(|(_, dep_module)| {
+                    dep_module.package_name != module.package_name
+                        && !declared.contains(&dep_module.package_name)
+                })
+                .map(|(dep, dep_module)| {
+                    (
+                        module_name.to_owned(),
+                        dep.to_owned(),
+                        dep_module.package_name.to_owned(),
+                    )
+                })
+                .collect::<Vec<(String, String, String)>>()
+        })
+        .collect::<Vec<(String, String, String)>>();
+    undeclared.sort();
+    undeclared
+}
+
+/// The modules that a single module depends on, read from the given ASTs of the module.
+pub fn get_module_deps(
+    build_state: &BuildState,
+    module_name: &str,
+    ast_paths: &[String],
+) -> AHashSet<String> {
+    let module = build_state.get_module(module_name).expect("Module not found");
+    let package = build_state
+        .get_package(&module.package_name)
+        .expect("Package not found");
+    let mut deps = ast_paths
+        .iter()
+        .flat_map(|ast_path| {
+            resolve_dep_modules(
+                &read_dep_modules(ast_path),
+                package.namespace.to_suffix(),
+                package.modules.as_ref().unwrap(),
+                &build_state.module_names,
+            )
+        })
+        .collect::<AHashSet<String>>();
+    deps.remove(module_name);
+    deps
+}
+
+fn is_dev_module(module: &Module) -> bool {
+    match &module.source_type {
+        SourceType::SourceFile(source_file) => source_file.is_dev,
+        SourceType::MlMap(_) => false,
+    }
+}
+
+/// The modules (outside of the dev sources) that depend on a module in the dev sources, with that
+/// dependency. The dev sources are left out of a production build (`--no-dev`), so these would
+/// fail to compile there. The dev modules can depend on all modules.
+pub fn get_dev_deps_of_non_dev_modules(build_state: &BuildState) -> Vec<(String, String)> {
+    let mut dev_deps = build_state
+        .modules
+        .iter()
+        .filter(|(_, module)| !is_dev_module(module))
+        .flat_map(|(module_name, module)| {
+            module
+                .deps
+                .iter()
+                .filter(|dep| build_state.get_module(dep).map(is_dev_module).unwrap_or(false))
+                .map(move |dep| (module_name.to_owned(), dep.to_owned()))
+        })
+        .collect::<Vec<(String, String)>>();
+    dev_deps.sort();
+    dev_deps
+}
+
+pub static DEPS_FILE: &str = ".rewatch-deps.json";
+/// The version of the format of the deps file, bump it when `ModuleDeps` changes.
+static DEPS_FILE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct DepsFile {
+    version: u32,
+    compiler_version: String,
+    modules: BTreeMap<String, ModuleDeps>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ModuleDeps {
+    file_path: String,
+    interface_path: Option<String>,
+    ast_path: String,
+    ast_deps: Vec<String>,
+    // used to skip reading the ASTs of unchanged modules in the next build
+    last_modified: Option<SystemTime>,
+    interface_last_modified: Option<SystemTime>,
+    ast_raw_deps: Vec<String>,
+    iast_raw_deps: Option<Vec<String>>,
+    // used to find the modules that were renamed in the next build (see `clean::get_renamed_modules`)
+    #[serde(default)]
+    source_hash: Option<String>,
+}
+
+fn sorted(deps: &AHashSet<String>) -> Vec<String> {
+    let mut deps = deps.iter().cloned().collect::<Vec<String>>();
+    deps.sort();
+    deps
+}
+
+/// Writes a `.rewatch-deps.json` to the build folder of every package, which maps the modules of the
+/// package to their source file, ast and dependencies. This is meant for external tooling (bundlers,
+/// test runners), so they don't have to read the .ast files themselves. The file is written from
+/// the modules of this build, so the modules that were removed since are dropped from it.
+pub fn write_deps_files(
+    build_state: &BuildState,
+    raw_deps: &AHashMap<String, RawDeps>,
+    compiler_version: &str,
+) {
+    build_state
+        .packages
+        .par_iter()
+        .for_each(|(package_name, package)| {
+            let previous_modules = read_deps_file(package);
+            let modules = build_state
+                .modules
+                .iter()
+                .filter(|(_, module)| &module.package_name == package_name)
+                .filter_map(|(module_name, module)| match &module.source_type {
+                    SourceType::SourceFile(source_file) => {
+                        let module_raw_deps = raw_deps.get(module_name).cloned().unwrap_or_default();
+                        let last_modified = Some(source_file.implementation.last_modified);
+                        // only hash the sources that changed since the previous build
+                        let source_hash = previous_modules
+                            .get(module_name)
+                            .filter(|previous| previous.last_modified == last_modified)
+                            .and_then(|previous| previous.source_hash.to_owned())
+                            .or_else(|| {
+                                let path = Path::new(&package.path).join(&source_file.implementation.path);
+                                helpers::compute_file_hash(&path.to_string_lossy())
+                                    .map(|hash| hash.to_string())
+                            });
+                        Some((
+                            module_name.to_owned(),
+                            ModuleDeps {
+                                file_path: source_file.implementation.path.to_owned(),
+                                interface_path: source_file.interface.as_ref().map(|i| i.path.to_owned()),
+                                ast_path: package.get_ast_path(&source_file.implementation.path),
+                                ast_deps: sorted(&module.deps),
+                                last_modified,
+                                interface_last_modified: source_file
+                                    .interface
+                                    .as_ref()
+                                    .map(|i| i.last_modified),
+                                ast_raw_deps: sorted(&module_raw_deps.implementation),
+                                iast_raw_deps: module_raw_deps.interface.as_ref().map(sorted),
+                                source_hash,
+                            },
+                        ))
+                    }
+                    SourceType::MlMap(_) => None,
+                })
+                .collect::<BTreeMap<String, ModuleDeps>>();
+
+            let path = std::path::Path::new(&package.get_bs_build_path()).join(DEPS_FILE);
+            let deps_file = DepsFile {
+                version: DEPS_FILE_VERSION,
+                compiler_version: compiler_version.to_string(),
+                modules,
+            };
+            let result = serde_json::to_string_pretty(&deps_file)
+                .map_err(|e| e.to_string())
+                .and_then(|json| helpers::write_atomically(&path, json).map_err(|e| e.to_string()));
+            if let Err(e) = result {
+                error!("Could not write {}. {}. \n{}", DEPS_FILE, &package_name, e);
+            }
         });
 }
+
+/// Escapes a path for a Makefile rule: spaces, `#` and `:` (ie. of a Windows drive) are escaped
+/// with a backslash, and `$` is doubled. The backslashes right before an escaped character are
+/// doubled, so they aren't read as its escape.
+fn escape_make_path(path: &str) -> String {
+    let mut escaped = String::new();
+    let mut backslashes = 0;
+    for c in path.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                continue;
+            }
+            ' ' | '#' | ':' => {
+                escaped.push_str(&"\\".repeat(backslashes * 2 + 1));
+                escaped.push(c);
+            }
+            '$' => {
+                escaped.push_str(&"\\".repeat(backslashes));
+                escaped.push_str("$$");
+            }
+            c => {
+                escaped.push_str(&"\\".repeat(backslashes));
+                escaped.push(c);
+            }
+        }
+        backslashes = 0;
+    }
+    escaped + &"\\".repeat(backslashes)
+}
+
+/// The Makefile rule of a depfile, ie. `A.cmj: B.cmi C.cmi`.
+fn get_depfile(target: &str, prerequisites: &[String]) -> String {
+    let mut rule = escape_make_path(target) + ":";
+    for prerequisite in prerequisites {
+        rule.push(' ');
+        rule.push_str(&escape_make_path(prerequisite));
+    }
+    rule + "\n"
+}
+
+/// The `.cmi` of a module, which its dependents are compiled against. For a namespace, it's the
+/// `.cmi` of its mlmap.
+fn get_cmi_path(build_state: &BuildState, module_name: &str) -> Option<String> {
+    let module = build_state.get_module(module_name)?;
+    let package = build_state.get_package(&module.package_name)?;
+    match &module.source_type {
+        SourceType::SourceFile(source_file) => Some(helpers::get_compiler_asset(
+            package,
+            &package.namespace,
+            &source_file.implementation.path,
+            "cmi",
+        )),
+        SourceType::MlMap(_) => Some(package.get_mlmap_compile_path()),
+    }
+}
+
+/// Writes a Makefile `.d` file next to the `.cmj` of every module (`--emit-depfiles`), in which its
+/// `.cmj` depends on the `.cmi` of the modules it depends on (see `Module::deps`). External build
+/// systems (Make, Ninja) can include them to know the dependencies of the modules, without reading
+/// the ASTs. The paths are absolute.
+pub fn write_depfiles(build_state: &BuildState) {
+    build_state.modules.par_iter().for_each(|(module_name, module)| {
+        let source_file = match &module.source_type {
+            SourceType::SourceFile(source_file) => source_file,
+            SourceType::MlMap(_) => return,
+        };
+        let package = match build_state.get_package(&module.package_name) {
+            Some(package) => package,
+            None => return,
+        };
+        let path = &source_file.implementation.path;
+        let target = helpers::get_compiler_asset(package, &package.namespace, path, "cmj");
+        let prerequisites = sorted(&module.deps)
+            .iter()
+            .filter_map(|dep| get_cmi_path(build_state, dep))
+            .collect::<Vec<String>>();
+        let depfile = helpers::get_compiler_asset(package, &package.namespace, path, "d");
+        if let Err(e) = helpers::write_atomically(&depfile, get_depfile(&target, &prerequisites)) {
+            error!("Could not write the depfile of {}. \n{}", module_name, e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        get_depfile, get_deps, get_dev_deps_of_non_dev_modules, get_external_modules, get_raw_deps,
+        get_undeclared_deps, get_unresolved_deps, read_deps_file, resolve_dep_modules, write_depfiles,
+        write_deps_files, ModuleDeps, RawDeps, DEPS_FILE,
+    };
+    use crate::build::build_types::*;
+    use crate::test_fixture::TestProject;
+    use ahash::{AHashMap, AHashSet};
+    use std::cell::RefCell;
+    use std::time::SystemTime;
+
+    fn modules(modules: &[&str]) -> AHashSet<String> {
+        modules.iter().map(|module| module.to_string()).collect()
+    }
+
+    fn source_file(dirty: bool, last_modified: SystemTime) -> SourceFile {
+        SourceFile {
+            implementation: Implementation {
+                path: "src/A.res".to_string(),
+                parse_state: ParseState::Pending,
+                compile_state: CompileState::Pending,
+                last_modified,
+                dirty,
+                dirty_reason: None,
+            },
+            interface: None,
+            interface_only: false,
+            is_dev: false,
+            virtual_contents: None,
+        }
+    }
+
+    fn cached_deps(last_modified: SystemTime) -> ModuleDeps {
+        ModuleDeps {
+            file_path: "src/A.res".to_string(),
+            interface_path: None,
+            ast_path: "lib/bs/src/A.ast".to_string(),
+            ast_deps: vec!["B".to_string()],
+            last_modified: Some(last_modified),
+            interface_last_modified: None,
+            ast_raw_deps: vec!["B".to_string()],
+            iast_raw_deps: None,
+            source_hash: None,
+        }
+    }
+
+    #[test]
+    fn should_only_resolve_the_internal_modules_of_a_namespace_within_its_package() {
+        // the package of the namespace Dep, with the (internal) module Internal
+        let dep_modules = modules(&["Internal-Dep"]);
+        let root_modules = modules(&["App"]);
+        let valid_modules = modules(&["Internal-Dep", "Dep", "App"]);
+
+        // within the package, by its own name, or through the namespace
+        let within_namespace = resolve_dep_modules(
+            &modules(&["Internal", "Dep.Internal"]),
+            Some("Dep".to_string()),
+            &dep_modules,
+            &valid_modules,
+        );
+        // other packages only see the namespace (the mlmap), which aliases Internal
+        let outside_namespace = resolve_dep_modules(
+            &modules(&["Internal", "Dep", "Dep.Internal"]),
+            None,
+            &root_modules,
+            &valid_modules,
+        );
+
+        assert_eq!(within_namespace, modules(&["Internal-Dep"]));
+        assert_eq!(outside_namespace, modules(&["Dep"]));
+    }
+
+    #[test]
+    fn should_not_read_the_ast_of_an_unchanged_module() {
+        let last_modified = SystemTime::now();
+        let reads = RefCell::new(vec![]);
+
+        let raw_deps = get_raw_deps(
+            &source_file(false, last_modified),
+            "lib/bs/src/A.ast",
+            None,
+            Some(&cached_deps(last_modified)),
+            |ast_path| {
+                reads.borrow_mut().push(ast_path.to_string());
+                modules(&["C"])
+            },
+        );
+
+        assert!(reads.borrow().is_empty());
+        assert_eq!(raw_deps.implementation, modules(&["B"]));
+    }
+
+    #[test]
+    fn should_read_the_ast_of_an_unchanged_module_without_cached_deps() {
+        let last_modified = SystemTime::now();
+        let reads = RefCell::new(vec![]);
+
+        let raw_deps = get_raw_deps(
+            &source_file(false, last_modified),
+            "lib/bs/src/A.ast",
+            None,
+            None,
+            |ast_path| {
+                reads.borrow_mut().push(ast_path.to_string());
+                modules(&["C"])
+            },
+        );
+
+        assert_eq!(*reads.borrow(), vec!["lib/bs/src/A.ast".to_string()]);
+        assert_eq!(raw_deps.implementation, modules(&["C"]));
+    }
+
+    #[test]
+    fn should_read_the_ast_of_a_regenerated_module() {
+        let last_modified = SystemTime::now();
+        let reads = RefCell::new(vec![]);
+
+        let raw_deps = get_raw_deps(
+            &source_file(true, last_modified),
+            "lib/bs/src/A.ast",
+            None,
+            Some(&cached_deps(last_modified)),
+            |ast_path| {
+                reads.borrow_mut().push(ast_path.to_string());
+                modules(&["C"])
+            },
+        );
+
+        assert_eq!(*reads.borrow(), vec!["lib/bs/src/A.ast".to_string()]);
+        assert_eq!(raw_deps.implementation, modules(&["C"]));
+    }
+
+    #[test]
+    fn should_only_allow_dev_modules_to_depend_on_dev_modules() {
+        let module = |is_dev: bool, deps: &[&str]| Module {
+            source_type: SourceType::SourceFile(SourceFile {
+                is_dev,
+                ..source_file(false, SystemTime::now())
+            }),
+            deps: modules(deps),
+            dependents: AHashSet::new(),
+            package_name: "package".to_string(),
+            compile_dirty: false,
+            last_compiled_cmi: None,
+            last_compiled_cmt: None,
+            compile_duration: None,
+            compile_outcome: None,
+            diagnostics: vec![],
+        };
+        let mut build_state = BuildState::new("/project".to_string(), "package".to_string(), AHashMap::new());
+        build_state
+            .modules
+            .insert("App".to_string(), module(false, &["TestUtils"]));
+        build_state
+            .modules
+            .insert("Utils".to_string(), module(false, &[]));
+        build_state
+            .modules
+            .insert("TestUtils".to_string(), module(true, &["Utils"]));
+        build_state
+            .modules
+            .insert("App_test".to_string(), module(true, &["App", "TestUtils"]));
+
+        assert_eq!(
+            get_dev_deps_of_non_dev_modules(&build_state),
+            vec![("App".to_string(), "TestUtils".to_string())]
+        );
+    }
+
+    #[test]
+    fn should_remove_the_modules_of_deleted_sources_from_the_deps_file() {
+        let project = TestProject::new("deps-file");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "");
+        project.write("src/B.res", "");
+        let build = || {
+            let build_state = project.build_state("root");
+            write_deps_files(&build_state, &AHashMap::new(), "11.0.0");
+            let package = build_state.get_package("root").unwrap();
+            read_deps_file(package).into_keys().collect::<Vec<String>>()
+        };
+
+        let first_build = build();
+        std::fs::remove_file(project.join("src/B.res")).unwrap();
+        let second_build = build();
+        let deps_file = std::fs::read_to_string(project.join("lib/bs").join(DEPS_FILE)).unwrap_or_default();
+
+        assert_eq!(first_build, vec!["A", "B"]);
+        assert_eq!(second_build, vec!["A"]);
+        assert!(deps_file.contains(r#""compiler_version": "11.0.0""#));
+    }
+
+    #[test]
+    fn should_read_the_deps_of_an_interface_from_its_iast() {
+        let project = TestProject::new("iast-deps");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        for file in ["A.res", "A.resi", "B.res", "C.res"] {
+            project.write(format!("src/{}", file), "");
+        }
+        let mut build_state = project.build_state("root");
+        // the ASTs as bsc writes them (see `ast::get_dep_modules`): the interface of A uses B, and
+        // the implementation uses C
+        let ast = |deps: &str, source_file: &str| {
+            [
+                b"\x84\x95\xa6\xbe\0\0\0\n".as_slice(),
+                deps.as_bytes(),
+                project.join("src").join(source_file).to_string_lossy().as_bytes(),
+                b"\n\x00binary".as_slice(),
+            ]
+            .concat()
+        };
+        let build_path = project.join("lib/ocaml");
+        std::fs::write(build_path.join("A.iast"), ast("B\n", "A.resi")).unwrap();
+        std::fs::write(build_path.join("A.ast"), ast("C\n", "A.res")).unwrap();
+        std::fs::write(build_path.join("B.ast"), ast("\n", "B.res")).unwrap();
+        std::fs::write(build_path.join("C.ast"), ast("\n", "C.res")).unwrap();
+
+        let raw_deps = get_deps(&mut build_state, &AHashSet::new());
+        write_deps_files(&build_state, &raw_deps, "11.0.0");
+        let deps_file = read_deps_file(build_state.get_package("root").unwrap());
+
+        assert_eq!(raw_deps["A"].interface, Some(modules(&["B"])));
+        assert_eq!(raw_deps["A"].implementation, modules(&["C"]));
+        assert_eq!(deps_file["A"].ast_deps, vec!["B", "C"]);
+        assert_eq!(deps_file["A"].iast_raw_deps, Some(vec![String::from("B")]));
+    }
+
+    #[test]
+    fn should_report_the_deps_that_are_not_a_module() {
+        let project = TestProject::new("unresolved-deps");
+        std::fs::create_dir_all(project.join("node_modules/rescript/linux")).unwrap();
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "namespace": "App" }"#,
+        );
+        for file in ["A.res", "B.res"] {
+            project.write(format!("src/{}", file), "");
+        }
+        project.write("node_modules/rescript/lib/ocaml/belt.cmi", "");
+        let build_state = project.build_state("root");
+        let external_modules = get_external_modules(
+            &build_state,
+            &project
+                .join("node_modules/rescript/linux/bsc.exe")
+                .to_string_lossy(),
+        );
+        let raw_deps = AHashMap::from([(
+            String::from("A-App"),
+            RawDeps {
+                implementation: modules(&["B", "App.B", "Belt.Array", "Missing", "Typo.make"]),
+                interface: Some(modules(&["Missing"])),
+            },
+        )]);
+        let unresolved = get_unresolved_deps(&build_state, &raw_deps, external_modules.as_ref().unwrap());
+
+        assert_eq!(external_modules, Some(modules(&["Belt"])));
+        assert_eq!(
+            unresolved,
+            vec![
+                (String::from("A-App"), String::from("Missing")),
+                (String::from("A-App"), String::from("Typo"))
+            ]
+        );
+    }
+
+    #[test]
+    fn should_write_the_same_deps_file_for_the_same_modules() {
+        let project = TestProject::new("deps-file-bytes");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        for index in 0..20 {
+            project.write(format!("src/M{}.res", index), "");
+        }
+        // every build state has its own (randomly seeded) hash maps, so the modules are visited in
+        // another order
+        let write = || {
+            let mut build_state = project.build_state("root");
+            let mut raw_deps = AHashMap::new();
+            for index in 1..20 {
+                let module_name = format!("M{}", index);
+                let deps = modules(&["M0", &format!("M{}", index - 1)]);
+                build_state.modules.get_mut(&module_name).unwrap().deps = deps.to_owned();
+                raw_deps.insert(
+                    module_name,
+                    RawDeps {
+                        implementation: deps,
+                        interface: None,
+                    },
+                );
+            }
+            write_deps_files(&build_state, &raw_deps, "11.0.0");
+            std::fs::read(project.join("lib/bs").join(DEPS_FILE)).unwrap()
+        };
+
+        let first = write();
+        let second = write();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn should_report_the_deps_on_packages_that_are_not_declared() {
+        let project = TestProject::new("undeclared-deps");
+        for (dir, bsconfig, file) in [
+            (
+                "",
+                r#"{ "name": "root", "sources": "src", "bs-dependencies": ["lib-a", "lib-b"] }"#,
+                "Main.res",
+            ),
+            (
+                "node_modules/lib-a/",
+                r#"{ "name": "lib-a", "sources": "src" }"#,
+                "A.res",
+            ),
+            (
+                "node_modules/lib-b/",
+                r#"{ "name": "lib-b", "sources": "src" }"#,
+                "B.res",
+            ),
+        ] {
+            project.write(format!("{}bsconfig.json", dir), bsconfig);
+            project.write(format!("{}src/{}", dir, file), "");
+        }
+        let mut build_state = project.build_state("root");
+        // lib-a uses lib-b, which only compiles in a project that has lib-b as well
+        build_state.modules.get_mut("Main").unwrap().deps = modules(&["A", "B"]);
+        build_state.modules.get_mut("A").unwrap().deps = modules(&["B"]);
+
+        let undeclared_in_lib_a = get_undeclared_deps(&build_state, &modules(&["lib-a"]));
+        let undeclared_in_root = get_undeclared_deps(&build_state, &modules(&["root"]));
+
+        assert_eq!(
+            undeclared_in_lib_a,
+            vec![(String::from("A"), String::from("B"), String::from("lib-b"))]
+        );
+        assert_eq!(undeclared_in_root, vec![]);
+    }
+
+    #[test]
+    fn should_escape_the_paths_of_a_depfile_for_make() {
+        assert_eq!(get_depfile("/p/A.cmj", &[]), "/p/A.cmj:\n");
+        assert_eq!(
+            get_depfile(
+                "/my project/A.cmj",
+                &["/my project/B.cmi".to_string(), "/p/$#C.cmi".to_string()]
+            ),
+            "/my\\ project/A.cmj: /my\\ project/B.cmi /p/$$\\#C.cmi\n"
+        );
+        assert_eq!(
+            get_depfile(
+                "C:\\my project\\A.cmj",
+                &["C:\\lib\\B.cmi".to_string(), "/p/a\\ b/C.cmi".to_string()]
+            ),
+            "C\\:\\my\\ project\\A.cmj: C\\:\\lib\\B.cmi /p/a\\\\\\ b/C.cmi\n"
+        );
+    }
+
+    #[test]
+    fn should_write_a_depfile_next_to_the_cmj_of_every_module() {
+        // a path with a space, which is escaped in the depfiles
+        let project = TestProject::new("depfiles with spaces");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "namespace": "App" }"#,
+        );
+        for file in ["A.res", "B.res", "C.res"] {
+            project.write(format!("src/{}", file), "");
+        }
+        let project_root = project.path();
+        let mut build_state = project.build_state("root");
+        for dep in ["C-App", "B-App"] {
+            build_state
+                .modules
+                .get_mut("A-App")
+                .unwrap()
+                .deps
+                .insert(dep.to_string());
+        }
+        std::fs::create_dir_all(project.join("lib/ocaml")).unwrap();
+
+        write_depfiles(&build_state);
+        let a_depfile = std::fs::read_to_string(project.join("lib/ocaml/A-App.d")).unwrap();
+        let b_depfile = std::fs::read_to_string(project.join("lib/ocaml/B-App.d")).unwrap();
+
+        let build_path = format!("{}/lib/ocaml", project_root.replace(' ', "\\ "));
+        assert_eq!(
+            a_depfile,
+            format!(
+                "{}/A-App.cmj: {}/B-App.cmi {}/C-App.cmi\n",
+                build_path, build_path, build_path
+            )
+        );
+        assert_eq!(b_depfile, format!("{}/B-App.cmj:\n", build_path));
+    }
+}
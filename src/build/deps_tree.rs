@@ -0,0 +1,303 @@
+use super::build_types::*;
+use super::deps;
+use super::explain;
+use super::packages;
+use crate::helpers;
+use ahash::{AHashMap, AHashSet};
+use console::style;
+use std::collections::BTreeMap;
+
+/// The packages of the standard library, that almost every module depends on.
+const STDLIB_PACKAGES: [&str; 3] = ["rescript", "@rescript/core", "@rescript/std"];
+
+/// Whether a module is in the standard library. `implicit_stdlib_module` is the module that bsc
+/// opens in every module (see `Package::get_implicit_stdlib_module`).
+fn is_stdlib_module(build_state: &BuildState, implicit_stdlib_module: &str, module_name: &str) -> bool {
+    module_name == implicit_stdlib_module
+        || build_state
+            .get_module(module_name)
+            .is_some_and(|module| STDLIB_PACKAGES.contains(&module.package_name.as_str()))
+}
+
+/// Leaves the modules for which `is_excluded` is true out of the graph, both as a module and as a
+/// dependency. This only changes what is printed, not the order in which modules are compiled.
+fn exclude_modules(
+    graph: AHashMap<String, AHashSet<String>>,
+    is_excluded: impl Fn(&str) -> bool,
+) -> AHashMap<String, AHashSet<String>> {
+    graph
+        .into_iter()
+        .filter(|(module_name, _)| !is_excluded(module_name))
+        .map(|(module_name, deps)| {
+            let deps = deps.into_iter().filter(|dep| !is_excluded(dep)).collect();
+            (module_name, deps)
+        })
+        .collect()
+}
+
+/// Turns the dependencies of the modules into their dependents.
+fn reverse(graph: &AHashMap<String, AHashSet<String>>) -> AHashMap<String, AHashSet<String>> {
+    let mut reversed: AHashMap<String, AHashSet<String>> = AHashMap::new();
+    for (module_name, deps) in graph {
+        for dep in deps {
+            reversed
+                .entry(dep.to_owned())
+                .or_default()
+                .insert(module_name.to_owned());
+        }
+    }
+    reversed
+}
+
+fn render_module(
+    graph: &AHashMap<String, AHashSet<String>>,
+    module_name: &str,
+    depth: usize,
+    max_depth: Option<usize>,
+    ancestors: &mut Vec<String>,
+    shown: &mut AHashSet<String>,
+    rendered: &mut String,
+) {
+    let indent = "  ".repeat(depth);
+    if ancestors.iter().any(|ancestor| ancestor == module_name) {
+        rendered.push_str(&format!("{}{} (cycle)\n", indent, module_name));
+        return;
+    }
+    let mut deps = graph
+        .get(module_name)
+        .map(|deps| deps.iter().collect::<Vec<&String>>())
+        .unwrap_or_default();
+    deps.sort();
+    // a module is only expanded once, the next time we just refer to it
+    if !deps.is_empty() && !shown.insert(module_name.to_string()) {
+        rendered.push_str(&format!("{}{} (already shown)\n", indent, module_name));
+        return;
+    }
+    rendered.push_str(&format!("{}{}\n", indent, module_name));
+    if max_depth.map(|max_depth| depth >= max_depth).unwrap_or(false) {
+        return;
+    }
+
+    ancestors.push(module_name.to_string());
+    for dep in deps {
+        render_module(graph, dep, depth + 1, max_depth, ancestors, shown, rendered);
+    }
+    ancestors.pop();
+}
+
+/// Renders the (transitive) dependencies of a module as an indented tree, up to `max_depth` levels
+/// deep. Modules that are part of a cycle are marked with `(cycle)`, and modules that are already
+/// expanded elsewhere in the tree with `(already shown)`.
+pub fn render_tree(
+    graph: &AHashMap<String, AHashSet<String>>,
+    module_name: &str,
+    max_depth: Option<usize>,
+) -> String {
+    let mut rendered = String::new();
+    render_module(
+        graph,
+        module_name,
+        0,
+        max_depth,
+        &mut vec![],
+        &mut AHashSet::new(),
+        &mut rendered,
+    );
+    rendered
+}
+
+/// Prints the dependency tree of a module, or the tree of its dependents when `reverse` is set. The
+/// dependencies are read from the deps files of the previous build. With `no_stdlib_edges`, the
+/// modules of the standard library are left out.
+pub fn print_deps_tree(
+    project_root: &str,
+    module_name: &str,
+    max_depth: Option<usize>,
+    reverse_deps: bool,
+    no_stdlib_edges: bool,
+    compile_options: &CompileOptions,
+    package_options: &packages::PackageOptions,
+) -> Result<(), String> {
+    let workspace_root = helpers::get_workspace_root(project_root);
+    let build_state = explain::read_build_state(project_root, workspace_root.to_owned(), package_options);
+
+    let module_name = match explain::resolve_module(&build_state.modules, module_name) {
+        Some((module_name, _)) => module_name,
+        None => {
+            return Err(format!(
+                "{}: Could not find module {}",
+                style("Error").red(),
+                module_name
+            ))
+        }
+    };
+
+    let graph = deps::read_deps_files(&build_state);
+    if !graph.contains_key(module_name) {
+        return Err(format!(
+            "{}: The dependencies of {} are unknown, please build the project first",
+            style("Error").red(),
+            module_name
+        ));
+    }
+
+    let graph = if no_stdlib_edges {
+        // without a compiler to get the version from, it's the module of curried mode
+        let root_package = build_state
+            .get_package(&build_state.root_config_name)
+            .expect("Could not find root package");
+        let implicit_stdlib_module = helpers::try_get_bsc(
+            project_root,
+            workspace_root,
+            package_options.node_modules.as_deref(),
+        )
+        .and_then(|bsc_path| super::try_get_version(&bsc_path, compile_options))
+        .map(|version| root_package.get_implicit_stdlib_module(&version, root_package))
+        .unwrap_or("Pervasives");
+        exclude_modules(graph, |module_name| {
+            is_stdlib_module(&build_state, implicit_stdlib_module, module_name)
+        })
+    } else {
+        graph
+    };
+    let graph = if reverse_deps { reverse(&graph) } else { graph };
+    print!("{}", render_tree(&graph, module_name, max_depth));
+    Ok(())
+}
+
+/// Returns all modules that (transitively) depend on a module: the modules that need to be compiled
+/// again when the module changes.
+pub fn get_transitive_dependents(
+    graph: &AHashMap<String, AHashSet<String>>,
+    module_name: &str,
+) -> AHashSet<String> {
+    let dependents = reverse(graph);
+    let mut result = AHashSet::new();
+    let mut to_visit = vec![module_name.to_string()];
+    while let Some(module_name) = to_visit.pop() {
+        for dependent in dependents.get(&module_name).into_iter().flatten() {
+            if result.insert(dependent.to_owned()) {
+                to_visit.push(dependent.to_owned());
+            }
+        }
+    }
+    result.remove(module_name);
+    result
+}
+
+/// Prints the modules that need to be compiled again when a module changes, grouped by package. The
+/// dependencies are read from the deps files of the previous build.
+pub fn print_impact(
+    project_root: &str,
+    module_name: &str,
+    package_options: &packages::PackageOptions,
+) -> Result<(), String> {
+    let workspace_root = helpers::get_workspace_root(project_root);
+    let build_state = explain::read_build_state(project_root, workspace_root, package_options);
+
+    let module_name = match explain::resolve_module(&build_state.modules, module_name) {
+        Some((module_name, _)) => module_name,
+        None => {
+            return Err(format!(
+                "{}: Could not find module {}",
+                style("Error").red(),
+                module_name
+            ))
+        }
+    };
+
+    let graph = deps::read_deps_files(&build_state);
+    if !graph.contains_key(module_name) {
+        return Err(format!(
+            "{}: The dependencies of {} are unknown, please build the project first",
+            style("Error").red(),
+            module_name
+        ));
+    }
+
+    let dependents = get_transitive_dependents(&graph, module_name);
+    let mut packages: BTreeMap<&String, Vec<&String>> = BTreeMap::new();
+    for dependent in dependents.iter() {
+        if let Some(module) = build_state.get_module(dependent) {
+            packages.entry(&module.package_name).or_default().push(dependent);
+        }
+    }
+
+    println!(
+        "Changing {} affects {} modules in {} packages",
+        style(module_name).bold(),
+        dependents.len(),
+        packages.len()
+    );
+    for (package_name, mut module_names) in packages {
+        module_names.sort();
+        println!("\n{} ({})", style(package_name).bold(), module_names.len());
+        for module_name in module_names {
+            println!("  {}", module_name);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{exclude_modules, get_transitive_dependents, render_tree, reverse};
+    use ahash::{AHashMap, AHashSet};
+
+    fn graph(edges: &[(&str, &[&str])]) -> AHashMap<String, AHashSet<String>> {
+        edges
+            .iter()
+            .map(|(module_name, deps)| {
+                (
+                    module_name.to_string(),
+                    deps.iter().map(|dep| dep.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_render_the_tree_with_cycles_and_shared_deps() {
+        let graph = graph(&[("A", &["B", "C"]), ("B", &["C"]), ("C", &["D", "A"])]);
+
+        assert_eq!(
+            render_tree(&graph, "A", None),
+            "A\n  B\n    C\n      A (cycle)\n      D\n  C (already shown)\n"
+        );
+        assert_eq!(render_tree(&graph, "A", Some(1)), "A\n  B\n  C\n");
+        assert_eq!(
+            render_tree(&reverse(&graph), "D", None),
+            "D\n  C\n    A\n      C (cycle)\n    B\n      A (already shown)\n"
+        );
+    }
+
+    #[test]
+    fn should_get_the_transitive_dependents() {
+        let graph = graph(&[("A", &["B"]), ("B", &["C"]), ("C", &["B"]), ("D", &[])]);
+
+        assert_eq!(
+            get_transitive_dependents(&graph, "C"),
+            AHashSet::from_iter(vec![String::from("A"), String::from("B")])
+        );
+        assert_eq!(get_transitive_dependents(&graph, "D"), AHashSet::new());
+    }
+
+    #[test]
+    fn should_leave_the_excluded_modules_out_of_the_tree() {
+        let graph = graph(&[
+            ("A", &["B", "Pervasives", "Core__Array"]),
+            ("B", &["Pervasives"]),
+            ("Core__Array", &["Pervasives"]),
+        ]);
+        let is_stdlib = |module_name: &str| module_name == "Pervasives" || module_name.starts_with("Core__");
+
+        assert_eq!(
+            render_tree(&graph, "A", None),
+            "A\n  B\n    Pervasives\n  Core__Array\n    Pervasives\n  Pervasives\n"
+        );
+        assert_eq!(
+            render_tree(&exclude_modules(graph, is_stdlib), "A", None),
+            "A\n  B\n"
+        );
+    }
+}
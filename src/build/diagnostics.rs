@@ -0,0 +1,319 @@
+use console::style;
+use regex::Regex;
+use std::collections::BTreeMap;
+
+// bsc prints errors and warnings as blocks that are indented with two spaces:
+//
+//   We've found a bug for you!
+//   /path/to/src/File.res:3:9-11
+//
+//   1 │ let a = b
+//
+//   The value b can't be found
+//
+// The first line is the title (`We've found a bug for you!`, `Syntax error!` or
+// `Warning number 27`, or `Warning number 27 (configured as error)` for a warning that is turned into
+// an error with `-warn-error`), followed by the location, and the code frame + message. We parse these
+// blocks, so we can show them grouped by file.
+//
+// When bsc exits with an error but doesn't print one (ie. when it crashes), we report a block in
+// the same format, titled `The compiler failed!`.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub title: String,
+    pub file: String,
+    /// The line and column(s), ie. `3:9-11`
+    pub location: String,
+    /// The code frame and the message, without the indentation of bsc
+    pub message: String,
+}
+
+const COMPILER_FAILED: &str = "The compiler failed!";
+
+fn get_severity(title: &str) -> Option<Severity> {
+    match title {
+        "We've found a bug for you!" | "Syntax error!" | COMPILER_FAILED => Some(Severity::Error),
+        // a warning of `-warn-error`, which fails the build like an error
+        title if title.starts_with("Warning number ") && title.ends_with("(configured as error)") => {
+            Some(Severity::Error)
+        }
+        title if title.starts_with("Warning number ") => Some(Severity::Warning),
+        _ => None,
+    }
+}
+
+/// The output that is reported for a module when bsc exits with an error code (or is killed by a
+/// signal, which has no code), without printing an error.
+pub fn compiler_failed(file: &str, code: Option<i32>) -> String {
+    let status = match code {
+        Some(code) => format!("bsc exited with code {}", code),
+        None => "bsc was terminated by a signal".to_string(),
+    };
+    format!(
+        "\n  {}\n\n  {} while compiling {}\n",
+        COMPILER_FAILED, status, file
+    )
+}
+
+fn finish(diagnostic: Option<Diagnostic>, diagnostics: &mut Vec<Diagnostic>) {
+    if let Some(mut diagnostic) = diagnostic {
+        diagnostic.message = diagnostic.message.trim_end().to_string();
+        diagnostics.push(diagnostic);
+    }
+}
+
+/// Parses the errors and warnings from the output of bsc. Returns the diagnostics, and all the
+/// output that isn't part of a diagnostic (ie. the message about a circular dependency).
+pub fn parse_diagnostics(output: &str) -> (Vec<Diagnostic>, String) {
+    let location_regex = Regex::new(r"^(.+):(\d+:\d+(?:-\d+(?::\d+)?)?)$").unwrap();
+    let mut diagnostics = vec![];
+    let mut other_output = String::new();
+    let mut current: Option<Diagnostic> = None;
+
+    let mut lines = output.lines().peekable();
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+        if let Some(severity) = get_severity(trimmed) {
+            finish(current.take(), &mut diagnostics);
+            // the location is on the next line, but not every diagnostic has one
+            let (file, location) = match lines.peek().and_then(|next| location_regex.captures(next.trim())) {
+                Some(captures) => {
+                    let location = (captures[1].to_string(), captures[2].to_string());
+                    lines.next();
+                    location
+                }
+                None => (String::new(), String::new()),
+            };
+            current = Some(Diagnostic {
+                severity,
+                title: trimmed.to_string(),
+                file,
+                location,
+                message: String::new(),
+            });
+        } else if let Some(diagnostic) = current
+            .as_mut()
+            .filter(|_| trimmed.is_empty() || line.starts_with(char::is_whitespace))
+        {
+            if !(diagnostic.message.is_empty() && trimmed.is_empty()) {
+                diagnostic
+                    .message
+                    .push_str(line.strip_prefix("  ").unwrap_or(line));
+                diagnostic.message.push('\n');
+            }
+        } else if !trimmed.is_empty() {
+            finish(current.take(), &mut diagnostics);
+            other_output.push_str(line);
+            other_output.push('\n');
+        }
+    }
+    finish(current.take(), &mut diagnostics);
+
+    (diagnostics, other_output)
+}
+
+fn pluralize(count: usize, word: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, word)
+    } else {
+        format!("{} {}s", count, word)
+    }
+}
+
+/// The line and column of a location (ie. `3:9-11`), to sort the diagnostics of a file by.
+fn get_position(location: &str) -> (usize, usize) {
+    let mut position = location
+        .split(['-', ':'])
+        .map(|number| number.parse::<usize>().unwrap_or(0));
+    (position.next().unwrap_or(0), position.next().unwrap_or(0))
+}
+
+/// Renders the diagnostics grouped by file (in red for errors, yellow for warnings), followed by
+/// a summary line: `N errors, M warnings across K files`. The modules are compiled in parallel,
+/// so the diagnostics arrive in a different order every build; they are rendered sorted by file
+/// (the paths are absolute, so the files of a package are together), and by line and column.
+pub fn render_diagnostics(diagnostics: &[Diagnostic], color: bool) -> String {
+    let mut files: BTreeMap<&str, Vec<&Diagnostic>> = BTreeMap::new();
+    for diagnostic in diagnostics {
+        files.entry(&diagnostic.file).or_default().push(diagnostic);
+    }
+    for diagnostics in files.values_mut() {
+        diagnostics.sort_by_key(|diagnostic| get_position(&diagnostic.location));
+    }
+
+    let mut rendered = String::new();
+    for (file, diagnostics) in files.iter() {
+        let file = if file.is_empty() { "(unknown file)" } else { file };
+        rendered.push_str(&format!(
+            "\n{}\n",
+            style(file).bold().underlined().force_styling(color)
+        ));
+        for diagnostic in diagnostics {
+            let title = match diagnostic.severity {
+                Severity::Error => style(&diagnostic.title).red().bold(),
+                Severity::Warning => style(&diagnostic.title).yellow().bold(),
+            };
+            rendered.push_str(&format!(
+                "\n  {} {}\n",
+                title.force_styling(color),
+                style(&diagnostic.location).dim().force_styling(color)
+            ));
+            for line in diagnostic.message.lines() {
+                rendered.push_str(&format!("  {}\n", line).replace("  \n", "\n"));
+            }
+        }
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    let summary = format!(
+        "{}, {} across {}",
+        pluralize(errors, "error"),
+        pluralize(diagnostics.len() - errors, "warning"),
+        pluralize(files.len(), "file")
+    );
+    let summary = if errors > 0 {
+        style(summary).red()
+    } else {
+        style(summary).yellow()
+    };
+    rendered.push_str(&format!("\n{}\n", summary.force_styling(color)));
+    rendered
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    static OUTPUT: &str = "
+  Warning number 27
+  /project/src/B.res:1:9
+
+  1 │ let f = x => 1
+
+  unused variable x.
+
+  We've found a bug for you!
+  /project/src/A.res:3:9-11
+
+  3 │ let a = bcd
+
+  The value bcd can't be found
+
+  Syntax error!
+  /project/src/B.res:5:1-6:2
+
+  Did you forget a `}` here?
+
+Can't continue... Found a circular dependency in your code:
+";
+
+    #[test]
+    fn should_parse_diagnostics() {
+        let (diagnostics, other_output) = parse_diagnostics(OUTPUT);
+
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].file, "/project/src/B.res");
+        assert_eq!(diagnostics[0].location, "1:9");
+        assert_eq!(diagnostics[0].message, "1 │ let f = x => 1\n\nunused variable x.");
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+        assert_eq!(diagnostics[1].file, "/project/src/A.res");
+        assert_eq!(diagnostics[1].location, "3:9-11");
+        assert_eq!(diagnostics[2].title, "Syntax error!");
+        assert_eq!(diagnostics[2].location, "5:1-6:2");
+        assert_eq!(
+            other_output,
+            "Can't continue... Found a circular dependency in your code:\n"
+        );
+    }
+
+    #[test]
+    fn should_render_diagnostics_grouped_by_file() {
+        let (diagnostics, _) = parse_diagnostics(OUTPUT);
+
+        let rendered = render_diagnostics(&diagnostics, false);
+
+        assert_eq!(
+            rendered,
+            "
+/project/src/A.res
+
+  We've found a bug for you! 3:9-11
+  3 │ let a = bcd
+
+  The value bcd can't be found
+
+/project/src/B.res
+
+  Warning number 27 1:9
+  1 │ let f = x => 1
+
+  unused variable x.
+
+  Syntax error! 5:1-6:2
+  Did you forget a `}` here?
+
+2 errors, 1 warning across 2 files
+"
+        );
+    }
+    #[test]
+    fn should_parse_a_warning_that_is_configured_as_error_as_an_error() {
+        let output = "
+  Warning number 27 (configured as error)
+  /project/src/A.res:1:9
+
+  1 │ let f = x => 1
+
+  unused variable x.
+
+  Warning number 26
+  /project/src/A.res:2:5
+
+  unused variable y.
+";
+        let (diagnostics, _) = parse_diagnostics(output);
+        let rendered = render_diagnostics(&diagnostics, false);
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].title, "Warning number 27 (configured as error)");
+        assert_eq!(diagnostics[1].severity, Severity::Warning);
+        assert!(rendered.ends_with("1 error, 1 warning across 1 file\n"));
+    }
+
+    #[test]
+    fn should_render_the_diagnostics_in_the_same_order_every_build() {
+        let (mut diagnostics, _) = parse_diagnostics(OUTPUT);
+        diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            title: "Warning number 26".to_string(),
+            file: "/project/src/B.res".to_string(),
+            location: "10:5".to_string(),
+            message: String::new(),
+        });
+        let rendered = render_diagnostics(&diagnostics, false);
+
+        // the order in which the modules finished compiling
+        for rotation in 1..diagnostics.len() {
+            let mut reordered = diagnostics.to_owned();
+            reordered.rotate_left(rotation);
+            reordered.swap(0, 1);
+            assert_eq!(render_diagnostics(&reordered, false), rendered);
+        }
+        // by line, not alphabetically (10 after 5)
+        let b_res = &rendered[rendered.find("/project/src/B.res").unwrap()..];
+        assert!(b_res.find("Syntax error!").unwrap() < b_res.find("Warning number 26").unwrap());
+    }
+}
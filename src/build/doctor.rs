@@ -0,0 +1,259 @@
+use super::packages;
+use crate::bsconfig;
+use crate::helpers;
+use crate::helpers::emojis::*;
+use ahash::AHashSet;
+use console::style;
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+
+struct Check {
+    name: String,
+    result: Result<String, String>,
+    hint: &'static str,
+}
+
+fn print_check(check: &Check) {
+    match &check.result {
+        Ok(details) => println!("{}{} {}", CHECKMARK, check.name, style(details).dim()),
+        Err(e) => {
+            println!("{}{} {}", CROSS, check.name, style(e).red());
+            println!("    {} {}", style("hint:").bold(), check.hint);
+        }
+    }
+}
+
+fn check_version(bsc_path: &str) -> Result<String, String> {
+    let version = super::try_get_bsc_version(bsc_path)?;
+    let version_regex = Regex::new(r"^\d+\.\d+\.\d+").unwrap();
+    if version_regex.is_match(&version) {
+        Ok(version)
+    } else {
+        Err(format!("Could not parse the version of bsc: '{}'", version))
+    }
+}
+
+/// The dependencies of a package that need to be installed: its bs-dependencies and
+/// pinned-dependencies, and with `with_dev`, its bs-dev-dependencies. Like bsb, the dev sources (with
+/// the dev dependencies) are only built for the root package and the pinned dependencies.
+fn get_dependencies(bsconfig: &bsconfig::T, with_dev: bool) -> Vec<String> {
+    [
+        bsconfig.bs_dependencies.to_owned(),
+        bsconfig.pinned_dependencies.to_owned(),
+        bsconfig.bs_dev_dependencies.to_owned().filter(|_| with_dev),
+    ]
+    .into_iter()
+    .flatten()
+    .flatten()
+    .collect()
+}
+
+/// Resolves the dependencies of all packages (like `packages::make` does, but without exiting on
+/// the first error), and checks that their config can be read.
+fn check_dependencies(
+    root_bsconfig: &bsconfig::T,
+    project_root: &str,
+    workspace_root: &Option<String>,
+    node_modules: Option<&str>,
+) -> Vec<Check> {
+    let mut checks = vec![];
+    let mut seen = AHashSet::new();
+    let pinned_dependencies = root_bsconfig.pinned_dependencies.to_owned().unwrap_or_default();
+    let mut queue = get_dependencies(root_bsconfig, true)
+        .into_iter()
+        .map(|package_name| (package_name, project_root.to_string()))
+        .collect::<Vec<(String, String)>>();
+
+    while let Some((package_name, parent_path)) = queue.pop() {
+        if !seen.insert(package_name.to_owned()) {
+            continue;
+        }
+        let result = match packages::resolve_package_path(
+            &package_name,
+            &parent_path,
+            project_root,
+            workspace_root,
+            node_modules,
+        ) {
+            Err(_) => Err(String::from("Not found in node_modules")),
+            Ok(package_dir) => {
+                bsconfig::try_read(packages::get_bsconfig_path(&package_dir)).map(|bsconfig| {
+                    let is_pinned = pinned_dependencies.contains(&bsconfig.name);
+                    queue.extend(
+                        get_dependencies(&bsconfig, is_pinned)
+                            .into_iter()
+                            .map(|dependency| (dependency, package_dir.to_owned())),
+                    );
+                    package_dir
+                })
+            }
+        };
+        checks.push(Check {
+            name: format!("Package {}", package_name),
+            result,
+            hint: "Install the dependencies (ie. `npm install`), and make sure every bs-dependency is a ReScript package with a valid bsconfig.json.",
+        });
+    }
+
+    checks
+}
+
+/// The node_modules folder with the rescript package (see `helpers::get_node_modules_path`), which
+/// should contain the installed packages.
+fn check_node_modules(project_root: &str, node_modules: Option<&str>) -> Result<String, String> {
+    let node_modules = helpers::get_node_modules_path(project_root, node_modules).ok_or(format!(
+        "No node_modules with the rescript package in {} or its parent folders",
+        project_root
+    ))?;
+    match fs::read_dir(&node_modules).map(|mut entries| entries.next().is_some()) {
+        Ok(true) => Ok(node_modules),
+        Ok(false) => Err(format!("{} is empty", node_modules)),
+        Err(e) => Err(format!("Could not read {}: {}", node_modules, e)),
+    }
+}
+
+/// The `rescript` command of the rescript package, next to the bsc of the platforms.
+fn check_rescript_binary(node_modules: &str) -> Result<String, String> {
+    let path = Path::new(node_modules).join("rescript").join("rescript");
+    match path.is_file() {
+        true => Ok(path.to_string_lossy().to_string()),
+        false => Err(format!("{} not found", path.to_string_lossy())),
+    }
+}
+
+fn get_checks(project_root: &str, package_options: &packages::PackageOptions) -> Vec<Check> {
+    let workspace_root = helpers::get_workspace_root(project_root);
+    let node_modules = package_options.node_modules.as_deref();
+    let mut checks = vec![];
+
+    let root_bsconfig = bsconfig::try_read(packages::get_bsconfig_path(project_root));
+    checks.push(Check {
+        name: String::from("Root config"),
+        result: root_bsconfig
+            .as_ref()
+            .map(|bsconfig| bsconfig.name.to_owned())
+            .map_err(|e| e.to_owned()),
+        hint: "Run rewatch in the root of your project, next to a valid bsconfig.json or rescript.json.",
+    });
+
+    let node_modules_path = check_node_modules(project_root, node_modules);
+    checks.push(Check {
+        name: String::from("node_modules"),
+        result: node_modules_path.to_owned(),
+        hint: "Install the dependencies (ie. `npm install`), or give the node_modules folder with --node-modules.",
+    });
+
+    if let Ok(node_modules_path) = &node_modules_path {
+        checks.push(Check {
+            name: String::from("rescript"),
+            result: check_rescript_binary(node_modules_path),
+            hint: "Install the rescript package (ie. `npm install rescript`), and reinstall it when it's incomplete.",
+        });
+    }
+
+    let bsc_path = helpers::try_get_bsc(project_root, workspace_root.to_owned(), node_modules);
+    checks.push(Check {
+        name: String::from("bsc"),
+        result: bsc_path.to_owned(),
+        hint: "Install the rescript package (ie. `npm install rescript`) for this platform.",
+    });
+
+    if let Ok(bsc_path) = &bsc_path {
+        checks.push(Check {
+            name: String::from("ReScript version"),
+            result: check_version(bsc_path),
+            hint: "Make sure bsc.exe is executable on this platform, and reinstall the rescript package when it isn't.",
+        });
+    }
+
+    if let Ok(root_bsconfig) = &root_bsconfig {
+        checks.extend(check_dependencies(
+            root_bsconfig,
+            project_root,
+            &workspace_root,
+            node_modules,
+        ));
+        for external_js in root_bsconfig.external_js.iter().flatten() {
+            let path = Path::new(project_root).join(external_js);
+            checks.push(Check {
+                name: format!("External JS {}", external_js),
+                result: match path.is_file() {
+                    true => Ok(String::from("Found")),
+                    false => Err(String::from("Not found")),
+                },
+                hint: "Every file in the external-js of the bsconfig.json should be a hand-written JavaScript file, relative to the project.",
+            });
+        }
+    }
+
+    checks
+}
+
+/// Checks the setup of the project (the compiler, the dependencies and their configs) and prints a
+/// checklist with hints on how to fix the failing checks.
+pub fn doctor(project_root: &str, package_options: &packages::PackageOptions) -> Result<(), String> {
+    let checks = get_checks(project_root, package_options);
+    checks.iter().for_each(print_check);
+
+    let failed = checks.iter().filter(|check| check.result.is_err()).count();
+    if failed > 0 {
+        Err(format!("\n{} of {} checks failed", failed, checks.len()))
+    } else {
+        println!("\nAll {} checks passed", checks.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::get_checks;
+    use crate::build::packages::PackageOptions;
+    use crate::test_fixture::TestProject;
+
+    #[test]
+    fn should_fail_the_checks_of_a_missing_dependency_and_a_missing_bsc() {
+        let project = TestProject::new("doctor");
+        project.write(
+            "bsconfig.json",
+            r#"{
+                "name": "root",
+                "sources": "src",
+                "bs-dependencies": ["dep"],
+                "pinned-dependencies": ["pinned"],
+                "bs-dev-dependencies": ["missing-dev"]
+            }"#,
+        );
+        // the rescript package without the bsc of this platform
+        project.write("node_modules/rescript/package.json", r#"{ "version": "11.0.0" }"#);
+        project.write("node_modules/rescript/rescript", "");
+        project.write(
+            "node_modules/dep/bsconfig.json",
+            r#"{ "name": "dep", "sources": "src", "bs-dev-dependencies": ["dev-of-dep"] }"#,
+        );
+        project.write(
+            "node_modules/pinned/bsconfig.json",
+            r#"{ "name": "pinned", "sources": "src", "bs-dev-dependencies": ["dev-of-pinned"] }"#,
+        );
+
+        let checks = get_checks(&project.path(), &PackageOptions::default());
+        let result = |name: &str| {
+            checks
+                .iter()
+                .find(|check| check.name == name)
+                .map(|check| check.result.is_ok())
+        };
+
+        assert_eq!(result("Root config"), Some(true));
+        assert_eq!(result("node_modules"), Some(true));
+        assert_eq!(result("rescript"), Some(true));
+        assert_eq!(result("bsc"), Some(false));
+        assert_eq!(result("ReScript version"), None);
+        assert_eq!(result("Package dep"), Some(true));
+        assert_eq!(result("Package pinned"), Some(true));
+        // the dev dependencies are only needed for the root package and the pinned dependencies
+        assert_eq!(result("Package missing-dev"), Some(false));
+        assert_eq!(result("Package dev-of-pinned"), Some(false));
+        assert_eq!(result("Package dev-of-dep"), None);
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::mpsc::Sender;
+
+/// Events that are emitted during a build, keyed by module name. This allows embedders (editors for
+/// instance) to follow a build while it happens, instead of waiting for the final result.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildEvent {
+    ParseStarted { module_name: String },
+    ParseFinished { module_name: String, success: bool },
+    CompileStarted { module_name: String },
+    CompileFinished { module_name: String, success: bool },
+    Diagnostic { module_name: String, message: String },
+}
+
+pub fn emit(events: Option<&Sender<BuildEvent>>, event: BuildEvent) {
+    if let Some(events) = events {
+        // the receiving end may be gone, which is fine, nobody is listening anymore
+        let _ = events.send(event);
+    }
+}
+
+pub fn emit_diagnostic(events: Option<&Sender<BuildEvent>>, module_name: &str, message: &str) {
+    emit(
+        events,
+        BuildEvent::Diagnostic {
+            module_name: module_name.to_string(),
+            message: message.to_string(),
+        },
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn should_emit_events_to_the_receiver() {
+        let (sender, receiver) = channel();
+        emit(
+            Some(&sender),
+            BuildEvent::ParseStarted {
+                module_name: String::from("Main"),
+            },
+        );
+        emit(
+            None,
+            BuildEvent::ParseStarted {
+                module_name: String::from("Other"),
+            },
+        );
+        drop(sender);
+
+        assert_eq!(
+            receiver.iter().collect::<Vec<BuildEvent>>(),
+            vec![BuildEvent::ParseStarted {
+                module_name: String::from("Main")
+            }]
+        );
+    }
+}
@@ -0,0 +1,749 @@
+use super::build_types::*;
+use super::compile;
+use super::packages;
+use super::parse;
+use crate::bsconfig::FlagSource;
+use crate::helpers;
+use ahash::{AHashMap, AHashSet};
+use console::style;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+fn print_command(bsc_path: &str, cwd: &str, args: &[String]) {
+    println!("  {} {}", style("cwd:").dim(), cwd);
+    println!("  {} {} {}", style("cmd:").dim(), bsc_path, args.join(" "));
+}
+
+/// Finds a module by its name. The name can be given without the namespace suffix, so `MyModule`
+/// finds `MyModule-MyNamespace`, as long as only one namespace has a module with that name.
+pub fn resolve_module<'a>(
+    modules: &'a AHashMap<String, Module>,
+    module_name: &str,
+) -> Option<(&'a String, &'a Module)> {
+    if let Some(module) = modules.get_key_value(module_name) {
+        return Some(module);
+    }
+    let mut candidates = modules
+        .iter()
+        .filter(|(name, _)| name.split('-').next() == Some(module_name))
+        .collect::<Vec<(&String, &Module)>>();
+    candidates.sort_by_key(|(name, _)| *name);
+    match candidates.as_slice() {
+        [candidate] => Some(*candidate),
+        [] => None,
+        _ => {
+            println!(
+                "{}: {} is ambiguous, found: {}",
+                style("Error").red(),
+                module_name,
+                candidates
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            );
+            None
+        }
+    }
+}
+
+/// Finds the module of a source file (the implementation or the interface), by its absolute path.
+pub fn file_path_to_module<'a>(build_state: &'a BuildState, file_path: &str) -> Option<&'a String> {
+    build_state
+        .modules
+        .iter()
+        .find(|(_, module)| {
+            let package_path = match build_state.get_package(&module.package_name) {
+                Some(package) => Path::new(&package.path),
+                None => return false,
+            };
+            match &module.source_type {
+                SourceType::SourceFile(source_file) => {
+                    package_path.join(&source_file.implementation.path) == Path::new(file_path)
+                        || source_file
+                            .interface
+                            .as_ref()
+                            .map(|interface| package_path.join(&interface.path) == Path::new(file_path))
+                            .unwrap_or(false)
+                }
+                SourceType::MlMap(_) => false,
+            }
+        })
+        .map(|(module_name, _)| module_name)
+}
+
+/// Reads the packages and modules of the project, for the commands that look at a build. It doesn't
+/// write anything (ie. the mlmaps), so the next build still sees what changed since the last one.
+pub fn read_build_state(
+    project_root: &str,
+    workspace_root: Option<String>,
+    package_options: &packages::PackageOptions,
+) -> BuildState {
+    let root_config_name = packages::get_package_name(project_root);
+    let packages =
+        packages::read_package_tree(&None, project_root, workspace_root, true, &[], package_options);
+    let mut build_state = BuildState::new(project_root.to_owned(), root_config_name, packages);
+    packages::parse_packages(&mut build_state, false);
+    build_state
+}
+
+/// Prints where the source files and the ASTs of a module are.
+pub fn where_module(
+    project_root: &str,
+    module_name: &str,
+    package_options: &packages::PackageOptions,
+) -> Result<(), String> {
+    let workspace_root = helpers::get_workspace_root(project_root);
+    let build_state = read_build_state(project_root, workspace_root, package_options);
+
+    let (module_name, module) = match resolve_module(&build_state.modules, module_name) {
+        Some(module) => module,
+        None => {
+            return Err(format!(
+                "{}: Could not find module {}",
+                style("Error").red(),
+                module_name
+            ))
+        }
+    };
+    let package = build_state
+        .get_package(&module.package_name)
+        .expect("Package not found");
+
+    println!("{} {}", style("Module:").bold(), module_name);
+    println!("{} {}", style("Package:").bold(), package.name);
+    match &module.source_type {
+        SourceType::MlMap(_) => {
+            println!("{} {}", style("mlmap:").bold(), package.get_mlmap_path());
+        }
+        SourceType::SourceFile(source_file) => {
+            let implementation = &source_file.implementation.path;
+            if !source_file.interface_only {
+                println!(
+                    "{} {}",
+                    style("Source:").bold(),
+                    Path::new(&package.path).join(implementation).to_string_lossy()
+                );
+                println!(
+                    "{} {}",
+                    style("AST:").bold(),
+                    package.get_ast_path(implementation)
+                );
+            }
+            if let Some(interface) = &source_file.interface {
+                println!(
+                    "{} {}",
+                    style("Interface:").bold(),
+                    Path::new(&package.path).join(&interface.path).to_string_lossy()
+                );
+                println!(
+                    "{} {}",
+                    style("Interface AST:").bold(),
+                    package.get_iast_path(&interface.path)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints how a single module is parsed to an AST and compiled, without running bsc. This uses
+/// the same argument construction as the build, so it shows exactly what a build would invoke.
+pub fn explain(
+    project_root: &str,
+    module_name: &str,
+    compile_options: &CompileOptions,
+    package_options: &packages::PackageOptions,
+) -> Result<(), String> {
+    let workspace_root = helpers::get_workspace_root(project_root);
+    let bsc_path = helpers::get_bsc(
+        project_root,
+        workspace_root.to_owned(),
+        package_options.node_modules.as_deref(),
+    );
+    let rescript_version = super::get_version(&bsc_path, compile_options);
+    let build_state = read_build_state(project_root, workspace_root.to_owned(), package_options);
+
+    let (module_name, module) = match resolve_module(&build_state.modules, module_name) {
+        Some(module) => module,
+        None => {
+            return Err(format!(
+                "{}: Could not find module {}",
+                style("Error").red(),
+                module_name
+            ))
+        }
+    };
+    let package = build_state
+        .get_package(&module.package_name)
+        .expect("Package not found");
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Could not find root package");
+    let build_path = package.get_build_path();
+    // a package can have its own bsc (see `Package::get_bsc_path`)
+    let rescript_version = package.get_version(&bsc_path, &rescript_version, compile_options);
+    let bsc_path = package.get_bsc_path(&bsc_path);
+
+    println!("{} {}", style("Module:").bold(), module_name);
+    println!("{} {}", style("Package:").bold(), package.name);
+    println!("{} {}", style("Package path:").bold(), package.path);
+    println!(
+        "{} {}",
+        style("Namespace:").bold(),
+        package.namespace.to_suffix().unwrap_or("-".to_string())
+    );
+    println!("{} {}", style("bsc:").bold(), bsc_path);
+    println!("{} {}", style("ReScript version:").bold(), rescript_version);
+
+    let source_file = match &module.source_type {
+        SourceType::MlMap(_) => {
+            println!(
+                "\n{} {} (namespace map, compiled with -no-alias-deps)",
+                style("mlmap:").bold(),
+                package.get_mlmap_path()
+            );
+            return Ok(());
+        }
+        SourceType::SourceFile(source_file) => source_file,
+    };
+
+    let mut files = vec![];
+    if !source_file.interface_only {
+        files.push((source_file.implementation.path.to_owned(), false));
+    }
+    if let Some(interface) = &source_file.interface {
+        files.insert(0, (interface.path.to_owned(), true));
+    }
+
+    for (file, is_interface) in files {
+        let (_, parser_args) = parse::parser_args(
+            package,
+            root_package,
+            &file,
+            &build_state.project_root,
+            &rescript_version,
+            workspace_root.to_owned(),
+            compile_options,
+        );
+        println!("\n{} {}", style("Parse").bold(), file);
+        print_command(
+            &bsc_path,
+            &package.get_ppx_working_directory(&build_state.project_root),
+            &[parse::get_refmt_args(&file, &bsc_path), parser_args].concat(),
+        );
+
+        let ast_path = if is_interface {
+            package.get_iast_path(&file)
+        } else {
+            package.get_ast_path(&file)
+        };
+        let compiler_args = compile::compiler_args(
+            package,
+            root_package,
+            &ast_path,
+            module,
+            is_interface,
+            &build_state.packages,
+            &CompileContext::new(&bsc_path, &rescript_version, compile_options),
+        );
+        println!("{} {}", style("Compile").bold(), file);
+        print_command(&bsc_path, &build_path, &compiler_args);
+    }
+
+    if source_file.interface_only {
+        println!(
+            "\n{} none (the module only has an interface)",
+            style("Output:").bold()
+        );
+        return Ok(());
+    }
+    for output_path in crate::bsconfig::get_output_paths(
+        &root_package.bsconfig,
+        &package.path,
+        &source_file.implementation.path,
+    ) {
+        println!("\n{} {}", style("Output:").bold(), output_path);
+    }
+    Ok(())
+}
+
+/// Lists the groups of flags next to where they come from, aligned.
+fn format_flag_sources(flag_sources: &[(FlagSource, Vec<String>)]) -> String {
+    let flags = flag_sources
+        .iter()
+        .map(|(_, args)| args.join(" "))
+        .collect::<Vec<String>>();
+    let width = flags.iter().map(|flags| flags.len()).max().unwrap_or(0);
+    flags
+        .iter()
+        .zip(flag_sources)
+        .map(|(flags, (source, _))| format!("  {:<width$}  {}\n", flags, style(source).dim(), width = width))
+        .collect()
+}
+
+/// Prints the flags that a module is parsed and compiled with, and where every flag comes from: the
+/// bsconfig.json of the package, the shared config, the root bsconfig.json, the command line, or
+/// rewatch itself. For debugging which config a flag is inherited from.
+pub fn explain_flags(
+    project_root: &str,
+    module_name: &str,
+    compile_options: &CompileOptions,
+    package_options: &packages::PackageOptions,
+) -> Result<(), String> {
+    let workspace_root = helpers::get_workspace_root(project_root);
+    let bsc_path = helpers::get_bsc(
+        project_root,
+        workspace_root.to_owned(),
+        package_options.node_modules.as_deref(),
+    );
+    let build_state = read_build_state(project_root, workspace_root.to_owned(), package_options);
+
+    let (module_name, module) = match resolve_module(&build_state.modules, module_name) {
+        Some(module) => module,
+        None => {
+            return Err(format!(
+                "{}: Could not find module {}",
+                style("Error").red(),
+                module_name
+            ))
+        }
+    };
+    let package = build_state
+        .get_package(&module.package_name)
+        .expect("Package not found");
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Could not find root package");
+    let rescript_version = package.get_version(
+        &bsc_path,
+        &super::get_version(&bsc_path, compile_options),
+        compile_options,
+    );
+    let bsc_path = package.get_bsc_path(&bsc_path);
+
+    println!("{} {}", style("Module:").bold(), module_name);
+    println!("{} {}", style("Package:").bold(), package.name);
+
+    let source_file = match &module.source_type {
+        SourceType::MlMap(_) => {
+            println!(
+                "\nThe namespace map is compiled with the flags of rewatch only: -w -49 -color always -no-alias-deps"
+            );
+            return Ok(());
+        }
+        SourceType::SourceFile(source_file) => source_file,
+    };
+
+    let mut files = vec![];
+    if !source_file.interface_only {
+        files.push((source_file.implementation.path.to_owned(), false));
+    }
+    if let Some(interface) = &source_file.interface {
+        files.insert(0, (interface.path.to_owned(), true));
+    }
+
+    for (file, is_interface) in files {
+        let (_, parser_args) = parse::parser_arg_sources(
+            package,
+            root_package,
+            &file,
+            &build_state.project_root,
+            &rescript_version,
+            workspace_root.to_owned(),
+            compile_options,
+        );
+        let refmt_args = (FlagSource::Rewatch, parse::get_refmt_args(&file, &bsc_path));
+        println!("\n{} {}", style("Parse").bold(), file);
+        print!(
+            "{}",
+            format_flag_sources(
+                &[vec![refmt_args], parser_args]
+                    .concat()
+                    .into_iter()
+                    .filter(|(_, args)| !args.is_empty())
+                    .collect::<Vec<(FlagSource, Vec<String>)>>()
+            )
+        );
+
+        let ast_path = if is_interface {
+            package.get_iast_path(&file)
+        } else {
+            package.get_ast_path(&file)
+        };
+        let compiler_args = compile::compiler_arg_sources(
+            package,
+            root_package,
+            &ast_path,
+            module,
+            is_interface,
+            &build_state.packages,
+            &CompileContext::new(&bsc_path, &rescript_version, compile_options),
+        );
+        println!("{} {}", style("Compile").bold(), file);
+        print!("{}", format_flag_sources(&compiler_args));
+    }
+    Ok(())
+}
+
+static EXPLAIN_DIRTY: AtomicBool = AtomicBool::new(false);
+
+/// Prints the modules that are parsed and compiled again, and why, before the build starts on them
+/// (`--explain-dirty`).
+pub fn set_explain_dirty(explain_dirty: bool) {
+    EXPLAIN_DIRTY.store(explain_dirty, Ordering::Relaxed);
+}
+
+pub fn is_explain_dirty() -> bool {
+    EXPLAIN_DIRTY.load(Ordering::Relaxed)
+}
+
+fn get_dirty_reason(source_file: &SourceFile) -> Option<String> {
+    let reason = |dirty: bool, reason: &Option<DirtyReason>| {
+        dirty.then(|| {
+            reason
+                .as_ref()
+                .map_or("unknown".to_string(), |reason| reason.to_string())
+        })
+    };
+    let implementation = &source_file.implementation;
+    let implementation_reason = match source_file.interface_only {
+        true => None,
+        false => reason(implementation.dirty, &implementation.dirty_reason),
+    };
+    implementation_reason.or_else(|| {
+        source_file
+            .interface
+            .as_ref()
+            .and_then(|interface| reason(interface.dirty, &interface.dirty_reason))
+            .map(|reason| format!("interface {}", reason))
+    })
+}
+
+/// Lists the dirty modules with the reason they are dirty, grouped by package, and how many modules
+/// are dirty for every reason. Modules that are only compiled again because one of their
+/// dependencies changed aren't known yet at this point, so they aren't listed.
+pub fn get_dirty_report(modules: &AHashMap<String, Module>) -> String {
+    let mut packages: BTreeMap<&String, BTreeMap<&String, String>> = BTreeMap::new();
+    let mut reasons: BTreeMap<String, usize> = BTreeMap::new();
+    for (module_name, module) in modules {
+        if let SourceType::SourceFile(source_file) = &module.source_type {
+            if let Some(reason) = get_dirty_reason(source_file) {
+                *reasons.entry(reason.to_owned()).or_insert(0) += 1;
+                packages
+                    .entry(&module.package_name)
+                    .or_default()
+                    .insert(module_name, reason);
+            }
+        }
+    }
+
+    let dirty_modules = reasons.values().sum::<usize>();
+    let mut report = format!("{} dirty modules", dirty_modules);
+    if dirty_modules > 0 {
+        report.push_str(&format!(
+            " ({})",
+            reasons
+                .iter()
+                .map(|(reason, count)| format!("{}: {}", reason, count))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ));
+    }
+    for (package_name, modules) in packages {
+        report.push_str(&format!("\n  {} ({})", package_name, modules.len()));
+        for (module_name, reason) in modules {
+            report.push_str(&format!("\n    {}: {}", module_name, reason));
+        }
+    }
+    report
+}
+
+/// The modules that the next build would parse or compile again (`rewatch check`), sorted, with the
+/// reason. Unlike `get_dirty_report`, this includes the modules of which only the compiled output is
+/// missing or outdated, and the removed modules of which the outputs are still there (or their
+/// namespace, when they had one).
+pub fn get_stale_modules(
+    modules: &AHashMap<String, Module>,
+    deleted_module_names: &AHashSet<String>,
+) -> Vec<(String, String)> {
+    let mut stale_modules = modules
+        .iter()
+        .filter_map(|(module_name, module)| {
+            let reason = match &module.source_type {
+                SourceType::SourceFile(source_file) => get_dirty_reason(source_file)
+                    .or_else(|| module.compile_dirty.then(|| "not compiled".to_string())),
+                SourceType::MlMap(MlMap { dirty }) => {
+                    (*dirty || module.compile_dirty).then(|| "namespace changed".to_string())
+                }
+            };
+            reason.map(|reason| (module_name.to_owned(), reason))
+        })
+        .collect::<BTreeMap<String, String>>();
+    for module_name in deleted_module_names {
+        stale_modules.entry(module_name.to_owned()).or_insert_with(|| {
+            match modules.contains_key(module_name) {
+                true => "module removed".to_string(),
+                false => "removed".to_string(),
+            }
+        });
+    }
+    stale_modules.into_iter().collect()
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct StaleModule {
+    pub module: String,
+    /// The absolute path of the source file (or the mlmap of a namespace), none for a module of
+    /// which the source file was removed
+    pub file_path: Option<String>,
+    pub reason: String,
+}
+
+/// The stale modules (see `get_stale_modules`) with their files, for `rewatch check --json`.
+pub fn get_stale_module_list(
+    build_state: &BuildState,
+    deleted_module_names: &AHashSet<String>,
+) -> Vec<StaleModule> {
+    get_stale_modules(&build_state.modules, deleted_module_names)
+        .into_iter()
+        .map(|(module_name, reason)| {
+            let file_path = build_state.get_module(&module_name).and_then(|module| {
+                let package = build_state.get_package(&module.package_name)?;
+                Some(match &module.source_type {
+                    SourceType::SourceFile(source_file) => Path::new(&package.path)
+                        .join(&source_file.implementation.path)
+                        .to_string_lossy()
+                        .to_string(),
+                    SourceType::MlMap(_) => package.get_mlmap_path(),
+                })
+            });
+            StaleModule {
+                module: module_name,
+                file_path,
+                reason,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        get_dirty_report, get_stale_module_list, get_stale_modules, read_build_state, resolve_module,
+        StaleModule,
+    };
+    use crate::build::build_types::*;
+    use crate::build::{namespaces, packages};
+    use crate::test_fixture::TestProject;
+    use ahash::{AHashMap, AHashSet};
+
+    fn modules(module_names: &[&str]) -> AHashMap<String, Module> {
+        module_names
+            .iter()
+            .map(|module_name| {
+                (
+                    module_name.to_string(),
+                    Module {
+                        source_type: SourceType::MlMap(MlMap { dirty: false }),
+                        deps: AHashSet::new(),
+                        dependents: AHashSet::new(),
+                        package_name: String::from("package"),
+                        compile_dirty: false,
+                        last_compiled_cmi: None,
+                        last_compiled_cmt: None,
+                        compile_duration: None,
+                        compile_outcome: None,
+                        diagnostics: vec![],
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn resolve(modules: &AHashMap<String, Module>, module_name: &str) -> Option<String> {
+        resolve_module(modules, module_name).map(|(module_name, _)| module_name.to_owned())
+    }
+
+    #[test]
+    fn should_resolve_modules_with_and_without_namespace() {
+        let modules = modules(&["Plain", "Foo-MyNamespace", "Bar-First", "Bar-Second"]);
+
+        assert_eq!(resolve(&modules, "Plain"), Some(String::from("Plain")));
+        assert_eq!(
+            resolve(&modules, "Foo-MyNamespace"),
+            Some(String::from("Foo-MyNamespace"))
+        );
+        assert_eq!(resolve(&modules, "Foo"), Some(String::from("Foo-MyNamespace")));
+        assert_eq!(resolve(&modules, "Bar"), None);
+        assert_eq!(resolve(&modules, "Bar-Second"), Some(String::from("Bar-Second")));
+        assert_eq!(resolve(&modules, "Missing"), None);
+    }
+
+    fn source_file_module(package_name: &str, dirty_reason: Option<DirtyReason>, interface: bool) -> Module {
+        let interface = interface.then(|| Interface {
+            path: String::from("src/Module.resi"),
+            parse_state: ParseState::Pending,
+            compile_state: CompileState::Pending,
+            last_modified: std::time::SystemTime::UNIX_EPOCH,
+            dirty: true,
+            dirty_reason: Some(DirtyReason::SourceModified),
+        });
+        Module {
+            source_type: SourceType::SourceFile(SourceFile {
+                implementation: Implementation {
+                    path: String::from("src/Module.res"),
+                    parse_state: ParseState::Pending,
+                    compile_state: CompileState::Pending,
+                    last_modified: std::time::SystemTime::UNIX_EPOCH,
+                    dirty: dirty_reason.is_some(),
+                    dirty_reason,
+                },
+                interface,
+                interface_only: false,
+                is_dev: false,
+                virtual_contents: None,
+            }),
+            package_name: String::from(package_name),
+            ..modules(&["Module"]).remove("Module").unwrap()
+        }
+    }
+
+    #[test]
+    fn should_report_the_dirty_modules_by_package() {
+        let mut modules = modules(&["Namespace"]);
+        modules.insert(
+            String::from("A"),
+            source_file_module("main", Some(DirtyReason::FlagsChanged), false),
+        );
+        modules.insert(
+            String::from("B"),
+            source_file_module("main", Some(DirtyReason::SourceModified), false),
+        );
+        modules.insert(String::from("C"), source_file_module("dep", None, true));
+        modules.insert(String::from("D"), source_file_module("dep", None, false));
+
+        assert_eq!(
+            get_dirty_report(&modules),
+            [
+                "3 dirty modules (flags changed: 1, interface source modified: 1, source modified: 1)",
+                "  dep (1)",
+                "    C: interface source modified",
+                "  main (2)",
+                "    A: flags changed",
+                "    B: source modified",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn should_list_the_modules_that_would_be_built_again() {
+        let mut modules = modules(&["Namespace"]);
+        modules.insert(
+            String::from("A"),
+            source_file_module("main", Some(DirtyReason::SourceModified), false),
+        );
+        modules.insert(String::from("B"), source_file_module("main", None, false));
+        modules.insert(
+            String::from("C"),
+            Module {
+                compile_dirty: true,
+                ..source_file_module("main", None, false)
+            },
+        );
+        let deleted_module_names = AHashSet::from_iter([String::from("Deleted"), String::from("Namespace")]);
+
+        assert_eq!(
+            get_stale_modules(&modules, &deleted_module_names),
+            vec![
+                (String::from("A"), String::from("source modified")),
+                (String::from("C"), String::from("not compiled")),
+                (String::from("Deleted"), String::from("removed")),
+                (String::from("Namespace"), String::from("module removed")),
+            ]
+        );
+        modules.retain(|module_name, _| module_name == "B" || module_name == "Namespace");
+        assert_eq!(get_stale_modules(&modules, &AHashSet::new()), vec![]);
+    }
+
+    #[test]
+    fn should_list_the_stale_modules_with_their_files_as_json() {
+        let project = TestProject::new("stale-json");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "");
+        project.write("src/B.res", "");
+        let mut build_state = project.build_state("root");
+        for (module_name, module) in build_state.modules.iter_mut() {
+            if let SourceType::SourceFile(source_file) = &mut module.source_type {
+                source_file.implementation.dirty = module_name == "A";
+                source_file.implementation.dirty_reason = Some(DirtyReason::SourceModified);
+            }
+            module.compile_dirty = false;
+        }
+
+        let stale_modules = get_stale_module_list(&build_state, &AHashSet::from_iter([String::from("Old")]));
+
+        let a_path = project.join("src/A.res").to_string_lossy().to_string();
+        assert_eq!(
+            stale_modules,
+            vec![
+                StaleModule {
+                    module: String::from("A"),
+                    file_path: Some(a_path.to_owned()),
+                    reason: String::from("source modified"),
+                },
+                StaleModule {
+                    module: String::from("Old"),
+                    file_path: None,
+                    reason: String::from("removed"),
+                },
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&stale_modules).unwrap()[0],
+            serde_json::json!({ "module": "A", "file_path": a_path, "reason": "source modified" })
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_not_hide_a_changed_namespace_from_the_next_build() {
+        let project = TestProject::new("read-namespace");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "namespace": "MyNamespace" }"#,
+        );
+        project.write("src/A.res", "");
+        // a bsc that writes the cmi of the mlmap
+        let bsc_path = project.write_script(
+            "bsc.exe",
+            "for arg in \"$@\"; do last=\"$arg\"; done\ntouch \"$(basename \"$last\" .mlmap).cmi\"\n",
+        );
+        let is_mlmap_dirty = || {
+            let build_state = project.build_state("root");
+            let mlmap = build_state.get_module("MyNamespace").unwrap();
+            matches!(mlmap.source_type, SourceType::MlMap(MlMap { dirty: true }))
+        };
+        is_mlmap_dirty();
+        let package = project.make().remove("root").unwrap();
+        namespaces::compile_mlmap(&package, "MyNamespace", &bsc_path.to_string_lossy(), None).unwrap();
+        let built = is_mlmap_dirty();
+
+        // ie. `rewatch where A`, before the next build
+        project.write("src/B.res", "");
+        let mlmap = std::fs::read_to_string(package.get_mlmap_path()).unwrap();
+        read_build_state(&project.path(), None, &packages::PackageOptions::default());
+        let mlmap_after_reading = std::fs::read_to_string(package.get_mlmap_path()).unwrap();
+        let added_file = is_mlmap_dirty();
+        // the next build fails before compiling the namespace
+        let added_file_again = is_mlmap_dirty();
+
+        assert!(!built);
+        assert_eq!(mlmap_after_reading, mlmap);
+        assert!(added_file);
+        assert!(added_file_again);
+    }
+}
@@ -0,0 +1,183 @@
+use super::build_types::*;
+use super::compile;
+use super::packages;
+use crate::bsconfig;
+use crate::helpers;
+use ahash::AHashSet;
+use log::error;
+use std::path::Path;
+
+// When the flags we pass to bsc for a package change (bsc-flags, ppx-flags, jsx, warnings, ...), the
+// compiler assets of that package are stale, even though none of the source files changed. So we
+// store a hash of all the flags in the build folder of the package, and when it differs from the
+// hash of the current flags, all modules of the package are rebuilt.
+
+pub static FLAGS_FILE: &str = ".rewatch-flags";
+pub static COMPILER_VERSION_FILE: &str = ".rewatch-compiler-version";
+
+pub fn get_flags_hash(
+    package: &packages::Package,
+    root_package: &packages::Package,
+    version: &str,
+    compile_options: &CompileOptions,
+) -> String {
+    let flags = [
+        vec![version.to_string()],
+        bsconfig::get_bsc_flags(&package.bsconfig, &compile_options.bsc_flags),
+        compile::get_profile_args(compile_options.release),
+        bsconfig::flatten_flags(&package.bsconfig.ppx_flags),
+        package.get_warning_args(compile_options),
+        root_package.get_jsx_args(),
+        root_package.get_jsx_module_args(),
+        root_package.get_jsx_mode_args(),
+        package.get_uncurried_args(version, root_package),
+        vec![package.namespace.to_suffix().unwrap_or_default()],
+        bsconfig::get_package_specs(&root_package.bsconfig)
+            .iter()
+            .map(|spec| {
+                format!(
+                    "{}:{}:{}",
+                    spec.module,
+                    spec.in_source,
+                    spec.get_suffix(&root_package.bsconfig)
+                )
+            })
+            .collect(),
+        package.bsconfig.bs_dependencies.to_owned().unwrap_or_default(),
+        package
+            .bsconfig
+            .bs_external_includes
+            .to_owned()
+            .unwrap_or_default(),
+        vec![format!("{:?}", package.bsconfig.ppx_working_directory)],
+        // the paths in the ASTs and compiled output are different, but only add it when it's used,
+        // so the hash of existing builds doesn't change
+        match compile_options.relative_paths {
+            true => vec!["--relative-paths".to_string()],
+            false => vec![],
+        },
+        // without the JavaScript, the modules need to be compiled again by a full build
+        match compile_options.check_only {
+            true => vec!["--check-only".to_string()],
+            false => vec![],
+        },
+    ]
+    .concat();
+
+    blake3::hash(flags.join("\n").as_bytes()).to_hex().to_string()
+}
+
+fn get_flags_file_path(package: &packages::Package) -> String {
+    Path::new(&package.get_bs_build_path())
+        .join(FLAGS_FILE)
+        .to_string_lossy()
+        .to_string()
+}
+
+fn get_compiler_version_file_path(build_state: &BuildState) -> String {
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Could not find root package");
+    Path::new(&root_package.get_bs_build_path())
+        .join(COMPILER_VERSION_FILE)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Returns the version of the compiler of the previous build, when it differs from the current
+/// version. The ASTs and compiler assets of another version are incompatible, so everything needs
+/// to be built again.
+pub fn get_changed_compiler_version(build_state: &BuildState, version: &str) -> Option<String> {
+    std::fs::read_to_string(get_compiler_version_file_path(build_state))
+        .ok()
+        .map(|previous_version| previous_version.trim().to_string())
+        .filter(|previous_version| previous_version != version)
+}
+
+/// Stores the version of the compiler, so the next build can detect a compiler upgrade.
+pub fn write_compiler_version(build_state: &BuildState, version: &str) {
+    if let Err(e) = helpers::write_atomically(get_compiler_version_file_path(build_state), version) {
+        error!("Could not write {}. \n{:?}", COMPILER_VERSION_FILE, e);
+    }
+}
+
+/// Marks all modules of packages with changed flags as dirty, so they are parsed and compiled again.
+/// When there is a `reason_for_all` (ie. the compiler version changed), all packages are marked.
+/// Returns the names of these packages.
+pub fn mark_packages_with_changed_flags_dirty(
+    build_state: &mut BuildState,
+    version: &str,
+    reason_for_all: Option<DirtyReason>,
+) -> AHashSet<String> {
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Could not find root package");
+
+    // packages that are not part of this build (no modules) are not compiled, so we leave them alone
+    let package_names_with_modules = build_state
+        .modules
+        .values()
+        .map(|module| &module.package_name)
+        .collect::<AHashSet<&String>>();
+
+    let changed_packages = build_state
+        .packages
+        .values()
+        .filter(|package| package_names_with_modules.contains(&package.name))
+        .filter(|package| {
+            if reason_for_all.is_some() {
+                return true;
+            }
+            let previous_hash = std::fs::read_to_string(get_flags_file_path(package)).ok();
+            previous_hash
+                != Some(get_flags_hash(
+                    package,
+                    root_package,
+                    version,
+                    &build_state.compile_options,
+                ))
+        })
+        .map(|package| package.name.to_owned())
+        .collect::<AHashSet<String>>();
+
+    let reason = reason_for_all.unwrap_or(DirtyReason::FlagsChanged);
+    build_state
+        .modules
+        .values_mut()
+        .filter(|module| changed_packages.contains(&module.package_name))
+        .for_each(|module| {
+            module.compile_dirty = true;
+            match module.source_type {
+                SourceType::SourceFile(ref mut source_file) => {
+                    source_file.implementation.dirty = true;
+                    source_file.implementation.dirty_reason = Some(reason.to_owned());
+                    if let Some(interface) = source_file.interface.as_mut() {
+                        interface.dirty = true;
+                        interface.dirty_reason = Some(reason.to_owned());
+                    }
+                }
+                SourceType::MlMap(ref mut mlmap) => mlmap.dirty = true,
+            }
+        });
+
+    changed_packages
+}
+
+/// Stores the hash of the current flags of the packages, after they are compiled with these flags.
+pub fn write_flags(build_state: &BuildState, package_names: &AHashSet<String>, version: &str) {
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Could not find root package");
+
+    package_names
+        .iter()
+        .filter_map(|package_name| build_state.get_package(package_name))
+        .for_each(|package| {
+            if let Err(e) = helpers::write_atomically(
+                get_flags_file_path(package),
+                get_flags_hash(package, root_package, version, &build_state.compile_options),
+            ) {
+                error!("Could not write {}. {}. \n{:?}", FLAGS_FILE, &package.name, e);
+            }
+        });
+}
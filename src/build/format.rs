@@ -0,0 +1,190 @@
+use super::build_types::*;
+use super::packages;
+use crate::helpers;
+use crate::helpers::emojis::*;
+use console::style;
+use rayon::prelude::*;
+use std::path::Path;
+use std::process::Command;
+
+/// The ReScript source files of the packages of the project itself (the root package and the pinned
+/// dependencies), sorted. The packages in node_modules aren't ours to format.
+fn get_source_files(build_state: &BuildState) -> Vec<String> {
+    let mut source_files = build_state
+        .modules
+        .values()
+        .filter_map(|module| {
+            let package = build_state.get_package(&module.package_name)?;
+            match &module.source_type {
+                SourceType::SourceFile(source_file) if package.is_root || package.is_pinned_dep => Some(
+                    (!source_file.interface_only)
+                        .then_some(&source_file.implementation.path)
+                        .into_iter()
+                        .chain(source_file.interface.as_ref().map(|interface| &interface.path))
+                        .map(|path| Path::new(&package.path).join(path).to_string_lossy().to_string())
+                        .collect::<Vec<String>>(),
+                ),
+                _ => None,
+            }
+        })
+        .flatten()
+        .filter(|path| path.ends_with(".res") || path.ends_with(".resi"))
+        .collect::<Vec<String>>();
+    source_files.sort();
+    source_files
+}
+
+/// Formats the source file with `bsc -format`, which prints the formatted source.
+fn format_file(bsc_path: &str, path: &str) -> Result<String, String> {
+    let output = helpers::run_command(Command::new(bsc_path).args(["-format", path]))
+        .map_err(|e| format!("Could not run bsc: {}", e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(helpers::decode_output(&output.stderr))
+    }
+}
+
+/// Returns the source files that aren't formatted. With `fix`, they are formatted in place. The
+/// files that bsc can't format (ie. because of a syntax error) are returned as errors.
+pub fn get_unformatted_files(
+    build_state: &BuildState,
+    bsc_path: &str,
+    fix: bool,
+) -> (Vec<String>, Vec<(String, String)>) {
+    let results = get_source_files(build_state)
+        .into_par_iter()
+        .map(|path| {
+            let result = format_file(bsc_path, &path).and_then(|formatted| {
+                let contents = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+                if contents == formatted {
+                    return Ok(false);
+                }
+                if fix {
+                    std::fs::write(&path, formatted).map_err(|e| e.to_string())?;
+                }
+                Ok(true)
+            });
+            (path, result)
+        })
+        .collect::<Vec<(String, Result<bool, String>)>>();
+
+    let mut unformatted = vec![];
+    let mut errors = vec![];
+    for (path, result) in results {
+        match result {
+            Ok(true) => unformatted.push(path),
+            Ok(false) => (),
+            Err(e) => errors.push((path, e)),
+        }
+    }
+    (unformatted, errors)
+}
+
+/// Checks that the source files of the project are formatted (`rewatch format-check`), and lists
+/// the ones that aren't. With `fix`, formats them instead.
+pub fn format_check(
+    project_root: &str,
+    fix: bool,
+    package_options: &packages::PackageOptions,
+) -> Result<(), String> {
+    let workspace_root = helpers::get_workspace_root(project_root);
+    let bsc_path = helpers::get_bsc(
+        project_root,
+        workspace_root.to_owned(),
+        package_options.node_modules.as_deref(),
+    );
+    // only the source files are read, the build folders are left alone
+    let packages =
+        packages::read_package_tree(&None, project_root, workspace_root, true, &[], package_options);
+    let root_config_name = packages::get_package_name(project_root);
+    let mut build_state = BuildState::new(project_root.to_owned(), root_config_name, packages);
+    packages::parse_packages(&mut build_state, false);
+
+    let (unformatted, errors) = get_unformatted_files(&build_state, &bsc_path, fix);
+    let relative = |path: &str| {
+        Path::new(path)
+            .strip_prefix(project_root)
+            .map_or(path.to_string(), |path| path.to_string_lossy().to_string())
+    };
+    for (path, e) in &errors {
+        println!(
+            "{}{} {}",
+            CROSS,
+            relative(path),
+            style("could not be formatted").red()
+        );
+        print!("{}", e);
+    }
+    for path in &unformatted {
+        match fix {
+            true => println!("{}{} {}", CHECKMARK, relative(path), style("formatted").dim()),
+            false => println!("{}{} {}", CROSS, relative(path), style("is not formatted").red()),
+        }
+    }
+
+    if !errors.is_empty() || (!fix && !unformatted.is_empty()) {
+        return Err(style(match fix {
+            true => format!("{} files could not be formatted", errors.len()),
+            false => format!(
+                "{} files are not formatted, run `rewatch format-check --fix` to format them",
+                unformatted.len() + errors.len()
+            ),
+        })
+        .red()
+        .to_string());
+    }
+    println!(
+        "{}{}",
+        CHECKMARK,
+        match fix {
+            true => format!("Formatted {} files", unformatted.len()),
+            false => "All files are formatted".to_string(),
+        }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::get_unformatted_files;
+    use crate::test_fixture::TestProject;
+
+    #[cfg(unix)]
+    #[test]
+    fn should_list_and_fix_the_unformatted_files() {
+        let project = TestProject::new("format-check");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "let a = 1\n");
+        project.write("src/B.res", "let b = 2   \n");
+        project.write("src/B.resi", "let b: int\n");
+        project.write("src/C.res", "let c = (\n");
+        // a formatter that removes trailing whitespace, and fails on unbalanced parentheses
+        let bsc_path = project.write_script(
+            "bsc.exe",
+            "grep -q '($' \"$2\" && { echo \"Syntax error in $2\" >&2; exit 1; }\nsed 's/ *$//' \"$2\"\n",
+        );
+        let bsc_path = bsc_path.to_string_lossy().to_string();
+
+        let build_state = project.build_state("root");
+        let (unformatted, errors) = get_unformatted_files(&build_state, &bsc_path, false);
+        let unchanged = std::fs::read_to_string(project.join("src/B.res")).unwrap();
+        let (fixed, _) = get_unformatted_files(&build_state, &bsc_path, true);
+        let formatted = std::fs::read_to_string(project.join("src/B.res")).unwrap();
+        let (after_fix, _) = get_unformatted_files(&build_state, &bsc_path, false);
+
+        let file_names = |paths: &[String]| {
+            paths
+                .iter()
+                .map(|path| path.rsplit('/').next().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(file_names(&unformatted), vec!["B.res"]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].0.ends_with("C.res") && errors[0].1.contains("Syntax error"));
+        assert_eq!(unchanged, "let b = 2   \n");
+        assert_eq!(file_names(&fixed), vec!["B.res"]);
+        assert_eq!(formatted, "let b = 2\n");
+        assert_eq!(after_fix, Vec::<String>::new());
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// When the build is interrupted (Ctrl-C), we don't want to leave half written ASTs, compiler
+// assets or JavaScript files behind, because they look up to date to the next build. So instead of
+// exiting right away, the signal handler only marks the build as interrupted. The build then stops
+// starting new bsc processes, waits for the running ones, removes the outputs of the modules it was
+// working on, and exits with `EXIT_CODE` without writing the caches of the build.
+//
+// Outside of a build, or on a second Ctrl-C, we exit right away. While the watcher waits for
+// changes, it's only marked as stopped, so it can print the summary of the session before it exits
+// (see `watcher::SessionStats`). On Windows there is no handler, so Ctrl-C exits right away as before.
+
+/// The exit code of an interrupted build (128 + SIGINT, like a shell).
+pub static EXIT_CODE: i32 = 130;
+
+static BUILDING: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static WATCHING: AtomicBool = AtomicBool::new(false);
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn handle_interrupt(_signal: libc::c_int) {
+    // only async-signal-safe calls here: atomics and _exit
+    let stops_gracefully = if BUILDING.load(Ordering::SeqCst) {
+        !INTERRUPTED.swap(true, Ordering::SeqCst)
+    } else {
+        WATCHING.load(Ordering::SeqCst) && !STOPPED.swap(true, Ordering::SeqCst)
+    };
+    if !stops_gracefully {
+        unsafe { libc::_exit(EXIT_CODE) }
+    }
+}
+
+pub fn install_handler() {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handle_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Marks that the watcher is running, so an interrupt between builds stops it with `is_stopped`
+/// instead of exiting right away.
+pub fn start_watching() {
+    WATCHING.store(true, Ordering::SeqCst);
+}
+
+/// Whether the watcher was interrupted while it waited for changes.
+pub fn is_stopped() -> bool {
+    STOPPED.load(Ordering::SeqCst)
+}
+
+/// Whether the build should stop starting bsc processes: on Ctrl-C, or when an embedder cancelled
+/// the build with its token (see `build::build`).
+pub fn is_cancelled(cancel: Option<&AtomicBool>) -> bool {
+    is_interrupted() || cancel.is_some_and(|cancel| cancel.load(Ordering::SeqCst))
+}
+
+/// Marks that a build is running until it's dropped, so an interrupt stops the build gracefully.
+pub struct Building;
+
+pub fn start_build() -> Building {
+    BUILDING.store(true, Ordering::SeqCst);
+    Building
+}
+
+impl Drop for Building {
+    fn drop(&mut self) {
+        BUILDING.store(false, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,182 @@
+use super::build_types::*;
+use crate::helpers;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+// With `--junit`, the build writes a JUnit XML report, so CI systems show the modules that failed
+// to compile next to the failing tests. Every package is a test suite, and every module of the
+// package a test case, that fails with the errors of bsc as the message. The modules that were up
+// to date pass without a time. Like the metrics, the report is only written when the build gets to
+// compiling, not when it fails before that (ie. on a parse error).
+
+static JUNIT_FILE: RwLock<Option<String>> = RwLock::new(None);
+
+/// Writes a JUnit XML report of the build to this file (`--junit`) with `write_junit_report`.
+pub fn set_junit_file(junit_file: Option<String>) {
+    *JUNIT_FILE.write().unwrap() = junit_file;
+}
+
+/// Escapes text for the attributes and the contents of XML elements. The colors of bsc are left
+/// out, as well as the control characters that XML doesn't allow.
+fn escape(text: &str) -> String {
+    console::strip_ansi_codes(text)
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t'))
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&apos;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+fn is_failed(source_file: &SourceFile) -> bool {
+    source_file.implementation.compile_state == CompileState::Error
+        || source_file
+            .interface
+            .as_ref()
+            .is_some_and(|interface| interface.compile_state == CompileState::Error)
+}
+
+fn get_test_case(module_name: &str, package_name: &str, module: &Module, source_file: &SourceFile) -> String {
+    let time = module.compile_duration.unwrap_or(Duration::ZERO).as_secs_f64();
+    let diagnostics = escape(&module.diagnostics.join("\n"));
+    let body = if is_failed(source_file) {
+        format!(
+            "      <failure message=\"{} failed to compile\">{}</failure>\n",
+            escape(module_name),
+            diagnostics
+        )
+    } else if !module.diagnostics.is_empty() {
+        format!("      <system-out>{}</system-out>\n", diagnostics)
+    } else {
+        "".to_string()
+    };
+    let attributes = format!(
+        "name=\"{}\" classname=\"{}\" file=\"{}\" time=\"{:.3}\"",
+        escape(module_name),
+        escape(package_name),
+        escape(&source_file.implementation.path),
+        time
+    );
+    if body.is_empty() {
+        format!("    <testcase {} />\n", attributes)
+    } else {
+        format!("    <testcase {}>\n{}    </testcase>\n", attributes, body)
+    }
+}
+
+/// The JUnit XML report of the build: a test suite for every package (sorted by name), with a test
+/// case for every module (without the namespaces).
+pub fn get_junit_report(build_state: &BuildState) -> String {
+    let mut packages: BTreeMap<&str, Vec<(&String, &Module, &SourceFile)>> = BTreeMap::new();
+    for (module_name, module) in &build_state.modules {
+        if let SourceType::SourceFile(source_file) = &module.source_type {
+            packages.entry(module.package_name.as_str()).or_default().push((
+                module_name,
+                module,
+                source_file,
+            ));
+        }
+    }
+
+    let mut suites = String::new();
+    let (mut total_tests, mut total_failures, mut total_time) = (0, 0, Duration::ZERO);
+    for (package_name, mut modules) in packages {
+        modules.sort_by_key(|(module_name, _, _)| *module_name);
+        let failures = modules
+            .iter()
+            .filter(|(_, _, source_file)| is_failed(source_file))
+            .count();
+        let time: Duration = modules
+            .iter()
+            .filter_map(|(_, module, _)| module.compile_duration)
+            .sum();
+        total_tests += modules.len();
+        total_failures += failures;
+        total_time += time;
+        suites.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+            escape(package_name),
+            modules.len(),
+            failures,
+            time.as_secs_f64()
+        ));
+        for (module_name, module, source_file) in modules {
+            suites.push_str(&get_test_case(module_name, package_name, module, source_file));
+        }
+        suites.push_str("  </testsuite>\n");
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"rewatch\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n{}</testsuites>\n",
+        total_tests,
+        total_failures,
+        total_time.as_secs_f64(),
+        suites
+    )
+}
+
+/// Writes the JUnit XML report of the build to the file of `set_junit_file`, if any.
+pub fn write_junit_report(build_state: &BuildState) -> Result<(), String> {
+    let junit_file = match JUNIT_FILE.read().unwrap().as_ref() {
+        Some(junit_file) => junit_file.to_owned(),
+        None => return Ok(()),
+    };
+    helpers::write_atomically(&junit_file, get_junit_report(build_state))
+        .map_err(|e| format!("Could not write the JUnit report to {}: {}", junit_file, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::get_junit_report;
+    use crate::build::build_types::*;
+    use crate::test_fixture::TestProject;
+    use std::time::Duration;
+
+    #[test]
+    fn should_report_the_modules_as_test_cases_of_their_package() {
+        let project = TestProject::new("junit");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "");
+        project.write("src/B.res", "");
+        let mut build_state = project.build_state("root");
+        let a = build_state.modules.get_mut("A").unwrap();
+        a.compile_duration = Some(Duration::from_millis(250));
+        if let SourceType::SourceFile(source_file) = &mut a.source_type {
+            source_file.implementation.compile_state = CompileState::Success;
+        }
+        let b = build_state.modules.get_mut("B").unwrap();
+        b.compile_duration = Some(Duration::from_millis(500));
+        b.diagnostics = vec![
+            "\u{1b}[31mWe've found a bug for you!\u{1b}[0m\n  let x: list<int> = \"a\" & 'b'".to_string(),
+        ];
+        if let SourceType::SourceFile(source_file) = &mut b.source_type {
+            source_file.implementation.compile_state = CompileState::Error;
+        }
+
+        let report = get_junit_report(&build_state);
+
+        assert_eq!(
+            report,
+            [
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>",
+                "<testsuites name=\"rewatch\" tests=\"2\" failures=\"1\" time=\"0.750\">",
+                "  <testsuite name=\"root\" tests=\"2\" failures=\"1\" time=\"0.750\">",
+                "    <testcase name=\"A\" classname=\"root\" file=\"src/A.res\" time=\"0.250\" />",
+                "    <testcase name=\"B\" classname=\"root\" file=\"src/B.res\" time=\"0.500\">",
+                "      <failure message=\"B failed to compile\">We&apos;ve found a bug for you!",
+                "  let x: list&lt;int&gt; = &quot;a&quot; &amp; &apos;b&apos;</failure>",
+                "    </testcase>",
+                "  </testsuite>",
+                "</testsuites>",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use super::build_types::*;
+use crate::helpers;
+use std::sync::RwLock;
+use std::time::Duration;
+
+// With `--metrics-file`, the build writes its metrics in the text format of Prometheus (for the
+// textfile collector of the node exporter, or to push them from CI), to chart the build times over
+// time. The names and labels of the metrics are stable:
+//
+// - `rewatch_build_success`: 1 when all modules compiled, 0 when there were errors
+// - `rewatch_build_duration_seconds`: the duration of the whole build
+// - `rewatch_phase_duration_seconds{phase}`: the duration of every phase of the build, with the
+//   phases `package_tree`, `source_files`, `cleanup`, `parse`, `deps` and `compile`
+// - `rewatch_packages`: the number of packages in the build
+// - `rewatch_modules`: the number of modules in the build (without the namespaces)
+// - `rewatch_modules_by_outcome{outcome}`: the number of modules that were `compiled`, `restored`
+//   from the cache of `--cache-dir`, or `cached` (up to date from the previous build)
+//
+// The metrics are only written when the build gets to compiling, not when it fails before that.
+
+static METRICS_FILE: RwLock<Option<String>> = RwLock::new(None);
+
+/// Writes the metrics of the build to this file (`--metrics-file`) with `write_metrics`.
+pub fn set_metrics_file(metrics_file: Option<String>) {
+    *METRICS_FILE.write().unwrap() = metrics_file;
+}
+
+fn gauge(name: &str, help: &str, values: &[(Option<(&str, &str)>, f64)]) -> String {
+    let mut metric = format!("# HELP {} {}\n# TYPE {} gauge\n", name, help, name);
+    for (label, value) in values {
+        match label {
+            Some((label, label_value)) => {
+                metric.push_str(&format!("{}{{{}=\"{}\"}} {}\n", name, label, label_value, value))
+            }
+            None => metric.push_str(&format!("{} {}\n", name, value)),
+        }
+    }
+    metric
+}
+
+/// The metrics of the build in the text format of Prometheus.
+pub fn get_metrics(
+    build_state: &BuildState,
+    phase_durations: &[(&str, Duration)],
+    duration: Duration,
+    success: bool,
+) -> String {
+    let modules = build_state
+        .modules
+        .values()
+        .filter(|module| !module.is_mlmap())
+        .collect::<Vec<&Module>>();
+    let count = |outcome: Option<CompileOutcome>| {
+        modules
+            .iter()
+            .filter(|module| module.compile_outcome == outcome)
+            .count() as f64
+    };
+
+    [
+        gauge(
+            "rewatch_build_success",
+            "Whether all modules compiled.",
+            &[(None, if success { 1.0 } else { 0.0 })],
+        ),
+        gauge(
+            "rewatch_build_duration_seconds",
+            "The duration of the build.",
+            &[(None, duration.as_secs_f64())],
+        ),
+        gauge(
+            "rewatch_phase_duration_seconds",
+            "The duration of a phase of the build.",
+            &phase_durations
+                .iter()
+                .map(|(phase, duration)| (Some(("phase", *phase)), duration.as_secs_f64()))
+                .collect::<Vec<(Option<(&str, &str)>, f64)>>(),
+        ),
+        gauge(
+            "rewatch_packages",
+            "The number of packages in the build.",
+            &[(None, build_state.packages.len() as f64)],
+        ),
+        gauge(
+            "rewatch_modules",
+            "The number of modules in the build.",
+            &[(None, modules.len() as f64)],
+        ),
+        gauge(
+            "rewatch_modules_by_outcome",
+            "The number of modules that were compiled, restored from the cache or up to date.",
+            &[
+                (
+                    Some(("outcome", "compiled")),
+                    count(Some(CompileOutcome::Compiled)),
+                ),
+                (
+                    Some(("outcome", "restored")),
+                    count(Some(CompileOutcome::Restored)),
+                ),
+                (Some(("outcome", "cached")), count(None)),
+            ],
+        ),
+    ]
+    .concat()
+}
+
+/// Writes the metrics of the build to the file of `set_metrics_file`, if any.
+pub fn write_metrics(
+    build_state: &BuildState,
+    phase_durations: &[(&str, Duration)],
+    duration: Duration,
+    success: bool,
+) -> Result<(), String> {
+    let metrics_file = match METRICS_FILE.read().unwrap().as_ref() {
+        Some(metrics_file) => metrics_file.to_owned(),
+        None => return Ok(()),
+    };
+    // the textfile collector can read the file at any time
+    helpers::write_atomically(
+        &metrics_file,
+        get_metrics(build_state, phase_durations, duration, success),
+    )
+    .map_err(|e| format!("Could not write the metrics to {}: {}", metrics_file, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::get_metrics;
+    use crate::build::build_types::*;
+    use crate::test_fixture::TestProject;
+    use std::time::Duration;
+
+    #[test]
+    fn should_write_the_metrics_of_the_build_as_prometheus_gauges() {
+        let project = TestProject::new("metrics");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "namespace": "MyNamespace" }"#,
+        );
+        project.write("src/A.res", "");
+        project.write("src/B.res", "");
+        let mut build_state = project.build_state("root");
+        build_state
+            .modules
+            .get_mut("A-MyNamespace")
+            .unwrap()
+            .compile_outcome = Some(CompileOutcome::Compiled);
+
+        let metrics = get_metrics(
+            &build_state,
+            &[
+                ("parse", Duration::from_millis(250)),
+                ("compile", Duration::from_millis(1500)),
+            ],
+            Duration::from_secs(2),
+            true,
+        );
+
+        assert_eq!(
+            metrics,
+            [
+                "# HELP rewatch_build_success Whether all modules compiled.",
+                "# TYPE rewatch_build_success gauge",
+                "rewatch_build_success 1",
+                "# HELP rewatch_build_duration_seconds The duration of the build.",
+                "# TYPE rewatch_build_duration_seconds gauge",
+                "rewatch_build_duration_seconds 2",
+                "# HELP rewatch_phase_duration_seconds The duration of a phase of the build.",
+                "# TYPE rewatch_phase_duration_seconds gauge",
+                "rewatch_phase_duration_seconds{phase=\"parse\"} 0.25",
+                "rewatch_phase_duration_seconds{phase=\"compile\"} 1.5",
+                "# HELP rewatch_packages The number of packages in the build.",
+                "# TYPE rewatch_packages gauge",
+                "rewatch_packages 1",
+                "# HELP rewatch_modules The number of modules in the build.",
+                "# TYPE rewatch_modules gauge",
+                "rewatch_modules 2",
+                "# HELP rewatch_modules_by_outcome The number of modules that were compiled, restored from the cache or up to date.",
+                "# TYPE rewatch_modules_by_outcome gauge",
+                "rewatch_modules_by_outcome{outcome=\"compiled\"} 1",
+                "rewatch_modules_by_outcome{outcome=\"restored\"} 0",
+                "rewatch_modules_by_outcome{outcome=\"cached\"} 1",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
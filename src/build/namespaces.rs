@@ -1,8 +1,10 @@
+use crate::build::diagnostics;
 use crate::build::packages;
+use crate::build::trace;
 use crate::helpers;
 use ahash::AHashSet;
-use std::fs::File;
-use std::io::Write;
+use log::debug;
+use std::fs;
 use std::process::Command;
 
 // Namespaces work like the following: The build system will generate a file
@@ -19,21 +21,11 @@ use std::process::Command;
 // constructing the AST, so these modules are hidden from compilation.
 // in the top namespace however, we alias with the proper names
 
-pub fn gen_mlmap(
-    package: &packages::Package,
-    namespace: &str,
-    depending_modules: &AHashSet<String>,
-) -> String {
-    let build_path_abs = package.get_build_path();
+fn get_mlmap_contents(depending_modules: &AHashSet<String>) -> String {
     // we don't really need to create a digest, because we track if we need to
     // recompile in a different way but we need to put it in the file for it to
     // be readable.
-
-    let path = build_path_abs.to_string() + "/" + namespace + ".mlmap";
-    let mut file = File::create(&path).expect("Unable to create mlmap");
-
-    file.write_all(b"randjbuildsystem\n" as &[u8])
-        .expect("Unable to write mlmap");
+    let mut contents = String::from("randjbuildsystem\n");
 
     let mut modules = Vec::from_iter(depending_modules.to_owned());
     modules.sort();
@@ -42,21 +34,207 @@ pub fn gen_mlmap(
         // (only contains A-Z a-z 0-9 and _ and only starts with a capital letter)
         // if not, it does not make sense to export as part of the name space
         // this helps compile times of exotic modules such as MyModule.test
-        file.write_all(module.as_bytes()).unwrap();
-        file.write_all(b"\n").unwrap();
+        contents.push_str(&module);
+        contents.push('\n');
+    }
+
+    contents
+}
+
+/// Whether the modules in the namespace changed since the mlmap at the given path was written.
+fn is_mlmap_changed(path: &str, contents: &str) -> bool {
+    fs::read_to_string(path).ok().as_deref() != Some(contents)
+}
+
+/// A copy of the mlmap that was compiled the last time, next to its `.cmi`. The mlmap itself can be
+/// written without compiling it (ie. by a build that fails before compiling the namespace), so the
+/// modules of the compiled namespace are only known from this copy.
+fn get_compiled_mlmap_path(path: &str) -> String {
+    format!("{}.compiled", path)
+}
+
+/// Writes the mlmap to the given path, but only when the modules in the namespace changed since
+/// the mlmap was written the last time. Returns whether the mlmap was (re)written.
+fn write_mlmap(path: &str, depending_modules: &AHashSet<String>) -> bool {
+    let contents = get_mlmap_contents(depending_modules);
+    if !is_mlmap_changed(path, &contents) {
+        return false;
     }
 
-    path.to_string()
+    // compile_mlmap reads the mlmap right after this
+    helpers::write_atomically(path, contents).expect("Unable to write mlmap");
+    true
 }
 
-pub fn compile_mlmap(package: &packages::Package, namespace: &str, bsc_path: &str) {
+/// Generates the mlmap of the namespace of the package. Returns its path, and whether the modules
+/// in the namespace changed since the mlmap was compiled the last time, in which case it needs to
+/// be compiled again. Without `write_file`, the mlmap isn't written.
+pub fn gen_mlmap(
+    package: &packages::Package,
+    namespace: &str,
+    depending_modules: &AHashSet<String>,
+    write_file: bool,
+) -> (String, bool) {
+    let build_path_abs = package.get_build_path();
+    let path = build_path_abs.to_string() + "/" + namespace + ".mlmap";
+    if write_file {
+        write_mlmap(&path, depending_modules);
+    }
+    let changed = is_mlmap_changed(
+        &get_compiled_mlmap_path(&path),
+        &get_mlmap_contents(depending_modules),
+    );
+
+    (path, changed)
+}
+
+/// Compiles the mlmap of a namespace. Returns the output of bsc when it fails, like `compile_file`.
+pub fn compile_mlmap(
+    package: &packages::Package,
+    namespace: &str,
+    bsc_path: &str,
+    trace: Option<&trace::Trace>,
+) -> Result<(), String> {
     let build_path_abs = package.get_build_path();
     let mlmap_name = format!("{}.mlmap", namespace);
     let args = vec!["-w", "-49", "-color", "always", "-no-alias-deps", &mlmap_name];
 
-    let _ = Command::new(bsc_path)
-        .current_dir(helpers::canonicalize_string_path(&build_path_abs).unwrap())
-        .args(args)
-        .output()
-        .expect("err");
+    let result = trace::span(trace, "mlmap", namespace, || {
+        helpers::run_command(
+            Command::new(bsc_path)
+                .current_dir(helpers::canonicalize_string_path(&build_path_abs).unwrap())
+                .args(args),
+        )
+    });
+    match result {
+        Ok(x) => {
+            let output = helpers::decode_output(&x.stderr) + &helpers::decode_output(&x.stdout);
+            if x.status.success() {
+                if helpers::contains_ascii_characters(&output) {
+                    debug!("Output of compiling {}: {}", mlmap_name, output);
+                }
+                // without the copy, the next build compiles the mlmap again
+                let path = build_path_abs + "/" + &mlmap_name;
+                let _ = fs::copy(&path, get_compiled_mlmap_path(&path));
+                Ok(())
+            } else {
+                let (diagnostics, _) = diagnostics::parse_diagnostics(&output);
+                if diagnostics
+                    .iter()
+                    .any(|diagnostic| diagnostic.severity == diagnostics::Severity::Error)
+                {
+                    Err(output)
+                } else {
+                    let path = build_path_abs + "/" + &mlmap_name;
+                    Err(output + &diagnostics::compiler_failed(&path, x.status.code()))
+                }
+            }
+        }
+        Err(e) => Err(format!("Could not compile {}: {}", mlmap_name, e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compile_mlmap, get_mlmap_contents, write_mlmap};
+    use crate::test_fixture::TestProject;
+    use ahash::AHashSet;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn should_only_write_mlmap_when_the_modules_change() {
+        let project = TestProject::new("mlmap");
+        let path = project.join("Namespace.mlmap").to_string_lossy().to_string();
+        let mut modules = AHashSet::from_iter(vec![String::from("A"), String::from("B")]);
+
+        let first_build = write_mlmap(&path, &modules);
+        // editing the body of a module doesn't change the modules in the namespace
+        let same_modules = write_mlmap(&path, &modules);
+        modules.insert(String::from("C"));
+        let added_module = write_mlmap(&path, &modules);
+        let contents = std::fs::read_to_string(&path).unwrap();
+
+        assert!(first_build);
+        assert!(!same_modules);
+        assert!(added_module);
+        assert_eq!(contents, "randjbuildsystem\nA\nB\nC\n");
+    }
+
+    #[test]
+    fn should_write_the_same_mlmap_for_the_same_modules() {
+        // two sets with the same modules, that were built in another order (and with another seed)
+        let modules = (0..5000).map(|i| format!("Module{}", i)).collect::<Vec<String>>();
+        let forward = AHashSet::from_iter(modules.iter().cloned());
+        let backward = AHashSet::from_iter(modules.iter().rev().cloned());
+
+        let contents = get_mlmap_contents(&forward);
+        assert_eq!(contents.as_bytes(), get_mlmap_contents(&backward).as_bytes());
+        assert!(contents.starts_with("randjbuildsystem\nModule0\nModule1\nModule10\n"));
+    }
+
+    #[test]
+    fn should_never_read_a_partially_written_mlmap() {
+        let project = TestProject::new("mlmap-concurrent");
+        let path = project.join("Namespace.mlmap").to_string_lossy().to_string();
+        let modules = |count: usize| AHashSet::from_iter((0..count).map(|i| format!("Module{}", i)));
+        let (small, large) = (modules(1000), modules(5000));
+        let expected = [get_mlmap_contents(&small), get_mlmap_contents(&large)];
+        write_mlmap(&path, &small);
+
+        let writing = AtomicBool::new(true);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..200 {
+                    write_mlmap(&path, if i % 2 == 0 { &large } else { &small });
+                }
+                writing.store(false, Ordering::SeqCst);
+            });
+            while writing.load(Ordering::SeqCst) {
+                let contents = std::fs::read_to_string(&path).unwrap();
+                assert!(expected.contains(&contents));
+            }
+        });
+
+        // no temporary files are left behind
+        let files = std::fs::read_dir(&project.root).unwrap().count();
+        assert_eq!(files, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_report_an_error_when_the_mlmap_does_not_compile() {
+        use crate::build::diagnostics;
+        use std::os::unix::fs::PermissionsExt;
+
+        let project = TestProject::new("mlmap-error");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "namespace": "MyNamespace" }"#,
+        );
+        project.write("src/A.res", "");
+        let packages = project.make();
+        let package = packages.get("root").unwrap();
+        let bsc = |name: &str, script: &str| {
+            let bsc_path = project.join(name);
+            std::fs::write(&bsc_path, script).unwrap();
+            std::fs::set_permissions(&bsc_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            bsc_path.to_string_lossy().to_string()
+        };
+        // the way bsc reports a malformed mlmap
+        let failing_bsc = bsc(
+            "failing-bsc.exe",
+            "#!/bin/sh\necho 'File \"MyNamespace.mlmap\", line 1\nError: Corrupted compiled interface' >&2\nexit 2\n",
+        );
+        let passing_bsc = bsc("passing-bsc.exe", "#!/bin/sh\nexit 0\n");
+
+        let failed = compile_mlmap(package, "MyNamespace", &failing_bsc, None);
+        let passed = compile_mlmap(package, "MyNamespace", &passing_bsc, None);
+
+        let error = failed.unwrap_err();
+        assert!(error.contains("Corrupted compiled interface"));
+        let (diagnostics, _) = diagnostics::parse_diagnostics(&error);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, diagnostics::Severity::Error);
+        assert_eq!(passed, Ok(()));
+    }
 }
@@ -1,23 +1,33 @@
 use super::build_types::*;
 use super::namespaces;
 use super::packages;
+use super::parse;
 use crate::bsconfig;
 use crate::helpers;
 use crate::helpers::emojis::*;
+use crate::reportln;
 use ahash::{AHashMap, AHashSet};
 use console::style;
 use convert_case::{Case, Casing};
+use ignore::WalkBuilder;
 use log::{debug, error};
 use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::error;
 use std::fs::{self};
 use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::time::SystemTime;
 
 #[derive(Debug, Clone)]
 pub struct SourceFileMeta {
     pub modified: SystemTime,
+    /// The file is in a source folder with `"type": "dev"`
+    pub is_dev: bool,
+    /// The contents of a virtual source (see `VirtualSource`)
+    pub virtual_contents: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -60,15 +70,29 @@ pub struct Package {
     pub dirs: Option<AHashSet<PathBuf>>,
     pub is_pinned_dep: bool,
     pub is_root: bool,
+    // the node_modules folder from the command line (see `PackageOptions::node_modules`)
+    pub node_modules: Option<String>,
 }
 
 impl Package {
+    /// The compiler assets, next to the folders of their source files, for the editor tooling
     pub fn get_bs_build_path(&self) -> String {
         format!("{}/lib/bs", self.path)
     }
 
+    /// The flat folder that bsc compiles in. The packages that depend on this package include it,
+    /// so its cmi, cmj and cmt files are what the package publishes to its dependents. It's
+    /// `lib/ocaml`, or the `build-dir` of the bsconfig.json of the package (relative to the package),
+    /// for a dependency that was built into another folder (ie. `lib/bs`).
     pub fn get_build_path(&self) -> String {
-        format!("{}/lib/ocaml", self.path)
+        format!(
+            "{}/{}",
+            self.path,
+            self.bsconfig
+                .build_dir
+                .as_deref()
+                .unwrap_or(bsconfig::DEFAULT_BUILD_DIR)
+        )
     }
 
     pub fn get_mlmap_path(&self) -> String {
@@ -91,6 +115,73 @@ impl Package {
             + ".cmi"
     }
 
+    /// The working directory of bsc when it generates the ASTs of this package, see
+    /// `bsconfig::PpxWorkingDirectory`.
+    pub fn get_ppx_working_directory(&self, project_root: &str) -> String {
+        match self.bsconfig.ppx_working_directory {
+            None | Some(bsconfig::PpxWorkingDirectory::Build) => self.get_build_path(),
+            Some(bsconfig::PpxWorkingDirectory::Package) => self.path.to_owned(),
+            Some(bsconfig::PpxWorkingDirectory::Project) => project_root.to_owned(),
+        }
+    }
+
+    /// The bsc that parses and compiles the package: the `bsc` of its bsconfig.json (relative to the
+    /// package), for a package that needs another version of the compiler, or `default_bsc_path`.
+    pub fn get_bsc_path(&self, default_bsc_path: &str) -> String {
+        match &self.bsconfig.bsc {
+            Some(bsc) => Path::new(&self.path).join(bsc).to_string_lossy().to_string(),
+            None => default_bsc_path.to_owned(),
+        }
+    }
+
+    /// The hand-written JavaScript files of the package (the `external-js` of its bsconfig.json,
+    /// relative to the package), that the modules bind to with `external` or `%raw`. They can sit
+    /// next to the generated JavaScript, and are never removed (see `is_external_js`).
+    pub fn get_external_js_paths(&self) -> Vec<PathBuf> {
+        self.bsconfig
+            .external_js
+            .iter()
+            .flatten()
+            .map(|path| Path::new(&self.path).join(path))
+            .collect()
+    }
+
+    /// Whether a file is one of the hand-written JavaScript files of the package, so `clean` and the
+    /// cleanup of the outputs of removed modules leave it alone, even when a module has (or had) the
+    /// same name.
+    pub fn is_external_js(&self, path: &str) -> bool {
+        self.get_external_js_paths()
+            .iter()
+            .any(|external_js| external_js == Path::new(path))
+    }
+
+    /// The version of the compiler of the package (see `get_bsc_path`), or `default_version` when it
+    /// uses the bsc of the project. The version of every bsc is only determined once.
+    pub fn get_version(
+        &self,
+        default_bsc_path: &str,
+        default_version: &str,
+        compile_options: &CompileOptions,
+    ) -> String {
+        if self.bsconfig.bsc.is_none() {
+            return default_version.to_owned();
+        }
+        let bsc_path = self.get_bsc_path(default_bsc_path);
+        if let Some(version) = BSC_VERSIONS.lock().unwrap().get(&bsc_path) {
+            return version.to_owned();
+        }
+        // the lock isn't held while bsc runs, so the other packages don't wait for it. When two
+        // packages get the version of the same bsc at the same time, they both run it once.
+        let version =
+            super::try_get_version(&bsc_path, compile_options).unwrap_or(default_version.to_owned());
+        BSC_VERSIONS
+            .lock()
+            .unwrap()
+            .entry(bsc_path)
+            .or_insert(version)
+            .to_owned()
+    }
+
     pub fn get_ast_path(&self, source_file: &str) -> String {
         helpers::get_compiler_asset(self, &packages::Namespace::NoNamespace, source_file, "ast")
     }
@@ -124,49 +215,55 @@ pub fn read_folders(
     package_dir: &Path,
     path: &Path,
     recurse: bool,
+    respect_ignore: bool,
 ) -> Result<AHashMap<String, SourceFileMeta>, Box<dyn error::Error>> {
     let mut map: AHashMap<String, SourceFileMeta> = AHashMap::new();
-    let path_buf = PathBuf::from(path);
-    let meta = fs::metadata(package_dir.join(&path));
-    let path_with_meta = meta.map(|meta| {
-        (
-            path.to_owned(),
-            SourceFileMeta {
-                modified: meta.modified().unwrap(),
-            },
-        )
-    });
-
-    for entry in fs::read_dir(package_dir.join(&path_buf))? {
-        let entry_path_buf = entry.map(|entry| entry.path())?;
-        let metadata = fs::metadata(&entry_path_buf)?;
+    let source_dir = package_dir.join(path);
+
+    // when respecting ignore files, files excluded by a .gitignore / .ignore (in the source folder, or
+    // any of its parents) are not picked up. Think of build outputs or vendored sources.
+    let walker = WalkBuilder::new(&source_dir)
+        .standard_filters(false)
+        .git_ignore(respect_ignore)
+        .git_exclude(respect_ignore)
+        .ignore(respect_ignore)
+        .parents(respect_ignore)
+        .require_git(false)
+        .follow_links(true)
+        .max_depth(if recurse { None } else { Some(1) })
+        .build();
+
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // the source folder itself can't be read
+            Err(e) if e.depth().unwrap_or(0) == 0 => return Err(Box::new(e)),
+            Err(e) => {
+                println!("Error reading directory: {}", e);
+                continue;
+            }
+        };
+        let entry_path_buf = entry.path();
         let name = entry_path_buf.file_name().unwrap().to_str().unwrap().to_string();
-
         let path_ext = entry_path_buf.extension().and_then(|x| x.to_str());
-        let new_path = path_buf.join(&name);
-        if metadata.file_type().is_dir() && recurse {
-            match read_folders(&filter, package_dir, &new_path, recurse) {
-                Ok(s) => map.extend(s),
-                Err(e) => println!("Error reading directory: {}", e),
-            }
-        }
 
         match path_ext {
-            Some(extension) if helpers::is_source_file(extension) => match path_with_meta {
-                Ok((ref path, _)) if matches_filter(filter, &name) => {
-                    let mut path = path.to_owned();
-                    path.push(&name);
+            Some(extension) if helpers::is_source_file(extension) && !entry_path_buf.is_dir() => {
+                if matches_filter(filter, &name) {
+                    let relative_path = entry_path_buf.strip_prefix(&source_dir).unwrap();
+                    let metadata = fs::metadata(entry_path_buf)?;
                     map.insert(
-                        path.to_string_lossy().to_string(),
+                        path.join(relative_path).to_string_lossy().to_string(),
                         SourceFileMeta {
                             modified: metadata.modified().unwrap(),
+                            is_dev: false,
+                            virtual_contents: None,
                         },
                     );
+                } else {
+                    println!("Filtered: {:?}", name)
                 }
-
-                Ok(_) => println!("Filtered: {:?}", name),
-                Err(ref e) => println!("Error reading directory: {}", e),
-            },
+            }
             _ => (),
         }
     }
@@ -211,7 +308,8 @@ fn get_source_dirs(source: bsconfig::Source, sub_path: Option<PathBuf>) -> AHash
     source_folders
 }
 
-fn read_bsconfig(package_dir: &str) -> bsconfig::T {
+/// The path of the config of a package: rescript.json when it exists, bsconfig.json otherwise.
+pub fn get_bsconfig_path(package_dir: &str) -> String {
     let prefix = if package_dir == "" {
         "".to_string()
     } else {
@@ -222,12 +320,106 @@ fn read_bsconfig(package_dir: &str) -> bsconfig::T {
     let bsconfig_json_path = prefix.to_string() + "bsconfig.json";
 
     if Path::new(&rescript_json_path).exists() {
-        bsconfig::read(rescript_json_path)
+        rescript_json_path
     } else {
-        bsconfig::read(bsconfig_json_path)
+        bsconfig_json_path
     }
 }
 
+fn read_bsconfig(package_dir: &str) -> bsconfig::T {
+    bsconfig::read(get_bsconfig_path(package_dir))
+}
+
+/// Returns the names under which a dependency is installed by the package in `package_path`, when
+/// the package.json of that package installs it under an alias, ie. `"lib": "npm:@org/lib@^1.0.0"`
+/// installs `@org/lib` in node_modules/lib.
+fn get_package_aliases(package_path: &str, package_name: &str) -> Vec<String> {
+    let package_json = match fs::read_to_string(Path::new(package_path).join("package.json"))
+        .ok()
+        .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())
+    {
+        Some(package_json) => package_json,
+        None => return vec![],
+    };
+
+    [
+        "dependencies",
+        "devDependencies",
+        "peerDependencies",
+        "optionalDependencies",
+    ]
+    .iter()
+    .filter_map(|field| package_json.get(field).and_then(|deps| deps.as_object()))
+    .flat_map(|deps| deps.iter())
+    .filter_map(|(alias, version_range)| {
+        let aliased = version_range.as_str()?.strip_prefix("npm:")?;
+        // the version range is optional, the @ of a scope is not the start of the version range
+        let aliased_name = match aliased.rfind('@') {
+            Some(index) if index > 0 => &aliased[..index],
+            _ => aliased,
+        };
+        (aliased_name == package_name && alias != package_name).then(|| alias.to_owned())
+    })
+    .collect()
+}
+
+/// Finds the directory of a dependency like node does: in the node_modules of the package that
+/// depends on it, and of all the parent directories of that package, and finally in the
+/// node_modules of the project and the workspace. Because the (canonicalized) path of the
+/// dependent package is used, this also finds the dependencies of packages that are symlinked
+/// into node_modules by pnpm (which live next to the package in node_modules/.pnpm). When the
+/// package.json of the dependent package installs the dependency under an alias, the directory of
+/// the alias is used. The node_modules given with `--node-modules` is tried last. Returns the
+/// canonicalized path, or all the paths that were tried.
+pub fn resolve_package_path(
+    package_name: &str,
+    parent_path: &str,
+    project_root: &str,
+    workspace_root: &Option<String>,
+    node_modules: Option<&str>,
+) -> Result<String, Vec<String>> {
+    let installed_names = [
+        vec![package_name.to_string()],
+        get_package_aliases(parent_path, package_name),
+    ]
+    .concat();
+    let mut candidates = Path::new(parent_path)
+        .ancestors()
+        .flat_map(|dir| {
+            installed_names
+                .iter()
+                .map(move |installed_name| dir.join("node_modules").join(installed_name))
+        })
+        .collect::<Vec<PathBuf>>();
+    candidates.push(PathBuf::from(helpers::package_path(
+        project_root,
+        package_name,
+        false,
+    )));
+    if let Some(workspace_root) = workspace_root {
+        candidates.push(PathBuf::from(helpers::package_path(
+            workspace_root,
+            package_name,
+            false,
+        )));
+    }
+    if let Some(node_modules) = helpers::get_node_modules_path(project_root, node_modules) {
+        candidates.push(Path::new(&node_modules).join(package_name));
+    }
+
+    let mut tried_paths: Vec<String> = vec![];
+    for candidate in candidates {
+        if let Ok(dir) = candidate.canonicalize() {
+            return Ok(dir.to_string_lossy().to_string());
+        }
+        let candidate = candidate.to_string_lossy().to_string();
+        if !tried_paths.contains(&candidate) {
+            tried_paths.push(candidate);
+        }
+    }
+    Err(tried_paths)
+}
+
 /// # Make Package
 
 /// Given a bsconfig, reqursively finds all dependencies.
@@ -239,8 +431,10 @@ fn read_bsconfig(package_dir: &str) -> bsconfig::T {
 fn read_dependencies<'a>(
     registered_dependencies_set: &'a mut AHashSet<String>,
     parent_bsconfig: &bsconfig::T,
+    parent_path: &str,
     project_root: &str,
     workspace_root: Option<String>,
+    node_modules: Option<&str>,
 ) -> Vec<Dependency> {
     return parent_bsconfig
         .bs_dependencies
@@ -259,20 +453,22 @@ fn read_dependencies<'a>(
         // Read all bsconfig files in parallel instead of blocking
         .par_iter()
         .map(|package_name| {
-            let path = match (
-                PathBuf::from(helpers::package_path(project_root, package_name, false)).canonicalize(),
-                workspace_root.as_ref().map(|workspace_root| {
-                    PathBuf::from(helpers::package_path(&workspace_root, package_name, false)).canonicalize()
-                }),
+            let path = match resolve_package_path(
+                package_name,
+                parent_path,
+                project_root,
+                &workspace_root,
+                node_modules,
             ) {
-                (Ok(dir), _) => dir.to_string_lossy().to_string(),
-                (_, Some(Ok(dir))) => dir.to_string_lossy().to_string(),
-                (Err(e), _) => {
+                Ok(dir) => dir,
+                Err(tried_paths) => {
                     print!(
-        "{} {} Error building package tree (are node_modules up-to-date?)... \n More details: {}",
+        "{} {} Error building package tree (are node_modules up-to-date?)... \n Could not find {} (a dependency of {}) in:\n  {}\n",
                           style("[1/2]").bold().dim(),
                           CROSS,
-                          e.to_string()
+                          package_name,
+                          parent_bsconfig.name,
+                          tried_paths.join("\n  ")
                         );
                     std::process::exit(2)
                 }
@@ -288,8 +484,10 @@ fn read_dependencies<'a>(
             let dependencies = read_dependencies(
                 &mut registered_dependencies_set.to_owned(),
                 &bsconfig,
+                &path,
                 project_root,
                 workspace_root.to_owned(),
+                node_modules,
             );
 
             Dependency {
@@ -313,7 +511,34 @@ fn flatten_dependencies(dependencies: Vec<Dependency>) -> Vec<Dependency> {
     flattened
 }
 
-fn make_package(bsconfig: bsconfig::T, package_path: &str, is_pinned_dep: bool, is_root: bool) -> Package {
+/// The options from the command line that change how the packages are read.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackageOptions {
+    /// Reads these packages without their namespace (`--no-namespace`), to debug namespace issues
+    pub no_namespace: Vec<String>,
+    /// Reads these packages with a namespace (`--namespace`), named after the package, when their
+    /// bsconfig.json doesn't set one. Together with `--no-namespace`, this allows moving the
+    /// packages of a monorepo to namespaces one at a time. `--no-namespace` takes precedence.
+    pub namespace: Vec<String>,
+    /// Leaves out the dev sources (`"type": "dev"`, `--no-dev`), ie. for a production build. Like
+    /// bsb, only the dev sources of the root package and the pinned dependencies are read otherwise,
+    /// the dev sources of other dependencies are usually not published.
+    pub no_dev: bool,
+    /// Uses this node_modules folder for the compiler, its standard library and the PPXes, and to
+    /// look for the dependencies that aren't found otherwise (`--node-modules`, see
+    /// `helpers::get_node_modules_path`)
+    pub node_modules: Option<String>,
+}
+
+static BSC_VERSIONS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+fn make_package(
+    bsconfig: bsconfig::T,
+    package_path: &str,
+    is_pinned_dep: bool,
+    is_root: bool,
+    options: &PackageOptions,
+) -> Package {
     let source_folders = match bsconfig.sources.to_owned() {
         bsconfig::OneOrMore::Single(source) => get_source_dirs(source, None),
         bsconfig::OneOrMore::Multiple(sources) => {
@@ -335,6 +560,18 @@ fn make_package(bsconfig: bsconfig::T, package_path: &str, is_pinned_dep: bool,
         source_folders,
         source_files: None,
         namespace: match (bsconfig.namespace, bsconfig.namespace_entry) {
+            _ if options.no_namespace.contains(&bsconfig.name) => Namespace::NoNamespace,
+            (None | Some(bsconfig::Namespace::Bool(false)), entry)
+                if options.namespace.contains(&bsconfig.name) =>
+            {
+                match entry {
+                    Some(entry) => Namespace::NamespaceWithEntry {
+                        namespace: namespace_from_package,
+                        entry,
+                    },
+                    None => Namespace::Namespace(namespace_from_package),
+                }
+            }
             (Some(bsconfig::Namespace::Bool(false)), _) => Namespace::NoNamespace,
             (None, _) => Namespace::NoNamespace,
             (Some(bsconfig::Namespace::Bool(true)), None) => Namespace::Namespace(namespace_from_package),
@@ -369,17 +606,22 @@ fn make_package(bsconfig: bsconfig::T, package_path: &str, is_pinned_dep: bool,
         dirs: None,
         is_pinned_dep: is_pinned_dep,
         is_root,
+        node_modules: options.node_modules.to_owned(),
     }
 }
 
-fn read_packages(project_root: &str, workspace_root: Option<String>) -> AHashMap<String, Package> {
+fn read_packages(
+    project_root: &str,
+    workspace_root: Option<String>,
+    options: &PackageOptions,
+) -> AHashMap<String, Package> {
     let root_bsconfig = read_bsconfig(project_root);
 
     // Store all packages and completely deduplicate them
     let mut map: AHashMap<String, Package> = AHashMap::new();
     map.insert(
         root_bsconfig.name.to_owned(),
-        make_package(root_bsconfig.to_owned(), project_root, false, true),
+        make_package(root_bsconfig.to_owned(), project_root, false, true, options),
     );
 
     let mut registered_dependencies_set: AHashSet<String> = AHashSet::new();
@@ -387,17 +629,25 @@ fn read_packages(project_root: &str, workspace_root: Option<String>) -> AHashMap
         &mut registered_dependencies_set,
         &root_bsconfig,
         project_root,
+        project_root,
         workspace_root,
+        options.node_modules.as_deref(),
     ));
     dependencies.iter().for_each(|d| {
         if !map.contains_key(&d.name) {
             map.insert(
                 d.name.to_owned(),
-                make_package(d.bsconfig.to_owned(), &d.path, d.is_pinned, false),
+                make_package(d.bsconfig.to_owned(), &d.path, d.is_pinned, false, options),
             );
         }
     });
 
+    if let Some(shared_config) = &root_bsconfig.shared_config {
+        map.values_mut().for_each(|package| {
+            package.bsconfig = bsconfig::merge_shared_config(&package.bsconfig, shared_config);
+        });
+    }
+
     return map;
 }
 
@@ -407,12 +657,15 @@ fn read_packages(project_root: &str, workspace_root: Option<String>) -> AHashMap
 /// can be specified as being fully recursive (`{ subdirs: true }`). This wrapper pulls out that
 /// data from the config and pushes it forwards. Another thing is the 'type_', some files / folders
 /// can be marked with the type 'dev'. Which means that they may not be around in the distributed
-/// NPM package. The file reader allows for this, just warns when this happens.
+/// NPM package. The file reader allows for this, just warns when this happens. The dev sources are
+/// only read with `include_dev`.
 /// TODO -> Check wether we actually need the `fs::Metadata`
 pub fn get_source_files(
     package_dir: &Path,
     filter: &Option<regex::Regex>,
     source: &bsconfig::PackageSource,
+    respect_ignore: bool,
+    include_dev: bool,
 ) -> AHashMap<String, SourceFileMeta> {
     let mut map: AHashMap<String, SourceFileMeta> = AHashMap::new();
 
@@ -426,10 +679,14 @@ pub fn get_source_files(
     };
 
     let path_dir = Path::new(&source.dir);
-    // don't include dev sources for now
-    if type_ != &Some("dev".to_string()) {
-        match read_folders(&filter, package_dir, path_dir, recurse) {
-            Ok(files) => map.extend(files),
+    let is_dev = type_ == &Some("dev".to_string());
+    if !is_dev || include_dev {
+        match read_folders(&filter, package_dir, path_dir, recurse, respect_ignore) {
+            Ok(files) => map.extend(
+                files
+                    .into_iter()
+                    .map(|(file, metadata)| (file, SourceFileMeta { is_dev, ..metadata })),
+            ),
             Err(_e) if type_ == &Some("dev".to_string()) => {
                 println!(
                     "Could not read folder: {}... Probably ok as type is dev",
@@ -443,12 +700,90 @@ pub fn get_source_files(
     map
 }
 
+/// Derives the namespace of a package from its name, ie. `@scope/my-package` becomes
+/// `ScopeMyPackage`. The result is always a valid OCaml module identifier: any character that is
+/// not an ASCII letter or digit (`@`, `/`, `-`, `.`, unicode, ...) acts as a word boundary, and when
+/// the name doesn't start with a letter (`3d-utils`) the namespace is prefixed with `N`.
 pub fn namespace_from_package_name(package_name: &str) -> String {
-    package_name
-        .to_owned()
-        .replace("@", "")
-        .replace("/", "_")
-        .to_case(Case::Pascal)
+    let namespace = package_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>()
+        .to_case(Case::Pascal);
+
+    match namespace.chars().next() {
+        Some(c) if c.is_ascii_uppercase() => namespace,
+        _ => format!("N{}", namespace),
+    }
+}
+
+/// A source file that is generated in memory (ie. by codegen tooling) instead of being read from a
+/// source folder. It's built as a module of its package, next to the source files (see `make`).
+/// bsc can only read files, so the contents are written to `lib/bs/virtual` of the package. The
+/// file is only written when the contents changed, so an unchanged virtual source isn't built
+/// again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualSource {
+    pub package_name: String,
+    pub module_name: String,
+    pub contents: String,
+}
+
+fn get_virtual_source_path(virtual_source: &VirtualSource) -> String {
+    format!("lib/bs/virtual/{}.res", virtual_source.module_name)
+}
+
+/// Returns the virtual sources of a package like the source files that were found in its source
+/// folders, without writing them (see `write_virtual_sources`). A virtual source was modified just
+/// now, unless its copy in the build folder has the same contents already.
+fn get_virtual_source_files(
+    package: &Package,
+    virtual_sources: &[VirtualSource],
+) -> AHashMap<String, SourceFileMeta> {
+    virtual_sources
+        .iter()
+        .filter(|virtual_source| virtual_source.package_name == package.name)
+        .map(|virtual_source| {
+            let path = get_virtual_source_path(virtual_source);
+            let full_path = Path::new(&package.path).join(&path);
+            let modified = match fs::read_to_string(&full_path) {
+                Ok(contents) if contents == virtual_source.contents => full_path
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .unwrap_or_else(|_| SystemTime::now()),
+                _ => SystemTime::now(),
+            };
+            (
+                path,
+                SourceFileMeta {
+                    modified,
+                    is_dev: false,
+                    virtual_contents: Some(virtual_source.contents.to_owned()),
+                },
+            )
+        })
+        .collect()
+}
+
+/// Writes the virtual sources of the packages to their build folders, when the contents changed.
+fn write_virtual_sources(packages: &AHashMap<String, Package>) {
+    for package in packages.values() {
+        for (path, source_file) in package.source_files.iter().flatten() {
+            let contents = match &source_file.virtual_contents {
+                Some(contents) => contents,
+                None => continue,
+            };
+            let full_path = Path::new(&package.path).join(path);
+            if fs::read_to_string(&full_path).ok().as_ref() == Some(contents) {
+                continue;
+            }
+            if let Err(e) =
+                fs::create_dir_all(full_path.parent().unwrap()).and_then(|_| fs::write(&full_path, contents))
+            {
+                error!("Could not write the virtual source {}: {}", path, e);
+            }
+        }
+    }
 }
 
 /// This takes the tree of packages, and finds all the source files for each, adding them to the
@@ -456,16 +791,29 @@ pub fn namespace_from_package_name(package_name: &str) -> String {
 fn extend_with_children(
     filter: &Option<regex::Regex>,
     mut build: AHashMap<String, Package>,
+    respect_ignore: bool,
+    virtual_sources: &[VirtualSource],
+    options: &PackageOptions,
 ) -> AHashMap<String, Package> {
     for (_key, value) in build.iter_mut() {
         let mut map: AHashMap<String, SourceFileMeta> = AHashMap::new();
+        let include_dev = !options.no_dev && (value.is_root || value.is_pinned_dep);
         value
             .source_folders
             .par_iter()
-            .map(|source| get_source_files(Path::new(&value.path), &filter, source))
+            .map(|source| {
+                get_source_files(
+                    Path::new(&value.path),
+                    &filter,
+                    source,
+                    respect_ignore,
+                    include_dev,
+                )
+            })
             .collect::<Vec<AHashMap<String, SourceFileMeta>>>()
             .into_iter()
             .for_each(|source| map.extend(source));
+        map.extend(get_virtual_source_files(value, virtual_sources));
 
         let mut modules = AHashSet::from_iter(
             map.keys()
@@ -498,26 +846,294 @@ fn extend_with_children(
 /// 2. Take the (by then deduplicated) packages, and find all the '.re', '.res', '.ml' and
 ///    interface files.
 /// The two step process is there to reduce IO overhead
+///
+/// With `respect_ignore`, source files excluded by `.gitignore` / `.ignore` files are skipped. The
+/// `virtual_sources` are added to the source files of their packages (see `VirtualSource`).
+///
+/// This prepares the build: it creates the build folders and writes the virtual sources. See
+/// `read_package_tree` to only read the packages.
 pub fn make(
     filter: &Option<regex::Regex>,
     root_folder: &str,
     workspace_root: Option<String>,
+    respect_ignore: bool,
+    virtual_sources: &[VirtualSource],
+    options: &PackageOptions,
+) -> AHashMap<String, Package> {
+    let result = read_package_tree(
+        filter,
+        root_folder,
+        workspace_root,
+        respect_ignore,
+        virtual_sources,
+        options,
+    );
+    create_build_dirs(&result);
+    write_virtual_sources(&result);
+    result
+}
+
+/// Reads the tree of packages like `make`, without writing anything.
+pub fn read_package_tree(
+    filter: &Option<regex::Regex>,
+    root_folder: &str,
+    workspace_root: Option<String>,
+    respect_ignore: bool,
+    virtual_sources: &[VirtualSource],
+    options: &PackageOptions,
 ) -> AHashMap<String, Package> {
-    let map = read_packages(root_folder, workspace_root);
+    let map = read_packages(root_folder, workspace_root, options);
 
     /* Once we have the deduplicated packages, we can add the source files for each - to minimize
      * the IO */
-    let result = extend_with_children(&filter, map);
-    result
+    extend_with_children(filter, map, respect_ignore, virtual_sources, options)
+}
+
+/// Creates all the folders that bsc writes to: the build folders of every package, with the
+/// directory structure of its sources, and the output folders of the package-specs that are not
+/// in-source. This runs once, before the (parallel) parsing and compiling, so bsc never has to
+/// write to a folder that doesn't exist, and we don't create the same folders concurrently.
+fn create_build_dirs(packages: &AHashMap<String, Package>) {
+    let package_specs = packages
         .values()
-        .into_iter()
-        .for_each(|package| match &package.dirs {
-            Some(dirs) => dirs.iter().for_each(|dir| {
-                let _ = std::fs::create_dir_all(std::path::Path::new(&package.get_bs_build_path()).join(dir));
-            }),
-            None => (),
-        });
-    result
+        .find(|package| package.is_root)
+        .map(|root_package| bsconfig::get_package_specs(&root_package.bsconfig))
+        .unwrap_or_default();
+
+    for package in packages.values() {
+        let _ = fs::create_dir_all(package.get_build_path());
+        for dir in package.dirs.iter().flatten() {
+            let _ = fs::create_dir_all(Path::new(&package.get_bs_build_path()).join(dir));
+            for spec in package_specs.iter().filter(|spec| !spec.in_source) {
+                let _ = fs::create_dir_all(Path::new(&package.path).join(spec.get_output_dir(dir)));
+            }
+        }
+    }
+}
+
+/// Reads the package tree of a project with the source files of every package, without building
+/// anything. Unlike `make`, this doesn't write anything (see `read_package_tree`).
+pub fn load_packages(
+    project_root: &str,
+    options: &PackageOptions,
+) -> Result<AHashMap<String, Package>, String> {
+    if !Path::new(&get_bsconfig_path(project_root)).exists() {
+        return Err(format!(
+            "Could not find a rescript.json or bsconfig.json in {}",
+            project_root
+        ));
+    }
+    let workspace_root = helpers::get_workspace_root(project_root);
+    let packages = read_package_tree(&None, project_root, workspace_root, true, &[], options);
+    // the unallowed dependencies are printed already
+    if !validate_packages_dependencies(&packages) {
+        return Err(String::from("The packages have unallowed dependencies"));
+    }
+    Ok(packages)
+}
+
+/// Prints the packages of a project, with their namespace, number of source files and
+/// dependencies.
+pub fn print_packages(path: &str, options: &PackageOptions) -> Result<(), String> {
+    let packages = load_packages(path, options)?;
+    let mut package_names = packages.keys().collect::<Vec<&String>>();
+    package_names.sort();
+
+    for package_name in package_names {
+        let package = &packages[package_name];
+        let mut dependencies = package.bsconfig.bs_dependencies.to_owned().unwrap_or_default();
+        dependencies.sort();
+        println!("{}", style(package_name).bold());
+        println!(
+            "  namespace:    {}",
+            package.namespace.to_suffix().unwrap_or("-".to_string())
+        );
+        println!(
+            "  source files: {}",
+            package
+                .source_files
+                .as_ref()
+                .map(|files| files.len())
+                .unwrap_or(0)
+        );
+        println!(
+            "  dependencies: {}",
+            if dependencies.is_empty() {
+                "-".to_string()
+            } else {
+                dependencies.join(", ")
+            }
+        );
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct ResolvedPackageSpec {
+    pub module: String,
+    pub in_source: bool,
+    pub suffix: String,
+}
+
+/// The settings of a package as rewatch uses them: after merging the `shared-config` of the root
+/// package and applying the defaults, and with the flags from the command line.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct ResolvedConfig {
+    pub name: String,
+    pub namespace: Option<String>,
+    pub package_specs: Vec<ResolvedPackageSpec>,
+    pub bsc_flags: Vec<String>,
+    pub ppx_flags: Vec<String>,
+    pub bs_dependencies: Vec<String>,
+    pub bs_dev_dependencies: Vec<String>,
+}
+
+pub fn get_resolved_config(
+    package: &Package,
+    project_root: &str,
+    workspace_root: Option<String>,
+    compile_options: &CompileOptions,
+) -> ResolvedConfig {
+    ResolvedConfig {
+        name: package.name.to_owned(),
+        namespace: package.namespace.to_suffix(),
+        package_specs: bsconfig::get_package_specs(&package.bsconfig)
+            .iter()
+            .map(|spec| ResolvedPackageSpec {
+                module: spec.module.to_owned(),
+                in_source: spec.in_source,
+                suffix: spec.get_suffix(&package.bsconfig),
+            })
+            .collect(),
+        bsc_flags: bsconfig::get_bsc_flags(&package.bsconfig, &compile_options.bsc_flags),
+        ppx_flags: parse::get_ppx_args(package, project_root, workspace_root),
+        bs_dependencies: package.bsconfig.bs_dependencies.to_owned().unwrap_or_default(),
+        bs_dev_dependencies: package
+            .bsconfig
+            .bs_dev_dependencies
+            .to_owned()
+            .unwrap_or_default(),
+    }
+}
+
+/// Prints the resolved settings (see `ResolvedConfig`) of every package, as JSON with `json`.
+pub fn print_config(
+    project_root: &str,
+    compile_options: &CompileOptions,
+    package_options: &PackageOptions,
+    json: bool,
+) -> Result<(), String> {
+    let packages = load_packages(project_root, package_options)?;
+    let workspace_root = helpers::get_workspace_root(project_root);
+    let mut configs = packages
+        .values()
+        .map(|package| get_resolved_config(package, project_root, workspace_root.to_owned(), compile_options))
+        .collect::<Vec<ResolvedConfig>>();
+    configs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&configs).expect("Could not serialize the config")
+        );
+        return Ok(());
+    }
+    let list = |values: &[String]| {
+        if values.is_empty() {
+            "-".to_string()
+        } else {
+            values.join(" ")
+        }
+    };
+    for config in configs {
+        println!("{}", style(&config.name).bold());
+        println!(
+            "  namespace:           {}",
+            config.namespace.unwrap_or("-".to_string())
+        );
+        for spec in config.package_specs {
+            println!(
+                "  package-spec:        {}{} {}",
+                spec.module,
+                if spec.in_source { " (in-source)" } else { "" },
+                spec.suffix
+            );
+        }
+        println!("  bsc-flags:           {}", list(&config.bsc_flags));
+        println!("  ppx-flags:           {}", list(&config.ppx_flags));
+        println!("  bs-dependencies:     {}", list(&config.bs_dependencies));
+        println!("  bs-dev-dependencies: {}", list(&config.bs_dev_dependencies));
+    }
+    Ok(())
+}
+
+/// Returns the names of all the (transitive) `bs-dependencies` of a package, that are part of the
+/// package tree.
+pub fn get_dependencies(packages: &AHashMap<String, Package>, package_name: &str) -> AHashSet<String> {
+    let mut dependencies = AHashSet::new();
+    let mut to_visit = vec![package_name.to_string()];
+    while let Some(package_name) = to_visit.pop() {
+        let package_dependencies = packages
+            .get(&package_name)
+            .and_then(|package| package.bsconfig.bs_dependencies.to_owned())
+            .unwrap_or(vec![]);
+        for dependency in package_dependencies {
+            if packages.contains_key(&dependency) && dependencies.insert(dependency.to_owned()) {
+                to_visit.push(dependency);
+            }
+        }
+    }
+    dependencies
+}
+
+/// Returns the names of the packages which name matches the package filter. With `with_deps`, the
+/// (transitive) `bs-dependencies` of those packages are included as well.
+pub fn filter_package_names(
+    packages: &AHashMap<String, Package>,
+    package_filter: &regex::Regex,
+    with_deps: bool,
+) -> AHashSet<String> {
+    let package_names = packages
+        .keys()
+        .filter(|package_name| package_filter.is_match(package_name))
+        .map(|package_name| package_name.to_owned())
+        .collect::<AHashSet<String>>();
+
+    if with_deps {
+        let dependencies = package_names
+            .iter()
+            .flat_map(|package_name| get_dependencies(packages, package_name))
+            .collect::<Vec<String>>();
+        package_names.into_iter().chain(dependencies).collect()
+    } else {
+        package_names
+    }
+}
+
+/// Returns the listed packages with their (transitive) `bs-dependencies`, or the listed packages
+/// that aren't in the package tree.
+pub fn get_listed_package_names(
+    packages: &AHashMap<String, Package>,
+    listed_package_names: &[String],
+) -> Result<AHashSet<String>, Vec<String>> {
+    let missing = listed_package_names
+        .iter()
+        .filter(|package_name| !packages.contains_key(*package_name))
+        .map(|package_name| package_name.to_owned())
+        .collect::<Vec<String>>();
+    if !missing.is_empty() {
+        return Err(missing);
+    }
+    Ok(listed_package_names
+        .iter()
+        .flat_map(|package_name| {
+            get_dependencies(packages, package_name)
+                .into_iter()
+                .chain([package_name.to_owned()])
+        })
+        .collect())
 }
 
 pub fn get_package_name(path: &str) -> String {
@@ -525,7 +1141,9 @@ pub fn get_package_name(path: &str) -> String {
     bsconfig.name
 }
 
-pub fn parse_packages(build_state: &mut BuildState) {
+/// Adds the modules of all packages to the build state. With `write_files` false (`rewatch check`),
+/// the build folders and the mlmaps of the namespaces aren't written.
+pub fn parse_packages(build_state: &mut BuildState, write_files: bool) {
     build_state
         .packages
         .clone()
@@ -536,20 +1154,28 @@ pub fn parse_packages(build_state: &mut BuildState) {
                 Some(package_modules) => build_state.module_names.extend(package_modules),
                 None => (),
             }
-            let build_path_abs = package.get_build_path();
-            let bs_build_path = package.get_bs_build_path();
-            helpers::create_build_path(&build_path_abs);
-            helpers::create_build_path(&bs_build_path);
+            if write_files {
+                helpers::create_build_path(&package.get_build_path());
+                helpers::create_build_path(&package.get_bs_build_path());
+            }
 
             package.namespace.to_suffix().iter().for_each(|namespace| {
                 // generate the mlmap "AST" file for modules that have a namespace configured
-                let source_files = match package.source_files.to_owned() {
-                    Some(source_files) => source_files
-                        .keys()
-                        .map(|key| key.to_owned())
-                        .collect::<Vec<String>>(),
-                    None => unreachable!(),
-                };
+                let source_files = package
+                    .source_files
+                    .as_ref()
+                    .map(|source_files| {
+                        source_files
+                            .keys()
+                            .map(|key| key.to_owned())
+                            .collect::<Vec<String>>()
+                    })
+                    .unwrap_or_default();
+                // a package without source files (yet) doesn't have anything to put in the namespace,
+                // and nothing that is compiled with it
+                if source_files.is_empty() {
+                    return;
+                }
                 let entry = match &package.namespace {
                     packages::Namespace::NamespaceWithEntry { entry, namespace: _ } => Some(entry),
                     _ => None,
@@ -568,7 +1194,10 @@ pub fn parse_packages(build_state: &mut BuildState) {
                     .filter(|module_name| helpers::is_non_exotic_module_name(module_name))
                     .collect::<AHashSet<String>>();
 
-                let mlmap = namespaces::gen_mlmap(&package, namespace, &depending_modules);
+                let (mlmap, mlmap_changed) =
+                    namespaces::gen_mlmap(&package, namespace, &depending_modules, write_files);
+                // the mlmap only needs to be compiled when the modules in the namespace changed
+                let mlmap_dirty = mlmap_changed || !Path::new(&package.get_mlmap_compile_path()).exists();
 
                 // mlmap will be compiled in the AST generation step
                 // compile_mlmap(&package, namespace, &project_root);
@@ -593,12 +1222,15 @@ pub fn parse_packages(build_state: &mut BuildState) {
                 build_state.insert_module(
                     &helpers::file_path_to_module_name(&mlmap.to_owned(), &packages::Namespace::NoNamespace),
                     Module {
-                        source_type: SourceType::MlMap(MlMap { dirty: false }),
+                        source_type: SourceType::MlMap(MlMap { dirty: mlmap_dirty }),
                         deps,
                         dependents: AHashSet::new(),
                         package_name: package.name.to_owned(),
                         compile_dirty: false,
                         last_compiled_cmt: None,
+                        compile_duration: None,
+                        compile_outcome: None,
+                        diagnostics: vec![],
                         last_compiled_cmi: None,
                     },
                 );
@@ -628,8 +1260,12 @@ pub fn parse_packages(build_state: &mut BuildState) {
                                         panic!("Unable to continue... See log output above...");
                                     }
                                     source_file.implementation.path = file.to_owned();
+                                    source_file.interface_only = false;
+                                    source_file.is_dev = metadata.is_dev;
+                                    source_file.virtual_contents = metadata.virtual_contents.to_owned();
                                     source_file.implementation.last_modified = metadata.modified;
                                     source_file.implementation.dirty = true;
+                                    source_file.implementation.dirty_reason = Some(DirtyReason::AstMissing);
                                 }
                                 _ => (),
                             })
@@ -641,70 +1277,78 @@ pub fn parse_packages(build_state: &mut BuildState) {
                                         compile_state: CompileState::Pending,
                                         last_modified: metadata.modified,
                                         dirty: true,
+                                        dirty_reason: Some(DirtyReason::AstMissing),
                                     },
                                     interface: None,
+                                    interface_only: false,
+                                    is_dev: metadata.is_dev,
+                                    virtual_contents: metadata.virtual_contents.to_owned(),
                                 }),
                                 deps: AHashSet::new(),
                                 dependents: AHashSet::new(),
                                 package_name: package.name.to_owned(),
                                 compile_dirty: true,
                                 last_compiled_cmt: None,
+                                compile_duration: None,
+                                compile_outcome: None,
+                                diagnostics: vec![],
                                 last_compiled_cmi: None,
                             });
                     } else {
                         // remove last character of string: resi -> res, rei -> re, mli -> ml
                         let mut implementation_filename = file.to_owned();
                         implementation_filename.pop();
-                        match source_files.get(&implementation_filename) {
-                            None => {
-                                println!(
-                                "{}\rWarning: No implementation file found for interface file (skipping): {}",
-                                LINE_CLEAR, file
-                            )
-                            }
-                            Some(_) => {
-                                build_state
-                                    .modules
-                                    .entry(module_name.to_string())
-                                    .and_modify(|module| match module.source_type {
-                                        SourceType::SourceFile(ref mut source_file) => {
-                                            source_file.interface = Some(Interface {
-                                                path: file.to_owned(),
-                                                parse_state: ParseState::Pending,
-                                                compile_state: CompileState::Pending,
-                                                last_modified: metadata.modified,
-                                                dirty: true,
-                                            });
-                                        }
-                                        _ => (),
-                                    })
-                                    .or_insert(Module {
-                                        source_type: SourceType::SourceFile(SourceFile {
-                                            // this will be overwritten later
-                                            implementation: Implementation {
-                                                path: implementation_filename.to_string(),
-                                                parse_state: ParseState::Pending,
-                                                compile_state: CompileState::Pending,
-                                                last_modified: metadata.modified,
-                                                dirty: true,
-                                            },
-                                            interface: Some(Interface {
-                                                path: file.to_owned(),
-                                                parse_state: ParseState::Pending,
-                                                compile_state: CompileState::Pending,
-                                                last_modified: metadata.modified,
-                                                dirty: true,
-                                            }),
-                                        }),
-                                        deps: AHashSet::new(),
-                                        dependents: AHashSet::new(),
-                                        package_name: package.name.to_owned(),
-                                        compile_dirty: true,
-                                        last_compiled_cmt: None,
-                                        last_compiled_cmi: None,
+                        let interface_only = !source_files.contains_key(&implementation_filename);
+                        build_state
+                            .modules
+                            .entry(module_name.to_string())
+                            .and_modify(|module| match module.source_type {
+                                SourceType::SourceFile(ref mut source_file) => {
+                                    source_file.interface = Some(Interface {
+                                        path: file.to_owned(),
+                                        parse_state: ParseState::Pending,
+                                        compile_state: CompileState::Pending,
+                                        last_modified: metadata.modified,
+                                        dirty: true,
+                                        dirty_reason: Some(DirtyReason::AstMissing),
                                     });
-                            }
-                        }
+                                }
+                                _ => (),
+                            })
+                            .or_insert(Module {
+                                source_type: SourceType::SourceFile(SourceFile {
+                                    // this will be overwritten later, unless the module only has an
+                                    // interface
+                                    implementation: Implementation {
+                                        path: implementation_filename.to_string(),
+                                        parse_state: ParseState::Pending,
+                                        compile_state: CompileState::Pending,
+                                        last_modified: metadata.modified,
+                                        dirty: true,
+                                        dirty_reason: Some(DirtyReason::AstMissing),
+                                    },
+                                    interface: Some(Interface {
+                                        path: file.to_owned(),
+                                        parse_state: ParseState::Pending,
+                                        compile_state: CompileState::Pending,
+                                        last_modified: metadata.modified,
+                                        dirty: true,
+                                        dirty_reason: Some(DirtyReason::AstMissing),
+                                    }),
+                                    interface_only,
+                                    is_dev: metadata.is_dev,
+                                    virtual_contents: None,
+                                }),
+                                deps: AHashSet::new(),
+                                dependents: AHashSet::new(),
+                                package_name: package.name.to_owned(),
+                                compile_dirty: true,
+                                last_compiled_cmt: None,
+                                compile_duration: None,
+                                compile_outcome: None,
+                                diagnostics: vec![],
+                                last_compiled_cmi: None,
+                            });
                     }
                 }),
             }
@@ -717,19 +1361,37 @@ fn check_if_rescript11_or_higher(version: &str) -> bool {
 
 impl Package {
     pub fn get_jsx_args(&self) -> Vec<String> {
-        match (self.bsconfig.reason.to_owned(), self.bsconfig.jsx.to_owned()) {
-            (_, Some(jsx)) => match jsx.version {
-                Some(version) if version == 3 || version == 4 => {
-                    vec!["-bs-jsx".to_string(), version.to_string()]
-                }
-                Some(_version) => panic!("Unsupported JSX version"),
-                None => vec![],
-            },
-            (Some(reason), None) => {
-                vec!["-bs-jsx".to_string(), format!("{}", reason.react_jsx)]
-            }
-            _ => vec![],
-        }
+        bsconfig::get_jsx_args(&self.bsconfig)
+    }
+
+    /// Like bsb, warnings are only turned into errors (`-warn-error`) for the root package and the
+    /// pinned dependencies, so a dependency that isn't free of warnings doesn't fail the build. To
+    /// build a dependency strictly, add it to the pinned-dependencies.
+    pub fn get_warning_args(&self, compile_options: &CompileOptions) -> Vec<String> {
+        self.get_warning_arg_sources(None, compile_options)
+            .into_iter()
+            .flat_map(|(_, args)| args)
+            .collect()
+    }
+
+    /// The arguments of `get_warning_args` with where they come from (see
+    /// `bsconfig::get_warning_arg_sources`). Warnings are only errors in the local packages, and not
+    /// at all with `--fail-on-warning`.
+    pub fn get_warning_arg_sources(
+        &self,
+        shared_config: Option<&bsconfig::SharedConfig>,
+        compile_options: &CompileOptions,
+    ) -> Vec<(bsconfig::FlagSource, Vec<String>)> {
+        let warn_error = (self.is_root || self.is_pinned_dep) && !compile_options.fail_on_warning;
+        bsconfig::get_warning_arg_sources(
+            &self.bsconfig,
+            shared_config,
+            compile_options.warn_spec.to_owned(),
+            compile_options.warn_error_spec.to_owned(),
+        )
+        .into_iter()
+        .filter(|(_, args)| warn_error || args[0] != "-warn-error")
+        .collect()
     }
 
     pub fn get_jsx_mode_args(&self) -> Vec<String> {
@@ -768,7 +1430,25 @@ impl Package {
                 _ => vec!["-uncurried".to_string()],
             }
         } else {
-            vec![]
+            match root_package.bsconfig.uncurried.to_owned() {
+                // before v11, uncurried mode is opt-in by setting it to true in the root bsconfig.json
+                Some(true) => vec!["-uncurried".to_string()],
+                _ => vec![],
+            }
+        }
+    }
+
+    /// The module of the standard library that bsc opens in every module, so every module depends
+    /// on it without referencing it: `PervasivesU` in uncurried mode, `Pervasives` otherwise.
+    pub fn get_implicit_stdlib_module(
+        &self,
+        version: &str,
+        root_package: &packages::Package,
+    ) -> &'static str {
+        if self.get_uncurried_args(version, root_package).is_empty() {
+            "Pervasives"
+        } else {
+            "PervasivesU"
         }
     }
 }
@@ -861,19 +1541,180 @@ pub fn validate_packages_dependencies(packages: &AHashMap<String, Package>) -> b
         println!(
             "\nUpdate the {} value in the {} of the unallowed dependencies to solve the issue!",
             console::style("unallowed_dependents").bold().dim(),
-            console::style("bsconfig.json").bold().dim()
+            console::style("bsconfig.json / rescript.json").bold().dim()
         )
     }
     return !has_any_unallowed_dependent;
 }
 
+fn is_in_sources(package: &Package, file: &Path) -> bool {
+    package.source_folders.iter().any(|source| {
+        let dir = Path::new(&source.dir);
+        match source.subdirs {
+            Some(bsconfig::Subdirs::Recurse(true)) => file.starts_with(dir),
+            _ => file.parent() == Some(dir),
+        }
+    })
+}
+
+/// The source files (relative to the package) on disk that none of the `sources` of the package
+/// pick up. The build output (lib), node_modules, hidden folders and the folders of nested
+/// packages (with their own bsconfig.json / rescript.json) are not scanned.
+pub fn get_files_outside_sources(package: &Package, respect_ignore: bool) -> Vec<String> {
+    let package_dir = Path::new(&package.path);
+    let walker = WalkBuilder::new(package_dir)
+        .standard_filters(false)
+        .git_ignore(respect_ignore)
+        .git_exclude(respect_ignore)
+        .ignore(respect_ignore)
+        .parents(respect_ignore)
+        .require_git(false)
+        .filter_entry(move |entry| {
+            let path = entry.path();
+            if entry.depth() == 0 || !path.is_dir() {
+                return true;
+            }
+            let name = entry.file_name().to_string_lossy();
+            !(name.starts_with('.')
+                || name == "node_modules"
+                || (entry.depth() == 1 && name == "lib")
+                || Path::new(&get_bsconfig_path(&path.to_string_lossy())).exists())
+        })
+        .build();
+
+    let mut files = walker
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(helpers::is_source_file)
+                && !entry.path().is_dir()
+        })
+        .filter_map(|entry| {
+            let file = entry.path().strip_prefix(package_dir).ok()?.to_path_buf();
+            (!is_in_sources(package, &file)).then(|| file.to_string_lossy().to_string())
+        })
+        .collect::<Vec<String>>();
+    files.sort();
+    files
+}
+
+/// Reports the source files of the packages that aren't in their `sources` (`--strict-sources`),
+/// returns whether there are none. Like the warnings, this only checks the root package and the
+/// pinned dependencies, the packages that are developed along with the project.
+pub fn validate_sources(packages: &AHashMap<String, Package>, respect_ignore: bool) -> bool {
+    let mut is_valid = true;
+    let mut package_names = packages.keys().collect::<Vec<&String>>();
+    package_names.sort();
+    for package_name in package_names {
+        let package = &packages[package_name];
+        if !(package.is_root || package.is_pinned_dep) {
+            continue;
+        }
+        for file in get_files_outside_sources(package, respect_ignore) {
+            is_valid = false;
+            reportln!(
+                "{}: {} (in {}) is not in the sources of the package",
+                style("Error").red(),
+                style(&file).bold(),
+                style(package_name).bold()
+            );
+        }
+    }
+    if !is_valid {
+        reportln!(
+            "\nAdd the folders of these files to the {} of the {} of the package, or remove them.",
+            style("sources").bold().dim(),
+            style("bsconfig.json / rescript.json").bold().dim()
+        );
+    }
+    is_valid
+}
+
+/// Returns the module names that more than one source file compiles to, with the package and the
+/// path of those files. Modules are identified by their name alone (the compiler assets of all
+/// packages are found by name), so two packages can only have a module with the same name when a
+/// namespace tells them apart.
+pub fn get_duplicate_modules(packages: &AHashMap<String, Package>) -> Vec<(String, Vec<(String, String)>)> {
+    let mut files_per_module: AHashMap<String, Vec<(String, String)>> = AHashMap::new();
+    for package in packages.values() {
+        for file in package
+            .source_files
+            .as_ref()
+            .map(|files| files.keys())
+            .into_iter()
+            .flatten()
+        {
+            let extension = Path::new(file)
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_string())
+                .unwrap_or_default();
+            // the interface of a module with an implementation is the same module
+            if !helpers::is_implementation_file(&extension)
+                && package
+                    .source_files
+                    .as_ref()
+                    .is_some_and(|files| files.contains_key(&file[..file.len() - 1]))
+            {
+                continue;
+            }
+            files_per_module
+                .entry(helpers::file_path_to_module_name(file, &package.namespace))
+                .or_default()
+                .push((package.name.to_owned(), file.to_owned()));
+        }
+    }
+    let mut duplicates = files_per_module
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|(module_name, mut files)| {
+            files.sort();
+            (module_name, files)
+        })
+        .collect::<Vec<(String, Vec<(String, String)>)>>();
+    duplicates.sort();
+    duplicates
+}
+
+pub fn validate_module_names(packages: &AHashMap<String, Package>) -> bool {
+    let duplicates = get_duplicate_modules(packages);
+    for (module_name, files) in &duplicates {
+        reportln!(
+            "{}: the module {} is defined more than once:",
+            style("Error").red(),
+            style(module_name).bold()
+        );
+        for (package_name, file) in files {
+            reportln!("  {} (in {})", file, style(package_name).bold());
+        }
+    }
+    if !duplicates.is_empty() {
+        reportln!(
+            "\nRename one of the files, or give the package a {} in its {}.",
+            style("namespace").bold().dim(),
+            style("bsconfig.json / rescript.json").bold().dim()
+        );
+    }
+    duplicates.is_empty()
+}
+
 #[cfg(test)]
 mod test {
     use crate::bsconfig::Source;
+    use crate::test_fixture::TestProject;
     use ahash::{AHashMap, AHashSet};
 
+    use super::super::build_types::{CompileOptions, MlMap, SourceType};
+    use super::super::namespaces;
     use super::{Namespace, Package};
 
+    fn with_uncurried(mut package: Package, uncurried: Option<bool>) -> Package {
+        package.bsconfig.uncurried = uncurried;
+        package
+    }
+
     fn create_package(
         name: String,
         bs_deps: Vec<String>,
@@ -892,6 +1733,7 @@ mod test {
                 pinned_dependencies: Some(pinned_deps),
                 bs_dependencies: Some(bs_deps),
                 bs_dev_dependencies: Some(dev_deps),
+                bs_external_includes: None,
                 ppx_flags: None,
                 bsc_flags: None,
                 reason: None,
@@ -900,6 +1742,11 @@ mod test {
                 uncurried: None,
                 namespace_entry: None,
                 allowed_dependents,
+                shared_config: None,
+                ppx_working_directory: None,
+                bsc: None,
+                external_js: None,
+                build_dir: None,
             },
             source_folders: AHashSet::new(),
             source_files: None,
@@ -909,6 +1756,7 @@ mod test {
             dirs: None,
             is_pinned_dep: false,
             is_root: false,
+            node_modules: None,
         };
     }
     #[test]
@@ -1022,4 +1870,733 @@ mod test {
         let is_valid = super::validate_packages_dependencies(&packages);
         assert_eq!(is_valid, true)
     }
+
+    #[test]
+    fn should_pass_uncurried_flag_when_enabled() {
+        let package = with_uncurried(
+            create_package(String::from("Package1"), vec![], vec![], vec![], None),
+            Some(true),
+        );
+        assert_eq!(package.get_uncurried_args("10.1.4", &package), vec!["-uncurried"]);
+        assert_eq!(package.get_uncurried_args("11.0.0", &package), vec!["-uncurried"]);
+    }
+
+    #[test]
+    fn should_not_pass_uncurried_flag_when_disabled() {
+        let package = with_uncurried(
+            create_package(String::from("Package1"), vec![], vec![], vec![], None),
+            Some(false),
+        );
+        assert_eq!(
+            package.get_uncurried_args("10.1.4", &package),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            package.get_uncurried_args("11.0.0", &package),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn should_default_uncurried_flag_by_version() {
+        let package = create_package(String::from("Package1"), vec![], vec![], vec![], None);
+        assert_eq!(
+            package.get_uncurried_args("10.1.4", &package),
+            Vec::<String>::new()
+        );
+        assert_eq!(package.get_uncurried_args("11.0.0", &package), vec!["-uncurried"]);
+    }
+
+    #[test]
+    fn should_open_the_stdlib_module_of_the_uncurried_mode() {
+        let curried = with_uncurried(
+            create_package(String::from("Package1"), vec![], vec![], vec![], None),
+            Some(false),
+        );
+        let uncurried = with_uncurried(
+            create_package(String::from("Package1"), vec![], vec![], vec![], None),
+            Some(true),
+        );
+
+        assert_eq!(
+            curried.get_implicit_stdlib_module("11.0.0", &curried),
+            "Pervasives"
+        );
+        assert_eq!(
+            uncurried.get_implicit_stdlib_module("11.0.0", &uncurried),
+            "PervasivesU"
+        );
+        assert_eq!(
+            uncurried.get_implicit_stdlib_module("10.1.4", &uncurried),
+            "PervasivesU"
+        );
+    }
+
+    #[test]
+    fn should_filter_packages_by_name() {
+        let mut packages: AHashMap<String, Package> = AHashMap::new();
+        packages.insert(
+            String::from("app-main"),
+            create_package(
+                String::from("app-main"),
+                vec![String::from("lib")],
+                vec![],
+                vec![],
+                None,
+            ),
+        );
+        packages.insert(
+            String::from("lib"),
+            create_package(
+                String::from("lib"),
+                vec![String::from("base")],
+                vec![],
+                vec![],
+                None,
+            ),
+        );
+        packages.insert(
+            String::from("base"),
+            create_package(String::from("base"), vec![], vec![], vec![], None),
+        );
+        let package_filter = regex::Regex::new("^app-").unwrap();
+
+        let without_deps = super::filter_package_names(&packages, &package_filter, false);
+        assert_eq!(without_deps, AHashSet::from_iter(vec![String::from("app-main")]));
+
+        let with_deps = super::filter_package_names(&packages, &package_filter, true);
+        assert_eq!(
+            with_deps,
+            AHashSet::from_iter(vec![
+                String::from("app-main"),
+                String::from("lib"),
+                String::from("base")
+            ])
+        );
+    }
+
+    #[test]
+    fn should_build_the_listed_packages_with_their_dependencies() {
+        let mut packages: AHashMap<String, Package> = AHashMap::new();
+        for (name, dependencies) in [
+            ("app-a", vec!["lib"]),
+            ("app-b", vec!["lib"]),
+            ("lib", vec!["base"]),
+            ("base", vec![]),
+            ("other", vec![]),
+        ] {
+            packages.insert(
+                String::from(name),
+                create_package(
+                    String::from(name),
+                    dependencies.into_iter().map(String::from).collect(),
+                    vec![],
+                    vec![],
+                    None,
+                ),
+            );
+        }
+
+        // lib is listed, and a dependency of both apps
+        let listed = super::get_listed_package_names(
+            &packages,
+            &[String::from("app-a"), String::from("app-b"), String::from("lib")],
+        );
+        let missing =
+            super::get_listed_package_names(&packages, &[String::from("app-a"), String::from("app-c")]);
+
+        assert_eq!(
+            listed,
+            Ok(AHashSet::from_iter(
+                ["app-a", "app-b", "lib", "base"].map(String::from)
+            ))
+        );
+        assert_eq!(missing, Err(vec![String::from("app-c")]));
+    }
+
+    #[test]
+    fn should_respect_ignore_files_when_reading_folders() {
+        let project = TestProject::new("read-folders");
+        project.write(".gitignore", "generated/\n");
+        project.write("src/Main.res", "");
+        project.write("src/generated/Generated.res", "");
+        let source_dir = std::path::Path::new("src");
+
+        let respected = super::read_folders(&None, &project.root, source_dir, true, true).unwrap();
+        let mut respected = respected.keys().cloned().collect::<Vec<String>>();
+        respected.sort();
+
+        let not_respected = super::read_folders(&None, &project.root, source_dir, true, false).unwrap();
+        let mut not_respected = not_respected.keys().cloned().collect::<Vec<String>>();
+        not_respected.sort();
+
+        assert_eq!(respected, vec!["src/Main.res"]);
+        assert_eq!(not_respected, vec!["src/Main.res", "src/generated/Generated.res"]);
+    }
+
+    #[test]
+    fn should_return_transitive_dependencies() {
+        let mut packages: AHashMap<String, Package> = AHashMap::new();
+        packages.insert(
+            String::from("app"),
+            create_package(
+                String::from("app"),
+                vec![String::from("lib")],
+                vec![],
+                vec![],
+                None,
+            ),
+        );
+        packages.insert(
+            String::from("lib"),
+            create_package(
+                String::from("lib"),
+                vec![String::from("base"), String::from("not-in-tree")],
+                vec![],
+                vec![],
+                None,
+            ),
+        );
+        packages.insert(
+            String::from("base"),
+            create_package(String::from("base"), vec![], vec![], vec![], None),
+        );
+
+        assert_eq!(
+            super::get_dependencies(&packages, "app"),
+            AHashSet::from_iter(vec![String::from("lib"), String::from("base")])
+        );
+        assert_eq!(super::get_dependencies(&packages, "base"), AHashSet::new());
+    }
+
+    #[test]
+    fn should_derive_valid_namespaces_from_package_names() {
+        let cases = [
+            ("my-package", "MyPackage"),
+            ("@scope/my-package", "ScopeMyPackage"),
+            ("@my/sub/pkg", "MySubPkg"),
+            ("@my/sub-pkg", "MySubPkg"),
+            ("my_package", "MyPackage"),
+            ("my.package", "MyPackage"),
+            ("3d-utils", "N3DUtils"),
+            ("@1password/sdk", "N1PasswordSdk"),
+            ("pkg2", "Pkg2"),
+            ("café-utils", "CafUtils"),
+            ("日本", "N"),
+        ];
+
+        for (package_name, namespace) in cases {
+            assert_eq!(super::namespace_from_package_name(package_name), namespace);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_resolve_dependencies_of_symlinked_packages() {
+        // the layout of pnpm: node_modules/a links to node_modules/.pnpm/a@1/node_modules/a, and
+        // the dependencies of a live next to it in node_modules/.pnpm/a@1/node_modules
+        let project = TestProject::new("pnpm");
+        let store = project.join("node_modules/.pnpm/a@1/node_modules");
+        std::fs::create_dir_all(store.join("a")).unwrap();
+        std::fs::create_dir_all(store.join("b")).unwrap();
+        std::os::unix::fs::symlink(store.join("a"), project.join("node_modules/a")).unwrap();
+        let project_root = project.path();
+
+        let a = super::resolve_package_path("a", &project_root, &project_root, &None, None);
+        let b = a
+            .as_ref()
+            .map(|a| super::resolve_package_path("b", a, &project_root, &None, None));
+        let missing = super::resolve_package_path("missing", &project_root, &project_root, &None, None);
+
+        assert_eq!(a, Ok(store.join("a").to_string_lossy().to_string()));
+        assert_eq!(b, Ok(Ok(store.join("b").to_string_lossy().to_string())));
+        assert!(missing
+            .unwrap_err()
+            .contains(&project.join("node_modules/missing").to_string_lossy().to_string()));
+    }
+
+    #[test]
+    fn should_resolve_dependencies_from_the_given_node_modules_last() {
+        let project = TestProject::new("node-modules");
+        std::fs::create_dir_all(project.join("vendor/node_modules/dep")).unwrap();
+        std::fs::create_dir_all(project.join("vendor/node_modules/local")).unwrap();
+        std::fs::create_dir_all(project.join("node_modules/local")).unwrap();
+        let project_root = project.path();
+        let node_modules = project.join("vendor/node_modules").to_string_lossy().to_string();
+
+        let resolve = |package_name: &str, node_modules: Option<&str>| {
+            super::resolve_package_path(package_name, &project_root, &project_root, &None, node_modules)
+        };
+
+        assert!(resolve("dep", None).is_err());
+        assert_eq!(
+            resolve("dep", Some(&node_modules)),
+            Ok(project
+                .join("vendor/node_modules/dep")
+                .to_string_lossy()
+                .to_string())
+        );
+        assert_eq!(
+            resolve("local", Some(&node_modules)),
+            Ok(project.join("node_modules/local").to_string_lossy().to_string())
+        );
+    }
+
+    #[test]
+    fn should_resolve_scoped_and_aliased_dependencies() {
+        let project = TestProject::new("alias");
+        std::fs::create_dir_all(project.join("node_modules/@org/lib")).unwrap();
+        std::fs::create_dir_all(project.join("node_modules/other-lib")).unwrap();
+        project.write(
+            "package.json",
+            r#"{ "dependencies": { "@org/lib": "^1.0.0", "other-lib": "npm:@org/other@^2.0.0" } }"#,
+        );
+        let project_root = project.path();
+
+        let scoped = super::resolve_package_path("@org/lib", &project_root, &project_root, &None, None);
+        let aliased = super::resolve_package_path("@org/other", &project_root, &project_root, &None, None);
+
+        let scoped = scoped.unwrap();
+        assert_eq!(scoped, project.join("node_modules/@org/lib").to_string_lossy());
+        assert_eq!(
+            aliased,
+            Ok(project
+                .join("node_modules/other-lib")
+                .to_string_lossy()
+                .to_string())
+        );
+        // the include path (-I) of the dependency is the build folder in its install directory
+        let package = super::make_package(
+            serde_json::from_str(r#"{ "name": "@org/lib", "sources": "src" }"#).unwrap(),
+            &scoped,
+            false,
+            false,
+            &super::PackageOptions::default(),
+        );
+        assert_eq!(
+            package.get_build_path(),
+            project.join("node_modules/@org/lib/lib/ocaml").to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn should_find_the_same_source_files_for_every_shape_of_sources() {
+        let project = TestProject::new("sources");
+        project.write("src/Main.res", "");
+        project.write("test/Main_test.res", "");
+
+        let source_files = |sources: &str| {
+            let bsconfig = serde_json::from_str::<crate::bsconfig::T>(&format!(
+                r#"{{ "name": "package", "sources": {} }}"#,
+                sources
+            ))
+            .unwrap();
+            let package = super::make_package(
+                bsconfig,
+                &project.path(),
+                false,
+                true,
+                &super::PackageOptions::default(),
+            );
+            let mut files = package
+                .source_folders
+                .iter()
+                .flat_map(|source| {
+                    super::get_source_files(&project.root, &None, source, true, true).into_keys()
+                })
+                .collect::<Vec<String>>();
+            files.sort();
+            files
+        };
+
+        let src = vec![String::from("src/Main.res")];
+        let src_and_test = vec![String::from("src/Main.res"), String::from("test/Main_test.res")];
+        assert_eq!(source_files(r#""src""#), src);
+        assert_eq!(source_files(r#"{ "dir": "src" }"#), src);
+        assert_eq!(source_files(r#"["src", "test"]"#), src_and_test);
+        assert_eq!(
+            source_files(r#"[{ "dir": "src" }, { "dir": "test", "subdirs": true }]"#),
+            src_and_test
+        );
+    }
+
+    #[test]
+    fn should_resolve_the_config_after_merging_the_shared_config() {
+        let project = TestProject::new("resolved-config");
+        std::fs::create_dir_all(project.join("src")).unwrap();
+        std::fs::create_dir_all(project.join("node_modules/dep/src")).unwrap();
+        project.write(
+            "bsconfig.json",
+            r#"{
+                "name": "root",
+                "sources": "src",
+                "namespace": true,
+                "bs-dependencies": ["dep"],
+                "shared-config": { "bsc-flags": ["-open Belt"] }
+            }"#,
+        );
+        project.write(
+            "node_modules/dep/bsconfig.json",
+            r#"{ "name": "dep", "sources": "src", "package-specs": { "module": "commonjs", "in-source": false }, "suffix": ".bs.js" }"#,
+        );
+        let project_root = project.path();
+
+        let packages = super::load_packages(&project_root, &super::PackageOptions::default()).unwrap();
+        let compile_options = CompileOptions::default();
+        let root_config =
+            super::get_resolved_config(&packages["root"], &project_root, None, &compile_options);
+        let dep_config = super::get_resolved_config(&packages["dep"], &project_root, None, &compile_options);
+
+        assert_eq!(root_config.namespace, Some(String::from("Root")));
+        assert_eq!(root_config.bs_dependencies, vec![String::from("dep")]);
+        // the defaults
+        assert_eq!(
+            root_config.package_specs,
+            vec![super::ResolvedPackageSpec {
+                module: String::from("es6"),
+                in_source: true,
+                suffix: String::from(".mjs"),
+            }]
+        );
+        assert_eq!(dep_config.bsc_flags, vec!["-open", "Belt"]);
+        assert_eq!(
+            dep_config.package_specs,
+            vec![super::ResolvedPackageSpec {
+                module: String::from("commonjs"),
+                in_source: false,
+                suffix: String::from(".bs.js"),
+            }]
+        );
+        let json = serde_json::to_value(&dep_config).unwrap();
+        assert_eq!(json["bsc-flags"], serde_json::json!(["-open", "Belt"]));
+        assert_eq!(json["package-specs"][0]["in-source"], serde_json::json!(false));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_update_the_namespace_when_a_file_is_added() {
+        let project = TestProject::new("namespace");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "namespace": "MyNamespace" }"#,
+        );
+        project.write("src/A.res", "");
+        // a bsc that writes the cmi of the mlmap
+        let bsc_path = project.write_script(
+            "bsc.exe",
+            "for arg in \"$@\"; do last=\"$arg\"; done\ntouch \"$(basename \"$last\" .mlmap).cmi\"\n",
+        );
+        // the watcher reads the package tree again on every change, and compiles the mlmap when it's
+        // dirty, like this
+        let build = || {
+            let build_state = project.build_state("root");
+            let mlmap = build_state.get_module("MyNamespace").unwrap().to_owned();
+            let dirty = matches!(mlmap.source_type, SourceType::MlMap(MlMap { dirty: true }));
+            if dirty {
+                let package = build_state.get_package("root").unwrap();
+                namespaces::compile_mlmap(package, "MyNamespace", &bsc_path.to_string_lossy(), None).unwrap();
+            }
+            let mut deps = mlmap.deps.iter().cloned().collect::<Vec<String>>();
+            deps.sort();
+            (dirty, deps)
+        };
+
+        let first_build = build();
+        let unchanged = build();
+        project.write("src/B.res", "");
+        let added_file = build();
+        let mlmap = std::fs::read_to_string(project.join("lib/ocaml/MyNamespace.mlmap")).unwrap();
+        let after_added_file = build();
+
+        assert_eq!(first_build, (true, vec![String::from("A-MyNamespace")]));
+        assert_eq!(unchanged, (false, vec![String::from("A-MyNamespace")]));
+        assert_eq!(
+            added_file,
+            (
+                true,
+                vec![String::from("A-MyNamespace"), String::from("B-MyNamespace")]
+            )
+        );
+        assert_eq!(mlmap, "randjbuildsystem\nA\nB\n");
+        assert!(!after_added_file.0);
+    }
+
+    #[test]
+    fn should_only_include_the_dev_sources_when_asked() {
+        let project = TestProject::new("dev-sources");
+        project.write("src/Main.res", "");
+        project.write("test/Main_test.res", "");
+        let bsconfig = serde_json::from_str::<crate::bsconfig::T>(
+            r#"{ "name": "package", "sources": ["src", { "dir": "test", "type": "dev" }] }"#,
+        )
+        .unwrap();
+        let package = super::make_package(
+            bsconfig,
+            &project.path(),
+            false,
+            true,
+            &super::PackageOptions::default(),
+        );
+
+        let source_files = |include_dev: bool| {
+            let mut files = package
+                .source_folders
+                .iter()
+                .flat_map(|source| super::get_source_files(&project.root, &None, source, true, include_dev))
+                .map(|(file, metadata)| (file, metadata.is_dev))
+                .collect::<Vec<(String, bool)>>();
+            files.sort();
+            files
+        };
+
+        assert_eq!(
+            source_files(true),
+            vec![
+                (String::from("src/Main.res"), false),
+                (String::from("test/Main_test.res"), true)
+            ]
+        );
+        assert_eq!(source_files(false), vec![(String::from("src/Main.res"), false)]);
+    }
+
+    #[test]
+    fn should_find_the_source_files_outside_of_the_sources() {
+        let project = TestProject::new("strict-sources");
+        for file in [
+            "src/Main.res",
+            "src/Main.resi",
+            "src/nested/Nested.res",
+            "scripts/Script.res",
+            "lib/bs/src/Main.res",
+            "node_modules/dep/src/Dep.res",
+            "packages/other/src/Other.res",
+        ] {
+            project.write(file, "");
+        }
+        project.write("packages/other/rescript.json", "{}");
+
+        let files_outside_sources = |sources: &str| {
+            let bsconfig = serde_json::from_str::<crate::bsconfig::T>(&format!(
+                r#"{{ "name": "package", "sources": {} }}"#,
+                sources
+            ))
+            .unwrap();
+            let package = super::make_package(
+                bsconfig,
+                &project.path(),
+                false,
+                true,
+                &super::PackageOptions::default(),
+            );
+            super::get_files_outside_sources(&package, true)
+        };
+
+        assert_eq!(
+            files_outside_sources(r#""src""#),
+            vec![
+                String::from("scripts/Script.res"),
+                String::from("src/nested/Nested.res")
+            ]
+        );
+        assert_eq!(
+            files_outside_sources(r#"{ "dir": "src", "subdirs": true }"#),
+            vec![String::from("scripts/Script.res")]
+        );
+        assert_eq!(
+            files_outside_sources(r#"["scripts", { "dir": "src", "subdirs": ["nested"] }]"#),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn should_load_the_same_packages_from_rescript_json_and_bsconfig_json() {
+        let load = |root_config: &str, dependency_config: &str| {
+            let project = TestProject::new(&format!("config-name-{}", root_config));
+            project.write(
+                root_config,
+                r#"{ "name": "root", "sources": "src", "bs-dependencies": ["dep"] }"#,
+            );
+            project.write(
+                format!("node_modules/dep/{}", dependency_config),
+                r#"{ "name": "dep", "sources": "src" }"#,
+            );
+            project.write("src/Root.res", "");
+            project.write("node_modules/dep/src/Dep.res", "");
+
+            let packages = super::load_packages(&project.path(), &super::PackageOptions::default()).unwrap();
+            let mut packages = packages
+                .values()
+                .map(|package| {
+                    let mut source_files = package
+                        .source_files
+                        .as_ref()
+                        .unwrap()
+                        .keys()
+                        .cloned()
+                        .collect::<Vec<String>>();
+                    source_files.sort();
+                    (package.name.to_owned(), source_files)
+                })
+                .collect::<Vec<(String, Vec<String>)>>();
+            packages.sort();
+            packages
+        };
+
+        let from_bsconfig = load("bsconfig.json", "bsconfig.json");
+        assert_eq!(
+            from_bsconfig,
+            vec![
+                (String::from("dep"), vec![String::from("src/Dep.res")]),
+                (String::from("root"), vec![String::from("src/Root.res")])
+            ]
+        );
+        assert_eq!(load("rescript.json", "rescript.json"), from_bsconfig);
+        assert_eq!(load("rescript.json", "bsconfig.json"), from_bsconfig);
+    }
+
+    #[test]
+    fn should_only_turn_warnings_into_errors_for_local_packages() {
+        let mut package = create_package(String::from("dep"), vec![], vec![], vec![], None);
+        package.bsconfig.warnings = Some(crate::bsconfig::Warnings {
+            number: Some(String::from("+a")),
+            error: Some(crate::bsconfig::Error::Catchall(true)),
+        });
+
+        let compile_options = CompileOptions::default();
+
+        assert_eq!(package.get_warning_args(&compile_options), vec!["-w", "+a"]);
+
+        package.is_pinned_dep = true;
+        assert_eq!(
+            package.get_warning_args(&compile_options),
+            vec!["-w", "+a", "-warn-error", "A"]
+        );
+
+        let fail_on_warning = CompileOptions {
+            fail_on_warning: true,
+            ..CompileOptions::default()
+        };
+        assert_eq!(package.get_warning_args(&fail_on_warning), vec!["-w", "+a"]);
+    }
+
+    #[test]
+    fn should_create_the_build_folders_of_nested_sources() {
+        let project = TestProject::new("build-dirs");
+        project.write(
+            "bsconfig.json",
+            r#"{
+                "name": "root",
+                "sources": { "dir": "src", "subdirs": true },
+                "package-specs": { "module": "es6", "in-source": false }
+            }"#,
+        );
+        project.write("src/nested/deep/Deep.res", "");
+
+        super::make(
+            &None,
+            &project.path(),
+            None,
+            true,
+            &[],
+            &super::PackageOptions::default(),
+        );
+
+        for dir in ["lib/ocaml", "lib/bs/src/nested/deep", "lib/es6/src/nested/deep"] {
+            assert!(project.join(dir).is_dir(), "{} was not created", dir);
+        }
+    }
+
+    #[test]
+    fn should_only_write_the_virtual_sources_when_making_the_package_tree() {
+        let project = TestProject::new("read-package-tree");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "");
+        let project_root = project.path();
+        let virtual_sources = [super::VirtualSource {
+            package_name: "root".to_string(),
+            module_name: "Generated".to_string(),
+            contents: "let x = 1".to_string(),
+        }];
+
+        let packages = super::read_package_tree(
+            &None,
+            &project_root,
+            None,
+            true,
+            &virtual_sources,
+            &super::PackageOptions::default(),
+        );
+        let read_lib = project.join("lib").exists();
+        super::make(
+            &None,
+            &project_root,
+            None,
+            true,
+            &virtual_sources,
+            &super::PackageOptions::default(),
+        );
+        let written =
+            std::fs::read_to_string(project.join("lib/bs/virtual/Generated.res")).unwrap_or_default();
+
+        let source_files = packages["root"].source_files.as_ref().unwrap();
+        assert!(source_files.contains_key("lib/bs/virtual/Generated.res"));
+        assert!(source_files.contains_key("src/A.res"));
+        assert!(!read_lib);
+        assert_eq!(written, "let x = 1");
+    }
+
+    #[test]
+    fn should_report_modules_with_the_same_name_in_different_packages() {
+        let with_files = |name: &str, namespace: Namespace, files: Vec<&str>| {
+            let mut package = create_package(name.to_string(), vec![], vec![], vec![], None);
+            package.namespace = namespace;
+            package.source_files = Some(
+                files
+                    .into_iter()
+                    .map(|file| {
+                        (
+                            file.to_string(),
+                            super::SourceFileMeta {
+                                modified: std::time::SystemTime::UNIX_EPOCH,
+                                is_dev: false,
+                                virtual_contents: None,
+                            },
+                        )
+                    })
+                    .collect(),
+            );
+            (name.to_string(), package)
+        };
+        let packages = AHashMap::from([
+            with_files(
+                "root",
+                Namespace::NoNamespace,
+                vec!["src/Utils.res", "src/Utils.resi", "src/App.res"],
+            ),
+            with_files("dep", Namespace::NoNamespace, vec!["src/Utils.res"]),
+            // the namespace tells this Utils apart from the others
+            with_files(
+                "namespaced",
+                Namespace::Namespace(String::from("Namespaced")),
+                vec!["src/Utils.res"],
+            ),
+        ]);
+
+        assert_eq!(
+            super::get_duplicate_modules(&packages),
+            vec![(
+                String::from("Utils"),
+                vec![
+                    (String::from("dep"), String::from("src/Utils.res")),
+                    (String::from("root"), String::from("src/Utils.res"))
+                ]
+            )]
+        );
+        assert!(!super::validate_module_names(&packages));
+
+        let mut packages = packages;
+        packages.remove("dep");
+        assert!(super::get_duplicate_modules(&packages).is_empty());
+        assert!(super::validate_module_names(&packages));
+    }
 }
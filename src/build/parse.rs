@@ -1,26 +1,30 @@
+use super::ast;
 use super::build_types::*;
+use super::events::{self, BuildEvent};
+use super::interrupt;
 use super::logs;
 use super::namespaces;
 use super::packages;
+use super::trace;
 use crate::bsconfig;
-use crate::bsconfig::OneOrMore;
+use crate::bsconfig::{FlagSource, OneOrMore};
 use crate::helpers;
+use crate::helpers::emojis::LINE_CLEAR;
 use log::debug;
 use rayon::prelude::*;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
 pub fn generate_asts(
-    version: &str,
     build_state: &mut BuildState,
     inc: impl Fn() -> () + std::marker::Sync,
-    bsc_path: &str,
     workspace_root: Option<String>,
+    context: &CompileContext,
 ) -> Result<String, String> {
     let mut has_failure = false;
     let mut stderr = "".to_string();
 
-    let results = build_state
+    let mut results = build_state
         .modules
         .par_iter()
         .map(|(module_name, module)| {
@@ -30,13 +34,24 @@ pub fn generate_asts(
                 .get_package(&module.package_name)
                 .expect("Package not found");
             match &module.source_type {
+                SourceType::MlMap(MlMap { dirty: false }) => (
+                    module_name.to_owned(),
+                    Ok((package.get_mlmap_path(), None)),
+                    Ok(None),
+                    false,
+                ),
                 SourceType::MlMap(_) => {
                     // probably better to do this in a different function
                     // specific to compiling mlmaps
                     let path = package.get_mlmap_path();
                     let compile_path = package.get_mlmap_compile_path();
                     let mlmap_hash = helpers::compute_file_hash(&compile_path);
-                    namespaces::compile_mlmap(&package, module_name, bsc_path);
+                    let result = namespaces::compile_mlmap(
+                        &package,
+                        module_name,
+                        &package.get_bsc_path(context.bsc_path),
+                        context.trace,
+                    );
                     let mlmap_hash_after = helpers::compute_file_hash(&compile_path);
 
                     let is_dirty = match (mlmap_hash, mlmap_hash_after) {
@@ -44,26 +59,52 @@ pub fn generate_asts(
                         _ => true,
                     };
 
-                    (module_name.to_owned(), Ok((path, None)), Ok(None), is_dirty)
+                    (
+                        module_name.to_owned(),
+                        result.map(|_| (path, None)),
+                        Ok(None),
+                        is_dirty,
+                    )
                 }
 
                 SourceType::SourceFile(source_file) => {
                     let root_package = build_state.get_package(&build_state.root_config_name).unwrap();
+                    let package_bsc_path = package.get_bsc_path(context.bsc_path);
+                    let package_version =
+                        package.get_version(context.bsc_path, context.version, context.compile_options);
+                    let package_context = context.for_package(&package_bsc_path, &package_version);
 
-                    let (ast_path, iast_path, dirty) = if source_file.implementation.dirty
-                        || source_file.interface.as_ref().map(|i| i.dirty).unwrap_or(false)
+                    let (ast_path, iast_path, dirty) = if (source_file.implementation.dirty
+                        || source_file.interface.as_ref().map(|i| i.dirty).unwrap_or(false))
+                        // don't start parsing any more files when the build is interrupted
+                        && !interrupt::is_cancelled(context.cancel)
                     {
                         // dbg!("Compiling", source_file.implementation.path.to_owned());
                         inc();
-                        let ast_result = generate_ast(
-                            package.to_owned(),
-                            root_package.to_owned(),
-                            &source_file.implementation.path.to_owned(),
-                            &build_state.project_root,
-                            &version,
-                            bsc_path,
-                            workspace_root.to_owned(),
+                        if helpers::is_verbose() {
+                            print_dirty_reason(source_file);
+                        }
+                        events::emit(
+                            context.events,
+                            BuildEvent::ParseStarted {
+                                module_name: module_name.to_owned(),
+                            },
                         );
+                        let ast_result = if source_file.interface_only {
+                            Ok((
+                                helpers::get_basename(&source_file.implementation.path).to_string() + ".ast",
+                                None,
+                            ))
+                        } else {
+                            generate_ast(
+                                package.to_owned(),
+                                root_package.to_owned(),
+                                &source_file.implementation.path.to_owned(),
+                                &build_state.project_root,
+                                workspace_root.to_owned(),
+                                &package_context,
+                            )
+                        };
 
                         let iast_result = match source_file.interface.as_ref().map(|i| i.path.to_owned()) {
                             Some(interface_file_path) => generate_ast(
@@ -71,13 +112,19 @@ pub fn generate_asts(
                                 root_package.to_owned(),
                                 &interface_file_path.to_owned(),
                                 &build_state.project_root,
-                                &version,
-                                bsc_path,
                                 workspace_root.to_owned(),
+                                &package_context,
                             )
                             .map(|result| Some(result)),
                             _ => Ok(None),
                         };
+                        events::emit(
+                            context.events,
+                            BuildEvent::ParseFinished {
+                                module_name: module_name.to_owned(),
+                                success: ast_result.is_ok() && iast_result.is_ok(),
+                            },
+                        );
 
                         (ast_result, iast_result, true)
                     } else {
@@ -104,6 +151,9 @@ pub fn generate_asts(
             Result<Option<(String, Option<String>)>, String>,
             bool,
         )>>();
+    // the modules are a hash map, so the results are applied in the order of the module names, to log
+    // and report the errors in the same order in every build
+    results.sort_by(|a, b| a.0.cmp(&b.0));
 
     results
         .into_iter()
@@ -129,6 +179,7 @@ pub fn generate_asts(
                                 }
                                 logs::append(package, &err);
                                 stderr.push_str(&err);
+                                events::emit_diagnostic(context.events, &module_name, &err);
                             }
                         }
                     }
@@ -142,6 +193,7 @@ pub fn generate_asts(
                         logs::append(package, &err);
                         has_failure = true;
                         stderr.push_str(&err);
+                        events::emit_diagnostic(context.events, &module_name, &err);
                     }
                 };
                 match iast_path {
@@ -160,6 +212,7 @@ pub fn generate_asts(
                                 }
                                 logs::append(package, &err);
                                 stderr.push_str(&err);
+                                events::emit_diagnostic(context.events, &module_name, &err);
                             }
                         }
                     }
@@ -177,106 +230,348 @@ pub fn generate_asts(
                         logs::append(package, &err);
                         has_failure = true;
                         stderr.push_str(&err);
+                        events::emit_diagnostic(context.events, &module_name, &err);
                     }
                 };
             }
         });
 
     if has_failure {
+        for error in get_namespace_parse_errors(build_state) {
+            stderr.push_str(&error);
+        }
         Err(stderr)
     } else {
         Ok(stderr)
     }
 }
 
-fn generate_ast(
-    package: packages::Package,
-    root_package: packages::Package,
+/// The modules in the mlmap of a namespace of which the AST could not be generated. bsc compiles
+/// the mlmap regardless, so these would otherwise only show up later on, as a module of the
+/// namespace that can't be found.
+fn get_namespace_parse_errors(build_state: &BuildState) -> Vec<String> {
+    let mut errors = build_state
+        .modules
+        .iter()
+        .filter(|(_, module)| module.is_mlmap())
+        .flat_map(|(namespace, module)| {
+            module.deps.iter().filter_map(move |member| {
+                match &build_state.get_module(member)?.source_type {
+                    SourceType::SourceFile(source_file)
+                        if source_file.implementation.parse_state == ParseState::ParseError
+                            || source_file
+                                .interface
+                                .as_ref()
+                                .is_some_and(|interface| interface.parse_state == ParseState::ParseError) =>
+                    {
+                        Some(format!(
+                            "\nThe namespace {} lists {}, which could not be parsed (see above), so it's missing from it.\n",
+                            namespace,
+                            helpers::file_path_to_module_name(
+                                &source_file.implementation.path,
+                                &packages::Namespace::NoNamespace
+                            )
+                        ))
+                    }
+                    _ => None,
+                }
+            })
+        })
+        .collect::<Vec<String>>();
+    errors.sort();
+    errors
+}
+
+fn print_dirty_reason(source_file: &SourceFile) {
+    let implementation = &source_file.implementation;
+    if let (true, false, Some(reason)) = (
+        implementation.dirty,
+        source_file.interface_only,
+        &implementation.dirty_reason,
+    ) {
+        println!(
+            "{}Regenerating {} ({})",
+            LINE_CLEAR,
+            helpers::get_basename(&implementation.path) + ".ast",
+            reason
+        );
+    }
+    if let Some(Interface {
+        dirty: true,
+        dirty_reason: Some(reason),
+        path,
+        ..
+    }) = &source_file.interface
+    {
+        println!(
+            "{}Regenerating {} ({})",
+            LINE_CLEAR,
+            helpers::get_basename(path) + ".iast",
+            reason
+        );
+    }
+}
+
+/// The `-ppx` arguments of a package, with the paths of the PPXes resolved
+pub fn get_ppx_args(
+    package: &packages::Package,
+    root_path: &str,
+    workspace_root: Option<String>,
+) -> Vec<String> {
+    get_ppx_arg_sources(package, root_path, workspace_root, None)
+        .into_iter()
+        .flat_map(|(_, args)| args)
+        .collect()
+}
+
+/// The arguments of `get_ppx_args` with where they come from: a ppx that's configured the same in
+/// the shared config (see `bsconfig::merge_shared_config`) comes from the shared config.
+pub fn get_ppx_arg_sources(
+    package: &packages::Package,
+    root_path: &str,
+    workspace_root: Option<String>,
+    shared_config: Option<&bsconfig::SharedConfig>,
+) -> Vec<(FlagSource, Vec<String>)> {
+    let node_modules_dir = if let Some(workspace_root) = workspace_root {
+        format!("{}/node_modules", &workspace_root)
+    } else {
+        helpers::get_node_modules_path(root_path, package.node_modules.as_deref())
+            .unwrap_or(format!("{}/node_modules", &root_path))
+    };
+    let flatten = |flag: &OneOrMore<String>| {
+        bsconfig::flatten_ppx_flags(&node_modules_dir, &Some(vec![flag.to_owned()]), &package.name)
+    };
+    let shared_args = shared_config
+        .and_then(|shared_config| shared_config.ppx_flags.as_ref())
+        .map(|flags| flags.iter().map(flatten).collect::<Vec<Vec<String>>>())
+        .unwrap_or_default();
+    filter_ppx_flags(&package.bsconfig.ppx_flags)
+        .unwrap_or_default()
+        .iter()
+        .map(flatten)
+        .map(|args| match shared_args.contains(&args) {
+            true => (FlagSource::SharedConfig, args),
+            false => (FlagSource::Package, args),
+        })
+        .collect()
+}
+
+/// The source file as we pass it to bsc, which runs in `working_directory`: an absolute path with
+/// `-absname`, or with `relative_paths`, relative to the working directory.
+pub fn get_source_file_args(file: &str, working_directory: &str, relative_paths: bool) -> Vec<String> {
+    // bsc runs in the canonical working directory, so the path is relative to that
+    let paths = relative_paths
+        .then(|| {
+            helpers::canonicalize_string_path(working_directory).zip(helpers::canonicalize_string_path(file))
+        })
+        .flatten();
+    match paths {
+        Some((working_directory, file)) => {
+            vec![helpers::get_relative_path(
+                Path::new(&working_directory),
+                Path::new(&file),
+            )]
+        }
+        None => vec!["-absname".to_string(), file.to_string()],
+    }
+}
+
+pub fn parser_args(
+    package: &packages::Package,
+    root_package: &packages::Package,
     filename: &str,
     root_path: &str,
     version: &str,
-    bsc_path: &str,
     workspace_root: Option<String>,
-) -> Result<(String, Option<String>), String> {
+    compile_options: &CompileOptions,
+) -> (String, Vec<String>) {
+    let (ast_path, args) = parser_arg_sources(
+        package,
+        root_package,
+        filename,
+        root_path,
+        version,
+        workspace_root,
+        compile_options,
+    );
+    (ast_path, args.into_iter().flat_map(|(_, args)| args).collect())
+}
+
+/// The arguments of `parser_args`, grouped by where they come from (see `explain-flags`).
+pub fn parser_arg_sources(
+    package: &packages::Package,
+    root_package: &packages::Package,
+    filename: &str,
+    root_path: &str,
+    version: &str,
+    workspace_root: Option<String>,
+    compile_options: &CompileOptions,
+) -> (String, Vec<(FlagSource, Vec<String>)>) {
     let file = &filename.to_string();
-    let build_path_abs = package.get_build_path();
     let path = PathBuf::from(filename);
-    let ast_extension = path_to_ast_extension(&path);
-
-    let ast_path = (helpers::get_basename(&file.to_string()).to_owned()) + ast_extension;
-
-    let ppx_flags = bsconfig::flatten_ppx_flags(
-        &if let Some(workspace_root) = workspace_root {
-            format!("{}/node_modules", &workspace_root)
-        } else {
-            format!("{}/node_modules", &root_path)
-        },
-        &filter_ppx_flags(&package.bsconfig.ppx_flags),
-        &package.name,
-    );
+    let ast_extension = path
+        .extension()
+        .and_then(|extension| SourceFileKind::from_extension(&extension.to_string_lossy()))
+        .unwrap_or(SourceFileKind::Implementation)
+        .get_ast_extension();
+    let ast_path = helpers::get_basename(&file.to_string()) + "." + ast_extension;
 
+    let shared_config = root_package.bsconfig.shared_config.as_ref();
+    let ppx_flags = get_ppx_arg_sources(package, root_path, workspace_root, shared_config);
     let jsx_args = root_package.get_jsx_args();
     let jsx_module_args = root_package.get_jsx_module_args();
     let jsx_mode_args = root_package.get_jsx_mode_args();
-    let uncurried_args = root_package.get_uncurried_args(version, &root_package);
-    let bsc_flags = bsconfig::flatten_flags(&package.bsconfig.bsc_flags);
+    let uncurried_args = root_package.get_uncurried_args(version, root_package);
+    let bsc_flags =
+        bsconfig::get_bsc_flag_sources(&package.bsconfig, shared_config, &compile_options.bsc_flags);
 
-    let res_to_ast_args = |file: &str| -> Vec<String> {
-        let file = "../../".to_string() + file;
-        vec![
-            vec!["-bs-v".to_string(), format!("{}", version)],
+    // the paths are absolute, because bsc doesn't necessarily run in the build folder of the
+    // package (see `ppx-working-directory`)
+    let file = Path::new(&package.path).join(file).to_string_lossy().to_string();
+    let ast_output_path = Path::new(&package.get_build_path())
+        .join(&ast_path)
+        .to_string_lossy()
+        .to_string();
+    (
+        ast_path.to_string(),
+        [
+            vec![(
+                FlagSource::Rewatch,
+                vec!["-bs-v".to_string(), format!("{}", version)],
+            )],
             ppx_flags,
-            jsx_args,
-            jsx_module_args,
-            jsx_mode_args,
-            uncurried_args,
+            vec![
+                (FlagSource::Root, jsx_args),
+                (FlagSource::Root, jsx_module_args),
+                (FlagSource::Root, jsx_mode_args),
+                (FlagSource::Root, uncurried_args),
+            ],
             bsc_flags,
             vec![
-                "-absname".to_string(),
-                "-bs-ast".to_string(),
-                "-o".to_string(),
-                ast_path.to_string(),
-                file,
+                (
+                    FlagSource::Rewatch,
+                    vec!["-bs-ast".to_string(), "-o".to_string(), ast_output_path],
+                ),
+                (
+                    FlagSource::Rewatch,
+                    get_source_file_args(
+                        &file,
+                        &package.get_ppx_working_directory(root_path),
+                        compile_options.relative_paths,
+                    ),
+                ),
             ],
         ]
         .concat()
-    };
+        .into_iter()
+        .filter(|(_, args)| !args.is_empty())
+        .collect(),
+    )
+}
+
+/// Reason files (.re / .rei) are parsed by bsc with refmt, which the compiler ships next to bsc
+/// (until ReScript 10, which dropped the Reason syntax). refmt turns JSX into the same `[@JSX]`
+/// attributes as the ReScript parser does, so the JSX flags apply to Reason files as well.
+pub fn get_refmt_args(filename: &str, bsc_path: &str) -> Vec<String> {
+    let is_reason = matches!(
+        Path::new(filename)
+            .extension()
+            .and_then(|extension| extension.to_str()),
+        Some("re" | "rei")
+    );
+    let refmt_path = Path::new(bsc_path).with_file_name("refmt.exe");
+    match is_reason && refmt_path.exists() {
+        true => vec!["-bs-refmt".to_string(), refmt_path.to_string_lossy().to_string()],
+        false => vec![],
+    }
+}
+
+/// Generates the AST of a single source file of the package with bsc, and returns the path of the
+/// AST (relative to the build folder of the package) with the warnings of the parser. This doesn't
+/// need a build to run first, so it can be used on its own by tools that only need the AST of a file.
+///
+/// There is one bsc process per source file, the files are parsed in parallel instead (see
+/// `generate_asts`). They can't be batched into one process: bsc fails with "can not handle
+/// multiple files" when it gets more than one source file (see `anonymous` in
+/// `jscomp/main/js_main.ml` of the compiler), except with `-bs-syntax-only`, which doesn't write an
+/// AST, and `-o` only names a single output.
+pub fn generate_ast(
+    package: packages::Package,
+    root_package: packages::Package,
+    filename: &str,
+    root_path: &str,
+    workspace_root: Option<String>,
+    context: &CompileContext,
+) -> Result<(String, Option<String>), String> {
+    let working_directory = package.get_ppx_working_directory(root_path);
+    let (ast_path, parser_args) = parser_args(
+        &package,
+        &root_package,
+        filename,
+        root_path,
+        context.version,
+        workspace_root,
+        context.compile_options,
+    );
+    let working_directory = helpers::canonicalize_string_path(&working_directory).ok_or(format!(
+        "Could not find the working directory {} for file {} in package {}",
+        working_directory, filename, package.name
+    ))?;
+    let parser_args = [get_refmt_args(filename, context.bsc_path), parser_args].concat();
+    let response_file_path = Path::new(&package.get_build_path())
+        .join(format!("{}.rsp", ast_path))
+        .to_string_lossy()
+        .to_string();
+    let parser_args = helpers::get_command_line_args(
+        context.bsc_path,
+        parser_args,
+        &response_file_path,
+        helpers::get_max_command_line_length(),
+    )
+    .map_err(|e| {
+        format!(
+            "Could not write the arguments of bsc to {}: {}\n",
+            response_file_path, e
+        )
+    })?;
 
     /* Create .ast */
-    if let Some(res_to_ast) = Some(file).map(|file| {
-        Command::new(bsc_path)
-            .current_dir(helpers::canonicalize_string_path(&build_path_abs).unwrap())
-            .args(res_to_ast_args(file))
-            .output()
-            .expect("Error converting .res to .ast")
-    }) {
-        let stderr = std::str::from_utf8(&res_to_ast.stderr).expect("Expect StdErr to be non-null");
-        if helpers::contains_ascii_characters(stderr) {
-            if res_to_ast.status.success() {
-                Ok((ast_path, Some(stderr.to_string())))
-            } else {
-                println!("err: {}", stderr.to_string());
-                Err(stderr.to_string())
-            }
-        } else {
-            Ok((ast_path, None))
-        }
-    } else {
-        println!("Parsing file {}...", file);
+    let res_to_ast = trace::span(context.trace, "ast", filename, || {
+        helpers::run_command(
+            Command::new(context.bsc_path)
+                .current_dir(working_directory)
+                .args(parser_args),
+        )
+    });
+    let _ = std::fs::remove_file(&response_file_path);
+    let res_to_ast =
+        res_to_ast.map_err(|e| format!("Could not run bsc to parse file {}: {}", filename, e))?;
+    let stderr = helpers::decode_output(&res_to_ast.stderr);
+    if !res_to_ast.status.success() {
+        return match helpers::contains_ascii_characters(&stderr) {
+            true => Err(stderr.to_string()),
+            false => Err(format!(
+                "Could not parse file {}: bsc failed without output ({})\n",
+                filename, res_to_ast.status
+            )),
+        };
+    }
+    // the dependencies are read from the AST, so a missing or truncated AST would leave the module
+    // without dependencies
+    let ast_file = Path::new(&package.get_build_path()).join(&ast_path);
+    if let Err(e) = ast::get_dep_modules(&ast_file.to_string_lossy()) {
         return Err(format!(
-            "Could not find canonicalize_string_path for file {} in package {}",
-            file, package.name
+            "Could not parse file {}: bsc did not write a valid AST to {} ({})\n",
+            filename,
+            ast_file.to_string_lossy(),
+            e
         ));
     }
-}
-
-fn path_to_ast_extension(path: &Path) -> &str {
-    let extension = path.extension().unwrap().to_str().unwrap();
-    return if helpers::is_interface_ast_file(extension) {
-        ".iast"
+    if helpers::contains_ascii_characters(&stderr) {
+        Ok((ast_path, Some(stderr.to_string())))
     } else {
-        ".ast"
-    };
+        Ok((ast_path, None))
+    }
 }
 
 fn filter_ppx_flags(ppx_flags: &Option<Vec<OneOrMore<String>>>) -> Option<Vec<OneOrMore<String>>> {
@@ -302,3 +597,177 @@ fn filter_ppx_flags(ppx_flags: &Option<Vec<OneOrMore<String>>>) -> Option<Vec<On
         None => None,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{generate_ast, generate_asts, parser_args};
+    use crate::build::build_types::{CompileContext, CompileOptions};
+    use crate::build::logs;
+    use crate::test_fixture::TestProject;
+
+    #[test]
+    fn should_write_the_ast_of_an_interface_to_an_iast_file() {
+        let project = TestProject::new("iast");
+        std::fs::create_dir_all(project.join("src")).unwrap();
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        let project_root = project.path();
+        let package = project.make().remove("root").unwrap();
+
+        let (ast_path, _) = parser_args(
+            &package,
+            &package,
+            "src/A.res",
+            &project_root,
+            "11.0.0",
+            None,
+            &CompileOptions::default(),
+        );
+        let (iast_path, _) = parser_args(
+            &package,
+            &package,
+            "src/A.resi",
+            &project_root,
+            "11.0.0",
+            None,
+            &CompileOptions::default(),
+        );
+
+        assert_eq!(ast_path, "A.ast");
+        assert_eq!(iast_path, "A.iast");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_parse_reason_files_with_refmt_and_jsx() {
+        let project = TestProject::new("reason");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "jsx": { "version": 3 } }"#,
+        );
+        project.write("src/A.re", "let a = <div />;");
+        project.write("src/B.res", "let b = <div />");
+        project.write("bin/refmt.exe", "");
+        // a bsc that logs how it's called, and writes an AST without dependencies
+        let log_path = project.join("bsc.log");
+        let bsc_path = project.write_script(
+            "bin/bsc.exe",
+            &format!(
+                "echo \"$@\" >> {}\nfor arg in \"$@\"; do\n  [ \"$prev\" = \"-o\" ] && out=\"$arg\"\n  prev=\"$arg\"\ndone\ncase \"$*\" in *-bs-ast*) printf '\\n%s\\n' \"$arg\" > \"$out\";; esac\n",
+                log_path.to_string_lossy()
+            ),
+        );
+
+        let mut build_state = project.build_state("root");
+        logs::initialize(&build_state.packages);
+        let result = generate_asts(
+            &mut build_state,
+            || (),
+            None,
+            &CompileContext::new(&bsc_path.to_string_lossy(), "9.1.4", &CompileOptions::default()),
+        );
+        let log = std::fs::read_to_string(&log_path).unwrap();
+
+        assert!(result.is_ok());
+        let call = |file: &str| log.lines().find(|call| call.ends_with(file)).unwrap().to_string();
+        let refmt_args = format!("-bs-refmt {}", project.join("bin/refmt.exe").to_string_lossy());
+        assert!(call("A.re").contains(&refmt_args));
+        assert!(call("A.re").contains("-bs-jsx 3"));
+        assert!(!call("B.res").contains("-bs-refmt"));
+        assert!(call("B.res").contains("-bs-jsx 3"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_report_the_namespace_members_that_could_not_be_parsed() {
+        let project = TestProject::new("namespace-parse-error");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "root", "sources": "src", "namespace": "MyNamespace" }"#,
+        );
+        project.write("src/A.res", "let a = (");
+        project.write("src/B.res", "let b = 1");
+        // a bsc that fails to parse A.res, and parses and compiles everything else without output
+        let bsc_path = project.write_script(
+            "bsc.exe",
+            "case \"$*\" in *A.res*) echo 'Syntax error in A.res' >&2; exit 1;; esac\nfor arg in \"$@\"; do\n  [ \"$prev\" = \"-o\" ] && out=\"$arg\"\n  prev=\"$arg\"\ndone\ncase \"$*\" in *-bs-ast*) printf '\\n%s\\n' \"$arg\" > \"$out\";; esac\n",
+        );
+
+        let mut build_state = project.build_state("root");
+        logs::initialize(&build_state.packages);
+        let result = generate_asts(
+            &mut build_state,
+            || (),
+            None,
+            &CompileContext::new(&bsc_path.to_string_lossy(), "11.0.0", &CompileOptions::default()),
+        );
+
+        let stderr = result.unwrap_err();
+        assert!(stderr.contains("Syntax error in A.res"));
+        assert!(stderr.contains("The namespace MyNamespace lists A, which could not be parsed (see above)"));
+        assert!(!stderr.contains("lists B"));
+    }
+
+    #[test]
+    fn should_return_an_error_when_bsc_can_not_be_run() {
+        let project = TestProject::new("generate-ast");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "let a = 1");
+
+        let project_root = project.path();
+        let packages = project.make();
+        let package = packages.get("root").unwrap().to_owned();
+        let result = generate_ast(
+            package.to_owned(),
+            package,
+            "src/A.res",
+            &project_root,
+            None,
+            &CompileContext::new(
+                &project.join("missing-bsc.exe").to_string_lossy(),
+                "11.0.0",
+                &CompileOptions::default(),
+            ),
+        );
+
+        assert!(result
+            .unwrap_err()
+            .starts_with("Could not run bsc to parse file src/A.res"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_return_an_error_when_bsc_does_not_write_the_ast() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let project = TestProject::new("missing-ast");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "let a = 1");
+        let project_root = project.path();
+        let packages = project.make();
+        let package = packages.get("root").unwrap().to_owned();
+        std::fs::create_dir_all(package.get_build_path()).unwrap();
+        let bsc_path = project.join("bsc.exe");
+        let generate_ast_with = |script: &str| {
+            std::fs::write(&bsc_path, script).unwrap();
+            std::fs::set_permissions(&bsc_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            generate_ast(
+                package.to_owned(),
+                package.to_owned(),
+                "src/A.res",
+                &project_root,
+                None,
+                &CompileContext::new(&bsc_path.to_string_lossy(), "11.0.0", &CompileOptions::default()),
+            )
+        };
+
+        let without_ast = generate_ast_with("#!/bin/sh\n");
+        let failed_without_output = generate_ast_with("#!/bin/sh\nexit 2\n");
+
+        assert!(without_ast
+            .unwrap_err()
+            .starts_with("Could not parse file src/A.res: bsc did not write a valid AST"));
+        assert!(failed_without_output
+            .unwrap_err()
+            .starts_with("Could not parse file src/A.res: bsc failed without output"));
+    }
+}
@@ -1,9 +1,10 @@
 use super::build_types::*;
 use super::packages;
 use crate::helpers;
+use crate::helpers::LexicalAbsolute;
 use ahash::{AHashMap, AHashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
 pub fn read(build_state: &mut BuildState) -> CompileAssetsState {
@@ -52,7 +53,11 @@ pub fn read(build_state: &mut BuildState) -> CompileAssetsState {
 
     // scan all ast files in all packages
     for package in build_state.packages.values() {
-        let read_dir = fs::read_dir(std::path::Path::new(&package.get_build_path())).unwrap();
+        // a package that wasn't built yet has no build folder with `rewatch check`
+        let read_dir = match fs::read_dir(std::path::Path::new(&package.get_build_path())) {
+            Ok(read_dir) => read_dir,
+            Err(_) => continue,
+        };
 
         for entry in read_dir {
             match entry {
@@ -68,11 +73,10 @@ pub fn read(build_state: &mut BuildState) -> CompileAssetsState {
                                 );
 
                                 let ast_file_path = path.to_str().unwrap().to_owned();
-                                let res_file_path = get_res_path_from_ast(&ast_file_path);
-                                let root_package = build_state
-                                    .packages
-                                    .get(&build_state.root_config_name)
-                                    .expect("Could not find root package");
+                                let res_file_path = get_res_path_from_ast(
+                                    &ast_file_path,
+                                    &package.get_ppx_working_directory(&build_state.project_root),
+                                );
                                 match res_file_path {
                                     Some(res_file_path) => {
                                         let _ = ast_modules.insert(
@@ -84,7 +88,6 @@ pub fn read(build_state: &mut BuildState) -> CompileAssetsState {
                                                 last_modified: entry.metadata().unwrap().modified().unwrap(),
                                                 ast_file_path: ast_file_path,
                                                 is_root: package.is_root,
-                                                suffix: root_package.bsconfig.suffix.to_owned(),
                                             },
                                         );
                                         let _ = ast_rescript_file_locations.insert(res_file_path);
@@ -133,15 +136,27 @@ pub fn read(build_state: &mut BuildState) -> CompileAssetsState {
     }
 }
 
-fn get_res_path_from_ast(ast_file: &str) -> Option<String> {
+/// The path of the source file of an AST. With `--relative-paths`, it's relative to the folder bsc
+/// parsed it in, so we resolve it against that folder.
+fn get_res_path_from_ast(ast_file: &str, working_directory: &str) -> Option<String> {
     if let Ok(lines) = helpers::read_lines(ast_file.to_string()) {
         // we skip the first line with is some null characters
         // the following lines in the AST are the dependency modules
-        // we stop when we hit a line that starts with a "/", this is the path of the file.
+        // we stop when we hit the path of the file (see `helpers::is_source_path`).
         // this is the point where the dependencies end and the actual AST starts
         for line in lines.skip(1) {
             match line {
                 Ok(line) if line.trim_start().starts_with('/') => return Some(line),
+                Ok(line) if helpers::is_source_path(line.trim()) => {
+                    return helpers::canonicalize_string_path(working_directory)
+                        .and_then(|working_directory| {
+                            Path::new(&working_directory)
+                                .join(line.trim())
+                                .to_lexical_absolute()
+                                .ok()
+                        })
+                        .map(|path| path.to_string_lossy().to_string())
+                }
                 _ => (),
             }
         }
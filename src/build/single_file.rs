@@ -0,0 +1,180 @@
+use super::build_types::*;
+use super::compile;
+use super::deps;
+use super::diagnostics::{self, Diagnostic, Severity};
+use super::explain;
+use super::packages;
+use super::parse;
+use crate::helpers;
+use std::path::Path;
+
+// Editors that check the file that is being edited have the path of the file, not the name of its
+// module. Instead of building the whole project, we parse and compile only the module of the file
+// (its interface and implementation), against the compiler assets of its dependencies. So this
+// assumes the dependencies are built already, ie. by a running watcher.
+
+/// Compiles the module of a single source file (by its path), and returns the errors and warnings
+/// of bsc. The dependencies of the module need to be built already. Returns an error when the
+/// module can't be compiled at all (ie. the file isn't in the sources of a package).
+pub fn compile_single_file(
+    project_root: &str,
+    file_path: &Path,
+    package_options: &packages::PackageOptions,
+) -> Result<Vec<Diagnostic>, String> {
+    let workspace_root = helpers::get_workspace_root(project_root);
+    let build_state = explain::read_build_state(project_root, workspace_root.to_owned(), package_options);
+
+    let file = helpers::get_abs_path(&file_path.to_string_lossy());
+    let module_name = explain::file_path_to_module(&build_state, &file)
+        .or_else(|| {
+            helpers::canonicalize_string_path(&file)
+                .and_then(|file| explain::file_path_to_module(&build_state, &file))
+        })
+        .ok_or(format!("{} is not in the sources of any package", file))?;
+    let module = build_state.get_module(module_name).expect("Module not found");
+    let source_file = match &module.source_type {
+        SourceType::SourceFile(source_file) => source_file,
+        SourceType::MlMap(_) => return Err(format!("{} is not a source file", file)),
+    };
+    let package = build_state
+        .get_package(&module.package_name)
+        .expect("Package not found");
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Could not find root package");
+
+    let bsc_path = helpers::try_get_bsc(
+        project_root,
+        workspace_root.to_owned(),
+        package_options.node_modules.as_deref(),
+    )?;
+    let rescript_version = super::try_get_version(&bsc_path, &build_state.compile_options)?;
+
+    let context = CompileContext::new(&bsc_path, &rescript_version, &build_state.compile_options);
+    let compile = |is_interface: bool| -> compile::CompileResult {
+        let (path, ast_path) = match (is_interface, &source_file.interface) {
+            (true, Some(interface)) => (&interface.path, package.get_iast_path(&interface.path)),
+            _ => (
+                &source_file.implementation.path,
+                package.get_ast_path(&source_file.implementation.path),
+            ),
+        };
+        let (_, parse_warnings) = parse::generate_ast(
+            package.to_owned(),
+            root_package.to_owned(),
+            path,
+            &build_state.project_root,
+            workspace_root.to_owned(),
+            &context,
+        )?;
+
+        // the compiler assets of the dependencies need to be there, otherwise bsc reports them as
+        // unbound modules
+        let mut deps = deps::get_module_deps(&build_state, module_name, &[ast_path.to_owned()])
+            .into_iter()
+            .collect::<Vec<String>>();
+        deps.sort();
+        for dep in deps {
+            let dep_module = build_state.get_module(&dep).expect("Module not found");
+            let dep_package = build_state
+                .get_package(&dep_module.package_name)
+                .expect("Package not found");
+            let cmi_path = match &dep_module.source_type {
+                SourceType::SourceFile(dep_source_file) => helpers::get_compiler_asset(
+                    dep_package,
+                    &dep_package.namespace,
+                    &dep_source_file.implementation.path,
+                    "cmi",
+                ),
+                SourceType::MlMap(_) => dep_package.get_mlmap_compile_path(),
+            };
+            if !Path::new(&cmi_path).exists() {
+                return Err(format!(
+                    "{} depends on {}, which isn't built yet. Build the project first.",
+                    module_name, dep
+                ));
+            }
+        }
+
+        let compile_warnings = compile::compile_file(
+            package,
+            root_package,
+            &ast_path,
+            module,
+            is_interface,
+            &build_state.packages,
+            &context,
+        )?;
+        Ok(Some(
+            [parse_warnings, compile_warnings]
+                .into_iter()
+                .flatten()
+                .collect::<String>(),
+        ))
+    };
+
+    let (interface_result, result) = compile::compile_source_file(source_file, compile);
+    let mut output = String::new();
+    for result in interface_result.into_iter().chain([result]) {
+        match result {
+            Ok(warnings) => output.push_str(&warnings.unwrap_or_default()),
+            Err(error) => {
+                let (errors, _) = diagnostics::parse_diagnostics(&error);
+                // not an error of bsc about the source (ie. a missing dependency)
+                if !errors.iter().any(|error| error.severity == Severity::Error) {
+                    return Err(error);
+                }
+                output.push_str(&error);
+                // the implementation isn't compiled when the interface has errors
+                break;
+            }
+        }
+    }
+    let (diagnostics, _) = diagnostics::parse_diagnostics(&output);
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod test {
+    use super::compile_single_file;
+    use crate::build::diagnostics::Severity;
+    use crate::build::packages::PackageOptions;
+    use crate::test_fixture::TestProject;
+
+    #[test]
+    #[cfg(unix)]
+    fn should_only_compile_files_in_the_sources() {
+        let project = TestProject::new("single-file");
+        project.write("bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("src/A.res", "let a = 1");
+        project.write("src/B.res", "let b = c");
+        project.write("scripts/Script.res", "");
+        // a bsc that writes an AST without dependencies, and fails on B
+        project.write("node_modules/rescript/package.json", r#"{ "version": "11.0.0" }"#);
+        for platform in ["darwin", "darwinarm64", "linux"] {
+            project.write_script(
+                format!("node_modules/rescript/{}/bsc.exe", platform),
+                "for arg in \"$@\"; do\n  [ \"$prev\" = \"-o\" ] && out=\"$arg\"\n  prev=\"$arg\"\ndone\ncase \"$*\" in\n  *-bs-ast*) printf '\\n%s\\n' \"$arg\" > \"$out\";;\n  *B.ast*) printf '\\n  We'\"'\"'ve found a bug for you!\\n  /src/B.res:1:9\\n\\n  The value c can'\"'\"'t be found\\n' >&2; exit 1;;\nesac\n",
+            );
+        }
+
+        // the build folders of a previous build
+        project.make();
+
+        let project_root = project.path();
+        let compile =
+            |path: &str| compile_single_file(&project_root, &project.join(path), &PackageOptions::default());
+        let outside_sources = compile("scripts/Script.res");
+        let compiled = compile("src/A.res").unwrap();
+        let errors = compile("src/B.res").unwrap();
+
+        assert!(outside_sources
+            .unwrap_err()
+            .contains("is not in the sources of any package"));
+        assert_eq!(compiled, vec![]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].severity, Severity::Error);
+        assert_eq!(errors[0].file, "/src/B.res");
+        assert_eq!(errors[0].message, "The value c can't be found");
+    }
+}
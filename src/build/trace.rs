@@ -0,0 +1,135 @@
+use serde::Serialize;
+use std::fs;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// With `--trace`, every invocation of bsc is recorded as a span, and written at the end of the build
+// in the trace event format of Chrome, which can be opened in `chrome://tracing` or
+// https://ui.perfetto.dev. Every thread of rayon gets its own row, so it shows how well the build uses
+// the threads, and which modules hold up the others.
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Span {
+    pub name: String,
+    pub cat: String,
+    /// Always "X", a complete event with a duration
+    pub ph: &'static str,
+    /// The start of the span, in microseconds since the start of the build
+    pub ts: u64,
+    /// In microseconds
+    pub dur: u64,
+    pub pid: u32,
+    /// 0 for the main thread, and the index + 1 for the threads of rayon
+    pub tid: usize,
+}
+
+/// The spans of a traced build (`--trace`), recorded with `span` and written with `write`.
+#[derive(Debug)]
+pub struct Trace {
+    start: Instant,
+    spans: Mutex<Vec<Span>>,
+}
+
+impl Trace {
+    /// Starts recording, the spans are relative to now
+    pub fn start() -> Self {
+        Self {
+            start: Instant::now(),
+            spans: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn get_spans(&self) -> Vec<Span> {
+        self.spans.lock().unwrap().to_owned()
+    }
+
+    /// Writes the spans recorded so far to `trace_file`.
+    pub fn write(&self, trace_file: &str) -> Result<(), String> {
+        let trace = get_trace(&self.spans.lock().unwrap());
+        fs::write(trace_file, trace.to_string())
+            .map_err(|e| format!("Could not write the trace to {}: {}", trace_file, e))
+    }
+}
+
+fn get_thread_id() -> usize {
+    rayon::current_thread_index().map(|index| index + 1).unwrap_or(0)
+}
+
+/// Runs `f`, and records how long it took as a span of `category`, when the build is traced.
+pub fn span<T>(trace: Option<&Trace>, category: &str, name: &str, f: impl FnOnce() -> T) -> T {
+    let trace = match trace {
+        Some(trace) => trace,
+        None => return f(),
+    };
+    let begin = trace.start.elapsed();
+    let result = f();
+    let end = trace.start.elapsed();
+    trace.spans.lock().unwrap().push(Span {
+        name: name.to_string(),
+        cat: category.to_string(),
+        ph: "X",
+        ts: begin.as_micros() as u64,
+        dur: (end - begin).as_micros() as u64,
+        pid: 1,
+        tid: get_thread_id(),
+    });
+    result
+}
+
+/// The trace of `spans`, with the names of the threads they ran on.
+pub fn get_trace(spans: &[Span]) -> serde_json::Value {
+    let mut thread_ids = spans.iter().map(|span| span.tid).collect::<Vec<usize>>();
+    thread_ids.sort();
+    thread_ids.dedup();
+    let thread_names = thread_ids.into_iter().map(|tid| {
+        let name = match tid {
+            0 => "main".to_string(),
+            tid => format!("worker {}", tid),
+        };
+        serde_json::json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": 1,
+            "tid": tid,
+            "args": { "name": name }
+        })
+    });
+    let events = thread_names
+        .chain(spans.iter().map(|span| serde_json::to_value(span).unwrap()))
+        .collect::<Vec<serde_json::Value>>();
+    serde_json::json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_trace, span, Trace};
+    use rayon::prelude::*;
+
+    #[test]
+    fn should_record_a_span_per_task_on_the_thread_it_ran_on() {
+        let trace = Trace::start();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        pool.install(|| {
+            ["A", "B", "C"].par_iter().for_each(|name| {
+                span(Some(&trace), "trace-test", name, || {
+                    std::thread::sleep(std::time::Duration::from_millis(5))
+                })
+            })
+        });
+        span(Some(&trace), "trace-test", "main", || ());
+        span(None, "trace-test", "untraced", || ());
+        let spans = trace.get_spans();
+
+        assert_eq!(spans.len(), 4);
+        assert!(spans[..3]
+            .iter()
+            .all(|span| (1..=2).contains(&span.tid) && span.dur >= 5_000));
+        assert_eq!(spans[3].tid, 0);
+        let trace = get_trace(&spans);
+        let events = trace["traceEvents"].as_array().unwrap();
+        // the names of the threads come first
+        assert_eq!(events[0]["ph"], "M");
+        assert_eq!(events[0]["args"]["name"], "main");
+        assert_eq!(events.iter().filter(|event| event["ph"] == "X").count(), 4);
+    }
+}
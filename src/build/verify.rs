@@ -0,0 +1,119 @@
+use super::build_types::*;
+use crate::bsconfig;
+use crate::helpers;
+use std::path::Path;
+
+/// The outputs that a module should have after a build: the cmi of every module, and the cmj and
+/// JavaScript files (for all the package-specs of the root package) of the modules with an
+/// implementation. With `check_only`, bsc doesn't write any JavaScript.
+fn get_expected_artifacts(build_state: &BuildState, module: &Module, check_only: bool) -> Vec<String> {
+    let package = match build_state.get_package(&module.package_name) {
+        Some(package) => package,
+        None => return vec![],
+    };
+    match &module.source_type {
+        SourceType::MlMap(_) => vec![package.get_mlmap_compile_path()],
+        SourceType::SourceFile(source_file) => {
+            let path = &source_file.implementation.path;
+            let mut artifacts = vec![helpers::get_compiler_asset(
+                package,
+                &package.namespace,
+                path,
+                "cmi",
+            )];
+            if !source_file.interface_only {
+                artifacts.push(helpers::get_compiler_asset(
+                    package,
+                    &package.namespace,
+                    path,
+                    "cmj",
+                ));
+                if !check_only {
+                    let root_package = build_state
+                        .get_package(&build_state.root_config_name)
+                        .expect("Could not find root package");
+                    artifacts.extend(bsconfig::get_output_paths(
+                        &root_package.bsconfig,
+                        &package.path,
+                        path,
+                    ));
+                }
+            }
+            artifacts
+        }
+    }
+}
+
+/// Returns the missing outputs (the module and the path) of the modules that compiled without
+/// errors, sorted by module. The modules with errors aren't checked, as bsc doesn't write all their
+/// outputs.
+pub fn get_missing_artifacts(build_state: &BuildState, check_only: bool) -> Vec<(String, String)> {
+    let mut missing = build_state
+        .modules
+        .iter()
+        .filter(|(_, module)| match &module.source_type {
+            SourceType::MlMap(_) => true,
+            SourceType::SourceFile(source_file) => matches!(
+                source_file.implementation.compile_state,
+                CompileState::Success | CompileState::Warning
+            ),
+        })
+        .flat_map(|(module_name, module)| {
+            get_expected_artifacts(build_state, module, check_only)
+                .into_iter()
+                .filter(|path| !Path::new(path).exists())
+                .map(|path| (module_name.to_owned(), path))
+                .collect::<Vec<(String, String)>>()
+        })
+        .collect::<Vec<(String, String)>>();
+    missing.sort();
+    missing
+}
+
+#[cfg(test)]
+mod test {
+    use super::get_missing_artifacts;
+    use crate::bsconfig;
+    use crate::build::build_types::*;
+    use crate::helpers;
+    use crate::test_fixture::TestProject;
+
+    #[test]
+    fn should_report_the_outputs_that_bsc_did_not_write() {
+        let project = TestProject::new("verify-artifacts");
+        project.write(
+            "bsconfig.json",
+            r#"{ "name": "verify-artifacts-test", "sources": "src" }"#,
+        );
+        project.write("src/A.res", "");
+        project.write("src/B.res", "");
+        let mut build_state = project.build_state("verify-artifacts-test");
+        let package = build_state
+            .get_package("verify-artifacts-test")
+            .unwrap()
+            .to_owned();
+        std::fs::create_dir_all(package.get_build_path()).unwrap();
+        for path in ["src/A.res", "src/B.res"] {
+            for extension in ["cmi", "cmj"] {
+                std::fs::write(
+                    helpers::get_compiler_asset(&package, &package.namespace, path, extension),
+                    "",
+                )
+                .unwrap();
+            }
+        }
+        // bsc "succeeded" for B but didn't write its JavaScript
+        let a_output = bsconfig::get_output_paths(&package.bsconfig, &package.path, "src/A.res");
+        let b_output = bsconfig::get_output_paths(&package.bsconfig, &package.path, "src/B.res");
+        std::fs::write(&a_output[0], "").unwrap();
+        for module in build_state.modules.values_mut() {
+            if let SourceType::SourceFile(source_file) = &mut module.source_type {
+                source_file.implementation.compile_state = CompileState::Success;
+            }
+        }
+
+        let missing = get_missing_artifacts(&build_state, false);
+
+        assert_eq!(missing, vec![("B".to_string(), b_output[0].to_owned())]);
+    }
+}
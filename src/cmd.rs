@@ -4,7 +4,9 @@ use std::io::{BufRead, BufReader};
 use std::process::{Command, Stdio};
 use std::time::Instant;
 
-pub fn run(command_string: String) {
+/// Runs the command (ie. the `--after-build` command), printing its output. Returns whether the
+/// command succeeded.
+pub fn run(command_string: String) -> bool {
     let start_subcommand = Instant::now();
 
     print!(
@@ -17,12 +19,24 @@ pub fn run(command_string: String) {
     let parsed_command = command_string.split_whitespace().collect::<Vec<&str>>();
     let (command, params) = parsed_command.split_at(1);
 
-    let mut cmd = Command::new(command[0])
+    let mut cmd = match Command::new(command[0])
         .args(params)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
-        .expect("failed to execute process");
+    {
+        Ok(cmd) => cmd,
+        Err(e) => {
+            println!(
+                "{} {} Could not run subcommand {}: {}",
+                style("[...]").bold().dim(),
+                CROSS,
+                command_string,
+                e
+            );
+            return false;
+        }
+    };
 
     {
         let stdout = cmd.stdout.as_mut().unwrap();
@@ -41,8 +55,11 @@ pub fn run(command_string: String) {
         for line in std_err {
             println!("{}", line.unwrap());
         }
+    }
 
-        let subcommand_duration = start_subcommand.elapsed();
+    let status = cmd.wait().unwrap();
+    let subcommand_duration = start_subcommand.elapsed();
+    if status.success() {
         println!(
             "{}\n{} {} Ran subcommand in {:.2}s",
             style("────────"),
@@ -50,7 +67,15 @@ pub fn run(command_string: String) {
             CHECKMARK,
             subcommand_duration.as_secs_f64(),
         );
+    } else {
+        println!(
+            "{}\n{} {} Subcommand failed ({}) in {:.2}s",
+            style("────────"),
+            style("[...]").bold().dim(),
+            CROSS,
+            status,
+            subcommand_duration.as_secs_f64(),
+        );
     }
-
-    cmd.wait().unwrap();
+    status.success()
 }
@@ -1,9 +1,16 @@
+use crate::bsconfig;
+use crate::build::build_types::SourceFileKind;
 use crate::build::packages;
+use log::debug;
+use std::borrow::Cow;
 use std::ffi::OsString;
 use std::fs;
 use std::io::{self, BufRead};
 use std::path::{Component, Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub mod emojis {
     use console::Emoji;
@@ -19,6 +26,280 @@ pub mod emojis {
     pub static LINE_CLEAR: &str = "\x1b[2K";
 }
 
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Prints the status of the build to stderr instead of stdout, so stdout only contains
+/// machine-readable output (`--print-outputs`).
+pub fn set_status_to_stderr(status_to_stderr: bool) {
+    STATUS_TO_STDERR.store(status_to_stderr, Ordering::Relaxed);
+}
+
+pub fn is_status_to_stderr() -> bool {
+    STATUS_TO_STDERR.load(Ordering::Relaxed)
+}
+
+static SUMMARY_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Only prints the summary of the build, or the errors when it fails (`--summary-only`). The
+/// progress of the build and the warnings are left out.
+pub fn set_summary_only(summary_only: bool) {
+    SUMMARY_ONLY.store(summary_only, Ordering::Relaxed);
+}
+
+pub fn is_summary_only() -> bool {
+    SUMMARY_ONLY.load(Ordering::Relaxed)
+}
+
+/// Like `print!`, for the errors and the summary of the build (see `set_status_to_stderr`).
+#[macro_export]
+macro_rules! report {
+    ($($arg:tt)*) => {
+        if $crate::helpers::is_status_to_stderr() {
+            eprint!($($arg)*)
+        } else {
+            print!($($arg)*)
+        }
+    };
+}
+
+/// Like `println!`, for the errors and the summary of the build (see `set_status_to_stderr`).
+#[macro_export]
+macro_rules! reportln {
+    ($($arg:tt)*) => {
+        if $crate::helpers::is_status_to_stderr() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// Like `print!`, for the progress of the build. Left out with `--summary-only`.
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::helpers::is_summary_only() {
+            $crate::report!($($arg)*)
+        }
+    };
+}
+
+/// Like `println!`, for the progress of the build. Left out with `--summary-only`.
+#[macro_export]
+macro_rules! statusln {
+    ($($arg:tt)*) => {
+        if !$crate::helpers::is_summary_only() {
+            $crate::reportln!($($arg)*)
+        }
+    };
+}
+
+static LOG_COMMANDS: AtomicBool = AtomicBool::new(false);
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Enables printing why source files are parsed again (`--verbose`).
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Enables printing every command that is run with `run_command` (`--verbose-commands`).
+pub fn set_log_commands(log_commands: bool) {
+    LOG_COMMANDS.store(log_commands, Ordering::Relaxed);
+}
+
+/// Caps how many processes run at the same time, independently of the number of threads that
+/// start them. A limit of 0 means no limit.
+pub struct SpawnLimit {
+    max: AtomicUsize,
+    running: Mutex<usize>,
+    finished: Condvar,
+}
+
+/// Holds a place in the `SpawnLimit` until it's dropped.
+pub struct SpawnPermit<'a>(&'a SpawnLimit);
+
+impl SpawnLimit {
+    pub const fn new() -> SpawnLimit {
+        SpawnLimit {
+            max: AtomicUsize::new(0),
+            running: Mutex::new(0),
+            finished: Condvar::new(),
+        }
+    }
+
+    pub fn set_max(&self, max: usize) {
+        self.max.store(max, Ordering::Relaxed);
+        self.finished.notify_all();
+    }
+
+    /// Waits until fewer than the maximum number of processes run.
+    pub fn acquire(&self) -> SpawnPermit<'_> {
+        let mut running = self.running.lock().unwrap();
+        loop {
+            let max = self.max.load(Ordering::Relaxed);
+            if max == 0 || *running < max {
+                break;
+            }
+            running = self.finished.wait(running).unwrap();
+        }
+        *running += 1;
+        SpawnPermit(self)
+    }
+}
+
+impl Default for SpawnLimit {
+    fn default() -> Self {
+        SpawnLimit::new()
+    }
+}
+
+impl Drop for SpawnPermit<'_> {
+    fn drop(&mut self) {
+        *self.0.running.lock().unwrap() -= 1;
+        self.0.finished.notify_one();
+    }
+}
+
+static SPAWN_LIMIT: SpawnLimit = SpawnLimit::new();
+
+/// Limits the number of bsc processes that run at the same time (`--max-spawns`), which can run out
+/// of memory or file descriptors before the threads run out of CPU.
+pub fn set_max_spawns(max_spawns: Option<usize>) {
+    SPAWN_LIMIT.set_max(max_spawns.unwrap_or(0));
+}
+
+static PROFILE_MEMORY: AtomicBool = AtomicBool::new(false);
+static PEAK_MEMORY: Mutex<Vec<(String, u64)>> = Mutex::new(Vec::new());
+
+/// Measures the peak memory use of every command that is run with `run_command`
+/// (`--profile-memory`), see `get_memory_report`. Only supported on Unix.
+pub fn set_profile_memory(profile_memory: bool) {
+    PROFILE_MEMORY.store(profile_memory, Ordering::Relaxed);
+    PEAK_MEMORY.lock().unwrap().clear();
+}
+
+/// The peak memory use (in bytes) of the commands that were run, by their last argument (the file
+/// that bsc parses or compiles).
+pub fn get_peak_memory() -> Vec<(String, u64)> {
+    PEAK_MEMORY.lock().unwrap().to_owned()
+}
+
+/// Like `Command::output`, but also returns the peak memory use of the process in bytes (its
+/// maximum resident set size).
+#[cfg(unix)]
+pub fn output_with_peak_memory(command: &mut Command) -> io::Result<(Output, u64)> {
+    use std::io::Read;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Stdio};
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdout_pipe = child.stdout.take().expect("Could not read stdout");
+    let mut stderr_pipe = child.stderr.take().expect("Could not read stderr");
+    // both pipes need to be read at the same time, or the process blocks when one of them is full
+    let stderr_reader = std::thread::spawn(move || {
+        let mut stderr = vec![];
+        stderr_pipe.read_to_end(&mut stderr).map(|_| stderr)
+    });
+    let mut stdout = vec![];
+    stdout_pipe.read_to_end(&mut stdout)?;
+    let stderr = stderr_reader.join().expect("Could not read stderr")?;
+
+    // unlike `getrusage`, `wait4` returns the resource usage of this process only, and not of all
+    // the processes that ran in parallel
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        if unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut usage) } >= 0 {
+            break;
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+    // in kilobytes on Linux, and in bytes on macOS
+    let peak_memory = match cfg!(target_os = "macos") {
+        true => usage.ru_maxrss as u64,
+        false => usage.ru_maxrss as u64 * 1024,
+    };
+    Ok((
+        Output {
+            status: ExitStatus::from_raw(status),
+            stdout,
+            stderr,
+        },
+        peak_memory,
+    ))
+}
+
+fn get_output(command: &mut Command) -> io::Result<Output> {
+    #[cfg(unix)]
+    if PROFILE_MEMORY.load(Ordering::Relaxed) {
+        let file = command
+            .get_args()
+            .last()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .unwrap_or_default();
+        return output_with_peak_memory(command).map(|(output, peak_memory)| {
+            PEAK_MEMORY.lock().unwrap().push((file, peak_memory));
+            output
+        });
+    }
+    command.output()
+}
+
+/// With `--profile-memory`, lists the commands that used the most memory.
+pub fn get_memory_report(peak_memory: &[(String, u64)]) -> Option<String> {
+    if peak_memory.is_empty() {
+        return None;
+    }
+    let mut peak_memory = peak_memory.to_vec();
+    peak_memory.sort_by(|(a_file, a), (b_file, b)| b.cmp(a).then(a_file.cmp(b_file)));
+    let mut report = String::from("Most memory used by bsc:\n");
+    for (file, bytes) in peak_memory.iter().take(5) {
+        report.push_str(&format!("  {:.1} MB {}\n", *bytes as f64 / 1024.0 / 1024.0, file));
+    }
+    Some(report)
+}
+
+/// Runs the command and waits for its output. When `--verbose-commands` is on, prints the full
+/// command, its working directory and how long it took, to spot slow compilations.
+pub fn run_command(command: &mut Command) -> io::Result<Output> {
+    debug!("Running {:?} in {:?}", command, command.get_current_dir());
+    let permit = SPAWN_LIMIT.acquire();
+    let start = Instant::now();
+    let output = get_output(command);
+    drop(permit);
+
+    if LOG_COMMANDS.load(Ordering::Relaxed) {
+        let args = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<Cow<str>>>()
+            .join(" ");
+        println!(
+            "{:.3}s {} {} (cwd: {})",
+            start.elapsed().as_secs_f64(),
+            command.get_program().to_string_lossy(),
+            args,
+            command
+                .get_current_dir()
+                .map(|dir| dir.to_string_lossy().to_string())
+                .unwrap_or(".".to_string())
+        );
+    }
+
+    output
+}
+
 pub trait LexicalAbsolute {
     fn to_lexical_absolute(&self) -> std::io::Result<PathBuf>;
 }
@@ -43,6 +324,19 @@ impl LexicalAbsolute for Path {
     }
 }
 
+/// The path of `path` relative to the folder `from`, ie. `../../src/A.res` from `lib/ocaml`. Both
+/// paths are absolute.
+pub fn get_relative_path(from: &Path, path: &Path) -> String {
+    let from = from.components().collect::<Vec<Component>>();
+    let path = path.components().collect::<Vec<Component>>();
+    let common = from.iter().zip(path.iter()).take_while(|(a, b)| a == b).count();
+    std::iter::repeat_n(Component::ParentDir.as_os_str(), from.len() - common)
+        .chain(path[common..].iter().map(|component| component.as_os_str()))
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .to_string()
+}
+
 pub fn package_path(root: &str, package_name: &str, is_root: bool) -> String {
     match is_root {
         true => root.to_string(),
@@ -61,6 +355,32 @@ pub fn get_abs_path(path: &str) -> String {
         .to_string();
 }
 
+/// Resolves the folder of the project that rewatch is called with to an absolute path. This is done
+/// once at startup, the commands get the absolute path.
+pub fn get_project_root(path: &str) -> Result<String, String> {
+    let project_root = get_abs_path(path);
+    match fs::metadata(&project_root) {
+        Ok(metadata) if metadata.is_dir() => Ok(project_root),
+        Ok(_) => Err(format!("The project root {} is not a directory", project_root)),
+        Err(_) => Err(format!("The project root {} does not exist", project_root)),
+    }
+}
+
+/// Finds the root of the project when rewatch isn't given one, like git finds its repository: the
+/// closest folder (`dir` itself or one of its parents) with a bsconfig.json or rescript.json that
+/// has a `name` and `sources`.
+pub fn find_project_root(dir: &str) -> Result<String, String> {
+    let dir = get_abs_path(dir);
+    Path::new(&dir)
+        .ancestors()
+        .find(|dir| bsconfig::try_read(packages::get_bsconfig_path(&dir.to_string_lossy())).is_ok())
+        .map(|dir| dir.to_string_lossy().to_string())
+        .ok_or(format!(
+            "Could not find a bsconfig.json or rescript.json with a name and sources in {} or its parent folders, pass the root of the project with --root",
+            dir
+        ))
+}
+
 pub fn get_basename(path: &str) -> String {
     let path_buf = PathBuf::from(path);
     return path_buf
@@ -81,7 +401,7 @@ pub fn change_extension(path: &str, new_extension: &str) -> String {
 }
 
 /// Capitalizes the first character in s.
-fn capitalize(s: &str) -> String {
+pub fn capitalize(s: &str) -> String {
     let mut c = s.chars();
     match c.next() {
         None => String::new(),
@@ -125,6 +445,62 @@ pub fn contains_ascii_characters(str: &str) -> bool {
     return false;
 }
 
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes a file by writing a temporary file next to it, and renaming that into place. The rename
+/// replaces the file atomically, so a build (or another process) reading the file at the same time
+/// never sees it half written, and a crash leaves the previous version instead of a truncated file.
+pub fn write_atomically<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut temp_file_name = path.file_name().unwrap_or_default().to_owned();
+    temp_file_name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp_path = path.with_file_name(temp_file_name);
+
+    fs::write(&temp_path, contents)
+        .and_then(|_| fs::rename(&temp_path, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temp_path);
+        })
+}
+
+// Windows limits the length of a command line to 32767 characters, spawning a longer command fails
+// with an error that doesn't say why. We keep some margin for how the arguments get quoted.
+pub static MAX_WINDOWS_COMMAND_LINE_LENGTH: usize = 32_000;
+
+pub fn get_command_line_length(program: &str, args: &[String]) -> usize {
+    // every argument is separated by a space, and may be quoted
+    args.iter()
+        .fold(program.len(), |length, arg| length + arg.len() + 3)
+}
+
+/// The longest command that can be spawned, which is only limited on Windows.
+pub fn get_max_command_line_length() -> Option<usize> {
+    cfg!(windows).then_some(MAX_WINDOWS_COMMAND_LINE_LENGTH)
+}
+
+/// Returns the arguments to spawn the program with. When the command is longer than `max_length`,
+/// the arguments are written to the response file (one per line) instead, and bsc reads them from
+/// `@<response file>`. This happens for packages with a lot of dependencies (one include path for
+/// every dependency).
+pub fn get_command_line_args(
+    program: &str,
+    args: Vec<String>,
+    response_file_path: &str,
+    max_length: Option<usize>,
+) -> io::Result<Vec<String>> {
+    match max_length {
+        Some(max_length) if get_command_line_length(program, &args) > max_length => {
+            fs::write(response_file_path, args.join("\n") + "\n")?;
+            Ok(vec![format!("@{}", response_file_path)])
+        }
+        _ => Ok(args),
+    }
+}
+
 pub fn create_build_path(build_path: &str) {
     fs::DirBuilder::new()
         .recursive(true)
@@ -132,35 +508,88 @@ pub fn create_build_path(build_path: &str) {
         .unwrap();
 }
 
-pub fn get_bsc(root_path: &str, workspace_root: Option<String>) -> String {
+/// The node_modules folder with the rescript package: `node_modules` when it's given (see
+/// `PackageOptions::node_modules`), or the closest one in the project root or its parent folders,
+/// like node resolves packages. With npm or yarn workspaces, the packages are usually hoisted to the
+/// node_modules of the workspace.
+pub fn get_node_modules_path(project_root: &str, node_modules: Option<&str>) -> Option<String> {
+    node_modules
+        .map(|node_modules| node_modules.to_owned())
+        .or_else(|| {
+            Path::new(project_root)
+                .ancestors()
+                .map(|dir| dir.join("node_modules"))
+                .find(|node_modules| node_modules.join("rescript").is_dir())
+                .map(|node_modules| node_modules.to_string_lossy().to_string())
+        })
+}
+
+/// Finds bsc.exe of the rescript package in node_modules, of the project or of the workspace.
+pub fn try_get_bsc(
+    root_path: &str,
+    workspace_root: Option<String>,
+    node_modules: Option<&str>,
+) -> Result<String, String> {
     let subfolder = match (std::env::consts::OS, std::env::consts::ARCH) {
         ("macos", "aarch64") => "darwinarm64",
         ("macos", _) => "darwin",
         ("linux", _) => "linux",
         ("windows", _) => "win32",
-        _ => panic!("Unsupported architecture"),
+        (os, arch) => return Err(format!("Unsupported architecture: {} {}", os, arch)),
     };
 
-    match (
-        PathBuf::from(format!(
-            "{}/node_modules/rescript/{}/bsc.exe",
-            root_path, subfolder
-        ))
-        .canonicalize(),
-        workspace_root.map(|workspace_root| {
-            PathBuf::from(format!(
-                "{}/node_modules/rescript/{}/bsc.exe",
-                workspace_root, subfolder
-            ))
-            .canonicalize()
-        }),
-    ) {
-        (Ok(path), _) => path,
-        (_, Some(Ok(path))) => path,
-        _ => panic!("Could not find bsc.exe"),
-    }
-    .to_string_lossy()
-    .to_string()
+    let conventional_paths = get_node_modules_path(root_path, node_modules)
+        .into_iter()
+        .chain(
+            std::iter::once(root_path.to_string())
+                .chain(workspace_root)
+                .map(|path| format!("{}/node_modules", path)),
+        )
+        .map(|node_modules| PathBuf::from(format!("{}/rescript/{}/bsc.exe", node_modules, subfolder)))
+        .collect::<Vec<PathBuf>>();
+    find_bsc(&conventional_paths, std::env::var_os("PATH"))
+}
+
+/// Looks for bsc in the conventional paths (in node_modules) first, and falls back to a `bsc` or
+/// `bsc.exe` on the PATH, which is where it ends up with some Windows installs.
+fn find_bsc(conventional_paths: &[PathBuf], path_var: Option<OsString>) -> Result<String, String> {
+    let path_dirs = path_var
+        .map(|path_var| std::env::split_paths(&path_var).collect::<Vec<PathBuf>>())
+        .unwrap_or_default();
+    let candidates = conventional_paths
+        .iter()
+        .cloned()
+        .chain(
+            path_dirs
+                .iter()
+                .flat_map(|dir| ["bsc", "bsc.exe"].map(|name| dir.join(name))),
+        )
+        .collect::<Vec<PathBuf>>();
+    match candidates
+        .iter()
+        .find(|candidate| candidate.is_file())
+        .and_then(|candidate| candidate.canonicalize().ok())
+    {
+        Some(path) => Ok(path.to_string_lossy().to_string()),
+        None => Err(format!(
+            "Could not find bsc, looked in:\n{}{}",
+            conventional_paths
+                .iter()
+                .map(|path| format!("  {}\n", path.to_string_lossy()))
+                .collect::<String>(),
+            match path_dirs.is_empty() {
+                true => "  (PATH is empty)".to_string(),
+                false => "  the PATH, for bsc or bsc.exe".to_string(),
+            }
+        )),
+    }
+}
+
+pub fn get_bsc(root_path: &str, workspace_root: Option<String>, node_modules: Option<&str>) -> String {
+    match try_get_bsc(root_path, workspace_root, node_modules) {
+        Ok(path) => path,
+        Err(e) => panic!("{}", e),
+    }
 }
 
 pub fn string_ends_with_any(s: &PathBuf, suffixes: &[&str]) -> bool {
@@ -220,6 +649,16 @@ pub fn is_interface_ast_file(file: &str) -> bool {
     file.ends_with(".iast")
 }
 
+/// Decodes the output of a bsc invocation. bsc can in rare cases emit bytes that are not valid UTF-8
+/// (for instance a path with an odd encoding), so instead of panicking we replace the invalid
+/// sequences and add a note that this happened.
+pub fn decode_output(bytes: &[u8]) -> String {
+    match String::from_utf8_lossy(bytes) {
+        Cow::Borrowed(str) => str.to_string(),
+        Cow::Owned(str) => str + "\n(Note: output contained invalid UTF-8, which has been replaced)\n",
+    }
+}
+
 pub fn read_lines(filename: String) -> io::Result<io::Lines<io::BufReader<fs::File>>> {
     let file = fs::File::open(filename)?;
     Ok(io::BufReader::new(file).lines())
@@ -232,23 +671,27 @@ pub fn get_system_time() -> u128 {
 }
 
 pub fn is_interface_file(extension: &str) -> bool {
-    match extension {
-        "resi" | "mli" | "rei" => true,
-        _ => false,
-    }
+    SourceFileKind::from_extension(extension) == Some(SourceFileKind::Interface)
 }
 
 pub fn is_implementation_file(extension: &str) -> bool {
-    match extension {
-        "res" | "ml" | "re" => true,
-        _ => false,
-    }
+    SourceFileKind::from_extension(extension) == Some(SourceFileKind::Implementation)
 }
 
 pub fn is_source_file(extension: &str) -> bool {
     is_interface_file(extension) || is_implementation_file(extension)
 }
 
+/// Whether a line of an AST is the path of its source file, which ends the dependencies. The path is
+/// absolute, or relative to the folder bsc ran in with `--relative-paths`. Modules are capitalized,
+/// so `Belt.Array` isn't mistaken for a file.
+pub fn is_source_path(line: &str) -> bool {
+    line.starts_with('/')
+        || Path::new(line)
+            .extension()
+            .is_some_and(|extension| is_source_file(&extension.to_string_lossy()))
+}
+
 pub fn is_non_exotic_module_name(module_name: &str) -> bool {
     let mut chars = module_name.chars();
     if chars.next().unwrap().is_ascii_uppercase() && chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
@@ -280,6 +723,11 @@ pub fn format_namespaced_module_name(module_name: &str) -> String {
     };
 }
 
+/// Sets the modification time of the file, ie. to keep it when its contents didn't change.
+pub fn set_modified(path: &str, modified: SystemTime) -> io::Result<()> {
+    fs::File::options().write(true).open(path)?.set_modified(modified)
+}
+
 pub fn compute_file_hash(path: &str) -> Option<blake3::Hash> {
     match fs::read(path) {
         Ok(str) => Some(blake3::hash(&str)),
@@ -287,7 +735,7 @@ pub fn compute_file_hash(path: &str) -> Option<blake3::Hash> {
     }
 }
 
-// traverse up the directory tree until we find a bsconfig.json, if not return None
+// traverse up the directory tree until we find a rescript.json or bsconfig.json, if not return None
 pub fn get_workspace_root(project_root: &str) -> Option<String> {
     let mut current_dir = std::path::PathBuf::from(project_root);
     loop {
@@ -295,8 +743,8 @@ pub fn get_workspace_root(project_root: &str) -> Option<String> {
             None => return None,
             Some(parent) => current_dir = parent.to_path_buf(),
         }
-        let bsconfig_path = current_dir.join("bsconfig.json");
-        if bsconfig_path.exists() {
+        let bsconfig_path = packages::get_bsconfig_path(&current_dir.to_string_lossy());
+        if Path::new(&bsconfig_path).exists() {
             return Some(
                 current_dir
                     .to_str()
@@ -306,3 +754,309 @@ pub fn get_workspace_root(project_root: &str) -> Option<String> {
         }
     }
 }
+
+/// Reads the version of the compiler from the package.json of the rescript package that `bsc_path`
+/// is part of (`node_modules/rescript/<platform>/bsc.exe`), which is a lot cheaper than running
+/// `bsc -v`.
+pub fn get_rescript_version_from_package_json(bsc_path: &str) -> Option<String> {
+    let package_json = Path::new(bsc_path).parent()?.parent()?.join("package.json");
+    let json = fs::read_to_string(package_json).ok()?;
+    serde_json::from_str::<serde_json::Value>(&json)
+        .ok()?
+        .get("version")?
+        .as_str()
+        .map(|version| version.to_string())
+}
+
+/// The versions of the compiler that rewatch is tested with: from the first version (inclusive) up
+/// to the second one (exclusive). The ASTs and the arguments of bsc can change between major
+/// versions, so other versions may fail in unexpected ways.
+pub static SUPPORTED_VERSIONS: (&str, &str) = ("10.1.0", "12.0.0");
+
+/// Parses the major, minor and patch of a version like `11.0.0` or `11.1.0-rc.2`.
+fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version
+        .split(['-', '+'])
+        .next()?
+        .split('.')
+        .map(|part| part.parse::<u32>().ok());
+    Some((parts.next()??, parts.next()??, parts.next().unwrap_or(Some(0))?))
+}
+
+/// Parses a duration from the command line, in seconds (`2s` or `2`) or milliseconds (`500ms`).
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.trim() {
+        value if value.ends_with("ms") => (&value[..value.len() - 2], 0.001),
+        value if value.ends_with('s') => (&value[..value.len() - 1], 1.0),
+        value => (value, 1.0),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => Ok(Duration::from_secs_f64(number * unit)),
+        _ => Err(format!("Invalid duration {}, use ie. 2s or 500ms", value)),
+    }
+}
+
+/// Returns a warning when the version of the compiler is outside of the `SUPPORTED_VERSIONS`.
+pub fn get_unsupported_version_warning(version: &str) -> Option<String> {
+    let (min_version, max_version) = SUPPORTED_VERSIONS;
+    let parsed_version = parse_version(version)?;
+    if parsed_version >= parse_version(min_version)? && parsed_version < parse_version(max_version)? {
+        return None;
+    }
+    Some(format!(
+        "ReScript {} is not supported by rewatch (supported: >= {} and < {}). If the build fails in odd \
+         ways, this may be why.",
+        version, min_version, max_version
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        decode_output, file_path_to_compiler_asset_basename, file_path_to_module_name, find_bsc,
+        get_memory_report, get_project_root, get_rescript_version_from_package_json,
+        get_unsupported_version_warning, parse_duration, SpawnLimit,
+    };
+    use crate::build::packages::Namespace;
+    use crate::test_fixture::TestProject;
+    use std::time::Duration;
+
+    #[cfg(unix)]
+    #[test]
+    fn should_measure_the_peak_memory_of_a_command() {
+        let (output, peak_memory) = super::output_with_peak_memory(
+            std::process::Command::new("sh").args(["-c", "echo out; echo err >&2; exit 3"]),
+        )
+        .unwrap();
+
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "out\n");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "err\n");
+        assert!(peak_memory > 0);
+    }
+
+    #[test]
+    fn should_list_the_commands_that_used_the_most_memory() {
+        let megabytes = |file: &str, megabytes: u64| (file.to_string(), megabytes * 1024 * 1024);
+        let peak_memory = vec![
+            megabytes("src/A.ast", 20),
+            megabytes("src/B.ast", 300),
+            megabytes("src/C.res", 20),
+            megabytes("src/D.ast", 1),
+            megabytes("src/E.ast", 45),
+            megabytes("src/F.ast", 2),
+        ];
+
+        assert_eq!(get_memory_report(&[]), None);
+        assert_eq!(
+            get_memory_report(&peak_memory).unwrap(),
+            [
+                "Most memory used by bsc:",
+                "  300.0 MB src/B.ast",
+                "  45.0 MB src/E.ast",
+                "  20.0 MB src/A.ast",
+                "  20.0 MB src/C.res",
+                "  2.0 MB src/F.ast",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn should_never_run_more_processes_than_the_spawn_limit() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let limit = SpawnLimit::new();
+        limit.set_max(2);
+        let running = AtomicUsize::new(0);
+        let most_running = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..10 {
+                        let _permit = limit.acquire();
+                        let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most_running.fetch_max(now_running, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(1));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(most_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_look_for_bsc_in_node_modules_before_the_path() {
+        let project = TestProject::new("find-bsc");
+        let conventional_path = project.write("node_modules/rescript/linux/bsc.exe", "");
+        let path_bsc = project.write("bin/bsc", "");
+        let path_var = Some(std::env::join_paths([project.join("empty"), project.join("bin")]).unwrap());
+        let conventional_paths = vec![conventional_path.to_owned()];
+
+        let from_node_modules = find_bsc(&conventional_paths, path_var.to_owned());
+        std::fs::remove_file(&conventional_path).unwrap();
+        let from_path = find_bsc(&conventional_paths, path_var.to_owned());
+        std::fs::remove_file(&path_bsc).unwrap();
+        let not_found = find_bsc(&conventional_paths, path_var);
+
+        assert_eq!(
+            from_node_modules,
+            Ok(conventional_path.to_string_lossy().to_string())
+        );
+        assert_eq!(from_path, Ok(path_bsc.to_string_lossy().to_string()));
+        let error = not_found.unwrap_err();
+        assert!(error.contains(&conventional_path.to_string_lossy().to_string()));
+        assert!(error.contains("the PATH, for bsc or bsc.exe"));
+    }
+
+    #[test]
+    fn should_parse_durations_in_seconds_and_milliseconds() {
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert!(parse_duration("2m").is_err());
+        assert!(parse_duration("-1s").is_err());
+    }
+
+    #[test]
+    fn should_resolve_a_relative_project_root_like_an_absolute_one() {
+        let project = TestProject::new("project-root");
+        project.write("project/bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        project.write("project/src/A.res", "");
+        project.write("file.txt", "");
+        // the same folder, relative to the current directory (without changing it, as the tests run
+        // in parallel)
+        let current_dir = std::env::current_dir().unwrap();
+        let relative = std::iter::once(String::from("."))
+            .chain(current_dir.components().skip(1).map(|_| String::from("..")))
+            .chain(
+                project
+                    .root
+                    .components()
+                    .skip(1)
+                    .map(|c| c.as_os_str().to_string_lossy().to_string()),
+            )
+            .chain([String::from("project")])
+            .collect::<Vec<String>>()
+            .join("/");
+
+        let absolute_root = get_project_root(&project.join("project").to_string_lossy());
+        let relative_root = get_project_root(&relative);
+        let packages = |project_root: &str| {
+            let mut paths = crate::build::packages::load_packages(
+                project_root,
+                &crate::build::packages::PackageOptions::default(),
+            )
+            .unwrap()
+            .into_values()
+            .map(|package| (package.name, package.path))
+            .collect::<Vec<(String, String)>>();
+            paths.sort();
+            paths
+        };
+        let absolute_packages = packages(absolute_root.as_ref().unwrap());
+        let relative_packages = packages(relative_root.as_ref().unwrap());
+        let file_root = get_project_root(&project.join("file.txt").to_string_lossy());
+        let missing_root = get_project_root(&project.join("missing").to_string_lossy());
+
+        assert_eq!(absolute_root, relative_root);
+        assert_eq!(absolute_packages, relative_packages);
+        assert!(file_root.unwrap_err().contains("is not a directory"));
+        assert!(missing_root.unwrap_err().contains("does not exist"));
+    }
+
+    #[test]
+    fn should_find_the_project_root_from_a_nested_folder() {
+        let project = TestProject::new("find-project-root");
+        std::fs::create_dir_all(project.join("elsewhere")).unwrap();
+        project.write("project/bsconfig.json", r#"{ "name": "root", "sources": "src" }"#);
+        // a bsconfig.json without sources isn't the config of a project
+        project.write(
+            "project/src/nested/config/bsconfig.json",
+            r#"{ "name": "not-a-project" }"#,
+        );
+
+        let from_root = super::find_project_root(&project.join("project").to_string_lossy());
+        let from_nested =
+            super::find_project_root(&project.join("project/src/nested/config").to_string_lossy());
+        let from_elsewhere = super::find_project_root(&project.join("elsewhere").to_string_lossy());
+
+        let project_root = project.join("project").to_string_lossy().to_string();
+        assert_eq!(from_root, Ok(project_root.to_owned()));
+        assert_eq!(from_nested, Ok(project_root));
+        assert!(from_elsewhere
+            .unwrap_err()
+            .contains("Could not find a bsconfig.json"));
+    }
+
+    #[test]
+    fn should_capitalize_the_module_name_but_not_the_compiler_assets() {
+        let namespace = Namespace::Namespace(String::from("MyNamespace"));
+        for (path, module_name, asset_name) in [
+            ("src/foo.res", "Foo", "foo"),
+            ("src/Foo.res", "Foo", "Foo"),
+            ("src/fooBar.res", "FooBar", "fooBar"),
+        ] {
+            assert_eq!(
+                file_path_to_module_name(path, &Namespace::NoNamespace),
+                module_name
+            );
+            assert_eq!(
+                file_path_to_module_name(path, &namespace),
+                format!("{}-MyNamespace", module_name)
+            );
+            // bsc names the compiler assets after the file
+            assert_eq!(
+                file_path_to_compiler_asset_basename(path, &namespace),
+                format!("{}-MyNamespace", asset_name)
+            );
+        }
+    }
+
+    #[test]
+    fn should_only_warn_about_versions_outside_the_supported_range() {
+        assert_eq!(get_unsupported_version_warning("10.1.4"), None);
+        assert_eq!(get_unsupported_version_warning("11.1.0-rc.2"), None);
+        assert!(get_unsupported_version_warning("9.1.4").is_some());
+        assert!(get_unsupported_version_warning("10.0.1").is_some());
+        assert!(get_unsupported_version_warning("12.0.0-alpha.1").is_some());
+        // we can't tell
+        assert_eq!(get_unsupported_version_warning("unknown"), None);
+    }
+
+    #[test]
+    fn should_decode_valid_utf8_output_unchanged() {
+        assert_eq!(decode_output("Warning 🛑".as_bytes()), "Warning 🛑");
+    }
+
+    #[test]
+    fn should_replace_invalid_utf8_output_and_note_it() {
+        let output = decode_output(b"File \"/src/\xffFoo.res\"");
+        assert!(output.starts_with("File \"/src/\u{FFFD}Foo.res\""));
+        assert!(output.contains("invalid UTF-8"));
+    }
+
+    #[test]
+    fn should_read_the_version_from_the_package_json_of_bsc() {
+        let project = TestProject::new("version");
+        let package_json = project.write(
+            "rescript/package.json",
+            r#"{ "name": "rescript", "version": "11.0.1" }"#,
+        );
+        let bsc_path = project
+            .join("rescript/linux/bsc.exe")
+            .to_string_lossy()
+            .to_string();
+
+        let version = get_rescript_version_from_package_json(&bsc_path);
+        std::fs::remove_file(&package_json).unwrap();
+
+        assert_eq!(version, Some("11.0.1".to_string()));
+        assert_eq!(get_rescript_version_from_package_json(&bsc_path), None);
+    }
+}
@@ -4,4 +4,7 @@ pub mod cmd;
 pub mod helpers;
 pub mod lock;
 pub mod queue;
+pub mod rewatch_config;
+#[cfg(test)]
+pub mod test_fixture;
 pub mod watcher;
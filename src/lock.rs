@@ -1,74 +1,168 @@
-use std::fs;
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
 use std::path::Path;
 use std::process;
-use sysinfo::{PidExt, System, SystemExt};
+use std::thread;
+use std::time::{Duration, Instant};
 
-/* This locking mechanism is meant to never be deleted. Instead, it stores the PID of the process
- * that's running, when trying to aquire a lock, it checks wether that process is still running. If
- * not, it rewrites the lockfile to have its own PID instead. */
+/* The lock is an advisory lock on lib/rewatch.lock (flock on unix, an exclusive handle on Windows),
+ * held as long as the command runs. The operating system releases it when the process exits, also
+ * when it crashes or is killed, so a lock is never left behind. The file isn't deleted, it only
+ * contains the PID of the process that holds the lock, for the message of the processes that wait
+ * for it. */
 
 pub static LOCKFILE: &str = "rewatch.lock";
 
 pub enum Error {
-    Locked(u32),
-    ParsingLockfile(std::num::ParseIntError),
-    ReadingLockfile(std::io::Error),
+    Locked(Option<u32>),
+    TimedOut(Option<u32>),
+    OpeningLockfile(std::io::Error),
     WritingLockfile(std::io::Error),
 }
 
+fn format_holder(pid: &Option<u32>) -> String {
+    match pid {
+        Some(pid) => format!("with PID {}", pid),
+        None => String::from("for this project"),
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let msg = match self {
-            Error::Locked(pid) => format!("Rewatch is already running with PID {}", pid),
-            Error::ParsingLockfile(e) => format!("Could not parse lockfile: \n {}", e.to_string()),
-            Error::ReadingLockfile(e) => format!("Could not read lockfile: \n {}", e.to_string()),
-            Error::WritingLockfile(e) => format!("Could not write lockfile: \n {}", e.to_string()),
+            Error::Locked(pid) => format!("Rewatch is already running {}", format_holder(pid)),
+            Error::TimedOut(pid) => format!(
+                "Rewatch is still running {}, gave up waiting for it",
+                format_holder(pid)
+            ),
+            Error::OpeningLockfile(e) => format!("Could not open lockfile: \n {}", e),
+            Error::WritingLockfile(e) => format!("Could not write lockfile: \n {}", e),
         };
         write!(f, "{}", msg)
     }
 }
 
-pub enum Lock {
-    Aquired(u32),
-    Error(Error),
+/// Holds the lock until it's dropped, also when the command panics.
+pub struct Lock {
+    file: File,
 }
 
-fn exists(to_check_pid: u32) -> bool {
-    System::new_all()
-        .processes()
-        .into_iter()
-        .any(|(pid, _process)| pid.as_u32() == to_check_pid)
+impl Drop for Lock {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+            unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+        }
+        // on Windows, closing the file releases it
+    }
 }
 
-fn create(lockfile_location: &Path, pid: u32) -> Lock {
-    // Create /lib if not exists
-    match lockfile_location
-        .parent()
-        .map(|folder| fs::create_dir_all(folder))
+#[cfg(unix)]
+fn try_lock(location: &Path) -> Result<Option<File>, std::io::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    // not truncated yet, the PID of the process that holds the lock is still in there
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(location)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(Some(file));
+    }
+    match std::io::Error::last_os_error() {
+        e if e.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+        e => Err(e),
+    }
+}
+
+#[cfg(windows)]
+fn try_lock(location: &Path) -> Result<Option<File>, std::io::Error> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    // ERROR_SHARING_VIOLATION, another process has the file open
+    const SHARING_VIOLATION: i32 = 32;
+    match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .share_mode(0)
+        .open(location)
     {
-        Some(Err(e)) => return Lock::Error(Error::WritingLockfile(e)),
-        _ => (),
-    };
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.raw_os_error() == Some(SHARING_VIOLATION) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn get_holder(location: &Path) -> Option<u32> {
+    fs::read_to_string(location).ok()?.trim().parse::<u32>().ok()
+}
+
+fn write_pid(file: &mut File) -> Result<(), std::io::Error> {
+    file.set_len(0)?;
+    file.write_all(process::id().to_string().as_bytes())
+}
 
-    File::create(lockfile_location)
-        .and_then(|mut file| file.write(pid.to_string().as_bytes()).map(|_| Lock::Aquired(pid)))
-        .unwrap_or_else(|e| Lock::Error(Error::WritingLockfile(e)))
+/// Takes the lock of the project in `folder`. When another rewatch process holds it, waits until
+/// it's released, for at most `timeout` (without a timeout, as long as it takes). A timeout of zero
+/// fails right away (`--no-wait`).
+pub fn acquire(folder: &str, timeout: Option<Duration>) -> Result<Lock, Error> {
+    let location = Path::new(folder).join("lib").join(LOCKFILE);
+    fs::create_dir_all(Path::new(folder).join("lib")).map_err(Error::WritingLockfile)?;
+
+    let start = Instant::now();
+    let mut waiting = false;
+    loop {
+        match try_lock(&location).map_err(Error::OpeningLockfile)? {
+            Some(mut file) => {
+                write_pid(&mut file).map_err(Error::WritingLockfile)?;
+                return Ok(Lock { file });
+            }
+            None => {
+                let holder = get_holder(&location);
+                match timeout {
+                    Some(timeout) if timeout.is_zero() => return Err(Error::Locked(holder)),
+                    Some(timeout) if start.elapsed() >= timeout => return Err(Error::TimedOut(holder)),
+                    _ => (),
+                }
+                if !waiting {
+                    println!(
+                        "Rewatch is already running {}, waiting for it to finish...",
+                        format_holder(&holder)
+                    );
+                    waiting = true;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+    }
 }
 
-pub fn get(folder: &str) -> Lock {
-    let location = format!("{}/lib/{}", folder, LOCKFILE);
-    let path = Path::new(&location);
-    let pid = process::id();
-
-    match fs::read_to_string(&location) {
-        Err(e) if (e.kind() == std::io::ErrorKind::NotFound) => create(&path, pid),
-        Err(e) => Lock::Error(Error::ReadingLockfile(e)),
-        Ok(s) => match s.parse::<u32>() {
-            Ok(parsed_pid) if !exists(parsed_pid) => create(&path, pid),
-            Ok(parsed_pid) => Lock::Error(Error::Locked(parsed_pid)),
-            Err(e) => Lock::Error(Error::ParsingLockfile(e)),
-        },
+#[cfg(test)]
+mod test {
+    use super::{acquire, Error};
+    use crate::test_fixture::TestProject;
+    use std::time::Duration;
+
+    #[test]
+    fn should_hold_the_lock_until_it_is_dropped() {
+        let project = TestProject::new("lock");
+        let folder = project.path();
+
+        let lock = acquire(&folder, Some(Duration::ZERO));
+        let pid = std::fs::read_to_string(project.join("lib/rewatch.lock")).unwrap();
+        let locked = acquire(&folder, Some(Duration::ZERO));
+        let timed_out = acquire(&folder, Some(Duration::from_millis(200)));
+        drop(lock);
+        let relocked = acquire(&folder, Some(Duration::ZERO));
+
+        assert_eq!(pid, std::process::id().to_string());
+        assert!(matches!(locked, Err(Error::Locked(Some(holder))) if holder == std::process::id()));
+        assert!(matches!(timed_out, Err(Error::TimedOut(_))));
+        assert!(relocked.is_ok());
     }
 }
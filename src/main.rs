@@ -7,6 +7,9 @@ pub mod cmd;
 pub mod helpers;
 pub mod lock;
 pub mod queue;
+pub mod rewatch_config;
+#[cfg(test)]
+pub mod test_fixture;
 pub mod watcher;
 
 #[derive(Debug, Clone, ValueEnum)]
@@ -15,8 +18,43 @@ enum Command {
     Build,
     /// Build, then start a watcher
     Watch,
+    /// Only generate the ASTs, and print where they are
+    Ast,
     /// Clean the build artifacts
     Clean,
+    /// Check that the build is up-to-date, without compiling anything: exits with an error and lists
+    /// the modules that would be built again otherwise (see --json). Requires a previous build to
+    /// compare against
+    Check,
+    /// Print how a single module (see --module) is parsed and compiled
+    Explain,
+    /// Print the flags that a module (see --module) is parsed and compiled with, and where every flag
+    /// comes from (the package, the shared config, the root config, the command line or rewatch)
+    ExplainFlags,
+    /// Check the compiler, the dependencies and the configs of the project
+    Doctor,
+    /// Check that the source files of the project are formatted, and list the ones that aren't (see
+    /// --fix)
+    FormatCheck,
+    /// Print where the source files and ASTs of a module (see --module) are
+    Where,
+    /// Print the dependency tree of a module (see --module), as collected by the previous build
+    Deps,
+    /// Print which modules need to be compiled again when a module (see --module) changes
+    Impact,
+    /// List the packages of the project, with their namespace, source files and dependencies
+    Packages,
+    /// Print the settings of every package as they are used, after merging the shared config and
+    /// applying the defaults (see --json)
+    Config,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum Color {
+    Always,
+    Never,
+    /// Only when writing to a terminal, and `NO_COLOR` is not set
+    Auto,
 }
 
 /// Rewatch is an alternative build system for the Rescript Compiler bsb (which uses Ninja internally). It strives
@@ -28,9 +66,15 @@ struct Args {
     #[arg(value_enum)]
     command: Option<Command>,
 
-    /// The relative path to where the main bsconfig.json resides. IE - the root of your project.
+    /// The relative path to where the main bsconfig.json resides. IE - the root of your project. By
+    /// default, this is the closest folder with a bsconfig.json (or rescript.json) with a name and
+    /// sources, starting from the current folder and walking up.
     folder: Option<String>,
 
+    /// The root of the project, like the folder argument. Takes precedence over the folder argument.
+    #[arg(long)]
+    root: Option<String>,
+
     /// Filter allows for a regex to be supplied which will filter the files to be compiled. For
     /// instance, to filter out test files for compilation while doing feature work.
     #[arg(short, long)]
@@ -38,46 +82,600 @@ struct Args {
 
     /// This allows one to pass an additional command to the watcher, which allows it to run when
     /// finished. For instance, to play a sound when done compiling, or to run a test suite.
+    /// It only runs when the build succeeded, and a failing command fails the build command.
     /// NOTE - You may need to add '--color=always' to your subcommand in case you want to output
     /// colour as well
-    #[arg(short, long)]
+    #[arg(short, long, visible_alias = "after")]
     after_build: Option<String>,
 
     #[arg(short, long)]
     no_timing: Option<bool>,
+
+    /// The module to operate on, for commands that target a single module (explain, where, deps, impact). Can be
+    /// given with or without its namespace suffix, ie. `MyModule` or `MyModule-MyNamespace`.
+    #[arg(short, long)]
+    module: Option<String>,
+
+    /// For the deps command: how many levels of the dependency tree to print.
+    #[arg(long)]
+    depth: Option<usize>,
+
+    /// For the deps command: print the modules that depend on the module, instead of its
+    /// dependencies.
+    #[arg(long)]
+    reverse: bool,
+
+    /// For the deps command: leave out `Pervasives` (`PervasivesU` in uncurried mode) and the modules
+    /// of the standard library (ie. `@rescript/core`), so the tree only shows the modules of the
+    /// project.
+    #[arg(long)]
+    no_stdlib_edges: bool,
+
+    /// For the clean command: only remove the outputs (and ASTs) of this module, so it's built again
+    /// without a full clean. Can be given with or without its namespace suffix.
+    #[arg(long)]
+    only: Option<String>,
+
+    /// For `clean --only`: also remove the outputs of the modules that (transitively) depend on the
+    /// module, as collected by the previous build.
+    #[arg(long)]
+    with_dependents: bool,
+
+    /// For the config command: print the settings as JSON. For the check command: print the modules
+    /// that would be built again as JSON (`[{"module", "file_path", "reason"}]`), and don't fail.
+    #[arg(long)]
+    json: bool,
+
+    /// Only build the packages which name matches this regex. Note that `--filter` filters on file
+    /// names instead.
+    #[arg(long)]
+    package_filter: Option<String>,
+
+    /// When using `--package-filter`, also build the dependencies of the matching packages.
+    #[arg(long)]
+    with_deps: bool,
+
+    /// Build the packages of `--package-filter` like they are built on their own, ie. to check a
+    /// library before publishing it: only with their dependencies (like `--with-deps`), and fail when
+    /// they use modules of packages that are not in their bs-dependencies.
+    #[arg(long, requires = "package_filter")]
+    isolated: bool,
+
+    /// Pass the source files and include paths to bsc relative to the folder it runs in, instead of
+    /// absolute paths (`-absname`), so the build output is the same on every machine and can be
+    /// cached across checkouts. Editor integrations that rely on absolute paths in the errors and `.cmt` files
+    /// may not find the source files then.
+    #[arg(long)]
+    relative_paths: bool,
+
+    /// Only type check the modules, without writing any JavaScript, ie. for a fast type check in CI.
+    /// The next build without it compiles the modules again to write the JavaScript.
+    #[arg(long)]
+    check_only: bool,
+
+    /// Only build the packages listed in this file (one name per line), and their dependencies. For
+    /// a set of packages that is computed elsewhere, ie. the packages that a change affects.
+    #[arg(long, conflicts_with = "package_filter")]
+    packages_from: Option<String>,
+
+    /// Don't skip source files that are excluded by `.gitignore` / `.ignore` files.
+    #[arg(long)]
+    no_ignore: bool,
+
+    /// Fail right away when another rewatch process is running for this project, instead of
+    /// waiting for it to finish.
+    #[arg(long)]
+    no_wait: bool,
+
+    /// Wait at most this long (ie. `30s`) for another rewatch process that builds this project,
+    /// instead of until it's done.
+    #[arg(long, value_parser = helpers::parse_duration, conflicts_with = "no_wait")]
+    lock_timeout: Option<std::time::Duration>,
+
+    /// Print why source files are parsed again (ie. `Regenerating Foo.ast (source modified)`), and
+    /// log the phases of the build. Use `-vv` to also log the commands. Release builds only log
+    /// warnings and errors, the phases and commands are logged by debug builds. `RUST_LOG` takes
+    /// precedence over the log level.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Print every bsc invocation with its working directory and how long it took.
+    #[arg(long)]
+    verbose_commands: bool,
+
+    /// Measure the peak memory use of every bsc process, and list the modules that used the most after
+    /// compiling (ie. to find the modules to split up, or a limit for `--max-spawns`). Only
+    /// supported on Unix.
+    #[arg(long)]
+    profile_memory: bool,
+
+    /// Run at most this many bsc processes at the same time, regardless of the number of threads
+    /// (ie. to keep the memory use or the open files of a build down).
+    #[arg(long)]
+    max_spawns: Option<usize>,
+
+    /// Parse and compile all modules, ignoring the state of the previous build (modification
+    /// times and cached flags). Unlike `clean`, this doesn't delete the build folders first, so
+    /// it doesn't remove orphaned files either.
+    #[arg(long)]
+    force: bool,
+
+    /// Only parse and compile the modules whose source files changed since this git ref (and the
+    /// modules that depend on them), instead of relying on modification times. Meant for CI, where
+    /// a checkout makes all source files look modified. All modules of a package with a changed
+    /// bsconfig.json are rebuilt.
+    #[arg(long)]
+    changed_from: Option<String>,
+
+    /// After a successful build, print the absolute paths of the compiled JavaScript files to stdout,
+    /// one per line. The status of the build goes to stderr instead.
+    #[arg(long)]
+    print_outputs: bool,
+
+    /// Only print a summary of the build (ie. `Compiled 980 modules across 14 packages in 12.30s`),
+    /// or the errors when it fails. Unlike the default output, this leaves out the progress of the
+    /// build and the warnings, to keep CI logs short.
+    #[arg(long)]
+    summary_only: bool,
+
+    /// Fail the build when the root package (or a pinned dependency) has .res / .resi files that
+    /// aren't in its `sources`, instead of silently leaving them out of the build.
+    #[arg(long)]
+    strict_sources: bool,
+
+    /// An extra flag for bsc (ie. `--bsc-flag -bs-g`), can be given multiple times. The flags apply
+    /// to all packages, in addition to the `bsc-flags` of their bsconfig.json.
+    #[arg(long = "bsc-flag", allow_hyphen_values = true)]
+    bsc_flags: Vec<String>,
+
+    /// Build a package without its namespace (ie. `--no-namespace my-package`), to debug namespace
+    /// issues. Can be given multiple times. The modules get their bare names, so the imports in the
+    /// JavaScript output differ from a normal build, and other packages that use the namespace don't
+    /// compile.
+    #[arg(long)]
+    no_namespace: Vec<String>,
+
+    /// Build a package with a namespace named after the package (ie. `--namespace my-package`), when
+    /// its bsconfig.json doesn't set one. Can be given multiple times, and `--no-namespace` takes
+    /// precedence.
+    #[arg(long)]
+    namespace: Vec<String>,
+
+    /// After compiling, check that every module that compiled wrote its cmi, cmj and JavaScript
+    /// files, and fail the build when one is missing. This catches bsc exiting without errors but
+    /// without writing an output.
+    #[arg(long)]
+    verify_artifacts: bool,
+
+    /// A warning spec for bsc's `-w` (ie. `--warn-spec -27+20`), applied after the `warnings` of the
+    /// bsconfig.json of every package.
+    #[arg(long, allow_hyphen_values = true)]
+    warn_spec: Option<String>,
+
+    /// A warning spec for bsc's `-warn-error` (ie. `--warn-error-spec -27+20`, so warning 27 doesn't
+    /// fail the build but 20 does), applied after the `warnings` of the bsconfig.json. Like the
+    /// `warnings` of the bsconfig.json, it only applies to the root and pinned packages.
+    #[arg(long, allow_hyphen_values = true)]
+    warn_error_spec: Option<String>,
+
+    /// Exit with an error when bsc reported warnings, after compiling all modules. bsc doesn't turn
+    /// warnings into errors then (the `warnings.error` of the bsconfig.json and `--warn-error-spec`
+    /// are ignored), so the modules with warnings still get their JavaScript.
+    #[arg(long)]
+    fail_on_warning: bool,
+
+    /// Before parsing, print the modules that are built again and why (ie. `source modified`,
+    /// `flags changed` or `compiler changed`), grouped by package.
+    #[arg(long)]
+    explain_dirty: bool,
+
+    /// Build the dev sources (`"type": "dev"`) of the root package and the pinned dependencies, this
+    /// is the default.
+    #[arg(long, overrides_with = "no_dev")]
+    dev: bool,
+
+    /// Leave out the dev sources (`"type": "dev"`), ie. for a production build.
+    #[arg(long, overrides_with = "dev")]
+    no_dev: bool,
+
+    /// Compile with the debug profile, with debug info in the JavaScript output (`-bs-g`). This is the
+    /// default.
+    #[arg(long, overrides_with = "release")]
+    debug: bool,
+
+    /// Compile with the release profile, without debug info, ie. for a production build.
+    #[arg(long, overrides_with = "debug")]
+    release: bool,
+
+    /// Stop compiling after this many errors, and don't start compiling any more modules.
+    #[arg(long)]
+    max_errors: Option<usize>,
+
+    /// Warn about the modules that take longer than this to compile (ie. `2s` or `500ms`), and list
+    /// the slowest modules.
+    #[arg(long, value_parser = helpers::parse_duration)]
+    time_budget: Option<std::time::Duration>,
+
+    /// After compiling, print a table with how many modules of every package were compiled, succeeded
+    /// and failed, and how long they took.
+    #[arg(long)]
+    report_packages: bool,
+
+    /// With `format-check`, format the source files that aren't formatted, instead of failing.
+    #[arg(long)]
+    fix: bool,
+
+    /// Store the outputs of the compiled modules in this folder, by a hash of their sources, flags,
+    /// compiler version and the interfaces they use. Modules that are in there already are copied
+    /// from it instead of being compiled, so the folder can be shared between builds (ie. in CI).
+    #[arg(long)]
+    cache_dir: Option<String>,
+
+    /// Warn about the modules that modules depend on, but that are not a module of the project, its
+    /// dependencies or the standard library (ie. a package that is not installed). Modules of opened
+    /// modules are reported too, as they can't be told apart.
+    #[arg(long)]
+    check_deps: bool,
+
+    /// Write a Makefile dependency file (`.d`) next to the `.cmj` of every module, in which the
+    /// `.cmj` depends on the `.cmi` of the modules it uses, so Make or Ninja based builds can follow
+    /// the dependencies of the modules.
+    #[arg(long)]
+    emit_depfiles: bool,
+
+    /// Use this version of the compiler (ie. `11.1.0`) instead of the version of the installed
+    /// compiler, for the `-bs-v` flag of bsc and to decide whether everything needs to be compiled
+    /// again. This is an advanced override (ie. for a compiler that was built from source): when it
+    /// doesn't match the actual compiler, bsc can fail to read the files it wrote itself.
+    #[arg(long)]
+    compiler_version: Option<String>,
+
+    /// Compile against the standard library in this folder (with the `.cmi` files of `Pervasives`,
+    /// `Js`, `Belt`, ...) instead of the `node_modules/rescript/lib/ocaml` of the project. Can also be
+    /// given with the RESCRIPT_STDLIB_PATH environment variable.
+    #[arg(long)]
+    stdlib_path: Option<String>,
+
+    /// Write a trace of the build to this file, with a span for every invocation of the compiler on
+    /// the thread it ran on. Open it in `chrome://tracing` or https://ui.perfetto.dev.
+    #[arg(long)]
+    trace: Option<String>,
+
+    /// For the watch command: poll the source files for changes every interval (in milliseconds,
+    /// 1000 when no interval is given) instead of relying on the events of the filesystem, which
+    /// are unreliable on network filesystems and in some Docker setups. Use `--watch-poll=500` to
+    /// give an interval.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "1000")]
+    watch_poll: Option<u64>,
+
+    /// The node_modules folder with the rescript package and the dependencies. By default, this is the
+    /// closest node_modules with rescript in the project folder or its parent folders (ie. hoisted
+    /// in a workspace).
+    #[arg(long)]
+    node_modules: Option<String>,
+
+    /// Write the metrics of the build (ie. the duration of the build and its phases, and the number of
+    /// compiled modules) to this file, in the text format of Prometheus. See src/build/metrics.rs for
+    /// the names of the metrics.
+    #[arg(long)]
+    metrics_file: Option<String>,
+
+    /// Write a JUnit XML report of the build to this file, for CI: every package is a test suite, and
+    /// every module a test case that fails with the errors of bsc when it doesn't compile.
+    #[arg(long)]
+    junit: Option<String>,
+
+    /// When to use colors in the output.
+    #[arg(long, value_enum, default_value_t = Color::Auto)]
+    color: Color,
+}
+
+/// Exits after a command, with the error of the command printed when it failed.
+fn exit_with(result: Result<(), String>) -> ! {
+    match result {
+        Ok(()) => std::process::exit(0),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    }
 }
 
 fn main() {
-    env_logger::init();
     let args = Args::parse();
+    let project_root = match args.root.as_ref().or(args.folder.as_ref()) {
+        Some(folder) => helpers::get_project_root(folder),
+        None => helpers::find_project_root("."),
+    };
+    let folder = match project_root {
+        Ok(folder) => folder,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    };
+    let config = match rewatch_config::read(&folder) {
+        Ok(config) => config.override_with(rewatch_config::RewatchConfig {
+            filter: args.filter,
+            package_filter: args.package_filter,
+            with_deps: (args.with_deps || args.isolated).then_some(true),
+            no_ignore: args.no_ignore.then_some(true),
+            after_build: args.after_build,
+            verbose: (args.verbose > 0).then_some(args.verbose),
+            max_errors: args.max_errors,
+            dependency_rules: None,
+        }),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    };
+
+    let log_level = match config.get_verbose() {
+        0 => log::LevelFilter::Error,
+        1 => log::LevelFilter::Info,
+        _ => log::LevelFilter::Debug,
+    };
+    env_logger::Builder::new()
+        .filter_level(log_level)
+        .parse_env("RUST_LOG")
+        .init();
+
+    helpers::set_log_commands(args.verbose_commands);
+    helpers::set_max_spawns(args.max_spawns);
+    helpers::set_profile_memory(args.profile_memory);
+    helpers::set_verbose(config.get_verbose() > 0);
+    build::metrics::set_metrics_file(args.metrics_file.to_owned());
+    build::junit::set_junit_file(args.junit.to_owned());
+    // stdout is for the JSON of `check --json`
+    helpers::set_status_to_stderr(
+        args.print_outputs || (args.json && matches!(args.command, Some(Command::Check))),
+    );
+    helpers::set_summary_only(args.summary_only);
+    build::explain::set_explain_dirty(args.explain_dirty);
+    build::interrupt::install_handler();
+    match args.color {
+        Color::Always => console::set_colors_enabled(true),
+        Color::Never => console::set_colors_enabled(false),
+        Color::Auto if std::env::var("NO_COLOR").map(|v| !v.is_empty()).unwrap_or(false) => {
+            console::set_colors_enabled(false)
+        }
+        Color::Auto => (),
+    }
 
     let command = args.command.unwrap_or(Command::Build);
-    let folder = args.folder.unwrap_or(".".to_string());
-    let filter = args
+    let filter = config
         .filter
+        .to_owned()
         .map(|filter| Regex::new(filter.as_ref()).expect("Could not parse regex"));
-
-    match lock::get(&folder) {
-        lock::Lock::Error(ref e) => {
-            eprintln!("Error while trying to get lock: {}", e.to_string());
+    let package_filter = config.package_filter.to_owned().map(|package_filter| {
+        Regex::new(package_filter.as_ref()).unwrap_or_else(|e| {
+            eprintln!("Could not parse package filter regex: {}", e);
             std::process::exit(1)
+        })
+    });
+    let dependency_rules =
+        build::deps::compile_dependency_rules(&config.dependency_rules.to_owned().unwrap_or_default())
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            });
+    let compile_options = build::build_types::CompileOptions {
+        check_only: args.check_only,
+        bsc_flags: bsconfig::split_extra_bsc_flags(&args.bsc_flags),
+        release: args.release,
+        stdlib_path: args
+            .stdlib_path
+            .to_owned()
+            .or_else(|| {
+                std::env::var("RESCRIPT_STDLIB_PATH")
+                    .ok()
+                    .filter(|path| !path.is_empty())
+            })
+            .map(|path| helpers::get_abs_path(&path)),
+        warn_spec: args.warn_spec.to_owned(),
+        warn_error_spec: args.warn_error_spec.to_owned(),
+        fail_on_warning: args.fail_on_warning,
+        compiler_version: args.compiler_version.to_owned(),
+        relative_paths: args.relative_paths,
+    };
+    let packages_from = args
+        .packages_from
+        .as_ref()
+        .map(|path| match std::fs::read_to_string(path) {
+            Ok(contents) => contents
+                .lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect(),
+            Err(e) => {
+                eprintln!("Could not read the packages from {}: {}", path, e);
+                std::process::exit(1)
+            }
+        });
+    let package_options = build::packages::PackageOptions {
+        no_namespace: args.no_namespace.to_owned(),
+        namespace: args.namespace.to_owned(),
+        no_dev: args.no_dev,
+        node_modules: args.node_modules.as_deref().map(helpers::get_abs_path),
+    };
+    let build_options = build::BuildOptions {
+        filter,
+        no_timing: args.no_timing.unwrap_or(false),
+        package_filter,
+        with_deps: config.get_with_deps(),
+        isolated: args.isolated,
+        packages_from,
+        respect_ignore: !config.get_no_ignore(),
+        strict_sources: args.strict_sources,
+        force: args.force,
+        changed_from: args.changed_from.to_owned(),
+        compile_options: compile_options.to_owned(),
+        package_options: package_options.to_owned(),
+        dependency_rules,
+        virtual_sources: vec![],
+        events: None,
+        cancel: None,
+        max_errors: config.max_errors,
+        report_packages: args.report_packages,
+        check_deps: args.check_deps,
+        emit_depfiles: args.emit_depfiles,
+        cache_dir: args.cache_dir.as_deref().map(helpers::get_abs_path),
+        trace: args
+            .trace
+            .as_ref()
+            .map(|_| std::sync::Arc::new(build::trace::Trace::start())),
+        verify_artifacts: args.verify_artifacts,
+        time_budget: args.time_budget,
+    };
+
+    // only the commands that write to the build folders take the lock, so ie. `where` from an
+    // editor still runs while watching. It's released when main returns or panics, and by the
+    // operating system when `exit_with` exits the process.
+    let _lock = match command {
+        Command::Build | Command::Watch | Command::Clean | Command::Ast => {
+            let timeout = match args.no_wait {
+                true => Some(std::time::Duration::ZERO),
+                false => args.lock_timeout,
+            };
+            match lock::acquire(&folder, timeout) {
+                Ok(lock) => Some(lock),
+                Err(e) => {
+                    eprintln!("Error while trying to get lock: {}", e);
+                    std::process::exit(1)
+                }
+            }
         }
-        lock::Lock::Aquired(_) => match command {
-            Command::Clean => build::clean::clean(&folder),
-            Command::Build => {
-                match build::build(&filter, &folder, args.no_timing.unwrap_or(false)) {
-                    Err(()) => std::process::exit(1),
-                    Ok(_) => {
-                        args.after_build.map(|command| cmd::run(command));
-                        std::process::exit(0)
-                    }
-                };
+        _ => None,
+    };
+
+    match command {
+        Command::Clean => match &args.only {
+            Some(module) => exit_with(build::clean::clean_modules(
+                &folder,
+                module,
+                args.with_dependents,
+                &package_options,
+            )),
+            None => build::clean::clean(&folder, &package_options),
+        },
+        Command::Ast => exit_with(build::generate_asts_only(
+            &folder,
+            &build::BuildOptions {
+                no_timing: false,
+                changed_from: None,
+                ..build_options
+            },
+        )),
+        Command::Check => exit_with(build::check(
+            &folder,
+            &build::BuildOptions {
+                no_timing: false,
+                force: false,
+                changed_from: None,
+                ..build_options
+            },
+            args.json,
+        )),
+        Command::FormatCheck => exit_with(build::format::format_check(&folder, args.fix, &package_options)),
+        Command::Doctor => exit_with(build::doctor::doctor(&folder, &package_options)),
+        Command::Packages => exit_with(build::packages::print_packages(&folder, &package_options)),
+        Command::Config => exit_with(build::packages::print_config(
+            &folder,
+            &compile_options,
+            &package_options,
+            args.json,
+        )),
+        Command::Explain => match args.module {
+            Some(module) => exit_with(build::explain::explain(
+                &folder,
+                &module,
+                &compile_options,
+                &package_options,
+            )),
+            None => {
+                eprintln!("Please provide the module to explain with --module");
+                std::process::exit(1)
+            }
+        },
+        Command::ExplainFlags => match args.module {
+            Some(module) => exit_with(build::explain::explain_flags(
+                &folder,
+                &module,
+                &compile_options,
+                &package_options,
+            )),
+            None => {
+                eprintln!("Please provide the module to explain the flags of with --module");
+                std::process::exit(1)
             }
-            Command::Watch => {
-                let _initial_build = build::build(&filter, &folder, false);
-                args.after_build.clone().map(|command| cmd::run(command));
-                watcher::start(&filter, &folder, args.after_build);
+        },
+        Command::Deps => match args.module {
+            Some(module) => exit_with(build::deps_tree::print_deps_tree(
+                &folder,
+                &module,
+                args.depth,
+                args.reverse,
+                args.no_stdlib_edges,
+                &compile_options,
+                &package_options,
+            )),
+            None => {
+                eprintln!("Please provide the module to print the dependencies of with --module");
+                std::process::exit(1)
+            }
+        },
+        Command::Impact => match args.module {
+            Some(module) => exit_with(build::deps_tree::print_impact(&folder, &module, &package_options)),
+            None => {
+                eprintln!("Please provide the module to print the impact of with --module");
+                std::process::exit(1)
             }
         },
+        Command::Where => match args.module {
+            Some(module) => exit_with(build::explain::where_module(&folder, &module, &package_options)),
+            None => {
+                eprintln!("Please provide the module to look up with --module");
+                std::process::exit(1)
+            }
+        },
+        Command::Build => {
+            let result = build::build(&folder, &build_options);
+            if let (Some(trace_file), Some(trace)) = (&args.trace, &build_options.trace) {
+                if let Err(e) = trace.write(trace_file) {
+                    eprintln!("{}", e);
+                }
+            }
+            match result {
+                Err(_) => std::process::exit(1),
+                Ok(build_state) => {
+                    if args.print_outputs {
+                        build::get_compiled_output_paths(&build_state)
+                            .iter()
+                            .for_each(|output_path| println!("{}", output_path));
+                    }
+                    match config.after_build.map(cmd::run) {
+                        Some(false) => std::process::exit(1),
+                        _ => std::process::exit(0),
+                    }
+                }
+            };
+        }
+        Command::Watch => {
+            let build_options = build::BuildOptions {
+                no_timing: false,
+                ..build_options
+            };
+            let initial_build = build::build(&folder, &build_options);
+            if let (Ok(_), Some(command)) = (initial_build, config.after_build.clone()) {
+                cmd::run(command);
+            }
+            watcher::start(
+                &folder,
+                &build_options,
+                config.after_build.to_owned(),
+                args.watch_poll.map(std::time::Duration::from_millis),
+            );
+        }
     }
 }
@@ -0,0 +1,125 @@
+use serde::Deserialize;
+use std::path::Path;
+
+// The optional `rewatch.json` in the root of the project sets the defaults of the command line
+// options, so they don't need to be passed on every invocation. The precedence is:
+//
+//   command line flag > rewatch.json > built-in default
+//
+// Flags that are switches (ie. `--with-deps`) can only be turned on from the command line, so
+// when `rewatch.json` turns them on, they are on.
+
+pub static REWATCH_CONFIG_FILE: &str = "rewatch.json";
+
+/// A rule of the `dependency-rules`, which changes the dependencies of the modules that match the
+/// `module` regex (see `deps::compile_dependency_rules`).
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DependencyRule {
+    pub module: String,
+    /// The modules to add as dependencies
+    pub add: Option<Vec<String>>,
+    /// A regex of the dependencies to remove
+    pub remove: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RewatchConfig {
+    pub filter: Option<String>,
+    #[serde(rename = "package-filter")]
+    pub package_filter: Option<String>,
+    #[serde(rename = "with-deps")]
+    pub with_deps: Option<bool>,
+    #[serde(rename = "no-ignore")]
+    pub no_ignore: Option<bool>,
+    #[serde(rename = "after-build")]
+    pub after_build: Option<String>,
+    pub verbose: Option<u8>,
+    #[serde(rename = "max-errors")]
+    pub max_errors: Option<usize>,
+    /// Only in rewatch.json, there is no command line flag for it
+    #[serde(rename = "dependency-rules")]
+    pub dependency_rules: Option<Vec<DependencyRule>>,
+}
+
+impl RewatchConfig {
+    /// Returns the config where the options that are set in `overrides` (the command line) take
+    /// precedence over the options of this config.
+    pub fn override_with(self, overrides: RewatchConfig) -> RewatchConfig {
+        RewatchConfig {
+            filter: overrides.filter.or(self.filter),
+            package_filter: overrides.package_filter.or(self.package_filter),
+            with_deps: overrides.with_deps.or(self.with_deps),
+            no_ignore: overrides.no_ignore.or(self.no_ignore),
+            after_build: overrides.after_build.or(self.after_build),
+            verbose: overrides.verbose.or(self.verbose),
+            max_errors: overrides.max_errors.or(self.max_errors),
+            dependency_rules: overrides.dependency_rules.or(self.dependency_rules),
+        }
+    }
+
+    pub fn get_with_deps(&self) -> bool {
+        self.with_deps.unwrap_or(false)
+    }
+
+    pub fn get_no_ignore(&self) -> bool {
+        self.no_ignore.unwrap_or(false)
+    }
+
+    pub fn get_verbose(&self) -> u8 {
+        self.verbose.unwrap_or(0)
+    }
+}
+
+/// Reads the `rewatch.json` in the root of the project. Returns the default config when there is
+/// no such file.
+pub fn read(project_root: &str) -> Result<RewatchConfig, String> {
+    let path = Path::new(project_root).join(REWATCH_CONFIG_FILE);
+    if !path.exists() {
+        return Ok(RewatchConfig::default());
+    }
+    std::fs::read_to_string(&path)
+        .map_err(|e| format!("Could not read {}. {}", path.display(), e))
+        .and_then(|contents| {
+            serde_json::from_str::<RewatchConfig>(&contents)
+                .map_err(|e| format!("Could not parse {}. {}", path.display(), e))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_fixture::TestProject;
+
+    #[test]
+    fn should_give_the_command_line_precedence_over_rewatch_json() {
+        let file_config = serde_json::from_str::<RewatchConfig>(
+            r#"{ "filter": "^(?!Test)", "max-errors": 10, "with-deps": true }"#,
+        )
+        .unwrap();
+        let cli_config = RewatchConfig {
+            filter: Some("^Foo".to_string()),
+            verbose: Some(2),
+            ..RewatchConfig::default()
+        };
+
+        let config = file_config.override_with(cli_config);
+
+        assert_eq!(config.filter, Some("^Foo".to_string()));
+        assert_eq!(config.max_errors, Some(10));
+        assert!(config.get_with_deps());
+        assert_eq!(config.get_verbose(), 2);
+        assert!(!config.get_no_ignore());
+        assert_eq!(config.package_filter, None);
+    }
+
+    #[test]
+    fn should_use_the_defaults_without_rewatch_json() {
+        let project = TestProject::new("rewatch-config");
+
+        let config = read(&project.path()).unwrap();
+
+        assert_eq!(config, RewatchConfig::default());
+    }
+}
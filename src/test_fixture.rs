@@ -0,0 +1,82 @@
+use crate::build::build_types::BuildState;
+use crate::build::packages;
+use ahash::AHashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// two tests with the same name would remove each other's project otherwise
+static PROJECT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A project in a temporary folder, which is removed again when the test ends (also when it fails).
+pub struct TestProject {
+    pub root: PathBuf,
+}
+
+impl TestProject {
+    /// Creates an empty project folder, named after the test (and unique within the test run).
+    pub fn new(name: &str) -> TestProject {
+        let root = std::env::temp_dir().join(format!(
+            "rewatch-{}-{}-{}",
+            name,
+            std::process::id(),
+            PROJECT_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        TestProject {
+            root: root.canonicalize().unwrap(),
+        }
+    }
+
+    /// The path of the project, like the project root of a build.
+    pub fn path(&self) -> String {
+        self.root.to_string_lossy().to_string()
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// Writes a file of the project, and the folders it's in.
+    pub fn write<P: AsRef<Path>>(&self, path: P, contents: &str) -> PathBuf {
+        let path = self.root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Writes an executable shell script, ie. a fake bsc that logs how it's called.
+    #[cfg(unix)]
+    pub fn write_script<P: AsRef<Path>>(&self, path: P, script: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = self.write(path, &format!("#!/bin/sh\n{}", script));
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    /// Reads the packages of the project, like a build.
+    pub fn make(&self) -> AHashMap<String, packages::Package> {
+        packages::make(
+            &None,
+            &self.path(),
+            None,
+            true,
+            &[],
+            &packages::PackageOptions::default(),
+        )
+    }
+
+    /// The build state of the project, with the modules of all packages.
+    pub fn build_state(&self, root_config_name: &str) -> BuildState {
+        let mut build_state = BuildState::new(self.path(), root_config_name.to_string(), self.make());
+        packages::parse_packages(&mut build_state, true);
+        build_state
+    }
+}
+
+impl Drop for TestProject {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
@@ -1,23 +1,127 @@
 use crate::build;
+use crate::build::interrupt;
+use crate::build::packages;
 use crate::cmd;
 use crate::helpers;
 use crate::queue::FifoQueue;
 use crate::queue::*;
 use futures_timer::Delay;
-use notify::{Config, Error, Event, RecommendedWatcher, RecursiveMode, Watcher};
-use std::sync::Arc;
-use std::time::Duration;
+use notify::{Config, Error, Event, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The stats of the rebuilds of a watch session, summarized when the watcher is stopped (Ctrl-C).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionStats {
+    /// The durations of the rebuilds, in order (without the initial build)
+    pub rebuild_durations: Vec<Duration>,
+    /// The modules that were compiled by the rebuilds, added up
+    pub modules_compiled: usize,
+    /// How many rebuilds every module triggered by changing
+    pub changed_modules: BTreeMap<String, usize>,
+}
+
+impl SessionStats {
+    pub fn record_rebuild(
+        &mut self,
+        duration: Duration,
+        modules_compiled: usize,
+        changed_modules: &BTreeSet<String>,
+    ) {
+        self.rebuild_durations.push(duration);
+        self.modules_compiled += modules_compiled;
+        for module_name in changed_modules {
+            *self.changed_modules.entry(module_name.to_owned()).or_insert(0) += 1;
+        }
+    }
+
+    /// The module that changed most often, the first by name when there's a tie.
+    pub fn get_most_changed_module(&self) -> Option<(&str, usize)> {
+        self.changed_modules
+            .iter()
+            .max_by(|(a_name, a_count), (b_name, b_count)| a_count.cmp(b_count).then(b_name.cmp(a_name)))
+            .map(|(module_name, count)| (module_name.as_str(), *count))
+    }
+
+    /// The summary of the session, or none when nothing was rebuilt.
+    pub fn get_summary(&self) -> Option<String> {
+        let min = self.rebuild_durations.iter().min()?;
+        let max = self.rebuild_durations.iter().max()?;
+        let total: Duration = self.rebuild_durations.iter().sum();
+        let average = total / self.rebuild_durations.len() as u32;
+        let mut summary = format!(
+            "Rebuilt {} times in {:.2}s on average (min {:.2}s, max {:.2}s), compiling {} modules",
+            self.rebuild_durations.len(),
+            average.as_secs_f64(),
+            min.as_secs_f64(),
+            max.as_secs_f64(),
+            self.modules_compiled
+        );
+        if let Some((module_name, count)) = self.get_most_changed_module() {
+            summary.push_str(&format!(
+                "\nChanged most often: {} ({} times)",
+                module_name, count
+            ));
+        }
+        Some(summary)
+    }
+}
+
+static SESSION_STATS: Mutex<SessionStats> = Mutex::new(SessionStats {
+    rebuild_durations: Vec::new(),
+    modules_compiled: 0,
+    changed_modules: BTreeMap::new(),
+});
+
+/// The stats of the current watch session, they are reset when the watcher starts.
+pub fn get_session_stats() -> SessionStats {
+    SESSION_STATS.lock().unwrap().clone()
+}
+
+/// Whether a changed file is a source file that needs a rebuild.
+fn is_source_change(path: &Path, filter: &Option<regex::Regex>) -> bool {
+    let path_buf = path.to_path_buf();
+
+    let is_in_bs_build_path = path_buf
+        .to_str()
+        .map(|x| x.contains("/lib/bs/") || x.contains("/lib/ocaml/"))
+        .unwrap_or(false);
+
+    let name = path_buf
+        .file_name()
+        .and_then(|x| x.to_str())
+        .unwrap_or("Unknown")
+        .to_string();
+
+    let extension = path_buf.extension().and_then(|ext| ext.to_str());
+    match (is_in_bs_build_path, extension) {
+        (false, Some(extension)) => {
+            (helpers::is_implementation_file(&extension) || helpers::is_interface_file(&extension))
+                && filter.as_ref().map(|re| !re.is_match(&name)).unwrap_or(true)
+        }
+
+        _ => false,
+    }
+}
 
 async fn async_watch(
     q: Arc<FifoQueue<Result<Event, Error>>>,
     path: &str,
-    filter: &Option<regex::Regex>,
+    build_options: &build::BuildOptions,
     after_build: Option<String>,
 ) -> notify::Result<()> {
     loop {
         // We want to sleep for a little while so the CPU can schedule other work. That way we end
         // up not burning CPU cycles.
         Delay::new(Duration::from_millis(50)).await;
+        if interrupt::is_stopped() {
+            if let Some(summary) = get_session_stats().get_summary() {
+                println!("\n{}", summary);
+            }
+            std::process::exit(interrupt::EXIT_CODE);
+        }
         let mut events: Vec<Event> = vec![];
         while !q.is_empty() {
             match q.pop() {
@@ -26,33 +130,16 @@ async fn async_watch(
             }
         }
 
-        let needs_compile = events.iter().any(|event| {
-            event.paths.iter().any(|path| {
-                let path_buf = path.to_path_buf();
-
-                let is_in_bs_build_path = path_buf
-                    .to_str()
-                    .map(|x| x.contains("/lib/bs/") || x.contains("/lib/ocaml/"))
-                    .unwrap_or(false);
-
-                let name = path_buf
-                    .file_name()
-                    .and_then(|x| x.to_str())
-                    .unwrap_or("Unknown")
-                    .to_string();
-
-                let extension = path_buf.extension().and_then(|ext| ext.to_str());
-                match (is_in_bs_build_path, extension) {
-                    (false, Some(extension)) => {
-                        (helpers::is_implementation_file(&extension)
-                            || helpers::is_interface_file(&extension))
-                            && filter.as_ref().map(|re| !re.is_match(&name)).unwrap_or(true)
-                    }
-
-                    _ => false,
-                }
+        // the modules of the changed source files (by their bare name), for the stats of the session
+        let changed_modules = events
+            .iter()
+            .flat_map(|event| event.paths.iter())
+            .filter(|path| is_source_change(path, &build_options.filter))
+            .map(|path| {
+                helpers::file_path_to_module_name(&path.to_string_lossy(), &packages::Namespace::NoNamespace)
             })
-        });
+            .collect::<BTreeSet<String>>();
+        let needs_compile = !changed_modules.is_empty();
 
         if needs_compile {
             // Wait for events to settle
@@ -63,26 +150,104 @@ async fn async_watch(
                 let _ = q.pop();
             }
 
-            let _ = build::build(filter, path, false);
-            after_build.clone().map(|command| cmd::run(command));
+            // every build reads the package tree again, so added and removed source files also
+            // update the namespaces: their mlmaps are generated and compiled again, which makes
+            // the modules that use the namespace compile again (see `packages::parse_packages`)
+            let start_rebuild = Instant::now();
+            let build_result = build::build(path, build_options);
+            // a failed build doesn't return which modules it compiled
+            let modules_compiled = build_result.as_ref().map_or(0, |build_state| {
+                build_state
+                    .modules
+                    .values()
+                    .filter(|module| !module.is_mlmap() && module.compile_outcome.is_some())
+                    .count()
+            });
+            SESSION_STATS.lock().unwrap().record_rebuild(
+                start_rebuild.elapsed(),
+                modules_compiled,
+                &changed_modules,
+            );
+            // only run the after build command when the build succeeded
+            if let (Ok(_), Some(command)) = (build_result, after_build.clone()) {
+                cmd::run(command);
+            }
         }
     }
 }
 
-pub fn start(filter: &Option<regex::Regex>, folder: &str, after_build: Option<String>) {
+/// Watches the project and builds it again on every change. Only the initial build is forced or
+/// limited to the changes since a git ref, the rebuilds aren't.
+pub fn start(
+    folder: &str,
+    build_options: &build::BuildOptions,
+    after_build: Option<String>,
+    poll_interval: Option<Duration>,
+) {
+    let build_options = build::BuildOptions {
+        force: false,
+        changed_from: None,
+        ..build_options.to_owned()
+    };
+    *SESSION_STATS.lock().unwrap() = SessionStats::default();
+    interrupt::start_watching();
     futures::executor::block_on(async {
         let queue = Arc::new(FifoQueue::<Result<Event, Error>>::new());
         let producer = queue.clone();
         let consumer = queue.clone();
 
-        let mut watcher = RecommendedWatcher::new(move |res| producer.push(res), Config::default())
-            .expect("Could not create watcher");
+        // the native events (inotify, FSEvents) are not delivered reliably on network and some
+        // Docker filesystems, so there we poll the modification times instead
+        let mut watcher: Box<dyn Watcher> = match poll_interval {
+            Some(poll_interval) => Box::new(
+                PollWatcher::new(
+                    move |res| producer.push(res),
+                    Config::default().with_poll_interval(poll_interval),
+                )
+                .expect("Could not create watcher"),
+            ),
+            None => Box::new(
+                RecommendedWatcher::new(move |res| producer.push(res), Config::default())
+                    .expect("Could not create watcher"),
+            ),
+        };
         watcher
             .watch(folder.as_ref(), RecursiveMode::Recursive)
             .expect("Could not start watcher");
 
-        if let Err(e) = async_watch(consumer, folder, filter, after_build).await {
+        if let Err(e) = async_watch(consumer, folder, &build_options, after_build).await {
             println!("error: {:?}", e)
         }
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::SessionStats;
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    fn modules(modules: &[&str]) -> BTreeSet<String> {
+        modules.iter().map(|module| module.to_string()).collect()
+    }
+
+    #[test]
+    fn should_summarize_the_rebuilds_of_a_session() {
+        let mut stats = SessionStats::default();
+        assert_eq!(stats.get_summary(), None);
+
+        stats.record_rebuild(Duration::from_millis(200), 3, &modules(&["App", "Button"]));
+        stats.record_rebuild(Duration::from_millis(600), 1, &modules(&["Button"]));
+        stats.record_rebuild(Duration::from_millis(400), 0, &modules(&["App", "Button"]));
+
+        assert_eq!(stats.get_most_changed_module(), Some(("Button", 3)));
+        assert_eq!(
+            stats.get_summary(),
+            Some(
+                "Rebuilt 3 times in 0.40s on average (min 0.20s, max 0.60s), compiling 4 modules\n\
+                 Changed most often: Button (3 times)"
+                    .to_string()
+            )
+        );
+    }
+}
@@ -45,6 +45,31 @@ rewatch build --no-timing=true &> ../tests/snapshots/rename-file-with-interface.
 mv ./packages/main/src/ModuleWithInterface2.res ./packages/main/src/ModuleWithInterface.res
 rewatch build &> /dev/null
 
+# a module with only an interface should compile to a cmi, without a js file
+echo 'type t = {name: string}' > ./packages/main/src/InterfaceOnly.resi
+rewatch build &> /dev/null
+if [[ -f ./packages/main/lib/bs/src/InterfaceOnly.cmi && ! -f ./packages/main/src/InterfaceOnly.mjs ]];
+then
+  success "Interface only module compiled"
+else
+  error "Interface only module not compiled"
+  exit 1
+fi
+rm ./packages/main/src/InterfaceOnly.resi
+rewatch build &> /dev/null
+
+# with --fail-on-warning, a warning doesn't stop bsc from writing the js file, but fails the build
+echo 'let f = () => { let unused = 1; () }' > ./packages/main/src/Warning.res
+if ! rewatch "build --fail-on-warning" &> /dev/null && [[ -f ./packages/main/src/Warning.mjs ]];
+then
+  success "Failed on warning after compiling"
+else
+  error "Did not fail on warning after compiling"
+  exit 1
+fi
+rm ./packages/main/src/Warning.res ./packages/main/src/Warning.mjs
+rewatch build &> /dev/null
+
 # when deleting a file that other files depend on, the compile should fail
 rm packages/dep02/src/Dep02.res
 rewatch build --no-timing=true &> ../tests/snapshots/remove-file.txt
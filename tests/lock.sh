@@ -23,7 +23,7 @@ success "Watcher Started"
 
 sleep 1
 
-if rewatch watch 2>&1 | grep 'Error while trying to get lock:' &> /dev/null; 
+if rewatch watch --no-wait 2>&1 | grep 'Error while trying to get lock:' &> /dev/null; 
 then
   success "Lock is correctly set"
   exit_watcher
@@ -36,7 +36,7 @@ fi
 sleep 1
 
 touch tmp.txt
-rewatch watch &> tmp.txt &
+rewatch watch --no-wait &> tmp.txt &
 success "Watcher Started"
 
 sleep 1